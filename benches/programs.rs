@@ -0,0 +1,30 @@
+use shallot::{
+    bench_programs::{measure, PROGRAMS},
+    *,
+};
+
+create_layer!(atoms | builtins);
+
+/// Timed runs of each program, after one untimed run to warm up
+const RUNS: u32 = 20;
+
+fn main() {
+    // Like other harnesses, `cargo bench -- name` runs only matching programs
+    let filter = std::env::args().skip(1).find(|a| !a.starts_with("--"));
+    let programs = PROGRAMS.iter().filter(|program| {
+        filter
+            .as_ref()
+            .is_none_or(|f| program.name.contains(f.as_str()))
+    });
+    for program in programs {
+        let measurement = measure::<Expression>(program, builtins::set_environment, 1)
+            .and_then(|_| measure::<Expression>(program, builtins::set_environment, RUNS));
+        match measurement {
+            Ok(measurement) => println!("{measurement}"),
+            Err(error) => {
+                eprintln!("{error:#}");
+                std::process::exit(1);
+            }
+        }
+    }
+}
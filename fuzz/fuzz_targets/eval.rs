@@ -0,0 +1,16 @@
+//! Evaluating arbitrary text with the standard builtins must fail with
+//! errors, never panic. Shell and OS builtins stay disabled and input is
+//! captured so that no run touches the outside world or waits on stdin.
+//! Run with `cargo +nightly fuzz run eval`.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use shallot::*;
+
+create_layer!(atoms | builtins);
+
+fuzz_target!(|input: &str| {
+    let mut env: Environment<Expression> = Environment::default();
+    env.io = Io::new(CapturedIo::default());
+    builtins::set_environment(&mut env);
+    let _ = evaluate(input, &mut env);
+});
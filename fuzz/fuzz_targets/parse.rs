@@ -0,0 +1,16 @@
+//! Tokenizing and parsing arbitrary text must fail with errors, never panic.
+//! Run with `cargo +nightly fuzz run parse`.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use shallot::*;
+
+create_layer!(atoms | builtins);
+
+fuzz_target!(|input: &str| {
+    let mut tokens = tokenize(input).peekable();
+    while tokens.peek().is_some() {
+        if Expression::parse(&mut tokens).is_err() {
+            break;
+        }
+    }
+});
@@ -0,0 +1,38 @@
+/* C API of the shallot interpreter, built with its `ffi` feature */
+
+#ifndef SHALLOT_H
+#define SHALLOT_H
+
+#include <stdbool.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct ShallotInterpreter ShallotInterpreter;
+
+/* Names a value held by an interpreter, or no value if zero */
+typedef uint64_t ShallotHandle;
+
+ShallotInterpreter *shallot_new(void);
+void shallot_free(ShallotInterpreter *interpreter);
+
+/* Zero if evaluation failed, see shallot_last_error */
+ShallotHandle shallot_eval(ShallotInterpreter *interpreter, const char *input);
+
+/* Free the result with shallot_free_string */
+char *shallot_get_string(ShallotInterpreter *interpreter, ShallotHandle handle);
+bool shallot_get_number(ShallotInterpreter *interpreter, ShallotHandle handle, double *number);
+void shallot_release(ShallotInterpreter *interpreter, ShallotHandle handle);
+
+/* The error of the last eval or get call, or null if it succeeded.
+   Valid until the next such call. */
+const char *shallot_last_error(ShallotInterpreter *interpreter);
+void shallot_free_string(char *text);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -5,10 +5,10 @@
   (defun ∂ (f x) (/ (- (f (+ x ε)) (f (- x ε))) (* 2 ε)))
   (defun newton-improve (f x) (- x (/ (f x) ((∂ f) x))))
 
-  (defun abs (x) (cond (≤ x 0) (- 0 x) x))
+  (defun abs (x) (cond (<= x 0) (- 0 x) x))
 
   (define 'solve (λ (f self x)
-    (cond (≤ (abs (f x)) ε)
+    (cond (<= (abs (f x)) ε)
       x
       (self (newton-improve f x))
     )
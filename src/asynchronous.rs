@@ -0,0 +1,160 @@
+//! Evaluation which awaits the promises of async builtin functions on the
+//! host's async runtime.
+//!
+//! Evaluation is recursive and cannot itself be suspended, so it runs on a
+//! thread of its own. When it awaits a promise, that thread hands the promise
+//! to the future returned by [`evaluate_async`] and blocks until the future,
+//! polled by whichever runtime the host uses, has run it to completion.
+
+use anyhow::{Context, Result};
+use std::{
+    future::poll_fn,
+    sync::{mpsc, Arc, Mutex, MutexGuard, PoisonError},
+    task::{Poll, Waker},
+};
+
+use crate::{
+    cancellation::CancellationToken, errors::Cancelled, evaluate, Environment, LispExpression,
+    Promise,
+};
+
+/// Stack for each call evaluation may nest, as the default
+/// `Config::max_eval_depth` assumes of an 8MB stack
+const STACK_PER_CALL: usize = 8 * 1024;
+
+/// Stack for the evaluating thread besides its calls
+const BASE_STACK: usize = 1024 * 1024;
+
+enum Event<E> {
+    Await(Promise<E>, mpsc::SyncSender<Result<E>>),
+    Done(Result<E>, Box<Environment<E>>),
+}
+
+struct Exchange<E> {
+    event: Option<Event<E>>,
+    waker: Option<Waker>,
+}
+
+/// Hands promises from an evaluation thread to the `evaluate_async` future
+/// it was started by
+pub(crate) struct Awaiter<E>(Arc<Mutex<Exchange<E>>>);
+
+impl<E> Awaiter<E> {
+    fn lock(&self) -> MutexGuard<'_, Exchange<E>> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn post(&self, event: Event<E>) {
+        let waker = {
+            let mut exchange = self.lock();
+            exchange.event = Some(event);
+            exchange.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+
+    /// Blocks until the host has run `promise`, failing if it stopped waiting
+    pub(crate) fn wait(&self, promise: Promise<E>) -> Result<E> {
+        let (sender, receiver) = mpsc::sync_channel(1);
+        self.post(Event::Await(promise, sender));
+        receiver.recv().map_err(|_| Cancelled)?
+    }
+}
+
+impl<E> Clone for Awaiter<E> {
+    fn clone(&self) -> Self {
+        Awaiter(self.0.clone())
+    }
+}
+
+impl<E> PartialEq for Awaiter<E> {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// Cancels the evaluation if the future is dropped before it finishes
+struct Abandon<E> {
+    awaiter: Awaiter<E>,
+    cancellation: CancellationToken,
+    finished: bool,
+}
+
+impl<E> Drop for Abandon<E> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.cancellation.cancel();
+            // Dropping a posted promise's sender wakes the blocked thread
+            self.awaiter.lock().event = None;
+        }
+    }
+}
+
+/// Evaluates `input` like [`evaluate`], except that `(await promise)` runs the
+/// async builtin call in the promise as part of the returned future.
+///
+/// Evaluation happens on a new thread, so any runtime can poll the future
+/// without being blocked. Its stack is sized for the environment's
+/// `max_eval_depth`, so that deep recursion fails with a `RecursionLimit`
+/// error rather than overflowing. Dropping the future cancels the evaluation
+/// through the environment's cancellation token, which must be reset before
+/// the environment evaluates anything else.
+pub async fn evaluate_async<E>(input: &str, env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    let awaiter = Awaiter(Arc::new(Mutex::new(Exchange {
+        event: None,
+        waker: None,
+    })));
+    let stack_size = env
+        .config
+        .borrow()
+        .max_eval_depth
+        .saturating_mul(STACK_PER_CALL)
+        .saturating_add(BASE_STACK);
+    let mut evaluating = env.clone();
+    evaluating.awaiter = Some(awaiter.clone());
+    let input = input.to_owned();
+    let finish = awaiter.clone();
+    std::thread::Builder::new()
+        .stack_size(stack_size)
+        .spawn(move || {
+            let result = evaluate(&input, &mut evaluating);
+            evaluating.awaiter = None;
+            finish.post(Event::Done(result, Box::new(evaluating)));
+        })
+        .with_context(|| format!("Could not start a thread with a {stack_size} byte stack"))?;
+    let mut abandon = Abandon {
+        awaiter: awaiter.clone(),
+        cancellation: env.cancellation.clone(),
+        finished: false,
+    };
+    loop {
+        let event = poll_fn(|context| {
+            let mut exchange = awaiter.lock();
+            match exchange.event.take() {
+                Some(event) => Poll::Ready(event),
+                None => {
+                    exchange.waker = Some(context.waker().clone());
+                    Poll::Pending
+                }
+            }
+        })
+        .await;
+        match event {
+            Event::Await(promise, reply) => {
+                let result = promise.start().await;
+                // The thread is blocked on the receiver, so this cannot fail
+                let _ = reply.send(result);
+            }
+            Event::Done(result, evaluated) => {
+                abandon.finished = true;
+                *env = *evaluated;
+                return result;
+            }
+        }
+    }
+}
@@ -1,10 +1,29 @@
 use anyhow::{anyhow, bail, ensure, Context, Result};
-use std::{
-    fmt::{Debug, Display},
-    rc::Rc,
+use core::{
+    any::Any,
+    cell::RefCell,
+    cmp::Ordering,
+    fmt::{Debug, Display, Write},
+    future::Future,
+    hash::{Hash, Hasher},
+    ops::Range,
+    pin::Pin,
 };
 
-use crate::{expression::ToAndFrom, token::Token, Environment, LispExpression};
+use crate::{
+    collections::HashMap,
+    environment::allocation_size,
+    errors::{ArityError, IndexOutOfRange},
+    events::EventKind,
+    expression::ToAndFrom,
+    float,
+    global::thread_state,
+    prelude::*,
+    printer,
+    shared::{AnyValue, Mutable, Shared, Threadsafe},
+    token::{Token, TokenKind},
+    Environment, LispExpression,
+};
 
 pub trait Atom<E: LispExpression>: Display {
     // TODO find a better way to do this
@@ -24,6 +43,30 @@ pub trait Atom<E: LispExpression>: Display {
     {
         None
     }
+
+    /// Uncoloured source code which parses back into this atom
+    fn to_source(&self) -> Result<String> {
+        bail!("Cannot write {} as source code", self.name())
+    }
+
+    /// How this atom orders against another expression, or `None` if the two
+    /// cannot be compared
+    fn compare(&self, _other: &E) -> Option<Ordering> {
+        None
+    }
+
+    /// Feeds this atom to a hasher so that it can be a set element. Equal
+    /// atoms must hash the same, and mutable or callable atoms cannot be hashed
+    fn hash_into(&self, _state: &mut dyn Hasher) -> Result<()> {
+        bail!("Cannot hash {}", self.name())
+    }
+}
+
+/// Hashes a value along with the kind of atom it came from, so that e.g. a
+/// symbol and a string with the same text hash differently
+fn hash_tagged(tag: &str, value: &impl Hash, mut state: &mut dyn Hasher) {
+    tag.hash(&mut state);
+    value.hash(&mut state);
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
@@ -40,7 +83,7 @@ impl Symbol {
 }
 
 impl Display for Symbol {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(f, "\x1b[0;32m{}\x1b[0m", self.0)
     }
 }
@@ -59,6 +102,20 @@ impl<E: LispExpression> Atom<E> for Symbol {
     {
         Some(Self(token.value.clone()))
     }
+
+    fn to_source(&self) -> Result<String> {
+        Ok(self.0.clone())
+    }
+
+    fn compare(&self, other: &E) -> Option<Ordering> {
+        let other: &Self = other.try_into_atom().ok()?;
+        self.0.partial_cmp(&other.0)
+    }
+
+    fn hash_into(&self, state: &mut dyn Hasher) -> Result<()> {
+        hash_tagged("symbol", &self.0, state);
+        Ok(())
+    }
 }
 
 impl From<&str> for Symbol {
@@ -67,20 +124,80 @@ impl From<&str> for Symbol {
     }
 }
 
+/// Access to the outside world which a builtin grants, so that operators
+/// can review what untrusted scripts could do
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Capability {
+    /// Reading or writing files
+    Fs,
+    /// Network access
+    Net,
+    /// Starting or stopping processes
+    Proc,
+    /// Environment variables and other process state
+    Env,
+    /// Reading the time or measuring how long something takes
+    Clock,
+}
+
+impl Display for Capability {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let name = match self {
+            Capability::Fs => "fs",
+            Capability::Net => "net",
+            Capability::Proc => "proc",
+            Capability::Env => "env",
+            Capability::Clock => "clock",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Documentation carried by builtins, for help and reference generation
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Doc {
+    /// How to call the builtin, e.g. `(rand-int bound)`
+    pub signature: &'static str,
+    pub description: &'static str,
+    /// Name of the layer which provides the builtin
+    pub layer: &'static str,
+    pub capabilities: &'static [Capability],
+}
+
+/// The Rust function behind a builtin function
+#[cfg(not(feature = "sync"))]
+pub type BuiltinClosure<E> = dyn Fn(&[E], &mut Environment<E>) -> Result<E>;
+/// The Rust function behind a builtin function
+#[cfg(feature = "sync")]
+pub type BuiltinClosure<E> = dyn Fn(&[E], &mut Environment<E>) -> Result<E> + Send + Sync;
+
 #[derive(Clone)]
 pub struct BuiltinFunction<E: 'static> {
     pub name: &'static str,
-    pub function: Rc<dyn Fn(&[E], &mut Environment<E>) -> Result<E>>,
+    pub function: Shared<BuiltinClosure<E>>,
+    pub doc: Doc,
 }
 
 impl<E> BuiltinFunction<E> {
     pub fn new(name: &'static str, function: fn(&[E], &mut Environment<E>) -> Result<E>) -> Self {
         Self {
             name,
-            function: Rc::new(function),
+            function: Shared::new(function),
+            doc: Doc::default(),
         }
     }
 
+    pub fn with_doc(mut self, signature: &'static str, description: &'static str) -> Self {
+        self.doc.signature = signature;
+        self.doc.description = description;
+        self
+    }
+
+    pub fn with_capabilities(mut self, capabilities: &'static [Capability]) -> Self {
+        self.doc.capabilities = capabilities;
+        self
+    }
+
     // TODO What about other function signatures
     pub fn new_wrapped<U: 'static, V: 'static>(
         name: &'static str,
@@ -103,7 +220,8 @@ impl<E> BuiltinFunction<E> {
         };
         Self {
             name,
-            function: Rc::new(wrapped),
+            function: Shared::new(wrapped),
+            doc: Doc::default(),
         }
     }
 }
@@ -132,31 +250,186 @@ impl<E: LispExpression> Atom<E> for BuiltinFunction<E> {
             })
             .collect::<Result<Vec<_>>>()
             .with_context(|| anyhow!("Could not evaluate arguments to {}", self))?;
-        (self.function)(&arguments, env)
+        env.consume_fuel()?;
+        let result = (self.function)(&arguments, env)?;
+        env.charge_allocation(allocation_size(&result))?;
+        Ok(result)
+    }
+
+    fn to_source(&self) -> Result<String> {
+        Ok(self.name.to_owned())
     }
 }
 
 impl<E> Debug for BuiltinFunction<E> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(f, "«builtin function {}»", self.name)
     }
 }
 
 impl<E> Display for BuiltinFunction<E> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// The future an async builtin function's Rust function returns
+pub type AsyncResult<E> = Pin<Box<dyn Future<Output = Result<E>> + Send>>;
+
+/// The Rust function behind an async builtin function
+pub type AsyncClosure<E> = dyn Fn(Vec<E>) -> AsyncResult<E> + Send + Sync;
+
+/// A builtin function implemented by an async Rust function. Calling it does
+/// not run it but returns a promise, which `await` runs on the host's async
+/// runtime when evaluating with `evaluate_async`.
+#[derive(Clone)]
+pub struct AsyncBuiltinFunction<E> {
+    pub name: &'static str,
+    pub function: Shared<AsyncClosure<E>>,
+    pub doc: Doc,
+}
+
+impl<E> AsyncBuiltinFunction<E> {
+    pub fn new<F, R>(name: &'static str, function: F) -> Self
+    where
+        F: Fn(Vec<E>) -> R + Send + Sync + 'static,
+        R: Future<Output = Result<E>> + Send + 'static,
+    {
+        Self {
+            name,
+            function: Shared::new(move |arguments| Box::pin(function(arguments)) as AsyncResult<E>),
+            doc: Doc::default(),
+        }
+    }
+
+    pub fn with_doc(mut self, signature: &'static str, description: &'static str) -> Self {
+        self.doc.signature = signature;
+        self.doc.description = description;
+        self
+    }
+}
+
+impl<E> PartialEq for AsyncBuiltinFunction<E> {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl<E: LispExpression> Atom<E> for AsyncBuiltinFunction<E> {
+    fn sized_name() -> &'static str {
+        "async builtin function"
+    }
+
+    fn name(&self) -> &'static str {
+        "async builtin function"
+    }
+
+    fn call(&self, arguments: &[E], env: &mut Environment<E>) -> Result<E> {
+        let arguments: Vec<E> = arguments
+            .iter()
+            .enumerate()
+            .map(|(n, e)| {
+                e.eval(env)
+                    .with_context(|| anyhow!("Argument number {}: {}", n + 1, e))
+            })
+            .collect::<Result<Vec<_>>>()
+            .with_context(|| anyhow!("Could not evaluate arguments to {}", self))?;
+        env.consume_fuel()?;
+        Ok(Promise {
+            name: self.name,
+            function: self.function.clone(),
+            arguments,
+        }
+        .into())
+    }
+
+    fn to_source(&self) -> Result<String> {
+        Ok(self.name.to_owned())
+    }
+}
+
+impl<E> Debug for AsyncBuiltinFunction<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "«async builtin function {}»", self.name)
+    }
+}
+
+impl<E> Display for AsyncBuiltinFunction<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(f, "{:?}", self)
     }
 }
 
+/// A call of an async builtin function which has not run yet
+#[derive(Clone)]
+pub struct Promise<E> {
+    pub name: &'static str,
+    pub function: Shared<AsyncClosure<E>>,
+    pub arguments: Vec<E>,
+}
+
+impl<E: Clone> Promise<E> {
+    /// Starts the call, giving a future for the host's runtime to poll
+    pub fn start(&self) -> AsyncResult<E> {
+        (self.function)(self.arguments.clone())
+    }
+}
+
+/// Promises are the same only if they call the same function on equal
+/// arguments, as awaiting either then gives the same result
+impl<E: PartialEq> PartialEq for Promise<E> {
+    fn eq(&self, other: &Self) -> bool {
+        Shared::ptr_eq(&self.function, &other.function) && self.arguments == other.arguments
+    }
+}
+
+impl<E> Debug for Promise<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Promise of {}", self.name)
+    }
+}
+
+impl<E> Display for Promise<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "«promise {}»", self.name)
+    }
+}
+
+impl<E: LispExpression> Atom<E> for Promise<E> {
+    fn sized_name() -> &'static str {
+        "promise"
+    }
+
+    fn name(&self) -> &'static str {
+        "promise"
+    }
+}
+
 #[derive(Clone, PartialEq)]
 pub struct BuiltinMacro<E> {
     pub name: &'static str,
     pub function: fn(&[E], &mut Environment<E>) -> Result<E>,
+    pub doc: Doc,
 }
 
 impl<E> BuiltinMacro<E> {
     pub fn new(name: &'static str, function: fn(&[E], &mut Environment<E>) -> Result<E>) -> Self {
-        Self { name, function }
+        Self {
+            name,
+            function,
+            doc: Doc::default(),
+        }
+    }
+
+    pub fn with_doc(mut self, signature: &'static str, description: &'static str) -> Self {
+        self.doc.signature = signature;
+        self.doc.description = description;
+        self
+    }
+
+    pub fn with_capabilities(mut self, capabilities: &'static [Capability]) -> Self {
+        self.doc.capabilities = capabilities;
+        self
     }
 }
 
@@ -172,25 +445,311 @@ impl<E: LispExpression> Atom<E> for BuiltinMacro<E> {
     fn call(&self, arguments: &[E], env: &mut Environment<E>) -> Result<E> {
         (self.function)(arguments, env)
     }
+
+    fn to_source(&self) -> Result<String> {
+        Ok(self.name.to_owned())
+    }
 }
 
 impl<E> Debug for BuiltinMacro<E> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(f, "«builtin macro {}»", self.name)
     }
 }
 
 impl<E> Display for BuiltinMacro<E> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(f, "{:?}", self)
     }
 }
 
+/// Positional argument values, then keyword arguments with their values
+type CallArguments<'a, E> = (Vec<E>, Vec<(&'a Keyword, E)>);
+
+/// Evaluates the arguments of a lambda call, separating those passed by name
+/// as `:parameter value` from positional ones. Only a keyword written
+/// literally in the call names a parameter, so `(f ':k)` passes `:k` itself.
+fn evaluate_call_arguments<'a, E>(
+    arguments: &'a [E],
+    env: &mut Environment<E>,
+) -> Result<CallArguments<'a, E>>
+where
+    E: LispExpression,
+{
+    let mut positional = Vec::new();
+    let mut named = Vec::new();
+    let mut arguments = arguments.iter().enumerate();
+    while let Some((n, e)) = arguments.next() {
+        if let Ok(keyword) = ToAndFrom::<Keyword>::try_into_atom(e) {
+            let (n, e) = arguments
+                .next()
+                .with_context(|| anyhow!("Keyword argument :{} has no value", keyword.0))?;
+            let value = e
+                .eval(env)
+                .with_context(|| anyhow!("Argument number {}: {}", n + 1, e))?;
+            named.push((keyword, value));
+        } else {
+            let value = e
+                .eval(env)
+                .with_context(|| anyhow!("Argument number {}: {}", n + 1, e))?;
+            positional.push(value);
+        }
+    }
+    Ok((positional, named))
+}
+
+/// Matches the arguments of a call to a lambda or macro with its parameters,
+/// giving each parameter its argument if it has one. Positional arguments
+/// fill parameters in order and keyword arguments fill those of their name.
+fn bind_arguments<E>(
+    callee: &'static str,
+    name: &Option<Symbol>,
+    parameters: &[Symbol],
+    positional: Vec<E>,
+    named: Vec<(&Keyword, E)>,
+) -> Result<Vec<Option<E>>> {
+    if positional.len() > parameters.len() {
+        return Err(ArityError {
+            callee,
+            name: name.clone(),
+            parameters: parameters.to_vec(),
+            given: positional.len(),
+        }
+        .into());
+    }
+    let mut bound: Vec<Option<E>> = positional.into_iter().map(Some).collect();
+    bound.resize_with(parameters.len(), || None);
+    for (keyword, value) in named {
+        let index = parameters
+            .iter()
+            .position(|parameter| parameter.0 == keyword.0)
+            .with_context(|| {
+                let parameters: Vec<&str> = parameters.iter().map(|p| p.0.as_str()).collect();
+                anyhow!(
+                    "Unknown keyword argument :{}, expected one of: {}",
+                    keyword.0,
+                    parameters.join(" ")
+                )
+            })?;
+        ensure!(
+            bound[index].is_none(),
+            "Parameter {} was given more than once",
+            keyword.0
+        );
+        bound[index] = Some(value);
+    }
+    Ok(bound)
+}
+
+/// Binds every member of a recursive group in `env`, lambdas joined to the
+/// group so that they in turn see it when called
+pub(crate) fn bind_recursive<E>(group: &Shared<Vec<(Symbol, E)>>, env: &mut Environment<E>)
+where
+    E: LispExpression,
+{
+    for (name, value) in group.iter() {
+        let value = match ToAndFrom::<Lambda<E>>::try_into_atom(value) {
+            Ok(lambda) => Lambda {
+                recursive: Some(group.clone()),
+                ..lambda.clone()
+            }
+            .into(),
+            Err(_) => value.clone(),
+        };
+        env.set(name.clone(), value);
+    }
+}
+
+/// Binds the variables of `pattern` to the parts of `value` in `env`. A
+/// symbol binds the whole value, except `_` which ignores it, and a list such
+/// as `(a (b c) rest...)` binds the elements of a list, its final `name...`
+/// taking any left over.
+pub(crate) fn bind_pattern<E>(pattern: &E, value: E, env: &mut Environment<E>) -> Result<()>
+where
+    E: LispExpression,
+{
+    if let Ok(symbol) = pattern.as_symbol() {
+        if symbol.0 != "_" {
+            env.set(symbol.clone(), value);
+        }
+        return Ok(());
+    }
+    let patterns = &pattern
+        .as_list()
+        .with_context(|| anyhow!("Cannot bind to {pattern}, only to symbols and lists of them"))?
+        .0;
+    let elements = ElementPatterns::new(patterns);
+    let values = &value
+        .as_list()
+        .map_err(|_| anyhow!("Expected a list to bind {pattern}, got {value}"))?
+        .0;
+    if !elements.fit(values) {
+        match elements.rest {
+            Some(_) => bail!(
+                "Expected a list of at least {} elements to bind {pattern}, got {value}",
+                elements.fixed.len()
+            ),
+            None => bail!(
+                "Expected a list of {} elements to bind {pattern}, got {value}",
+                elements.fixed.len()
+            ),
+        }
+    }
+    for (pattern, value) in elements.fixed.iter().zip(values.iter()) {
+        bind_pattern(pattern, value.clone(), env)?;
+    }
+    if let Some(rest) = elements.rest {
+        env.set(rest, List::new(elements.rest_of(values).to_vec()));
+    }
+    Ok(())
+}
+
+/// The element patterns of a list or vector pattern: those matching one
+/// element each, and the name of a final `name...` taking any left over
+pub(crate) struct ElementPatterns<'a, E> {
+    pub fixed: &'a [E],
+    pub rest: Option<&'a str>,
+}
+
+impl<'a, E: LispExpression> ElementPatterns<'a, E> {
+    pub fn new(patterns: &'a [E]) -> Self {
+        let rest = patterns
+            .last()
+            .and_then(|last| last.as_symbol().ok())
+            .and_then(|last| last.0.strip_suffix("..."));
+        ElementPatterns {
+            fixed: &patterns[..patterns.len() - usize::from(rest.is_some())],
+            rest,
+        }
+    }
+
+    /// Whether there are as many values as the patterns take
+    pub fn fit(&self, values: &[E]) -> bool {
+        match self.rest {
+            Some(_) => values.len() >= self.fixed.len(),
+            None => values.len() == self.fixed.len(),
+        }
+    }
+
+    /// The values left over for the rest, of values which `fit`
+    pub fn rest_of<'v>(&self, values: &'v [E]) -> &'v [E] {
+        &values[self.fixed.len()..]
+    }
+}
+
+/// The variables `pattern` binds, see `bind_pattern`
+pub(crate) fn pattern_variables<E: LispExpression>(pattern: &E) -> Vec<Symbol> {
+    if let Ok(symbol) = pattern.as_symbol() {
+        let name = symbol.0.strip_suffix("...").unwrap_or(&symbol.0);
+        return match name {
+            "_" => Vec::new(),
+            name => vec![Symbol::from(name)],
+        };
+    }
+    match pattern.as_list() {
+        Ok(list) => list.0.iter().flat_map(pattern_variables).collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// A lambda parameter, optionally with a default expression written as
+/// `(name default)` in the parameter list. A parameter may instead be a
+/// pattern written `[a (b c) rest...]` destructuring its argument as a list,
+/// see `bind_pattern`.
 #[derive(Clone, PartialEq)]
+pub struct Parameter<E> {
+    /// The name keyword arguments use, or the pattern's text
+    pub name: Symbol,
+    pub default: Option<E>,
+    pub pattern: Option<E>,
+}
+
+impl<E> From<Symbol> for Parameter<E> {
+    fn from(name: Symbol) -> Self {
+        Parameter {
+            name,
+            default: None,
+            pattern: None,
+        }
+    }
+}
+
+impl<E: LispExpression> Parameter<E> {
+    /// Binds the parameter, or the variables of its pattern, to `argument`
+    pub fn bind(&self, argument: E, env: &mut Environment<E>) -> Result<()> {
+        match &self.pattern {
+            Some(pattern) => bind_pattern(pattern, argument, env),
+            None => {
+                env.set(self.name.clone(), argument);
+                Ok(())
+            }
+        }
+    }
+
+    /// The variables binding the parameter introduces
+    pub fn variables(&self) -> Vec<Symbol> {
+        match &self.pattern {
+            Some(pattern) => pattern_variables(pattern),
+            None => vec![self.name.clone()],
+        }
+    }
+
+    fn to_source(&self) -> Result<String> {
+        let name = match self.pattern.as_ref().map(E::as_list) {
+            Some(Ok(pattern)) => {
+                let elements: Vec<String> = pattern
+                    .0
+                    .iter()
+                    .map(|e| e.as_atom().to_source())
+                    .collect::<Result<_>>()?;
+                format!("[{}]", elements.join(" "))
+            }
+            _ => self.name.0.clone(),
+        };
+        match &self.default {
+            Some(default) => Ok(format!("({} {})", name, default.as_atom().to_source()?)),
+            None => Ok(name),
+        }
+    }
+}
+
+impl<E: Display> Display for Parameter<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match &self.default {
+            Some(default) => write!(f, "({} {})", self.name, default),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Lambda<E> {
-    pub parameters: Vec<Symbol>,
-    pub value: Box<E>,
-    pub env: Environment<E>,
+    pub parameters: Vec<Parameter<E>>,
+    pub value: Shared<E>,
+    pub env: Shared<Environment<E>>,
+    /// The name `define` first bound it to, shown in errors and when displayed
+    pub name: Option<Symbol>,
+    /// The functions of a `letrec` or `defun` which the body sees by name,
+    /// itself included. They are rebound with the same group on each call,
+    /// so that closures need not contain themselves.
+    pub recursive: Option<Shared<Vec<(Symbol, E)>>>,
+}
+
+/// Lambdas are equal with the same code closing over the same environment.
+/// Environments are compared by reference, as they reach the globals which
+/// hold the lambda itself.
+impl<E: PartialEq> PartialEq for Lambda<E> {
+    fn eq(&self, other: &Self) -> bool {
+        self.parameters == other.parameters
+            && self.value == other.value
+            && Shared::ptr_eq(&self.env, &other.env)
+    }
+}
+
+impl<E> Lambda<E> {
+    fn parameter_names(&self) -> Vec<Symbol> {
+        self.parameters.iter().map(|p| p.name.clone()).collect()
+    }
 }
 
 impl<E: LispExpression> Atom<E> for Lambda<E> {
@@ -203,54 +762,103 @@ impl<E: LispExpression> Atom<E> for Lambda<E> {
     }
 
     fn call(&self, arguments: &[E], env: &mut Environment<E>) -> Result<E> {
-        let arguments: Vec<E> = arguments
-            .iter()
-            .enumerate()
-            .map(|(n, e)| {
-                e.eval(env)
-                    .with_context(|| anyhow!("Argument number {}: {}", n + 1, e))
-            })
-            .collect::<Result<Vec<_>>>()
+        let (positional, named) = evaluate_call_arguments(arguments, env)
             .with_context(|| anyhow!("Could not evaluate arguments to {}", self))?;
-        ensure!(
-            arguments.len() <= self.parameters.len(),
-            "Too many arguments to lambda"
-        );
-        let mut env: Environment<E> = self.env.clone();
-        for (parameter, argument) in self.parameters.iter().zip(&arguments) {
-            env.set(parameter.clone(), argument.clone())
-        }
-        if arguments.len() < self.parameters.len() {
-            Ok(Lambda {
-                parameters: self.parameters[arguments.len()..].to_vec(),
-                env,
+        let bound = bind_arguments(
+            "lambda",
+            &self.name,
+            &self.parameter_names(),
+            positional,
+            named,
+        )?;
+
+        env.charge_allocation(self.parameters.len())?;
+        let mut env = self.env.scope();
+        if let Some(group) = &self.recursive {
+            bind_recursive(group, &mut env);
+        }
+        let mut unbound = Vec::new();
+        for (parameter, argument) in self.parameters.iter().zip(bound) {
+            match argument {
+                Some(argument) => parameter
+                    .bind(argument, &mut env)
+                    .with_context(|| anyhow!("Could not bind parameter {}", parameter.name))?,
+                None => unbound.push(parameter.clone()),
+            }
+        }
+        // Without every parameter lacking a default, this is a partial
+        // application and defaults wait until the remaining call
+        if unbound.iter().any(|parameter| parameter.default.is_none()) {
+            return Ok(Lambda {
+                parameters: unbound,
+                env: Shared::new(env),
                 value: self.value.clone(),
+                name: self.name.clone(),
+                recursive: self.recursive.clone(),
+            }
+            .into());
+        }
+        // Defaults see the closure environment and the given arguments
+        for parameter in unbound {
+            if let Some(default) = &parameter.default {
+                let value = default
+                    .eval(&mut env)
+                    .with_context(|| anyhow!("Could not evaluate default of {}", parameter.name))?;
+                parameter
+                    .bind(value, &mut env)
+                    .with_context(|| anyhow!("Could not bind parameter {}", parameter.name))?;
             }
-            .into())
-        } else {
-            self.value.eval(&mut env)
         }
+        self.value.eval(&mut env)
+    }
+
+    fn to_source(&self) -> Result<String> {
+        let parameters: Vec<String> = self
+            .parameters
+            .iter()
+            .map(Parameter::to_source)
+            .collect::<Result<_>>()?;
+        Ok(format!(
+            "(λ ({}) {})",
+            parameters.join(" "),
+            self.value.as_atom().to_source()?
+        ))
     }
 }
 
 impl<E> Debug for Lambda<E> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(f, "Lambda function")
     }
 }
 
 impl<E: Display> Display for Lambda<E> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         let parameters: Vec<String> = self.parameters.iter().map(|e| e.to_string()).collect();
-        write!(f, "λ ({}) {}", parameters.join(" "), self.value)
+        write!(f, "λ ")?;
+        if let Some(name) = &self.name {
+            write!(f, "{name} ")?;
+        }
+        write!(f, "({}) {}", parameters.join(" "), self.value)
     }
 }
 
-#[derive(Clone, PartialEq)]
+#[derive(Clone)]
 pub struct Macro<E> {
-    pub parameters: Vec<Symbol>,
-    pub value: Box<E>,
-    pub env: Environment<E>,
+    pub parameters: Vec<Parameter<E>>,
+    pub value: Shared<E>,
+    pub env: Shared<Environment<E>>,
+    /// The name `define` first bound it to, shown in errors and when displayed
+    pub name: Option<Symbol>,
+}
+
+/// Macros are equal as lambdas are
+impl<E: PartialEq> PartialEq for Macro<E> {
+    fn eq(&self, other: &Self) -> bool {
+        self.parameters == other.parameters
+            && self.value == other.value
+            && Shared::ptr_eq(&self.env, &other.env)
+    }
 }
 
 impl<E: LispExpression> Atom<E> for Macro<E> {
@@ -263,41 +871,73 @@ impl<E: LispExpression> Atom<E> for Macro<E> {
     }
 
     fn call(&self, arguments: &[E], env: &mut Environment<E>) -> Result<E> {
-        ensure!(
-            arguments.len() <= self.parameters.len(),
-            "Too many arguments to lambda"
-        );
+        // Macros take their arguments unevaluated, keywords included
+        let names: Vec<Symbol> = self.parameters.iter().map(|p| p.name.clone()).collect();
+        let bound = bind_arguments("macro", &self.name, &names, arguments.to_vec(), Vec::new())?;
 
-        let mut macro_env: Environment<E> = self.env.clone();
-        for (parameter, argument) in self.parameters.iter().zip(arguments) {
-            macro_env.set(parameter.clone(), argument.clone())
+        let mut macro_env = self.env.scope();
+        let mut unbound = Vec::new();
+        for (parameter, argument) in self.parameters.iter().zip(bound) {
+            match argument {
+                Some(argument) => parameter
+                    .bind(argument, &mut macro_env)
+                    .with_context(|| anyhow!("Could not bind parameter {}", parameter.name))?,
+                None => unbound.push(parameter.clone()),
+            }
         }
-        if arguments.len() < self.parameters.len() {
+        if !unbound.is_empty() {
             Ok(Macro {
-                parameters: self.parameters[arguments.len()..].to_vec(),
-                env: macro_env,
+                parameters: unbound,
+                env: Shared::new(macro_env),
                 value: self.value.clone(),
+                name: self.name.clone(),
             }
             .into())
         } else {
-            self.value
+            let _depth = env.enter_macro()?;
+            let expansion = self
+                .value
                 .eval(&mut macro_env)
-                .context("Could not expand macro")?
-                .eval(env)
+                .context("Could not expand macro")?;
+            if env.events.is_active() {
+                let kind = EventKind::MacroExpand {
+                    name: self.name.clone(),
+                    expansion: expansion.clone(),
+                };
+                env.events.emit(kind, env);
+            }
+            expansion.eval(env)
         }
     }
+
+    fn to_source(&self) -> Result<String> {
+        let parameters: Vec<String> = self
+            .parameters
+            .iter()
+            .map(Parameter::to_source)
+            .collect::<Result<_>>()?;
+        Ok(format!(
+            "(μ ({}) {})",
+            parameters.join(" "),
+            self.value.as_atom().to_source()?
+        ))
+    }
 }
 
 impl<E> Debug for Macro<E> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(f, "Macro function")
     }
 }
 
 impl<E: Display> Display for Macro<E> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         let parameters: Vec<String> = self.parameters.iter().map(|e| e.to_string()).collect();
-        write!(f, "μ ({}) {}", parameters.join(" "), self.value)
+        write!(f, "μ ")?;
+        if let Some(name) = &self.name {
+            write!(f, "{name} ")?;
+        }
+        write!(f, "({}) {}", parameters.join(" "), self.value)
     }
 }
 
@@ -305,9 +945,52 @@ impl<E: Display> Display for Macro<E> {
 pub struct Number(pub f64);
 
 impl Display for Number {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "\x1b[0;36m{}\x1b[0m", self.0)
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "\x1b[0;36m{}\x1b[0m", printer::number_text(self.0))
+    }
+}
+
+/// Reads a number literal: anything `f64::from_str` takes, such as `-5` or
+/// `1e6`, or a `0x` hexadecimal or `0b` binary integer, optionally signed.
+/// Digits may be grouped with `_` between them, as in `1_000_000`.
+pub(crate) fn parse_number(text: &str) -> Option<f64> {
+    let (negative, unsigned) = match text.strip_prefix('-') {
+        Some(unsigned) => (true, unsigned),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let radix = match unsigned.get(..2) {
+        Some("0x" | "0X") => Some(16),
+        Some("0b" | "0B") => Some(2),
+        _ => None,
+    };
+    let Some(radix) = radix else {
+        return without_separators(text, 10)?.parse().ok();
+    };
+    let digits = without_separators(&unsigned[2..], radix)?;
+    // `from_str_radix` takes a sign of its own, as in `0x-1` or `0x+1`
+    if digits.starts_with(['+', '-']) {
+        return None;
+    }
+    let value = u128::from_str_radix(&digits, radix).ok()? as f64;
+    Some(if negative { -value } else { value })
+}
+
+/// The text with each `_` which is between two digits removed, or `None` if
+/// there is any other `_`
+fn without_separators(text: &str, radix: u32) -> Option<String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut kept = String::with_capacity(text.len());
+    for (n, c) in chars.iter().enumerate() {
+        if *c != '_' {
+            kept.push(*c);
+            continue;
+        }
+        let digit = |c: Option<&char>| c.is_some_and(|c| c.is_digit(radix));
+        if n == 0 || !digit(chars.get(n - 1)) || !digit(chars.get(n + 1)) {
+            return None;
+        }
     }
+    Some(kept)
 }
 
 impl<E: LispExpression> Atom<E> for Number {
@@ -323,50 +1006,1127 @@ impl<E: LispExpression> Atom<E> for Number {
     where
         Self: Sized,
     {
-        token.value.parse().ok().map(Self)
+        parse_number(&token.value).map(Self)
+    }
+
+    fn to_source(&self) -> Result<String> {
+        Ok(printer::number_source(self.0))
+    }
+
+    fn compare(&self, other: &E) -> Option<Ordering> {
+        let other: &Self = other.try_into_atom().ok()?;
+        self.0.partial_cmp(&other.0)
+    }
+
+    fn hash_into(&self, state: &mut dyn Hasher) -> Result<()> {
+        // NaN is not equal to itself, so a set could never find it again
+        ensure!(
+            !self.0.is_nan(),
+            "Cannot hash NaN, as it is not equal to itself"
+        );
+        // 0 and -0 are equal so must hash the same
+        let number = if self.0 == 0. { 0. } else { self.0 };
+        hash_tagged("number", &number.to_bits(), state);
+        Ok(())
     }
 }
 
-#[derive(Clone, Debug, PartialEq, PartialOrd)]
-pub struct List<E>(pub Vec<E>);
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Str(pub String);
 
-impl<E: Display> Display for List<E> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let elements: Vec<String> = self.0.iter().map(|e| e.to_string()).collect();
-        write!(f, "({})", elements.join(" "))
+impl Display for Str {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "\x1b[0;33m{:?}\x1b[0m", self.0)
     }
 }
 
-impl<E: LispExpression> Atom<E> for List<E> {
+impl<E: LispExpression> Atom<E> for Str {
     fn sized_name() -> &'static str {
-        "list"
+        "string"
     }
 
     fn name(&self) -> &'static str {
-        "list"
+        "string"
     }
 
-    fn call(&self, arguments: &[E], _env: &mut Environment<E>) -> Result<E> {
-        // TODO should this be the case?
-        ensure!(
-            arguments.len() == 1,
-            "Cannot index array using more than one index"
-        );
-        if let Ok(number) = <E as ToAndFrom<Number>>::try_into_atom(&arguments[0]) {
-            if number.0 < 0. || number.0 > self.0.len() as f64 - 1.0 {
-                bail!(
-                    "Cannot index array of length {} at {}",
-                    self.0.len(),
-                    number
-                );
-            }
-            let index: usize = number.0 as usize;
-            Ok(self.0[index].clone())
-        } else {
-            bail!(
-                "Can only index into list using numbers, not {}",
-                arguments[0]
-            )
+    fn parse_from_token(token: &Token) -> Option<Self>
+    where
+        Self: Sized,
+    {
+        if token.kind != TokenKind::StringLiteral {
+            return None;
+        }
+        // The tokenizer has already resolved escapes but kept the quotes
+        token
+            .value
+            .strip_prefix('"')
+            .and_then(|v| v.strip_suffix('"'))
+            .map(|v| Self(v.to_owned()))
+    }
+
+    fn to_source(&self) -> Result<String> {
+        Ok(format!(
+            "\"{}\"",
+            self.0.replace('\\', "\\\\").replace('"', "\\\"")
+        ))
+    }
+
+    fn compare(&self, other: &E) -> Option<Ordering> {
+        let other: &Self = other.try_into_atom().ok()?;
+        self.0.partial_cmp(&other.0)
+    }
+
+    fn hash_into(&self, state: &mut dyn Hasher) -> Result<()> {
+        hash_tagged("string", &self.0, state);
+        Ok(())
+    }
+}
+
+impl From<&str> for Str {
+    fn from(value: &str) -> Self {
+        Str(value.to_owned())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Char(pub char);
+
+/// Characters written by name rather than as themselves after `#\`
+const CHAR_NAMES: [(&str, char); 4] = [
+    ("space", ' '),
+    ("newline", '\n'),
+    ("tab", '\t'),
+    ("nul", '\0'),
+];
+
+impl Char {
+    fn source(&self) -> String {
+        match CHAR_NAMES.iter().find(|(_, c)| *c == self.0) {
+            Some((name, _)) => format!("#\\{name}"),
+            None => format!("#\\{}", self.0),
+        }
+    }
+}
+
+impl Display for Char {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "\x1b[0;34m{}\x1b[0m", self.source())
+    }
+}
+
+impl<E: LispExpression> Atom<E> for Char {
+    fn sized_name() -> &'static str {
+        "char"
+    }
+
+    fn name(&self) -> &'static str {
+        "char"
+    }
+
+    fn parse_from_token(token: &Token) -> Option<Self>
+    where
+        Self: Sized,
+    {
+        let rest = token.value.strip_prefix("#\\")?;
+        let mut chars = rest.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Some(Char(c)),
+            _ => CHAR_NAMES
+                .iter()
+                .find(|(name, _)| *name == rest)
+                .map(|(_, c)| Char(*c)),
+        }
+    }
+
+    fn to_source(&self) -> Result<String> {
+        Ok(self.source())
+    }
+
+    fn compare(&self, other: &E) -> Option<Ordering> {
+        let other: &Self = other.try_into_atom().ok()?;
+        self.0.partial_cmp(&other.0)
+    }
+
+    fn hash_into(&self, state: &mut dyn Hasher) -> Result<()> {
+        hash_tagged("char", &self.0, state);
+        Ok(())
+    }
+}
+
+/// A symbol written with a leading `:` which evaluates to itself, for use as
+/// a map key or argument name. The colon is not part of the stored name.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Keyword(pub String);
+
+impl Display for Keyword {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "\x1b[0;35m:{}\x1b[0m", self.0)
+    }
+}
+
+impl<E: LispExpression> Atom<E> for Keyword {
+    fn sized_name() -> &'static str {
+        "keyword"
+    }
+
+    fn name(&self) -> &'static str {
+        "keyword"
+    }
+
+    fn to_source(&self) -> Result<String> {
+        Ok(format!(":{}", self.0))
+    }
+
+    fn compare(&self, other: &E) -> Option<Ordering> {
+        let other: &Self = other.try_into_atom().ok()?;
+        self.0.partial_cmp(&other.0)
+    }
+
+    fn hash_into(&self, state: &mut dyn Hasher) -> Result<()> {
+        hash_tagged("keyword", &self.0, state);
+        Ok(())
+    }
+}
+
+impl From<&str> for Keyword {
+    fn from(value: &str) -> Self {
+        Keyword(value.to_owned())
+    }
+}
+
+/// The first element of a lazy sequence and the rest, or `None` when empty
+pub type LazyStep<E> = Option<(E, LazySeq<E>)>;
+
+/// Computes a lazy sequence's first step, given the environment forcing it
+#[cfg(not(feature = "sync"))]
+pub type Thunk<E> = Box<dyn FnOnce(&mut Environment<E>) -> Result<LazyStep<E>>>;
+/// Computes a lazy sequence's first step, given the environment forcing it
+#[cfg(feature = "sync")]
+pub type Thunk<E> = Box<dyn FnOnce(&mut Environment<E>) -> Result<LazyStep<E>> + Send + Sync>;
+
+enum LazyState<E> {
+    Pending(Thunk<E>),
+    Forcing,
+    Forced(LazyStep<E>),
+    Failed(String),
+}
+
+thread_state! {
+    /// Addresses of the lazy sequences this thread is forcing, to catch one
+    /// which needs its own elements
+    static FORCING_SEQUENCES: RefCell<Vec<usize>> = RefCell::new(Vec::new());
+}
+
+/// A sequence whose elements are computed when first needed and then
+/// remembered, so it may be infinite
+pub struct LazySeq<E>(Shared<Mutable<LazyState<E>>>);
+
+impl<E: LispExpression> LazySeq<E> {
+    pub fn new(
+        thunk: impl FnOnce(&mut Environment<E>) -> Result<LazyStep<E>> + Threadsafe + 'static,
+    ) -> Self {
+        LazySeq(Shared::new(Mutable::new(LazyState::Pending(Box::new(
+            thunk,
+        )))))
+    }
+
+    pub fn empty() -> Self {
+        LazySeq(Shared::new(Mutable::new(LazyState::Forced(None))))
+    }
+
+    pub fn cons(first: E, rest: LazySeq<E>) -> Self {
+        LazySeq(Shared::new(Mutable::new(LazyState::Forced(Some((
+            first, rest,
+        ))))))
+    }
+
+    pub fn from_vec(elements: Vec<E>) -> Self {
+        elements
+            .into_iter()
+            .rev()
+            .fold(LazySeq::empty(), |rest, first| LazySeq::cons(first, rest))
+    }
+
+    /// Forces the first element, returning it with the rest of the sequence.
+    /// The sequence stays locked while it is forced, so that threads sharing
+    /// it wait for one of them to compute the element rather than see it half
+    /// done.
+    pub fn next(&self, env: &mut Environment<E>) -> Result<LazyStep<E>> {
+        let pointer = Shared::as_ptr(&self.0) as *const () as usize;
+        // Locking again on the thread which holds the lock would never return
+        if FORCING_SEQUENCES.with(|forcing| forcing.borrow().contains(&pointer)) {
+            bail!("Lazy sequence needs its own elements to compute them");
+        }
+        if let LazyState::Forced(step) = &*self.0.borrow() {
+            return Ok(step.clone());
+        }
+        let mut state = self.0.borrow_mut();
+        match core::mem::replace(&mut *state, LazyState::Forcing) {
+            LazyState::Pending(thunk) => {
+                FORCING_SEQUENCES.with(|forcing| forcing.borrow_mut().push(pointer));
+                let step = thunk(env);
+                FORCING_SEQUENCES.with(|forcing| forcing.borrow_mut().retain(|p| *p != pointer));
+                *state = match &step {
+                    Ok(step) => LazyState::Forced(step.clone()),
+                    Err(e) => LazyState::Failed(format!("{e:#}")),
+                };
+                step
+            }
+            // Another thread forced it while this one waited for the lock
+            LazyState::Forced(step) => {
+                *state = LazyState::Forced(step.clone());
+                Ok(step)
+            }
+            LazyState::Failed(error) => {
+                let message = format!("Lazy sequence failed earlier: {error}");
+                *state = LazyState::Failed(error);
+                bail!(message)
+            }
+            LazyState::Forcing => unreachable!("Sequences are only forcing while locked"),
+        }
+    }
+
+    /// Forces up to `count` elements into a vector
+    pub fn take(&self, count: usize, env: &mut Environment<E>) -> Result<Vec<E>> {
+        let mut elements = Vec::new();
+        let mut rest = self.clone();
+        while elements.len() < count {
+            env.check_allocation(elements.len() + 1)?;
+            let Some((first, next)) = rest.next(env)? else {
+                break;
+            };
+            elements.push(first);
+            rest = next;
+        }
+        Ok(elements)
+    }
+}
+
+impl<E> Clone for LazySeq<E> {
+    fn clone(&self) -> Self {
+        LazySeq(self.0.clone())
+    }
+}
+
+// Forced sequences are chains of nested cells, so dropping a long one
+// recursively could overflow the stack
+impl<E> Drop for LazySeq<E> {
+    fn drop(&mut self) {
+        let mut next = unlink_rest(self);
+        while let Some(mut seq) = next {
+            next = unlink_rest(&mut seq);
+        }
+    }
+}
+
+/// Detaches the rest of a forced sequence nothing else refers to
+fn unlink_rest<E>(seq: &mut LazySeq<E>) -> Option<LazySeq<E>> {
+    if Shared::strong_count(&seq.0) != 1 {
+        return None;
+    }
+    let mut state = seq.0.try_borrow_mut().ok()?;
+    match core::mem::replace(&mut *state, LazyState::Forcing) {
+        LazyState::Forced(Some((_, rest))) => Some(rest),
+        _ => None,
+    }
+}
+
+impl<E> PartialEq for LazySeq<E> {
+    fn eq(&self, other: &Self) -> bool {
+        Shared::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl<E> Debug for LazySeq<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Lazy sequence")
+    }
+}
+
+impl<E> Display for LazySeq<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "«lazy sequence»")
+    }
+}
+
+impl<E: LispExpression> Atom<E> for LazySeq<E> {
+    fn sized_name() -> &'static str {
+        "lazy sequence"
+    }
+
+    fn name(&self) -> &'static str {
+        "lazy sequence"
+    }
+}
+
+/// A mutable box made with `(box value)`. Clones share the box, so closures
+/// can keep state in one even though they capture copies of environments.
+pub struct Cell<E>(pub Shared<Mutable<E>>);
+
+impl<E> Cell<E> {
+    pub fn new(value: E) -> Self {
+        Cell(Shared::new(Mutable::new(value)))
+    }
+}
+
+impl<E> Clone for Cell<E> {
+    fn clone(&self) -> Self {
+        Cell(self.0.clone())
+    }
+}
+
+/// Boxes are the same only if they are the same box
+impl<E> PartialEq for Cell<E> {
+    fn eq(&self, other: &Self) -> bool {
+        Shared::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl<E> Debug for Cell<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Box")
+    }
+}
+
+thread_state! {
+    /// Addresses of the boxes and vectors whose contents are being displayed
+    /// or written as source, to stop at cycles
+    static DISPLAYING_BOXES: RefCell<Vec<usize>> = RefCell::new(Vec::new());
+}
+
+impl<E: Display> Display for Cell<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let pointer = Shared::as_ptr(&self.0) as *const () as usize;
+        let cycle = DISPLAYING_BOXES.with(|boxes| boxes.borrow().contains(&pointer));
+        let Ok(value) = self.0.try_borrow() else {
+            return write!(f, "«box»");
+        };
+        if cycle {
+            return write!(f, "«box ...»");
+        }
+        DISPLAYING_BOXES.with(|boxes| boxes.borrow_mut().push(pointer));
+        let result = write!(f, "«box {}»", value);
+        DISPLAYING_BOXES.with(|boxes| boxes.borrow_mut().pop());
+        result
+    }
+}
+
+impl<E: LispExpression> Atom<E> for Cell<E> {
+    fn sized_name() -> &'static str {
+        "box"
+    }
+
+    fn name(&self) -> &'static str {
+        "box"
+    }
+}
+
+/// An array written `[a b c]`, which unlike a list is data rather than code:
+/// evaluating a vector makes a new one of its evaluated elements. Copies share
+/// the elements, so changes by `push`, `pop` and `vec-set!` are seen through
+/// every copy.
+pub struct Vector<E>(pub Shared<Mutable<Vec<E>>>);
+
+impl<E> Vector<E> {
+    pub fn new(elements: Vec<E>) -> Self {
+        Vector(Shared::new(Mutable::new(elements)))
+    }
+}
+
+impl<E> Clone for Vector<E> {
+    fn clone(&self) -> Self {
+        Vector(self.0.clone())
+    }
+}
+
+/// Vectors are the same only if they are the same vector
+impl<E> PartialEq for Vector<E> {
+    fn eq(&self, other: &Self) -> bool {
+        Shared::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl<E> Debug for Vector<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Vector")
+    }
+}
+
+impl<E: Display> Display for Vector<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let pointer = Shared::as_ptr(&self.0) as *const () as usize;
+        let cycle = DISPLAYING_BOXES.with(|boxes| boxes.borrow().contains(&pointer));
+        let Ok(elements) = self.0.try_borrow() else {
+            return write!(f, "[...]");
+        };
+        if cycle {
+            return write!(f, "[...]");
+        }
+        DISPLAYING_BOXES.with(|boxes| boxes.borrow_mut().push(pointer));
+        let mut result = write!(f, "[");
+        for (n, element) in elements.iter().enumerate() {
+            if n > 0 {
+                result = result.and_then(|_| write!(f, " "));
+            }
+            result = result.and_then(|_| write!(f, "{element}"));
+        }
+        DISPLAYING_BOXES.with(|boxes| boxes.borrow_mut().pop());
+        result.and_then(|_| write!(f, "]"))
+    }
+}
+
+/// What calling a list or vector selects: the element at an index, or with
+/// a start and an end the elements from the start up to but excluding the end
+enum Selection {
+    Element(usize),
+    Slice(Range<usize>),
+}
+
+/// Evaluates the index arguments of a call to a sequence. Its length is taken
+/// only afterwards, as evaluating them may change the sequence.
+fn select<E: LispExpression>(
+    arguments: &[E],
+    length: impl FnOnce() -> usize,
+    env: &mut Environment<E>,
+) -> Result<Selection> {
+    match arguments {
+        [index] => {
+            let index = index.eval(env)?;
+            Ok(Selection::Element(sequence_index(&index, length(), false)?))
+        }
+        [start, end] => {
+            let (start, end) = (start.eval(env)?, end.eval(env)?);
+            let length = length();
+            let start = sequence_index(&start, length, true)?;
+            let end = sequence_index(&end, length, true)?;
+            Ok(Selection::Slice(start..end.max(start)))
+        }
+        _ => bail!(
+            "Cannot index with {} arguments, only an index or the start and end of a slice",
+            arguments.len()
+        ),
+    }
+}
+
+/// An index into a sequence of `length`, where -1 is the last element. As the
+/// end of a slice an index may also be the length itself.
+pub(crate) fn sequence_index<E: LispExpression>(
+    value: &E,
+    length: usize,
+    is_bound: bool,
+) -> Result<usize> {
+    let number = ToAndFrom::<Number>::try_into_atom(value)
+        .map_err(|_| anyhow!("Can only index using numbers, not {value}"))?;
+    ensure!(
+        float::fract(number.0) == 0. && number.0.is_finite(),
+        "Can only index using whole numbers, not {}",
+        number.0
+    );
+    // Resolved without converting to an integer first, which would saturate
+    // and report a different index than was given
+    let index = number.0;
+    let resolved = if index < 0. {
+        index + length as f64
+    } else {
+        index
+    };
+    let end = if is_bound { length + 1 } else { length };
+    if !(0. ..end as f64).contains(&resolved) {
+        return Err(IndexOutOfRange { index, length }.into());
+    }
+    Ok(resolved as usize)
+}
+
+impl<E: LispExpression> Atom<E> for Vector<E> {
+    fn sized_name() -> &'static str {
+        "vector"
+    }
+
+    fn name(&self) -> &'static str {
+        "vector"
+    }
+
+    fn call(&self, arguments: &[E], env: &mut Environment<E>) -> Result<E> {
+        let selection = select(arguments, || self.0.borrow().len(), env)?;
+        let elements = self.0.borrow();
+        let out_of_range = |index: usize| IndexOutOfRange {
+            index: index as f64,
+            length: elements.len(),
+        };
+        match selection {
+            Selection::Element(index) => match elements.get(index) {
+                Some(element) => Ok(element.clone()),
+                None => Err(out_of_range(index).into()),
+            },
+            Selection::Slice(range) => match elements.get(range.clone()) {
+                Some(slice) => Ok(Vector::new(slice.to_vec()).into()),
+                None => Err(out_of_range(range.end).into()),
+            },
+        }
+    }
+
+    fn to_source(&self) -> Result<String> {
+        let pointer = Shared::as_ptr(&self.0) as *const () as usize;
+        let cycle = DISPLAYING_BOXES.with(|boxes| boxes.borrow().contains(&pointer));
+        ensure!(
+            !cycle,
+            "Cannot write a vector which contains itself as source"
+        );
+        DISPLAYING_BOXES.with(|boxes| boxes.borrow_mut().push(pointer));
+        let elements: Result<Vec<String>> = self
+            .0
+            .borrow()
+            .iter()
+            .map(|e| e.as_atom().to_source())
+            .collect();
+        DISPLAYING_BOXES.with(|boxes| boxes.borrow_mut().pop());
+        Ok(format!("[{}]", elements?.join(" ")))
+    }
+}
+
+/// A function made by `defgeneric` which calls the method for the kind of
+/// its first argument. Copies share the method table, so methods added later
+/// are seen by closures which captured the function earlier.
+#[derive(Clone)]
+pub struct Generic<E> {
+    pub name: Symbol,
+    pub methods: Shared<Mutable<HashMap<String, E>>>,
+}
+
+impl<E> Generic<E> {
+    pub fn new(name: Symbol) -> Self {
+        Generic {
+            name,
+            methods: Default::default(),
+        }
+    }
+}
+
+/// The reader macro prefix which reads as a list headed by `head`
+fn reader_prefix<E: LispExpression>(head: &E) -> Option<&'static str> {
+    match head.as_symbol().ok()?.0.as_str() {
+        "'" => Some("'"),
+        "quasiquote" => Some("`"),
+        "unquote" => Some(","),
+        _ => None,
+    }
+}
+
+/// Dispatches on a record's or foreign value's type name, then the kind of
+/// atom, then `default`
+fn dispatch_keys<E: LispExpression>(value: &E) -> Vec<String> {
+    let mut keys = Vec::new();
+    if let Ok(record) = ToAndFrom::<Record<E>>::try_into_atom(value) {
+        keys.push(record.kind.name.0.clone());
+    }
+    if let Ok(foreign) = ToAndFrom::<Foreign>::try_into_atom(value) {
+        keys.push(foreign.type_name.to_owned());
+    }
+    keys.push(value.variant().to_owned());
+    keys.push("default".to_owned());
+    keys
+}
+
+impl<E> PartialEq for Generic<E> {
+    fn eq(&self, other: &Self) -> bool {
+        Shared::ptr_eq(&self.methods, &other.methods)
+    }
+}
+
+impl<E> Debug for Generic<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Generic function {}", self.name.0)
+    }
+}
+
+impl<E> Display for Generic<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let mut kinds: Vec<String> = self.methods.borrow().keys().cloned().collect();
+        kinds.sort();
+        write!(f, "«generic {} ({})»", self.name.0, kinds.join(", "))
+    }
+}
+
+impl<E: LispExpression> Atom<E> for Generic<E> {
+    fn sized_name() -> &'static str {
+        "generic function"
+    }
+
+    fn name(&self) -> &'static str {
+        "generic function"
+    }
+
+    fn call(&self, arguments: &[E], env: &mut Environment<E>) -> Result<E> {
+        let arguments: Vec<E> = arguments
+            .iter()
+            .enumerate()
+            .map(|(n, e)| {
+                e.eval(env)
+                    .with_context(|| anyhow!("Argument number {}: {}", n + 1, e))
+            })
+            .collect::<Result<Vec<_>>>()
+            .with_context(|| anyhow!("Could not evaluate arguments to {}", self))?;
+        let first = arguments
+            .first()
+            .with_context(|| anyhow!("Generic function {} needs an argument", self.name.0))?;
+        let keys = dispatch_keys(first);
+        let method = keys
+            .iter()
+            .find_map(|key| self.methods.borrow().get(key).cloned())
+            .with_context(|| {
+                anyhow!(
+                    "Generic function {} has no method for a {}",
+                    self.name.0,
+                    keys[0]
+                )
+            })?;
+        crate::builtins::apply(&method, &arguments, env)
+    }
+}
+
+/// A value of the embedding program, such as a database connection, which
+/// Lisp code can only pass around and give to the builtins that know its
+/// type. It has the name of its type, e.g. `connection`, which generic
+/// functions dispatch on. Copies share the value.
+#[derive(Clone)]
+pub struct Foreign {
+    pub type_name: &'static str,
+    pub value: Shared<AnyValue>,
+}
+
+impl Foreign {
+    pub fn new<T: Any + Threadsafe>(type_name: &'static str, value: T) -> Self {
+        Foreign {
+            type_name,
+            value: Shared::new(value),
+        }
+    }
+
+    /// The value if it is a `T`
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        self.value.downcast_ref()
+    }
+}
+
+/// Foreign values are the same only if they are the same value
+impl PartialEq for Foreign {
+    fn eq(&self, other: &Self) -> bool {
+        Shared::ptr_eq(&self.value, &other.value)
+    }
+}
+
+impl Debug for Foreign {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Foreign({})", self.type_name)
+    }
+}
+
+impl Display for Foreign {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "«foreign {}»", self.type_name)
+    }
+}
+
+impl<E: LispExpression> Atom<E> for Foreign {
+    fn sized_name() -> &'static str {
+        "foreign"
+    }
+
+    fn name(&self) -> &'static str {
+        "foreign"
+    }
+}
+
+/// The name and field names of a kind of record, made by `defstruct`
+#[derive(Debug, PartialEq)]
+pub struct RecordType {
+    pub name: Symbol,
+    pub fields: Vec<Symbol>,
+}
+
+/// An instance of a `defstruct` type, with a value for each field
+#[derive(Clone, Debug)]
+pub struct Record<E> {
+    pub kind: Shared<RecordType>,
+    pub values: Vec<E>,
+}
+
+impl<E> Record<E> {
+    pub fn get(&self, field: &str) -> Option<&E> {
+        let index = self.kind.fields.iter().position(|f| f.0 == field)?;
+        self.values.get(index)
+    }
+}
+
+/// Records of types defined separately differ even with the same name
+impl<E: PartialEq> PartialEq for Record<E> {
+    fn eq(&self, other: &Self) -> bool {
+        Shared::ptr_eq(&self.kind, &other.kind) && self.values == other.values
+    }
+}
+
+impl<E: Display> Display for Record<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "«{}", self.kind.name.0)?;
+        for (field, value) in self.kind.fields.iter().zip(&self.values) {
+            write!(f, " :{} {}", field.0, value)?;
+        }
+        write!(f, "»")
+    }
+}
+
+impl<E: LispExpression> Atom<E> for Record<E> {
+    fn sized_name() -> &'static str {
+        "record"
+    }
+
+    fn name(&self) -> &'static str {
+        "record"
+    }
+
+    fn hash_into(&self, state: &mut dyn Hasher) -> Result<()> {
+        hash_tagged("record", &self.kind.name.0, state);
+        for value in &self.values {
+            value.as_atom().hash_into(state)?;
+        }
+        Ok(())
+    }
+}
+
+/// An error made by `(error "message" data ...)`, carrying values which
+/// describe what went wrong for the code which catches it
+#[derive(Clone, Debug, PartialEq)]
+pub struct ErrorValue<E> {
+    pub message: String,
+    pub data: Vec<E>,
+}
+
+impl<E: Display> Display for ErrorValue<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "«error {:?}", self.message)?;
+        for value in &self.data {
+            write!(f, " {}", value)?;
+        }
+        write!(f, "»")
+    }
+}
+
+impl<E: LispExpression> Atom<E> for ErrorValue<E> {
+    fn sized_name() -> &'static str {
+        "error"
+    }
+
+    fn name(&self) -> &'static str {
+        "error"
+    }
+
+    fn to_source(&self) -> Result<String> {
+        let mut parts = vec!["error".to_owned(), format!("{:?}", self.message)];
+        for value in &self.data {
+            parts.push(value.as_atom().to_source()?);
+        }
+        Ok(format!("({})", parts.join(" ")))
+    }
+}
+
+/// An unordered collection of distinct hashable values, displayed in the
+/// order they were first added
+#[derive(Clone, Debug)]
+pub struct Set<E> {
+    elements: Vec<E>,
+    /// Indices into `elements` by hash
+    index: HashMap<u64, Vec<usize>>,
+}
+
+impl<E> Default for Set<E> {
+    fn default() -> Self {
+        Set {
+            elements: Vec::new(),
+            index: HashMap::new(),
+        }
+    }
+}
+
+impl<E: LispExpression> Set<E> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the value unless an equal one is present, returning whether it was added
+    pub fn insert(&mut self, value: E) -> Result<bool> {
+        let hash = value.hash_value()?;
+        let bucket = self.index.entry(hash).or_default();
+        if bucket.iter().any(|&n| self.elements[n] == value) {
+            return Ok(false);
+        }
+        bucket.push(self.elements.len());
+        self.elements.push(value);
+        Ok(true)
+    }
+
+    pub fn contains(&self, value: &E) -> Result<bool> {
+        let hash = value.hash_value()?;
+        Ok(self
+            .index
+            .get(&hash)
+            .is_some_and(|bucket| bucket.iter().any(|&n| &self.elements[n] == value)))
+    }
+
+    pub fn iter(&self) -> core::slice::Iter<'_, E> {
+        self.elements.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.elements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+}
+
+impl<E: LispExpression> PartialEq for Set<E> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self
+                .iter()
+                .all(|value| other.contains(value).unwrap_or(false))
+    }
+}
+
+impl<E: Display> Display for Set<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "«set")?;
+        for value in &self.elements {
+            write!(f, " {value}")?;
+        }
+        write!(f, "»")
+    }
+}
+
+impl<E: LispExpression> Atom<E> for Set<E> {
+    fn sized_name() -> &'static str {
+        "set"
+    }
+
+    fn name(&self) -> &'static str {
+        "set"
+    }
+
+    /// Independent of the order of the elements, as equality is
+    fn hash_into(&self, state: &mut dyn Hasher) -> Result<()> {
+        let sum = self.index.iter().fold(0u64, |sum, (hash, bucket)| {
+            sum.wrapping_add(hash.wrapping_mul(bucket.len() as u64))
+        });
+        hash_tagged("set", &(self.len(), sum), state);
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct List<E>(pub Shared<Vec<E>>);
+
+// Compared with an explicit stack rather than recursively, so that deeply
+// nested lists cannot overflow it, and shared lists without walking them
+impl<E: LispExpression> PartialEq for List<E> {
+    fn eq(&self, other: &Self) -> bool {
+        let mut stack = vec![(self, other)];
+        while let Some((a, b)) = stack.pop() {
+            if Shared::ptr_eq(&a.0, &b.0) {
+                continue;
+            }
+            if a.0.len() != b.0.len() {
+                return false;
+            }
+            for (a, b) in a.0.iter().zip(b.0.iter()) {
+                match (a.as_list(), b.as_list()) {
+                    (Ok(a), Ok(b)) => stack.push((a, b)),
+                    _ if a != b => return false,
+                    _ => {}
+                }
+            }
+        }
+        true
+    }
+}
+
+impl<E> List<E> {
+    /// Clones of a list share its elements, so passing one around is cheap
+    pub fn new(elements: Vec<E>) -> Self {
+        List(Shared::new(elements))
+    }
+}
+
+/// Lists nested deeper than this are displayed as `...`
+pub const MAX_DISPLAY_DEPTH: usize = 256;
+
+impl<E: LispExpression> Display for List<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        // Walk the nested lists with an explicit stack rather than recursing,
+        // so that pathologically deep structures cannot overflow. Printing
+        // stops when it runs out of the budget of `with_print_budget`.
+        let mut f = CountedWriter { out: f, written: 0 };
+        let mut stack = vec![(self.0.iter(), true)];
+        write!(f, "(")?;
+        while let Some((elements, first)) = stack.last_mut() {
+            if !printer::spend_print(core::mem::take(&mut f.written)) {
+                return Ok(());
+            }
+            let Some(element) = elements.next() else {
+                write!(f, ")")?;
+                stack.pop();
+                continue;
+            };
+            if !core::mem::replace(first, false) {
+                write!(f, " ")?;
+            }
+            match element.as_list() {
+                Ok(_) if stack.len() >= MAX_DISPLAY_DEPTH => write!(f, "...")?,
+                Ok(list) => {
+                    write!(f, "(")?;
+                    stack.push((list.0.iter(), true));
+                }
+                Err(_) => write!(f, "{element}")?,
+            }
+        }
+        printer::spend_print(f.written);
+        Ok(())
+    }
+}
+
+impl<E: LispExpression> Atom<E> for List<E> {
+    fn sized_name() -> &'static str {
+        "list"
+    }
+
+    fn name(&self) -> &'static str {
+        "list"
+    }
+
+    fn call(&self, arguments: &[E], env: &mut Environment<E>) -> Result<E> {
+        match select(arguments, || self.0.len(), env)? {
+            Selection::Element(index) => Ok(self.0[index].clone()),
+            Selection::Slice(range) => Ok(List::new(self.0[range].to_vec()).into()),
+        }
+    }
+
+    fn to_source(&self) -> Result<String> {
+        // Walk the nested lists with an explicit stack, as `Display` does
+        let mut source = String::new();
+        let mut stack = Vec::new();
+        open_list_source(self, &mut source, &mut stack)?;
+        let mut spent = 0;
+        while let Some((elements, first)) = stack.last_mut() {
+            if !printer::spend_print(source.len() - core::mem::replace(&mut spent, source.len())) {
+                bail!("Ran out of budget to write a list with");
+            }
+            let Some(element) = elements.next() else {
+                source.push(')');
+                stack.pop();
+                continue;
+            };
+            if !core::mem::replace(first, false) {
+                source.push(' ');
+            }
+            match element.as_list() {
+                Ok(list) => open_list_source(list, &mut source, &mut stack)?,
+                Err(_) => source.push_str(&element.as_atom().to_source()?),
+            }
+        }
+        ensure!(
+            printer::spend_print(source.len() - spent),
+            "Ran out of budget to write a list with"
+        );
+        Ok(source)
+    }
+
+    /// Lexicographic, so a prefix orders before the longer list. Nested
+    /// lists are compared with an explicit stack, as for equality.
+    fn compare(&self, other: &E) -> Option<Ordering> {
+        let mut stack = vec![(self, other.as_list().ok()?, 0)];
+        while let Some((a, b, index)) = stack.last_mut() {
+            let (a, b) = (*a, *b);
+            if Shared::ptr_eq(&a.0, &b.0) {
+                stack.pop();
+                continue;
+            }
+            let (Some(x), Some(y)) = (a.0.get(*index), b.0.get(*index)) else {
+                match a.0.len().cmp(&b.0.len()) {
+                    Ordering::Equal => {
+                        stack.pop();
+                        continue;
+                    }
+                    ordering => return Some(ordering),
+                }
+            };
+            *index += 1;
+            match (x.as_list(), y.as_list()) {
+                (Ok(x), Ok(y)) => stack.push((x, y, 0)),
+                _ => match x.as_atom().compare(y)? {
+                    Ordering::Equal => {}
+                    ordering => return Some(ordering),
+                },
+            }
+        }
+        Some(Ordering::Equal)
+    }
+
+    fn hash_into(&self, state: &mut dyn Hasher) -> Result<()> {
+        hash_tagged("list", &self.0.len(), state);
+        let mut stack = vec![self.0.iter()];
+        while let Some(elements) = stack.last_mut() {
+            let Some(element) = elements.next() else {
+                stack.pop();
+                continue;
+            };
+            match element.as_list() {
+                Ok(list) => {
+                    hash_tagged("list", &list.0.len(), state);
+                    stack.push(list.0.iter());
+                }
+                Err(_) => element.as_atom().hash_into(state)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Counts the bytes written through it, for `List` to spend from the print
+/// budget as it displays
+struct CountedWriter<'a, W> {
+    out: &'a mut W,
+    written: usize,
+}
+
+impl<W: Write> Write for CountedWriter<'_, W> {
+    fn write_str(&mut self, text: &str) -> core::fmt::Result {
+        self.written += text.len();
+        self.out.write_str(text)
+    }
+}
+
+/// Writes the opening of a list for `List::to_source` and pushes its
+/// elements to be written, or writes all of a quoted atom. What reader macros
+/// read back the same way is written with their prefix, e.g. `(' x)` as `'x`.
+fn open_list_source<'a, E: LispExpression>(
+    mut list: &'a List<E>,
+    source: &mut String,
+    stack: &mut Vec<(core::slice::Iter<'a, E>, bool)>,
+) -> Result<()> {
+    while let [head, quoted] = list.0.as_slice() {
+        let Some(prefix) = reader_prefix(head) else {
+            break;
+        };
+        source.push_str(prefix);
+        match quoted.as_list() {
+            Ok(quoted) => list = quoted,
+            Err(_) => {
+                source.push_str(&quoted.as_atom().to_source()?);
+                return Ok(());
+            }
         }
     }
+    source.push('(');
+    stack.push((list.0.iter(), true));
+    Ok(())
 }
@@ -0,0 +1,49 @@
+//! The table of accessors which `get-attr` reads the attributes of foreign
+//! values with, by their type name. Embedders add one per type rather than
+//! a builtin per attribute.
+
+use crate::{
+    prelude::*,
+    shared::{Mutable, Shared},
+    Foreign,
+};
+
+/// Reads an attribute of a foreign value by name, or gives `None` if it has
+/// no such attribute
+pub type Accessor<E> = fn(&Foreign, &str) -> Option<E>;
+
+type AccessorTable<E> = Vec<(&'static str, Accessor<E>)>;
+
+/// Accessors by type name, such as `connection`. Clones share one table,
+/// like `ReaderMacros`.
+#[derive(Clone)]
+pub struct Accessors<E>(Shared<Mutable<AccessorTable<E>>>);
+
+impl<E> Default for Accessors<E> {
+    fn default() -> Self {
+        Accessors(Shared::new(Mutable::new(Vec::new())))
+    }
+}
+
+impl<E> PartialEq for Accessors<E> {
+    fn eq(&self, other: &Self) -> bool {
+        Shared::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl<E> Accessors<E> {
+    /// Adds the accessor of a type, replacing any it had
+    pub fn set(&self, type_name: &'static str, accessor: Accessor<E>) {
+        let mut accessors = self.0.borrow_mut();
+        accessors.retain(|(existing, _)| *existing != type_name);
+        accessors.push((type_name, accessor));
+    }
+
+    pub fn get(&self, type_name: &str) -> Option<Accessor<E>> {
+        self.0
+            .borrow()
+            .iter()
+            .find(|(existing, _)| *existing == type_name)
+            .map(|(_, accessor)| *accessor)
+    }
+}
@@ -0,0 +1,41 @@
+use alloc::collections::{BTreeMap, BTreeSet};
+use anyhow::{anyhow, Context, Result};
+
+use crate::{
+    collections::HashSet, environment::referenced_symbols, prelude::*, token::skip_comments,
+    tokenize, Capability, Environment, LispExpression,
+};
+
+/// Builtins granting each capability which `source` refers to, found without
+/// evaluating it. Names built at run time cannot be seen, so this is a review
+/// aid rather than a sandbox.
+pub fn audit<E: LispExpression>(
+    source: &str,
+    env: &Environment<E>,
+) -> Result<BTreeMap<Capability, BTreeSet<String>>> {
+    let mut tokens = tokenize(source).peekable();
+    let mut symbols = HashSet::new();
+    loop {
+        skip_comments(&mut tokens);
+        if tokens.peek().is_none() {
+            break;
+        }
+        let expression =
+            E::parse(&mut tokens).with_context(|| anyhow!("Could not parse {}", source))?;
+        referenced_symbols(&expression, &mut symbols);
+    }
+
+    let mut report: BTreeMap<Capability, BTreeSet<String>> = BTreeMap::new();
+    for builtin in env.builtins() {
+        if !symbols.contains(builtin.symbol) {
+            continue;
+        }
+        for capability in builtin.doc.capabilities {
+            report
+                .entry(*capability)
+                .or_default()
+                .insert(builtin.symbol.0.clone());
+        }
+    }
+    Ok(report)
+}
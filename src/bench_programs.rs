@@ -0,0 +1,125 @@
+//! Programs which exercise different parts of the interpreter, so that
+//! performance work can be compared on the same workloads across changes.
+//! `cargo bench` times each of them; pass a name to run only those matching.
+
+use anyhow::{bail, ensure, Context, Result};
+use std::{
+    fmt::Display,
+    time::{Duration, Instant},
+};
+
+use crate::{evaluate, printer::strip_colours, Environment, LispExpression};
+
+/// A benchmark and the value it should evaluate to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BenchProgram {
+    pub name: &'static str,
+    /// What it spends its time on
+    pub description: &'static str,
+    pub source: &'static str,
+    /// The value displayed without colours
+    pub expected: &'static str,
+}
+
+pub const PROGRAMS: &[BenchProgram] = &[
+    BenchProgram {
+        name: "fib",
+        description: "Lambda calls and arithmetic",
+        source: "((λ (f n) (f f n))
+                  (λ (self n)
+                    (cond (<= n 1) n
+                          (+ (self self (- n 1)) (self self (- n 2)))))
+                  18)",
+        expected: "2584",
+    },
+    BenchProgram {
+        name: "ackermann",
+        description: "Deeply nested calls",
+        source: "((λ (f m n) (f f m n))
+                  (λ (self m n)
+                    (cond (= m 0) (+ n 1)
+                          (= n 0) (self self (- m 1) 1)
+                          (self self (- m 1) (self self m (- n 1)))))
+                  2 8)",
+        expected: "19",
+    },
+    BenchProgram {
+        name: "list-sort",
+        description: "Building lists and sorting them with a Lisp comparison",
+        source: "(take 5
+                   (sort-by (λ (a b) (<= b a))
+                            (map (λ (x) (remainder (* x 7919) 1000)) (range 2000))))",
+        expected: "(999 999 998 998 997)",
+    },
+    BenchProgram {
+        name: "macro-heavy",
+        description: "Expanding a macro on every call",
+        source: "((list
+                   (define 'my-if (μ (c a b) (list 'cond c a b)))
+                   ((λ (f n) (f f n))
+                    (λ (self n)
+                      (my-if (<= n 1) n (+ (self self (- n 1)) (self self (- n 2)))))
+                    15))
+                  1)",
+        expected: "610",
+    },
+];
+
+/// How long repeated runs of a program took
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Measurement {
+    pub program: &'static str,
+    pub runs: u32,
+    pub fastest: Duration,
+    pub mean: Duration,
+}
+
+impl Display for Measurement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:<12} min {:>10.3?}  mean {:>10.3?}  ({} runs)",
+            self.program, self.fastest, self.mean, self.runs
+        )
+    }
+}
+
+/// Evaluates the program `runs` times, each in a fresh environment with the
+/// layer's builtins which is not counted in the time, failing if it does not
+/// give the expected value
+pub fn measure<E>(
+    program: &BenchProgram,
+    layer: fn(&mut Environment<E>),
+    runs: u32,
+) -> Result<Measurement>
+where
+    E: LispExpression,
+{
+    ensure!(runs > 0, "A benchmark needs at least one run");
+    let mut fastest = Duration::MAX;
+    let mut total = Duration::ZERO;
+    for run in 1..=runs {
+        let mut env = Environment::default();
+        layer(&mut env);
+        let start = Instant::now();
+        let value = evaluate(program.source, &mut env)
+            .with_context(|| format!("Run {run} of {} failed", program.name))?;
+        let elapsed = start.elapsed();
+        let value = strip_colours(&value.to_string());
+        if value != program.expected {
+            bail!(
+                "{} gave {value} rather than {}",
+                program.name,
+                program.expected
+            );
+        }
+        fastest = fastest.min(elapsed);
+        total += elapsed;
+    }
+    Ok(Measurement {
+        program: program.name,
+        runs,
+        fastest,
+        mean: total / runs,
+    })
+}
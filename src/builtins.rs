@@ -1,8 +1,43 @@
 use crate::{
+    atoms::{bind_pattern, bind_recursive},
+    collections::HashSet,
+    errors::ReturnFrom,
     expression::{LispExpression, ToAndFrom},
-    BuiltinFunction, BuiltinMacro, Environment, Lambda, List, Macro, Number, Symbol,
+    float,
+    prelude::*,
+    printer::{display_text, write_text},
+    shared::Shared,
+    token::{skip_comments, tokenize},
+    tracer, BuiltinFunction, BuiltinMacro, Capability, Cell, Char, Environment, Keyword, Lambda,
+    LayerInfo, List, Macro, Number, Parameter, Str, Symbol, Vector,
 };
 use anyhow::{anyhow, bail, ensure, Context, Result};
+use core::cmp::Ordering;
+#[cfg(feature = "std")]
+use std::time::{Duration, Instant};
+
+pub mod attributes;
+pub mod conditions;
+pub mod datetime;
+pub mod format;
+pub mod generics;
+pub mod json;
+pub mod lazy;
+pub mod lists;
+pub mod matching;
+pub mod math;
+#[cfg(feature = "std")]
+pub mod os;
+#[cfg(feature = "std")]
+pub mod process;
+#[cfg(feature = "async")]
+pub mod promises;
+pub mod records;
+#[cfg(feature = "regex")]
+pub mod regex;
+pub mod sets;
+pub mod testing;
+pub mod vectors;
 
 fn expressions_to_homogeneous<E, T>(expressions: &[E]) -> Result<Vec<&T>>
 where
@@ -18,17 +53,29 @@ where
         .collect()
 }
 
+/// Calls `function` on already evaluated arguments
+pub fn apply<E>(function: &E, arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    // Quote the arguments, as calling evaluates them
+    let arguments: Vec<E> = arguments
+        .iter()
+        .map(|argument| {
+            List::new(vec![BuiltinMacro::new("'", quote).into(), argument.clone()]).into()
+        })
+        .collect();
+    tracer::call(function, function, &arguments, env)
+}
+
 pub fn le<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
 where
     E: LispExpression + ToAndFrom<Number>,
 {
     let arguments: Vec<&Number> =
-        expressions_to_homogeneous(arguments).context("Arguments to add are not all numbers")?;
-    let arguments: Vec<f64> = arguments.into_iter().map(|n| n.0).collect();
-    for i in 0..arguments.len() - 1 {
-        if arguments[i] > arguments[i + 1] {
-            return Ok(E::null());
-        }
+        expressions_to_homogeneous(arguments).context("Arguments to <= are not all numbers")?;
+    if arguments.windows(2).any(|pair| pair[0].0 > pair[1].0) {
+        return Ok(E::null());
     }
     Ok(Number(1.).into())
 }
@@ -81,6 +128,99 @@ where
     }
 }
 
+/// Integer arguments, as bitwise operations and integer division need, though
+/// results beyond 2^53 lose precision as numbers are floats
+fn integer_arguments<E>(arguments: &[E], name: &str) -> Result<Vec<i64>>
+where
+    E: LispExpression,
+{
+    arguments
+        .iter()
+        .enumerate()
+        .map(|(n, e)| {
+            e.extract()
+                .with_context(|| anyhow!("Argument number {}: {}", n + 1, e))
+        })
+        .collect::<Result<_>>()
+        .with_context(|| anyhow!("Arguments to {name} should be integers"))
+}
+
+pub fn bit_and<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    let integers = integer_arguments(arguments, "bit-and")?;
+    Ok(Number(integers.into_iter().fold(-1, |a, b| a & b) as f64).into())
+}
+
+pub fn bit_or<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    let integers = integer_arguments(arguments, "bit-or")?;
+    Ok(Number(integers.into_iter().fold(0, |a, b| a | b) as f64).into())
+}
+
+pub fn bit_xor<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    let integers = integer_arguments(arguments, "bit-xor")?;
+    Ok(Number(integers.into_iter().fold(0, |a, b| a ^ b) as f64).into())
+}
+
+pub fn bit_shift<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 2,
+        "Bit-shift must be called on exactly two arguments"
+    );
+    let [n, shift] = integer_arguments(arguments, "bit-shift")?[..] else {
+        unreachable!()
+    };
+    // Shifting by the width or more would panic, so saturate instead
+    let shifted = match shift {
+        0.. if shift < 64 => n << shift,
+        0.. => 0,
+        _ if shift > -64 => n >> -shift,
+        _ => n >> 63,
+    };
+    Ok(Number(shifted as f64).into())
+}
+
+fn integer_division<E>(arguments: &[E], name: &str) -> Result<(i64, i64)>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 2,
+        "{name} must be called on exactly two arguments"
+    );
+    let [dividend, divisor] = integer_arguments(arguments, name)?[..] else {
+        unreachable!()
+    };
+    ensure!(divisor != 0, "{name} by zero");
+    Ok((dividend, divisor))
+}
+
+pub fn quotient<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    let (dividend, divisor) = integer_division(arguments, "Quotient")?;
+    Ok(Number(dividend.wrapping_div(divisor) as f64).into())
+}
+
+pub fn remainder<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    let (dividend, divisor) = integer_division(arguments, "Remainder")?;
+    Ok(Number(dividend.wrapping_rem(divisor) as f64).into())
+}
+
 pub fn eq<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
 where
     E: LispExpression,
@@ -88,8 +228,9 @@ where
     if let Some(first) = arguments.first() {
         let mut last = first;
         for elt in arguments[1..].iter() {
-            if elt != last {
-                return Ok(List(vec![]).into());
+            // Identical references are equal without comparing their contents
+            if !core::ptr::eq(elt, last) && elt != last {
+                return Ok(List::new(vec![]).into());
             }
             last = elt;
         }
@@ -99,11 +240,41 @@ where
     }
 }
 
+pub fn eq_p<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 2,
+        "Eq? must be called on exactly two arguments"
+    );
+    if arguments[0].is_identical(&arguments[1]) {
+        Ok(Number(1.).into())
+    } else {
+        Ok(E::null())
+    }
+}
+
+pub fn equal_p<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 2,
+        "Equal? must be called on exactly two arguments"
+    );
+    if arguments[0].is_equal(&arguments[1]) {
+        Ok(Number(1.).into())
+    } else {
+        Ok(E::null())
+    }
+}
+
 pub fn list<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
 where
     E: LispExpression,
 {
-    Ok(List(arguments.to_vec()).into())
+    Ok(List::new(arguments.to_vec()).into())
 }
 
 pub fn define<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
@@ -114,11 +285,95 @@ where
     let symbol: &Symbol = arguments[0]
         .try_into_atom()
         .context("First argument to define should be a symbol")?;
-    env.set(symbol.clone(), arguments[1].clone());
+    env.check_redefinable(symbol)?;
+    env.define(symbol.clone(), named(&arguments[1], symbol));
     // This will never be None because we just set it
     env.get(symbol).cloned().ok_or_else(|| unreachable!())
 }
 
+/// A lambda or macro with no name yet given `name`, or else `value` as it is
+fn named<E: LispExpression>(value: &E, name: &Symbol) -> E {
+    if let Ok(lambda @ Lambda { name: None, .. }) = ToAndFrom::<Lambda<E>>::try_into_atom(value) {
+        Lambda {
+            name: Some(name.clone()),
+            ..lambda.clone()
+        }
+        .into()
+    } else if let Ok(macr @ Macro { name: None, .. }) = ToAndFrom::<Macro<E>>::try_into_atom(value)
+    {
+        Macro {
+            name: Some(name.clone()),
+            ..macr.clone()
+        }
+        .into()
+    } else {
+        value.clone()
+    }
+}
+
+/// Like `define` but also rebinds protected builtins
+pub fn define_unsafe<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(arguments.len() == 2, "Set!-unsafe requires two arguments");
+    let symbol = arguments[0]
+        .as_symbol()
+        .context("Set!-unsafe needs a symbol")?;
+    let value = named(&arguments[1], symbol);
+    env.define(symbol.clone(), value.clone());
+    Ok(value)
+}
+
+pub fn bindings<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(arguments.is_empty(), "Env must be called on no arguments");
+    let mut symbols: Vec<&Symbol> = env.inner.keys().collect();
+    symbols.sort();
+    let pairs = symbols
+        .into_iter()
+        .map(|symbol| List::new(vec![symbol.clone().into(), env.inner[symbol].clone()]).into())
+        .collect();
+    Ok(List::new(pairs).into())
+}
+
+pub fn bound_p<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 1,
+        "Bound? must be called on exactly one argument"
+    );
+    let symbol = arguments[0].as_symbol().context("Bound? needs a symbol")?;
+    if env.lookup(symbol).is_some() {
+        Ok(Number(1.).into())
+    } else {
+        Ok(E::null())
+    }
+}
+
+pub fn undefine<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 1,
+        "Undefine! must be called on exactly one argument"
+    );
+    let symbol = arguments[0]
+        .as_symbol()
+        .context("Undefine! needs a symbol")?;
+    env.check_redefinable(symbol)?;
+    if env.undefine(symbol) {
+        Ok(Number(1.).into())
+    } else {
+        Ok(E::null())
+    }
+}
+
 pub fn quote<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
 where
     E: LispExpression,
@@ -130,6 +385,52 @@ where
     Ok(arguments[0].clone())
 }
 
+pub fn quasiquote<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 1,
+        "Quasiquote must be called on exactly one argument"
+    );
+    unquote_within(&arguments[0], 0, env)
+}
+
+/// Copies `expression`, evaluating the unquotes which are not inside `level`
+/// further quasiquotes
+fn unquote_within<E>(expression: &E, level: usize, env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    let Ok(list) = expression.as_list() else {
+        return Ok(expression.clone());
+    };
+    let head = list.0.first().and_then(|head| head.as_symbol().ok());
+    let level = match (head.map(|head| head.0.as_str()), list.0.len()) {
+        (Some("unquote"), 2) if level == 0 => {
+            return list.0[1]
+                .eval(env)
+                .with_context(|| anyhow!("Could not evaluate unquoted {}", list.0[1]));
+        }
+        (Some("unquote"), 2) => level - 1,
+        (Some("quasiquote"), 2) => level + 1,
+        _ => level,
+    };
+    let elements = list
+        .0
+        .iter()
+        .map(|element| unquote_within(element, level, env))
+        .collect::<Result<Vec<E>>>()?;
+    Ok(List::new(elements).into())
+}
+
+pub fn unquote<E>(_arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    bail!("Unquote can only be used inside quasiquote")
+}
+
 pub fn lambda<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
 where
     E: LispExpression,
@@ -141,17 +442,176 @@ where
     let parameters: &List<_> = arguments[0]
         .try_into_atom()
         .context("First argument to lambda construction must be a list")?;
-    let parameters: Vec<&Symbol> = expressions_to_homogeneous(&parameters.0)
-        .context("Parameter names need to all be symbols")?;
+    let parameters: Vec<Parameter<E>> = parameters
+        .0
+        .iter()
+        .enumerate()
+        .map(|(n, e)| parse_parameter(e).with_context(|| anyhow!("Parameter number {}", n + 1)))
+        .collect::<Result<_>>()?;
+    check_distinct(&parameters)?;
     let value = arguments[1].clone();
     Ok(Lambda {
-        parameters: parameters.into_iter().cloned().collect(),
-        value: Box::new(value),
-        env: env.clone(),
+        parameters,
+        value: Shared::new(value),
+        env: Shared::new(env.clone()),
+        name: None,
+        recursive: None,
     }
     .into())
 }
 
+/// Defines a function which sees itself by its name, however it is bound
+/// later on
+pub fn defun<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 3,
+        "Defun must be called on a name, a parameter list and a body"
+    );
+    let name = arguments[0]
+        .as_symbol()
+        .context("Defun needs the name of the function")?;
+    env.check_redefinable(name)?;
+    let function = lambda(&arguments[1..], env)?;
+    let group = Shared::new(vec![(name.clone(), named(&function, name))]);
+    bind_recursive(&group, env);
+    let function = env
+        .get(name)
+        .cloned()
+        .context("Defun could not bind the function")?;
+    env.define(name.clone(), function.clone());
+    Ok(function)
+}
+
+/// Evaluates the body with the bindings, whose functions see each other and
+/// themselves by name so that they can be mutually recursive
+pub fn letrec<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 2,
+        "Letrec must be called on a list of bindings and a body"
+    );
+    let bindings = arguments[0]
+        .as_list()
+        .context("Letrec needs a list of (name value) bindings")?;
+    let mut scope = env.scope();
+    let mut group = Vec::with_capacity(bindings.0.len());
+    for (n, binding) in bindings.0.iter().enumerate() {
+        let Ok([name, value]) = binding.as_list().map(|list| list.0.as_slice()) else {
+            bail!(
+                "Binding number {} should be (name value), not {binding}",
+                n + 1
+            )
+        };
+        let name = name
+            .as_symbol()
+            .with_context(|| anyhow!("Binding number {} needs a symbol to bind", n + 1))?;
+        let value = value
+            .eval(&mut scope)
+            .with_context(|| anyhow!("Could not evaluate the value of {name}"))?;
+        group.push((name.clone(), named(&value, name)));
+    }
+    bind_recursive(&Shared::new(group), &mut scope);
+    arguments[1].eval(&mut scope)
+}
+
+/// Evaluates the body with each pattern bound to its value in turn, so that
+/// later values see earlier bindings
+pub fn let_bindings<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 2,
+        "Let must be called on a list of bindings and a body"
+    );
+    let bindings = arguments[0]
+        .as_list()
+        .context("Let needs a list of (pattern value) bindings")?;
+    let mut scope = env.scope();
+    for (n, binding) in bindings.0.iter().enumerate() {
+        let Ok([pattern, value]) = binding.as_list().map(|list| list.0.as_slice()) else {
+            bail!(
+                "Binding number {} should be (pattern value), not {binding}",
+                n + 1
+            )
+        };
+        let value = value
+            .eval(&mut scope)
+            .with_context(|| anyhow!("Could not evaluate the value of {pattern}"))?;
+        bind_pattern(pattern, value, &mut scope)
+            .with_context(|| anyhow!("Could not bind binding number {}", n + 1))?;
+    }
+    arguments[1].eval(&mut scope)
+}
+
+/// Reads `name`, `(name default)` or a destructuring pattern such as
+/// `[a (b c) rest...]` from a parameter list
+fn parse_parameter<E>(expression: &E) -> Result<Parameter<E>>
+where
+    E: LispExpression,
+{
+    if let Ok(name) = expression.as_symbol() {
+        return Ok(name.clone().into());
+    }
+    if let Some(pattern) = parameter_pattern(expression) {
+        return Ok(Parameter {
+            name: Symbol(expression.to_string()),
+            default: None,
+            pattern: Some(pattern),
+        });
+    }
+    match expression.as_list().map(|l| l.0.as_slice()) {
+        Ok([name, default]) if name.as_symbol().is_ok() => Ok(Parameter {
+            name: name.as_symbol()?.clone(),
+            default: Some(default.clone()),
+            pattern: None,
+        }),
+        _ => bail!(
+            "Parameters must be a symbol, (name default) or [pattern ...] to destructure, not {}",
+            expression
+        ),
+    }
+}
+
+/// The list pattern a parameter written `[pattern ...]` destructures with,
+/// vectors within it read as lists too
+fn parameter_pattern<E: LispExpression>(expression: &E) -> Option<E> {
+    fn as_list<E: LispExpression>(expression: &E) -> E {
+        match ToAndFrom::<Vector<E>>::try_into_atom(expression) {
+            Ok(vector) => List::new(vector.0.borrow().iter().map(as_list).collect()).into(),
+            Err(_) => expression.clone(),
+        }
+    }
+    ToAndFrom::<Vector<E>>::try_into_atom(expression).ok()?;
+    Some(as_list(expression)).filter(is_pattern)
+}
+
+/// Whether `expression` is a symbol or a list of patterns, see `bind_pattern`
+fn is_pattern<E: LispExpression>(expression: &E) -> bool {
+    expression.as_symbol().is_ok()
+        || expression
+            .as_list()
+            .is_ok_and(|list| list.0.iter().all(is_pattern))
+}
+
+/// Otherwise a keyword argument could not say which one it binds
+fn check_distinct<E: LispExpression>(parameters: &[Parameter<E>]) -> Result<()> {
+    let mut seen = HashSet::new();
+    for variable in parameters.iter().flat_map(Parameter::variables) {
+        ensure!(
+            seen.insert(variable.clone()),
+            "Parameter {} is declared more than once",
+            variable
+        );
+    }
+    Ok(())
+}
+
 pub fn macr<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
 where
     E: LispExpression,
@@ -163,13 +623,25 @@ where
     let parameters: &List<_> = arguments[0]
         .try_into_atom()
         .context("First argument to macros construction must be a list")?;
-    let parameters: Vec<&Symbol> = expressions_to_homogeneous(&parameters.0)
-        .context("Parameter names need to all be symbols")?;
+    let parameters: Vec<Parameter<E>> = parameters
+        .0
+        .iter()
+        .enumerate()
+        .map(|(n, e)| {
+            ensure!(
+                e.as_symbol().is_ok() || parameter_pattern(e).is_some(),
+                "Macro parameters must be symbols or [pattern ...] to destructure, not {e}"
+            );
+            parse_parameter(e).with_context(|| anyhow!("Parameter number {}", n + 1))
+        })
+        .collect::<Result<_>>()?;
+    check_distinct(&parameters)?;
     let value = arguments[1].clone();
     Ok(Macro {
-        parameters: parameters.into_iter().cloned().collect(),
-        value: Box::new(value),
-        env: env.clone(),
+        parameters,
+        value: Shared::new(value),
+        env: Shared::new(env.clone()),
+        name: None,
     }
     .into())
 }
@@ -191,7 +663,7 @@ where
         }
     }
     if arguments.len() % 2 == 0 {
-        Ok(List(vec![]).into())
+        Ok(List::new(vec![]).into())
     } else {
         arguments
             .last()
@@ -201,17 +673,1323 @@ where
     }
 }
 
-pub fn set_environment<E: LispExpression + ToAndFrom<Number>>(env: &mut Environment<E>) {
-    env.set("≤", BuiltinFunction::new("≤", le));
-    env.set("cond", BuiltinMacro::new("cond", cond));
-    env.set("+", BuiltinFunction::new("+", add));
-    env.set("*", BuiltinFunction::new("*", mul));
-    env.set("-", BuiltinFunction::new("-", sub));
-    env.set("/", BuiltinFunction::new("/", div));
-    env.set("list", BuiltinFunction::new("list", list));
-    env.set("=", BuiltinFunction::new("=", eq));
-    env.set("define", BuiltinFunction::new("define", define));
-    env.set("'", BuiltinMacro::new("'", quote));
-    env.set("λ", BuiltinMacro::new("λ", lambda));
-    env.set("μ", BuiltinMacro::new("μ", macr));
+pub fn partial<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(!arguments.is_empty(), "Partial needs a function to apply");
+    let function = arguments[0]
+        .eval(env)
+        .context("Could not evaluate function passed to partial")?;
+    ensure!(
+        ToAndFrom::<Lambda<E>>::try_into_atom(&function).is_ok()
+            || ToAndFrom::<BuiltinFunction<E>>::try_into_atom(&function).is_ok(),
+        "Partial can only apply functions, not a {}",
+        function.variant()
+    );
+
+    // The result is a lambda calling `%f` on `%1`, `%2`, ..., where the
+    // given arguments are bound in its environment and each `_` is a
+    // parameter, so leaving out arguments still curries
+    let mut closure = env.clone();
+    closure.set("%f", function);
+    let mut parameters = Vec::new();
+    let mut call: Vec<E> = vec![Symbol::from("%f").into()];
+    for (n, argument) in arguments[1..].iter().enumerate() {
+        let symbol = Symbol(format!("%{}", n + 1));
+        if matches!(argument.as_symbol(), Ok(s) if s.0 == "_") {
+            parameters.push(symbol.clone().into());
+        } else {
+            let value = argument
+                .eval(env)
+                .with_context(|| anyhow!("Argument number {}: {}", n + 2, argument))?;
+            closure.set(symbol.clone(), value);
+        }
+        call.push(symbol.into());
+    }
+    Ok(Lambda {
+        parameters,
+        value: Shared::new(List::new(call).into()),
+        env: Shared::new(closure),
+        name: None,
+        recursive: None,
+    }
+    .into())
+}
+
+/// Rewrites `(-> x (f a) g)` into `(g (f x a))`, or with `last` into
+/// `(g (f a x))`
+fn thread<E>(arguments: &[E], last: bool, name: &str) -> Result<E>
+where
+    E: LispExpression,
+{
+    let (value, steps) = arguments
+        .split_first()
+        .with_context(|| anyhow!("{name} needs a value to thread"))?;
+    Ok(steps.iter().fold(value.clone(), |value, step| {
+        let mut call = match step.as_list() {
+            Ok(List(call)) if !call.is_empty() => call.to_vec(),
+            _ => vec![step.clone()],
+        };
+        if last || call.len() == 1 {
+            call.push(value);
+        } else {
+            call.insert(1, value);
+        }
+        List::new(call).into()
+    }))
+}
+
+pub fn thread_first<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    thread(arguments, false, "->")?.eval(env)
+}
+
+pub fn thread_last<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    thread(arguments, true, "->>")?.eval(env)
+}
+
+pub fn compose<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    // (compose f g) is (λ (%x) (f (g %x)))
+    let parameter = Symbol::from("%x");
+    let body = arguments
+        .iter()
+        .rev()
+        .fold(parameter.clone().into(), |value: E, function| {
+            List::new(vec![function.clone(), value]).into()
+        });
+    Ok(Lambda {
+        parameters: vec![parameter.into()],
+        value: Shared::new(body),
+        env: Shared::new(env.clone()),
+        name: None,
+        recursive: None,
+    }
+    .into())
+}
+
+/// A stable merge sort which stops at the first failed comparison, unlike
+/// `sort_by` which cannot fail and may panic on inconsistent comparators
+fn merge_sort<E: Clone>(
+    items: &[E],
+    less: &mut dyn FnMut(&E, &E) -> Result<bool>,
+) -> Result<Vec<E>> {
+    if items.len() <= 1 {
+        return Ok(items.to_vec());
+    }
+    let (left, right) = items.split_at(items.len() / 2);
+    let left = merge_sort(left, less)?;
+    let right = merge_sort(right, less)?;
+    let mut sorted = Vec::with_capacity(items.len());
+    let (mut left, mut right) = (left.into_iter().peekable(), right.into_iter().peekable());
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        // Take from the right only when strictly less, keeping equal
+        // elements in their original order
+        if less(r, l)? {
+            sorted.extend(right.next());
+        } else {
+            sorted.extend(left.next());
+        }
+    }
+    sorted.extend(left);
+    sorted.extend(right);
+    Ok(sorted)
+}
+
+pub fn sort<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 1,
+        "Sort must be called on exactly one argument"
+    );
+    let list = arguments[0]
+        .as_list()
+        .context("Argument to sort should be a list")?;
+    // Each comparison takes a step, as sort-by calling a lambda would
+    let sorted = merge_sort(&list.0, &mut |a, b| {
+        env.consume_fuel()?;
+        Ok(a.compare(b)? == Ordering::Less)
+    })?;
+    Ok(List::new(sorted).into())
+}
+
+pub fn sort_by<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 2,
+        "Sort-by must be called on exactly two arguments"
+    );
+    let list = arguments[1]
+        .as_list()
+        .context("Second argument to sort-by should be a list")?;
+    let sorted = merge_sort(&list.0, &mut |a, b| {
+        let less = apply(&arguments[0], &[a.clone(), b.clone()], env)
+            .with_context(|| anyhow!("Could not compare {} with {}", a, b))?;
+        Ok(less.is_truthy())
+    })?;
+    Ok(List::new(sorted).into())
+}
+
+pub fn block<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    let (name, body) = arguments.split_first().context("Block needs a name")?;
+    let name = name.as_symbol().context("Block names must be symbols")?;
+    let mut result = Ok(E::null());
+    for (n, expression) in body.iter().enumerate() {
+        result = expression.eval(env).with_context(|| {
+            anyhow!(
+                "Could not evaluate expression number {} in block {}",
+                n + 1,
+                name
+            )
+        });
+        if result.is_err() {
+            break;
+        }
+    }
+    match result {
+        Err(error) if matches!(error.downcast_ref::<ReturnFrom>(), Some(ReturnFrom(target)) if target == name) => {
+            Ok(env.returning.borrow_mut().take().unwrap_or_else(E::null))
+        }
+        result => result,
+    }
+}
+
+pub fn return_from<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        matches!(arguments.len(), 1 | 2),
+        "Return-from must be called on one or two arguments"
+    );
+    let name = arguments[0]
+        .as_symbol()
+        .context("Return-from needs the name of a block")?;
+    let value = match arguments.get(1) {
+        Some(value) => value
+            .eval(env)
+            .context("Could not evaluate returned value")?,
+        None => E::null(),
+    };
+    *env.returning.borrow_mut() = Some(value);
+    Err(ReturnFrom(name.clone()).into())
+}
+
+#[cfg(feature = "std")]
+pub fn time<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 1,
+        "Time must be called on exactly one argument"
+    );
+    let start = Instant::now();
+    let result = arguments[0]
+        .eval(env)
+        .context("Could not evaluate timed expression");
+    env.io
+        .write_stdout(&format!("Elapsed time: {:?}\n", start.elapsed()))
+        .context("Could not print time")?;
+    result
+}
+
+pub fn profile<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 1,
+        "Profile must be called on exactly one argument"
+    );
+    // A profile already running, e.g. from `#profile on`, carries on after
+    let outer = env.profiler.suspend();
+    env.profiler.enable();
+    let result = arguments[0]
+        .eval(env)
+        .context("Could not evaluate profiled expression");
+    let report = env.profiler.disable();
+    env.profiler.resume(outer);
+    if let Some(report) = report {
+        env.io
+            .write_stdout(&format!("{report}\n"))
+            .context("Could not print profile")?;
+    }
+    result
+}
+
+pub fn trace<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 1,
+        "Trace must be called on exactly one argument"
+    );
+    let name = arguments[0]
+        .as_symbol()
+        .context("Trace must be given the name of a function")?;
+    let function = arguments[0].eval(env)?;
+    ensure!(
+        tracer::is_function(&function),
+        "{} is not a function, so cannot be traced",
+        name.0
+    );
+    env.tracer.trace(name.clone(), function);
+    Ok(arguments[0].clone())
+}
+
+pub fn untrace<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 1,
+        "Untrace must be called on exactly one argument"
+    );
+    let name = arguments[0]
+        .as_symbol()
+        .context("Untrace must be given the name of a function")?;
+    ensure!(env.tracer.untrace(name), "{} is not traced", name.0);
+    Ok(arguments[0].clone())
+}
+
+#[cfg(feature = "std")]
+pub fn bench<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression + ToAndFrom<Number>,
+{
+    ensure!(
+        arguments.len() == 2,
+        "Bench must be called with a number of runs and an expression"
+    );
+    let runs = arguments[0]
+        .eval(env)
+        .context("Could not evaluate number of runs")?;
+    let runs: &Number = runs
+        .try_into_atom()
+        .context("Number of runs should be a number")?;
+    ensure!(
+        runs.0 >= 1. && float::fract(runs.0) == 0.,
+        "Number of runs should be a positive integer, not {}",
+        runs
+    );
+    let runs = runs.0 as u32;
+
+    let mut fastest = Duration::MAX;
+    let mut total = Duration::ZERO;
+    for run in 1..=runs {
+        let start = Instant::now();
+        arguments[1]
+            .eval(env)
+            .with_context(|| anyhow!("Could not evaluate run number {run}"))?;
+        let elapsed = start.elapsed();
+        fastest = fastest.min(elapsed);
+        total += elapsed;
+    }
+    let mean = total / runs;
+    env.io
+        .write_stdout(&format!("{runs} runs: min {fastest:?}, mean {mean:?}\n"))
+        .context("Could not print benchmark")?;
+    Ok(List::new(vec![
+        Number(fastest.as_secs_f64()).into(),
+        Number(mean.as_secs_f64()).into(),
+    ])
+    .into())
+}
+
+fn single_number<'a, E>(arguments: &'a [E], name: &str) -> Result<&'a Number>
+where
+    E: LispExpression + ToAndFrom<Number>,
+{
+    ensure!(
+        arguments.len() == 1,
+        "{name} must be called on exactly one argument"
+    );
+    arguments[0]
+        .try_into_atom()
+        .with_context(|| anyhow!("Argument to {name} should be a number"))
+}
+
+pub fn rand<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression + ToAndFrom<Number>,
+{
+    ensure!(arguments.is_empty(), "Rand takes no arguments");
+    Ok(Number(env.rng.next_f64()).into())
+}
+
+pub fn rand_int<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression + ToAndFrom<Number>,
+{
+    let bound = single_number(arguments, "Rand-int")?;
+    ensure!(
+        bound.0 >= 1. && float::fract(bound.0) == 0.,
+        "Rand-int needs a positive integer bound, not {}",
+        bound
+    );
+    Ok(Number(float::floor(env.rng.next_f64() * bound.0)).into())
+}
+
+pub fn rand_range<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression + ToAndFrom<Number>,
+{
+    ensure!(
+        arguments.len() == 2,
+        "Rand-range must be called with a lower and an upper bound"
+    );
+    let bounds: Vec<&Number> =
+        expressions_to_homogeneous(arguments).context("Bounds to rand-range are not numbers")?;
+    let (low, high) = (bounds[0].0, bounds[1].0);
+    ensure!(
+        low < high,
+        "Lower bound {low} to rand-range must be below upper bound {high}"
+    );
+    Ok(Number(low + env.rng.next_f64() * (high - low)).into())
+}
+
+pub fn seed<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression + ToAndFrom<Number>,
+{
+    let seed = single_number(arguments, "Seed!")?;
+    ensure!(
+        float::fract(seed.0) == 0.,
+        "Seed! needs an integer seed, not {}",
+        seed
+    );
+    env.rng.seed(seed.0 as i64 as u64);
+    Ok(E::null())
+}
+
+pub fn print<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    let precision = env.config.borrow().number_precision;
+    for argument in arguments {
+        let text = env.print_within_limits(|| display_text(argument, precision))?;
+        env.io
+            .write_stdout(&format!("{text}\n"))
+            .context("Could not print")?;
+    }
+    Ok(E::null())
+}
+
+macro_rules! cargo_features {
+    ($($name:literal),* $(,)?) => {
+        &[$(($name, cfg!(feature = $name))),*]
+    };
+}
+
+/// Every feature in Cargo.toml, and whether the crate was built with it
+const CARGO_FEATURES: &[(&str, bool)] =
+    cargo_features!("std", "alloc", "serde", "regex", "sync", "async", "plugins", "wasm", "ffi");
+
+/// The cargo features the crate was built with, as `*features*` lists them
+pub(crate) fn enabled_features() -> impl Iterator<Item = &'static str> {
+    CARGO_FEATURES
+        .iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(feature, _)| *feature)
+}
+
+/// Whether the expression type has an atom of the name, written with `-`
+/// for spaces and optionally plural, a layer gave builtins, or the crate was
+/// built with a cargo feature of the name
+pub fn has_feature<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 1,
+        "Has-feature? must be called on exactly one argument"
+    );
+    let name = arguments[0]
+        .as_symbol()
+        .context("Has-feature? needs the name of a feature as a symbol")?
+        .0
+        .replace('-', " ");
+    let singular = name.strip_suffix('s').unwrap_or(&name);
+    let found = E::atom_names()
+        .iter()
+        .any(|atom| *atom == name || *atom == singular)
+        || env.builtins().iter().any(|entry| entry.doc.layer == name)
+        || env.layers.borrow().iter().any(|layer| layer.name == name)
+        || CARGO_FEATURES
+            .iter()
+            .any(|(feature, enabled)| *enabled && *feature == name);
+    Ok(if found { Number(1.).into() } else { E::null() })
+}
+
+pub fn layers<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.is_empty(),
+        "Layers must be called on no arguments"
+    );
+    let layers = env.layers.borrow().clone();
+    let layers = layers.into_iter().map(|layer| {
+        let atoms = layer.atoms.iter().map(|&atom| Str::from(atom).into());
+        let builtins = layer
+            .builtins
+            .iter()
+            .map(|name| Symbol::from(name.as_str()).into());
+        List::new(vec![
+            Keyword::from("name").into(),
+            Str::from(layer.name).into(),
+            Keyword::from("version").into(),
+            Str::from(layer.version).into(),
+            Keyword::from("atoms").into(),
+            List::new(atoms.collect()).into(),
+            Keyword::from("builtins").into(),
+            List::new(builtins.collect()).into(),
+        ])
+        .into()
+    });
+    Ok(List::new(layers.collect()).into())
+}
+
+pub fn warn<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    let precision = env.config.borrow().number_precision;
+    let texts: Vec<String> = arguments
+        .iter()
+        .map(|argument| env.print_within_limits(|| display_text(argument, precision)))
+        .collect::<Result<_>>()?;
+    env.diagnostics.warn(texts.join(" "));
+    Ok(E::null())
+}
+
+pub fn display<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    let precision = env.config.borrow().number_precision;
+    let texts: Vec<String> = arguments
+        .iter()
+        .map(|argument| env.print_within_limits(|| display_text(argument, precision)))
+        .collect::<Result<_>>()?;
+    env.io
+        .write_stdout(&texts.join(" "))
+        .context("Could not display")?;
+    Ok(E::null())
+}
+
+pub fn write<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    let texts: Vec<String> = arguments
+        .iter()
+        .map(|argument| env.print_within_limits(|| write_text(argument)))
+        .collect::<Result<_>>()?;
+    env.io
+        .write_stdout(&texts.join(" "))
+        .context("Could not write")?;
+    Ok(E::null())
+}
+
+pub fn eprint<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    let precision = env.config.borrow().number_precision;
+    for argument in arguments {
+        let text = env.print_within_limits(|| display_text(argument, precision))?;
+        env.io
+            .write_stderr(&format!("{text}\n"))
+            .context("Could not print to stderr")?;
+    }
+    Ok(E::null())
+}
+
+pub fn read_line<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(arguments.is_empty(), "Read-line takes no arguments");
+    let Some(mut line) = env.io.read_line().context("Could not read line")? else {
+        return Ok(E::null());
+    };
+    if line.ends_with('\n') {
+        line.pop();
+        if line.ends_with('\r') {
+            line.pop();
+        }
+    }
+    Ok(Str(line).into())
+}
+
+fn single_string<'a, E>(arguments: &'a [E], name: &str) -> Result<&'a Str>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 1,
+        "{name} must be called on exactly one argument"
+    );
+    arguments[0]
+        .try_into_atom()
+        .with_context(|| anyhow!("Argument to {name} should be a string"))
+}
+
+pub fn make_box<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 1,
+        "Box must be called on exactly one argument"
+    );
+    Ok(Cell::new(arguments[0].clone()).into())
+}
+
+fn box_argument<'a, E>(arguments: &'a [E], name: &str) -> Result<&'a Cell<E>>
+where
+    E: LispExpression,
+{
+    arguments
+        .first()
+        .with_context(|| anyhow!("{name} needs a box"))?
+        .try_into_atom()
+        .with_context(|| anyhow!("First argument to {name} should be a box"))
+}
+
+pub fn unbox<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 1,
+        "Unbox must be called on exactly one argument"
+    );
+    let cell = box_argument(arguments, "Unbox")?;
+    let value = cell.0.borrow().clone();
+    Ok(value)
+}
+
+pub fn set_box<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 2,
+        "Set-box! must be called on exactly two arguments"
+    );
+    let cell = box_argument(arguments, "Set-box!")?;
+    *cell.0.borrow_mut() = arguments[1].clone();
+    Ok(arguments[1].clone())
+}
+
+pub fn keyword_p<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 1,
+        "Keyword? must be called on exactly one argument"
+    );
+    if ToAndFrom::<Keyword>::try_into_atom(&arguments[0]).is_ok() {
+        Ok(Number(1.).into())
+    } else {
+        Ok(E::null())
+    }
+}
+
+pub fn string_to_list<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    let string = single_string(arguments, "String->list")?;
+    Ok(List::new(string.0.chars().map(|c| Char(c).into()).collect()).into())
+}
+
+pub fn list_to_string<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 1,
+        "List->string must be called on exactly one argument"
+    );
+    let list = arguments[0]
+        .as_list()
+        .context("Argument to list->string should be a list")?;
+    let chars: Vec<&Char> =
+        expressions_to_homogeneous(&list.0).context("List->string needs a list of chars")?;
+    Ok(Str(chars.into_iter().map(|c| c.0).collect()).into())
+}
+
+/// Source text for a value, or without colours what it displays as if it
+/// has no source, such as a builtin bound under another name
+pub fn to_string<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 1,
+        "To-string must be called on exactly one argument"
+    );
+    let text = env.print_within_limits(|| write_text(&arguments[0]))?;
+    Ok(Str(text).into())
+}
+
+pub fn read_string<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    let source = single_string(arguments, "Read-string")?;
+    let mut tokens = tokenize(&source.0).peekable();
+    let expression = E::parse_with(&mut tokens, &env.reader_macros)
+        .with_context(|| anyhow!("Could not read {}", source.0))?;
+    skip_comments(&mut tokens);
+    ensure!(
+        tokens.peek().is_none(),
+        "Read-string needs exactly one expression, not several as in {:?}",
+        source.0
+    );
+    Ok(expression)
+}
+
+pub fn char_to_int<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 1,
+        "Char->int must be called on exactly one argument"
+    );
+    let c: &Char = arguments[0]
+        .try_into_atom()
+        .context("Argument to char->int should be a char")?;
+    Ok(Number(u32::from(c.0) as f64).into())
+}
+
+pub fn int_to_char<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    let code = single_number(arguments, "Int->char")?;
+    let c = (float::fract(code.0) == 0. && code.0 >= 0. && code.0 <= u32::MAX as f64)
+        .then(|| char::from_u32(code.0 as u32))
+        .flatten()
+        .with_context(|| anyhow!("{} is not a Unicode scalar value", code))?;
+    Ok(Char(c).into())
+}
+
+#[cfg(feature = "std")]
+fn read_included<E>(arguments: &[E], env: &Environment<E>, name: &str) -> Result<String>
+where
+    E: LispExpression,
+{
+    let path = env.resolve_path(&single_string(arguments, name)?.0);
+    std::fs::read_to_string(&path).with_context(|| anyhow!("Could not read {}", path.display()))
+}
+
+#[cfg(feature = "std")]
+pub fn include_str<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    Ok(Str(read_included(arguments, env, "Include-str")?).into())
+}
+
+#[cfg(feature = "std")]
+pub fn include_data<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    let contents = read_included(arguments, env, "Include-data")?;
+    let mut tokens = crate::tokenize(&contents).peekable();
+    let data =
+        E::parse_with(&mut tokens, &env.reader_macros).context("Could not parse included data")?;
+    crate::token::skip_comments(&mut tokens);
+    ensure!(tokens.peek().is_none(), "Extra tokens in included data");
+    Ok(data)
+}
+
+#[cfg(feature = "std")]
+pub fn load<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    let path = single_string(arguments, "Load")?;
+    crate::evaluate_file(&path.0, env)
+}
+
+#[cfg(feature = "std")]
+pub fn require<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    let path = env.resolve_path(&single_string(arguments, "Require")?.0);
+    let canonical = path
+        .canonicalize()
+        .with_context(|| anyhow!("Could not find {}", path.display()))?;
+    if env.loaded_files.borrow().contains(&canonical) {
+        return Ok(E::null());
+    }
+    crate::evaluate_file(canonical, env)
+}
+
+pub fn set_environment<E: LispExpression + ToAndFrom<Number>>(env: &mut Environment<E>) {
+    let before = env.bound_symbols();
+    env.set(
+        "<=",
+        BuiltinFunction::new("<=", le).with_doc("(<= number ...)", "Whether numbers are ascending"),
+    );
+    env.set_alias("≤", "<=");
+    env.set(
+        "cond",
+        BuiltinMacro::new("cond", cond).with_doc(
+            "(cond condition consequence ... default)",
+            "Evaluates the consequence of the first truthy condition",
+        ),
+    );
+    env.set(
+        "partial",
+        BuiltinMacro::new("partial", partial).with_doc(
+            "(partial f argument _ ...)",
+            "Function calling f with the given arguments, filling each _ with the arguments it is called with",
+        ),
+    );
+    env.set(
+        "block",
+        BuiltinMacro::new("block", block).with_doc(
+            "(block name expression ...)",
+            "Evaluates the expressions in order, returning the last or a value passed to return-from",
+        ),
+    );
+    env.set(
+        "return-from",
+        BuiltinMacro::new("return-from", return_from).with_doc(
+            "(return-from name [value])",
+            "Leaves the innermost block with the name, making it return the value",
+        ),
+    );
+    env.set(
+        "->",
+        BuiltinMacro::new("->", thread_first).with_doc(
+            "(-> x (f a) g ...)",
+            "Threads x through each step as its first argument, so this is (g (f x a))",
+        ),
+    );
+    env.set(
+        "pipe",
+        BuiltinMacro::new("pipe", thread_first)
+            .with_doc("(pipe x (f a) g ...)", "Another name for ->"),
+    );
+    env.set(
+        "->>",
+        BuiltinMacro::new("->>", thread_last).with_doc(
+            "(->> x (f a) g ...)",
+            "Threads x through each step as its last argument, so this is (g (f a x))",
+        ),
+    );
+    env.set(
+        "compose",
+        BuiltinMacro::new("compose", compose).with_doc(
+            "(compose f g ...)",
+            "Function applying the last function first, so ((compose f g) x) is (f (g x))",
+        ),
+    );
+    env.set(
+        "sort",
+        BuiltinFunction::new("sort", sort).with_doc(
+            "(sort list)",
+            "Sorts numbers, strings, chars, symbols or keywords of one kind, or lists of them",
+        ),
+    );
+    env.set(
+        "sort-by",
+        BuiltinFunction::new("sort-by", sort_by).with_doc(
+            "(sort-by less? list)",
+            "Stably sorts a list, where (less? a b) is truthy when a goes before b",
+        ),
+    );
+    env.set(
+        "+",
+        BuiltinFunction::new("+", add).with_doc("(+ number ...)", "Sum of the numbers"),
+    );
+    env.set(
+        "*",
+        BuiltinFunction::new("*", mul).with_doc("(* number ...)", "Product of the numbers"),
+    );
+    env.set(
+        "-",
+        BuiltinFunction::new("-", sub).with_doc(
+            "(- number subtrahend ...)",
+            "First number minus all the others",
+        ),
+    );
+    env.set(
+        "/",
+        BuiltinFunction::new("/", div).with_doc(
+            "(/ number divisor ...)",
+            "First number divided by all the others",
+        ),
+    );
+    env.set(
+        "bit-and",
+        BuiltinFunction::new("bit-and", bit_and)
+            .with_doc("(bit-and integer ...)", "Bitwise and of the integers"),
+    );
+    env.set(
+        "bit-or",
+        BuiltinFunction::new("bit-or", bit_or)
+            .with_doc("(bit-or integer ...)", "Bitwise or of the integers"),
+    );
+    env.set(
+        "bit-xor",
+        BuiltinFunction::new("bit-xor", bit_xor).with_doc(
+            "(bit-xor integer ...)",
+            "Bitwise exclusive or of the integers",
+        ),
+    );
+    env.set(
+        "bit-shift",
+        BuiltinFunction::new("bit-shift", bit_shift).with_doc(
+            "(bit-shift integer shift)",
+            "The integer shifted left by shift bits, or right if shift is negative",
+        ),
+    );
+    env.set(
+        "quotient",
+        BuiltinFunction::new("quotient", quotient).with_doc(
+            "(quotient dividend divisor)",
+            "Integer division rounding towards zero",
+        ),
+    );
+    env.set(
+        "remainder",
+        BuiltinFunction::new("remainder", remainder).with_doc(
+            "(remainder dividend divisor)",
+            "What is left over from quotient, with the sign of the dividend",
+        ),
+    );
+    env.set(
+        "list",
+        BuiltinFunction::new("list", list).with_doc("(list value ...)", "List of the values"),
+    );
+    env.set(
+        "=",
+        BuiltinFunction::new("=", eq).with_doc(
+            "(= value ...)",
+            "Whether all values are equal, with lists compared by element and boxes by reference",
+        ),
+    );
+    env.set(
+        "eq?",
+        BuiltinFunction::new("eq?", eq_p).with_doc(
+            "(eq? a b)",
+            "Whether two values are the same atom, with lists, functions and boxes compared by reference",
+        ),
+    );
+    env.set(
+        "equal?",
+        BuiltinFunction::new("equal?", equal_p).with_doc(
+            "(equal? a b)",
+            "Whether two values have the same structure, including the contents of boxes",
+        ),
+    );
+    env.set(
+        "define",
+        BuiltinFunction::new("define", define).with_doc(
+            "(define 'symbol value)",
+            "Binds the symbol to the value, only in the enclosing function or let if in one",
+        ),
+    );
+    env.set(
+        "defun",
+        BuiltinMacro::new("defun", defun).with_doc(
+            "(defun name (parameter ...) body)",
+            "Binds the name to a function which can call itself by that name",
+        ),
+    );
+    env.set(
+        "let",
+        BuiltinMacro::new("let", let_bindings).with_doc(
+            "(let ((pattern value) ...) body)",
+            "Evaluates the body with each pattern bound in turn, where (a b rest...) destructures a list",
+        ),
+    );
+    env.set(
+        "letrec",
+        BuiltinMacro::new("letrec", letrec).with_doc(
+            "(letrec ((name value) ...) body)",
+            "Evaluates the body with the bindings, whose functions can call each other and themselves",
+        ),
+    );
+    env.set(
+        "set!-unsafe",
+        BuiltinFunction::new("set!-unsafe", define_unsafe).with_doc(
+            "(set!-unsafe 'symbol value)",
+            "Binds the symbol to the value even if it is a protected builtin",
+        ),
+    );
+    env.set(
+        "env",
+        BuiltinFunction::new("env", bindings).with_doc(
+            "(env)",
+            "The bindings of the current environment as (symbol value) pairs, sorted by symbol",
+        ),
+    );
+    env.set(
+        "bound?",
+        BuiltinFunction::new("bound?", bound_p).with_doc(
+            "(bound? 'symbol)",
+            "Returns 1 if the symbol is bound in the current environment, otherwise ()",
+        ),
+    );
+    env.set(
+        "undefine!",
+        BuiltinFunction::new("undefine!", undefine).with_doc(
+            "(undefine! 'symbol)",
+            "Removes the symbol's binding from the current environment, returning 1 if there was one, otherwise ()",
+        ),
+    );
+    env.set(
+        "'",
+        BuiltinMacro::new("'", quote).with_doc("(' expression)", "The expression unevaluated"),
+    );
+    env.set(
+        "quasiquote",
+        BuiltinMacro::new("quasiquote", quasiquote).with_doc(
+            "(quasiquote expression)",
+            "The expression unevaluated except for its unquoted parts, also written `expression",
+        ),
+    );
+    env.set(
+        "unquote",
+        BuiltinMacro::new("unquote", unquote).with_doc(
+            "(unquote expression)",
+            "Marks part of a quasiquoted expression to evaluate, also written ,expression",
+        ),
+    );
+    env.set(
+        "λ",
+        BuiltinMacro::new("λ", lambda).with_doc(
+            "(λ (parameter (parameter default) [pattern ...] ...) body)",
+            "Function closing over the current environment, whose arguments may be named as in (f :parameter value) and whose defaults fill missing arguments",
+        ),
+    );
+    env.set(
+        "μ",
+        BuiltinMacro::new("μ", macr).with_doc(
+            "(μ (parameter ...) body)",
+            "Macro whose expansion is evaluated where it is called",
+        ),
+    );
+    // Easier to type than the names they stand for
+    env.alias("lambda", "λ");
+    env.alias("macro", "μ");
+    #[cfg(feature = "std")]
+    env.set(
+        "time",
+        BuiltinMacro::new("time", time)
+            .with_doc(
+                "(time expression)",
+                "Evaluates the expression, printing how long it took",
+            )
+            .with_capabilities(&[Capability::Clock]),
+    );
+    env.set(
+        "profile",
+        BuiltinMacro::new("profile", profile)
+            .with_doc(
+                "(profile expression)",
+                "Evaluates the expression, printing the calls and time spent in each function",
+            )
+            .with_capabilities(&[Capability::Clock]),
+    );
+    env.set(
+        "trace",
+        BuiltinMacro::new("trace", trace).with_doc(
+            "(trace name)",
+            "Prints the arguments and result of each call of the named function, indented by nesting",
+        ),
+    );
+    env.set(
+        "untrace",
+        BuiltinMacro::new("untrace", untrace)
+            .with_doc("(untrace name)", "Stops tracing the named function"),
+    );
+    #[cfg(feature = "std")]
+    env.set(
+        "bench",
+        BuiltinMacro::new("bench", bench)
+            .with_doc(
+                "(bench runs expression)",
+                "Evaluates the expression repeatedly, returning the minimum and mean seconds taken",
+            )
+            .with_capabilities(&[Capability::Clock]),
+    );
+    env.set(
+        "rand",
+        BuiltinFunction::new("rand", rand).with_doc("(rand)", "Random number in [0, 1)"),
+    );
+    env.set(
+        "rand-int",
+        BuiltinFunction::new("rand-int", rand_int)
+            .with_doc("(rand-int bound)", "Random integer in [0, bound)"),
+    );
+    env.set(
+        "rand-range",
+        BuiltinFunction::new("rand-range", rand_range)
+            .with_doc("(rand-range low high)", "Random number in [low, high)"),
+    );
+    env.set(
+        "seed!",
+        BuiltinFunction::new("seed!", seed).with_doc(
+            "(seed! integer)",
+            "Seeds the random number generator, making it deterministic",
+        ),
+    );
+    env.set(
+        "print",
+        BuiltinFunction::new("print", print)
+            .with_doc("(print value ...)", "Prints each value on its own line"),
+    );
+    env.set(
+        "display",
+        BuiltinFunction::new("display", display).with_doc(
+            "(display value ...)",
+            "Prints the values separated by spaces, without a newline",
+        ),
+    );
+    env.set(
+        "warn",
+        BuiltinFunction::new("warn", warn).with_doc(
+            "(warn value ...)",
+            "Records a warning of the values separated by spaces, which the REPL prints without stopping",
+        ),
+    );
+    env.set(
+        "write",
+        BuiltinFunction::new("write", write).with_doc(
+            "(write value ...)",
+            "Prints the values as source code which reads back, strings quoted, separated by spaces without a newline",
+        ),
+    );
+    env.set(
+        "print-no-newline",
+        BuiltinFunction::new("print-no-newline", display).with_doc(
+            "(print-no-newline value ...)",
+            "Prints the values separated by spaces, without a newline",
+        ),
+    );
+    env.set(
+        "eprint",
+        BuiltinFunction::new("eprint", eprint).with_doc(
+            "(eprint value ...)",
+            "Prints each value on its own line to stderr",
+        ),
+    );
+    env.set(
+        "read-line",
+        BuiltinFunction::new("read-line", read_line).with_doc(
+            "(read-line)",
+            "Next line of input as a string, or () at the end of input",
+        ),
+    );
+    env.set(
+        "box",
+        BuiltinFunction::new("box", make_box).with_doc(
+            "(box value)",
+            "A mutable box holding the value, shared by copies",
+        ),
+    );
+    env.set(
+        "unbox",
+        BuiltinFunction::new("unbox", unbox).with_doc("(unbox box)", "The value in a box"),
+    );
+    env.set(
+        "set-box!",
+        BuiltinFunction::new("set-box!", set_box).with_doc(
+            "(set-box! box value)",
+            "Replaces the value in a box, returning the new value",
+        ),
+    );
+    env.set(
+        "keyword?",
+        BuiltinFunction::new("keyword?", keyword_p).with_doc(
+            "(keyword? x)",
+            "Returns 1 if x is a keyword like :name, otherwise ()",
+        ),
+    );
+    env.set(
+        "string->list",
+        BuiltinFunction::new("string->list", string_to_list).with_doc(
+            "(string->list \"text\")",
+            "The characters of a string as a list",
+        ),
+    );
+    env.set(
+        "list->string",
+        BuiltinFunction::new("list->string", list_to_string).with_doc(
+            "(list->string (list #\\a #\\b))",
+            "Joins a list of characters into a string",
+        ),
+    );
+    env.set(
+        "to-string",
+        BuiltinFunction::new("to-string", to_string).with_doc(
+            "(to-string value)",
+            "The value as source code without colours, which read-string reads back",
+        ),
+    );
+    env.set(
+        "read-string",
+        BuiltinFunction::new("read-string", read_string).with_doc(
+            "(read-string \"source\")",
+            "The expression written in a string, unevaluated",
+        ),
+    );
+    env.set(
+        "char->int",
+        BuiltinFunction::new("char->int", char_to_int)
+            .with_doc("(char->int #\\a)", "The Unicode code point of a character"),
+    );
+    env.set(
+        "int->char",
+        BuiltinFunction::new("int->char", int_to_char)
+            .with_doc("(int->char 97)", "The character with a Unicode code point"),
+    );
+    #[cfg(feature = "std")]
+    env.set(
+        "include-str",
+        BuiltinFunction::new("include-str", include_str)
+            .with_doc(
+                "(include-str \"path\")",
+                "Contents of a file, relative to the file being evaluated",
+            )
+            .with_capabilities(&[Capability::Fs]),
+    );
+    #[cfg(feature = "std")]
+    env.set(
+        "include-data",
+        BuiltinFunction::new("include-data", include_data)
+            .with_doc(
+                "(include-data \"path\")",
+                "Unevaluated expression read from a file, relative to the file being evaluated",
+            )
+            .with_capabilities(&[Capability::Fs]),
+    );
+    #[cfg(feature = "std")]
+    env.set(
+        "load",
+        BuiltinFunction::new("load", load)
+            .with_doc(
+                "(load \"path\")",
+                "Evaluates a file, relative to the file being evaluated",
+            )
+            .with_capabilities(&[Capability::Fs]),
+    );
+    #[cfg(feature = "std")]
+    env.set(
+        "require",
+        BuiltinFunction::new("require", require)
+            .with_doc(
+                "(require \"path\")",
+                "Evaluates a file unless it has been evaluated before",
+            )
+            .with_capabilities(&[Capability::Fs]),
+    );
+    attributes::set_environment(env);
+    conditions::set_environment(env);
+    datetime::set_environment(env);
+    format::set_environment(env);
+    generics::set_environment(env);
+    json::set_environment(env);
+    lazy::set_environment(env);
+    lists::set_environment(env);
+    matching::set_environment(env);
+    math::set_environment(env);
+    #[cfg(feature = "std")]
+    process::set_environment(env);
+    records::set_environment(env);
+    sets::set_environment(env);
+    testing::set_environment(env);
+    vectors::set_environment(env);
+    #[cfg(feature = "regex")]
+    regex::set_environment(env);
+    #[cfg(feature = "async")]
+    promises::set_environment(env);
+    #[cfg(feature = "std")]
+    if env.config.borrow().allow_os {
+        os::set_environment(env);
+    }
+    env.set(
+        "*features*",
+        List::new(
+            enabled_features()
+                .map(|feature| Str::from(feature).into())
+                .collect(),
+        ),
+    );
+    env.set("*shallot-version*", Str::from(crate::VERSION));
+    // A browser has no files, processes or clock for these, so they are left
+    // out rather than failing or panicking when called. WASI has them.
+    #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+    {
+        let unavailable: Vec<Symbol> = env
+            .builtins()
+            .iter()
+            .filter(|entry| !entry.doc.capabilities.is_empty())
+            .map(|entry| entry.symbol.clone())
+            .collect();
+        for symbol in unavailable {
+            env.inner.remove(&symbol);
+        }
+    }
+    env.set(
+        "has-feature?",
+        BuiltinFunction::new("has-feature?", has_feature).with_doc(
+            "(has-feature? 'name)",
+            "Returns 1 if the build has the type, such as 'strings, the layer or the cargo feature, otherwise ()",
+        ),
+    );
+    env.set(
+        "layers",
+        BuiltinFunction::new("layers", layers).with_doc(
+            "(layers)",
+            "The layers loaded, each as (:name name :version version :atoms (atom ...) :builtins (builtin ...))",
+        ),
+    );
+    env.assign_layer("core");
+    let builtins = env
+        .builtins()
+        .iter()
+        .filter(|entry| entry.doc.layer == "core")
+        .map(|entry| entry.symbol.0.clone())
+        .collect();
+    env.register_layer(LayerInfo {
+        name: "core",
+        version: crate::VERSION,
+        atoms: E::atom_names(),
+        builtins,
+    });
+    env.protect_new_bindings(&before);
 }
@@ -0,0 +1,109 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+
+use crate::{
+    expression::{LispExpression, ToAndFrom},
+    BuiltinFunction, BuiltinMacro, Environment, Foreign, Keyword, List, Record, Str,
+};
+
+/// The name of an attribute written as a symbol, keyword or string
+fn attribute_name<E: LispExpression>(key: &E) -> Result<&str> {
+    if let Ok(symbol) = key.as_symbol() {
+        Ok(&symbol.0)
+    } else if let Ok(keyword) = ToAndFrom::<Keyword>::try_into_atom(key) {
+        Ok(&keyword.0)
+    } else if let Ok(string) = ToAndFrom::<Str>::try_into_atom(key) {
+        Ok(&string.0)
+    } else {
+        bail!("Attribute names must be symbols, keywords or strings, not {key}")
+    }
+}
+
+/// A field of a record, the value of a key in an association list of
+/// `(key value)` pairs, or an attribute of a foreign value by its accessor
+fn attribute<E: LispExpression>(value: &E, key: &E, env: &Environment<E>) -> Result<E> {
+    let name = attribute_name(key)?;
+    if let Ok(record) = ToAndFrom::<Record<E>>::try_into_atom(value) {
+        let index = record
+            .kind
+            .fields
+            .iter()
+            .position(|field| field.0 == name)
+            .with_context(|| anyhow!("{} records have no field {name}", record.kind.name.0))?;
+        Ok(record.values[index].clone())
+    } else if let Ok(foreign) = ToAndFrom::<Foreign>::try_into_atom(value) {
+        let accessor = env
+            .accessors
+            .get(foreign.type_name)
+            .with_context(|| anyhow!("Foreign {} values have no accessor", foreign.type_name))?;
+        accessor(foreign, name).with_context(|| {
+            anyhow!(
+                "Foreign {} values have no attribute {name}",
+                foreign.type_name
+            )
+        })
+    } else if let Ok(pairs) = value.as_list() {
+        // Keys match by name, so `name` finds the JSON key "name"
+        pairs
+            .0
+            .iter()
+            .find_map(|pair| match pair.as_list() {
+                Ok(List(pair))
+                    if pair.len() == 2
+                        && (pair[0] == *key
+                            || attribute_name(&pair[0]).is_ok_and(|key| key == name)) =>
+                {
+                    Some(pair[1].clone())
+                }
+                _ => None,
+            })
+            .with_context(|| anyhow!("The association list has no key {name}"))
+    } else {
+        bail!("Cannot get attributes of a {}", value.variant())
+    }
+}
+
+pub fn get_attr<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 2,
+        "Get-attr must be called on exactly two arguments"
+    );
+    attribute(&arguments[0], &arguments[1], env)
+}
+
+/// `(. value a b)` is `(get-attr (get-attr value 'a) 'b)`
+pub fn dot<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    let (value, names) = arguments
+        .split_first()
+        .context("The . macro must be called as (. value name ...)")?;
+    ensure!(!names.is_empty(), "The . macro needs an attribute name");
+    let mut value = value
+        .eval(env)
+        .with_context(|| anyhow!("Could not evaluate {value}"))?;
+    for name in names {
+        value = attribute(&value, name, env)?;
+    }
+    Ok(value)
+}
+
+pub fn set_environment<E: LispExpression>(env: &mut Environment<E>) {
+    env.set(
+        "get-attr",
+        BuiltinFunction::new("get-attr", get_attr).with_doc(
+            "(get-attr value name)",
+            "Field of a record, value of a key in an association list or attribute of a foreign value",
+        ),
+    );
+    env.set(
+        ".",
+        BuiltinMacro::new(".", dot).with_doc(
+            "(. value name ...)",
+            "Gets attributes in turn as get-attr does, with the names unevaluated",
+        ),
+    );
+}
@@ -0,0 +1,172 @@
+//! Throwing and catching values. Any value can be thrown, but libraries
+//! should throw `(error "message" data ...)` so that handlers can tell what
+//! went wrong, e.g. with `match` and the pattern `(error e)`.
+
+use anyhow::{anyhow, ensure, Context, Result};
+
+use crate::{
+    errors::{AllocationLimit, Cancelled, FuelExhausted, ReturnFrom, Thrown},
+    expression::{LispExpression, ToAndFrom},
+    prelude::*,
+    printer, BuiltinFunction, BuiltinMacro, Environment, ErrorValue, List, Number, Str,
+};
+
+pub fn error<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    let (message, data) = arguments
+        .split_first()
+        .context("Error must be called on a message and any data")?;
+    let message: &Str = message
+        .try_into_atom()
+        .context("The message of an error must be a string")?;
+    Ok(ErrorValue {
+        message: message.0.clone(),
+        data: data.to_vec(),
+    }
+    .into())
+}
+
+fn error_argument<'a, E>(arguments: &'a [E], name: &str) -> Result<&'a ErrorValue<E>>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 1,
+        "{name} must be called on exactly one argument"
+    );
+    ToAndFrom::<ErrorValue<E>>::try_into_atom(&arguments[0])
+        .with_context(|| anyhow!("{name} must be called on an error"))
+}
+
+pub fn error_message<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    let error = error_argument(arguments, "Error-message")?;
+    Ok(Str(error.message.clone()).into())
+}
+
+pub fn error_data<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    let error = error_argument(arguments, "Error-data")?;
+    Ok(List::new(error.data.clone()).into())
+}
+
+pub fn error_p<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 1,
+        "Error? must be called on exactly one argument"
+    );
+    Ok(
+        match ToAndFrom::<ErrorValue<E>>::try_into_atom(&arguments[0]) {
+            Ok(_) => Number(1.).into(),
+            Err(_) => E::null(),
+        },
+    )
+}
+
+pub fn throw<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 1,
+        "Throw must be called on exactly one argument"
+    );
+    let text = printer::write_text(&arguments[0]);
+    *env.thrown.borrow_mut() = Some(arguments[0].clone());
+    Err(Thrown(text).into())
+}
+
+/// Whether a failure is control flow or a limit of the sandbox, which code
+/// being limited must not be able to catch
+fn is_uncatchable(error: &anyhow::Error) -> bool {
+    error.downcast_ref::<ReturnFrom>().is_some()
+        || error.downcast_ref::<Cancelled>().is_some()
+        || error.downcast_ref::<FuelExhausted>().is_some()
+        || error.downcast_ref::<AllocationLimit>().is_some()
+}
+
+pub fn catch<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 3,
+        "Catch must be called on an expression, a symbol and a handler"
+    );
+    let symbol = arguments[1]
+        .as_symbol()
+        .context("Catch needs a symbol to bind the thrown value to")?;
+    let error = match arguments[0].eval(env) {
+        Ok(value) => return Ok(value),
+        Err(error) if is_uncatchable(&error) => return Err(error),
+        Err(error) => error,
+    };
+    let thrown = match error.downcast_ref::<Thrown>() {
+        Some(_) => env.thrown.borrow_mut().take().unwrap_or_else(E::null),
+        // Errors from builtins are caught as error values of their message
+        None => ErrorValue {
+            message: error
+                .chain()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(": "),
+            data: Vec::new(),
+        }
+        .into(),
+    };
+    let mut scope = env.scope();
+    scope.set(symbol.clone(), thrown);
+    arguments[2]
+        .eval(&mut scope)
+        .context("Could not evaluate the handler of catch")
+}
+
+pub fn set_environment<E: LispExpression>(env: &mut Environment<E>) {
+    env.set(
+        "error",
+        BuiltinFunction::new("error", error).with_doc(
+            "(error \"message\" data ...)",
+            "An error value with a message and a list of data, for throw",
+        ),
+    );
+    env.set(
+        "error-message",
+        BuiltinFunction::new("error-message", error_message)
+            .with_doc("(error-message error)", "The message of an error value"),
+    );
+    env.set(
+        "error-data",
+        BuiltinFunction::new("error-data", error_data)
+            .with_doc("(error-data error)", "The data of an error value as a list"),
+    );
+    env.set(
+        "error?",
+        BuiltinFunction::new("error?", error_p).with_doc(
+            "(error? value)",
+            "Returns 1 if the value is an error value, otherwise ()",
+        ),
+    );
+    env.set(
+        "throw",
+        BuiltinFunction::new("throw", throw).with_doc(
+            "(throw value)",
+            "Unwinds to the nearest catch, which receives the value",
+        ),
+    );
+    env.set(
+        "catch",
+        BuiltinMacro::new("catch", catch).with_doc(
+            "(catch expression symbol handler)",
+            "The value of the expression, or if it throws, of the handler with the symbol bound to what was thrown. Other errors are caught as error values.",
+        ),
+    );
+}
@@ -0,0 +1,296 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use core::{iter::Peekable, str::Chars};
+#[cfg(feature = "std")]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{
+    expression::{LispExpression, ToAndFrom},
+    float,
+    prelude::*,
+    BuiltinFunction, Capability, Environment, Number, Str,
+};
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// A UTC calendar time to the second
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct DateTime {
+    year: i64,
+    month: u32,
+    day: u32,
+    hour: u32,
+    minute: u32,
+    second: u32,
+}
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Days since 1970-01-01 in the proleptic Gregorian calendar, counting years
+// from March so the leap day falls at the end
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month as i64 + 9) % 12) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+impl DateTime {
+    fn from_seconds(seconds: i64) -> Self {
+        let (year, month, day) = civil_from_days(seconds.div_euclid(SECONDS_PER_DAY));
+        let time = seconds.rem_euclid(SECONDS_PER_DAY) as u32;
+        DateTime {
+            year,
+            month,
+            day,
+            hour: time / 3600,
+            minute: time / 60 % 60,
+            second: time % 60,
+        }
+    }
+
+    fn to_seconds(self) -> i64 {
+        days_from_civil(self.year, self.month, self.day) * SECONDS_PER_DAY
+            + (self.hour * 3600 + self.minute * 60 + self.second) as i64
+    }
+
+    fn format(&self, format: &str) -> Result<String> {
+        let mut output = String::new();
+        let mut chars = format.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                output.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('Y') => output.push_str(&format!("{:04}", self.year)),
+                Some('m') => output.push_str(&format!("{:02}", self.month)),
+                Some('d') => output.push_str(&format!("{:02}", self.day)),
+                Some('H') => output.push_str(&format!("{:02}", self.hour)),
+                Some('M') => output.push_str(&format!("{:02}", self.minute)),
+                Some('S') => output.push_str(&format!("{:02}", self.second)),
+                Some('%') => output.push('%'),
+                Some(other) => bail!("Unknown time format directive %{other}"),
+                None => bail!("Time format ends with a lone %"),
+            }
+        }
+        Ok(output)
+    }
+
+    fn parse(text: &str, format: &str) -> Result<Self> {
+        let mut time = DateTime::from_seconds(0);
+        let mut text = text.chars().peekable();
+        let mut format = format.chars();
+        while let Some(c) = format.next() {
+            let expected = if c == '%' {
+                match format.next() {
+                    Some('Y') => {
+                        time.year = read_digits(&mut text, 4, 'Y')? as i64;
+                        continue;
+                    }
+                    Some(directive @ ('m' | 'd' | 'H' | 'M' | 'S')) => {
+                        let value = read_digits(&mut text, 2, directive)?;
+                        match directive {
+                            'm' => time.month = value,
+                            'd' => time.day = value,
+                            'H' => time.hour = value,
+                            'M' => time.minute = value,
+                            _ => time.second = value,
+                        }
+                        continue;
+                    }
+                    Some('%') => '%',
+                    Some(other) => bail!("Unknown time format directive %{other}"),
+                    None => bail!("Time format ends with a lone %"),
+                }
+            } else {
+                c
+            };
+            ensure!(
+                text.next() == Some(expected),
+                "Expected {expected:?} in time"
+            );
+        }
+        ensure!(text.next().is_none(), "Extra characters after time");
+        ensure!(
+            (1..=12).contains(&time.month),
+            "Month {} out of range",
+            time.month
+        );
+        ensure!(
+            (1..=days_in_month(time.year, time.month)).contains(&time.day),
+            "Day {} out of range",
+            time.day
+        );
+        ensure!(
+            time.hour < 24 && time.minute < 60 && time.second < 60,
+            "Time of day {:02}:{:02}:{:02} out of range",
+            time.hour,
+            time.minute,
+            time.second
+        );
+        Ok(time)
+    }
+}
+
+/// Reads between one and `max` digits for the format directive `directive`
+fn read_digits(text: &mut Peekable<Chars>, max: usize, directive: char) -> Result<u32> {
+    let mut digits = String::new();
+    while digits.len() < max {
+        match text.peek() {
+            Some(c) if c.is_ascii_digit() => digits.push(*c),
+            _ => break,
+        }
+        text.next();
+    }
+    digits
+        .parse()
+        .map_err(|_| anyhow!("Expected digits for %{directive} in time"))
+}
+
+fn seconds_argument<E>(argument: &E, name: &str) -> Result<i64>
+where
+    E: LispExpression + ToAndFrom<Number>,
+{
+    let seconds: &Number = argument
+        .try_into_atom()
+        .with_context(|| anyhow!("Time passed to {name} should be a number of seconds"))?;
+    ensure!(
+        seconds.0.is_finite() && seconds.0.abs() < 1e15,
+        "{name} cannot represent the time {}",
+        seconds
+    );
+    Ok(float::floor(seconds.0) as i64)
+}
+
+pub fn now<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(arguments.is_empty(), "Now takes no arguments");
+    Ok(Number(seconds_now()?).into())
+}
+
+/// Seconds since the Unix epoch by the system clock
+#[cfg(feature = "std")]
+fn seconds_now() -> Result<f64> {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .context("System clock is before 1970")?;
+    Ok(since_epoch.as_secs_f64())
+}
+
+/// Without std there is no clock to read
+#[cfg(not(feature = "std"))]
+fn seconds_now() -> Result<f64> {
+    bail!("Now needs a system clock, which is unavailable without std")
+}
+
+pub fn timestamp<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(arguments.len() <= 1, "Timestamp takes at most one argument");
+    let seconds = match arguments.first() {
+        Some(argument) => seconds_argument(argument, "Timestamp")?,
+        None => seconds_argument(&now(&[], env)?, "Timestamp")?,
+    };
+    let text = DateTime::from_seconds(seconds).format("%Y-%m-%dT%H:%M:%SZ")?;
+    Ok(Str(text).into())
+}
+
+pub fn format_time<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 2,
+        "Format-time must be called on exactly two arguments"
+    );
+    let seconds = seconds_argument(&arguments[0], "Format-time")?;
+    let format: &Str = ToAndFrom::<Str>::try_into_atom(&arguments[1])
+        .context("Format passed to format-time should be a string")?;
+    Ok(Str(DateTime::from_seconds(seconds).format(&format.0)?).into())
+}
+
+pub fn parse_time<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 2,
+        "Parse-time must be called on exactly two arguments"
+    );
+    let text: &Str = ToAndFrom::<Str>::try_into_atom(&arguments[0])
+        .context("Time passed to parse-time should be a string")?;
+    let format: &Str = ToAndFrom::<Str>::try_into_atom(&arguments[1])
+        .context("Format passed to parse-time should be a string")?;
+    let time = DateTime::parse(&text.0, &format.0)
+        .with_context(|| anyhow!("Could not parse {:?} as {:?}", text.0, format.0))?;
+    Ok(Number(time.to_seconds() as f64).into())
+}
+
+pub fn set_environment<E: LispExpression>(env: &mut Environment<E>) {
+    env.set(
+        "now",
+        BuiltinFunction::new("now", now)
+            .with_doc(
+                "(now)",
+                "Returns the seconds since 1970-01-01 UTC, with a fractional part",
+            )
+            .with_capabilities(&[Capability::Clock]),
+    );
+    env.set(
+        "timestamp",
+        BuiltinFunction::new("timestamp", timestamp)
+            .with_doc(
+                "(timestamp [seconds])",
+                "Returns a UTC time, now by default, as an ISO 8601 string",
+            )
+            .with_capabilities(&[Capability::Clock]),
+    );
+    env.set(
+        "format-time",
+        BuiltinFunction::new("format-time", format_time).with_doc(
+            "(format-time seconds \"%Y-%m-%d %H:%M:%S\")",
+            "Formats a UTC time, supporting %Y %m %d %H %M %S and %%",
+        ),
+    );
+    env.set(
+        "parse-time",
+        BuiltinFunction::new("parse-time", parse_time).with_doc(
+            "(parse-time \"text\" \"%Y-%m-%d\")",
+            "Parses a UTC time written in the format-time format, returning seconds",
+        ),
+    );
+}
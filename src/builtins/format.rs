@@ -0,0 +1,248 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+
+use crate::{
+    expression::{LispExpression, ToAndFrom},
+    prelude::*,
+    printer::plain_text,
+    BuiltinFunction, Environment, Keyword, Number, Str,
+};
+
+const MAX_FORMAT_DIGITS: usize = u16::MAX as usize;
+
+/// How to write a number, from `format-number` options or a `{:...}` spec
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct NumberFormat {
+    precision: Option<usize>,
+    width: usize,
+    pad: char,
+    scientific: bool,
+}
+
+impl Default for NumberFormat {
+    fn default() -> Self {
+        NumberFormat {
+            precision: None,
+            width: 0,
+            pad: ' ',
+            scientific: false,
+        }
+    }
+}
+
+impl NumberFormat {
+    /// Parses specs like `.2`, `8.3`, `08.3` and `.3e`, where a leading 0 on
+    /// the width pads with zeros and a trailing `e` means scientific notation
+    fn parse(spec: &str) -> Result<Self> {
+        let mut format = NumberFormat::default();
+        let mut rest = spec;
+        if let Some(scientific) = rest.strip_suffix('e') {
+            format.scientific = true;
+            rest = scientific;
+        }
+        let (width, precision) = match rest.split_once('.') {
+            Some((width, precision)) => (width, Some(precision)),
+            None => (rest, None),
+        };
+        if width.starts_with('0') {
+            format.pad = '0';
+        }
+        if !width.is_empty() {
+            format.width = width
+                .parse()
+                .with_context(|| anyhow!("Invalid width in format spec {{:{spec}}}"))?;
+        }
+        if let Some(precision) = precision {
+            format.precision = Some(
+                precision
+                    .parse()
+                    .with_context(|| anyhow!("Invalid precision in format spec {{:{spec}}}"))?,
+            );
+        }
+        Ok(format)
+    }
+
+    fn format(&self, number: f64) -> Result<String> {
+        // Rust's formatting machinery panics on larger widths and precisions
+        ensure!(
+            self.width <= MAX_FORMAT_DIGITS && self.precision.unwrap_or(0) <= MAX_FORMAT_DIGITS,
+            "Width and precision are limited to {MAX_FORMAT_DIGITS}"
+        );
+        let body = match (self.scientific, self.precision) {
+            (true, Some(precision)) => format!("{number:.precision$e}"),
+            (true, None) => format!("{number:e}"),
+            (false, Some(precision)) => format!("{number:.precision$}"),
+            (false, None) => number.to_string(),
+        };
+        let padding = self.width.saturating_sub(body.chars().count());
+        let fill: String = core::iter::repeat_n(self.pad, padding).collect();
+        Ok(match body.strip_prefix('-') {
+            // Zeros go between the sign and the digits
+            Some(digits) if self.pad == '0' => format!("-{fill}{digits}"),
+            _ => format!("{fill}{body}"),
+        })
+    }
+}
+
+pub fn format_number<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() % 2 == 1,
+        "Format-number must be called on a number and pairs of options and values"
+    );
+    let number: f64 = arguments[0]
+        .extract()
+        .context("Format-number needs a number to format")?;
+    let mut format = NumberFormat::default();
+    for pair in arguments[1..].chunks(2) {
+        let option = ToAndFrom::<Keyword>::try_into_atom(&pair[0]).with_context(|| {
+            anyhow!("Format-number options should be keywords, not {}", pair[0])
+        })?;
+        let value = &pair[1];
+        match option.0.as_str() {
+            "precision" => format.precision = Some(value.extract().context("Invalid :precision")?),
+            "width" => format.width = value.extract().context("Invalid :width")?,
+            "pad" => format.pad = value.extract().context(":pad should be a char")?,
+            "scientific" => format.scientific = value.is_truthy(),
+            other => bail!(
+                "Unknown option :{other} to format-number, expected :precision, :width, :pad or :scientific"
+            ),
+        }
+    }
+    Ok(Str(format.format(number)?).into())
+}
+
+pub fn parse_number<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 1,
+        "Parse-number must be called on exactly one argument"
+    );
+    let text =
+        ToAndFrom::<Str>::try_into_atom(&arguments[0]).context("Parse-number needs a string")?;
+    Ok(match text.0.trim().parse() {
+        Ok(number) => Number(number).into(),
+        Err(_) => E::null(),
+    })
+}
+
+pub fn format<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        !arguments.is_empty(),
+        "Format must be called on a format string and its arguments"
+    );
+    let template = ToAndFrom::<Str>::try_into_atom(&arguments[0])
+        .context("The first argument to format should be a string")?;
+    let values = &arguments[1..];
+    let mut output = String::new();
+    let mut used = 0;
+    let mut chars = template.0.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.as_str().starts_with('{') => {
+                chars.next();
+                output.push('{');
+            }
+            '}' if chars.as_str().starts_with('}') => {
+                chars.next();
+                output.push('}');
+            }
+            '{' => {
+                let rest = chars.as_str();
+                let end = rest
+                    .find('}')
+                    .context("Unclosed { in format string, write {{ for a brace")?;
+                let placeholder = &rest[..end];
+                chars = rest[end + 1..].chars();
+                let value = values.get(used).with_context(|| {
+                    anyhow!(
+                        "Format string has more placeholders than the {} arguments given",
+                        values.len()
+                    )
+                })?;
+                used += 1;
+                match placeholder.strip_prefix(':') {
+                    Some(spec) => {
+                        let number: f64 = value.extract().with_context(|| {
+                            anyhow!("Format spec {{:{spec}}} needs a number, not {value}")
+                        })?;
+                        output.push_str(&NumberFormat::parse(spec)?.format(number)?);
+                    }
+                    None if placeholder.is_empty() => output.push_str(&plain_text(value)),
+                    None => {
+                        bail!("Invalid placeholder {{{placeholder}}}, expected {{}} or {{:spec}}")
+                    }
+                }
+            }
+            '}' => bail!("Unmatched }} in format string, write }}}} for a brace"),
+            c => output.push(c),
+        }
+    }
+    ensure!(
+        used == values.len(),
+        "Format string has {} placeholders but was given {} arguments",
+        used,
+        values.len()
+    );
+    Ok(Str(output).into())
+}
+
+pub fn set_number_precision<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 1,
+        "Number-precision! must be called on exactly one argument"
+    );
+    if arguments[0].as_list().is_ok_and(|list| list.0.is_empty()) {
+        env.config.borrow_mut().number_precision = None;
+    } else {
+        let precision: usize = arguments[0]
+            .extract()
+            .context("Number-precision! needs a number of decimals or ()")?;
+        ensure!(
+            precision <= MAX_FORMAT_DIGITS,
+            "Precision is limited to {MAX_FORMAT_DIGITS}"
+        );
+        env.config.borrow_mut().number_precision = Some(precision);
+    }
+    Ok(arguments[0].clone())
+}
+
+pub fn set_environment<E: LispExpression>(env: &mut Environment<E>) {
+    env.set(
+        "format-number",
+        BuiltinFunction::new("format-number", format_number).with_doc(
+            "(format-number n [:precision digits] [:width w] [:pad char] [:scientific 1])",
+            "The number as a string with a fixed number of decimals, padding or an exponent",
+        ),
+    );
+    env.set(
+        "parse-number",
+        BuiltinFunction::new("parse-number", parse_number).with_doc(
+            "(parse-number string)",
+            "The number written in the string, or () if it is not one",
+        ),
+    );
+    env.set(
+        "format",
+        BuiltinFunction::new("format", format).with_doc(
+            "(format template value ...)",
+            "Fills each {} in the template with a value, or {:.2}, {:8.3} or {:e} for numbers",
+        ),
+    );
+    env.set(
+        "number-precision!",
+        BuiltinFunction::new("number-precision!", set_number_precision).with_doc(
+            "(number-precision! decimals)",
+            "Displays numbers with this many decimals from now on, or as many as needed after '()",
+        ),
+    );
+}
@@ -0,0 +1,74 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+
+use super::lambda;
+use crate::{
+    expression::{LispExpression, ToAndFrom},
+    BuiltinMacro, Environment, Generic, Str,
+};
+
+pub fn defgeneric<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 1,
+        "Defgeneric must be called on exactly one argument"
+    );
+    let name = arguments[0]
+        .as_symbol()
+        .context("Defgeneric needs the name of the function")?;
+    env.check_redefinable(name)?;
+    let generic: E = Generic::new(name.clone()).into();
+    env.define(name.clone(), generic.clone());
+    Ok(generic)
+}
+
+pub fn defmethod<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 4,
+        "Defmethod must be called as (defmethod name kind (parameter ...) body)"
+    );
+    let name = arguments[0]
+        .as_symbol()
+        .context("Defmethod needs the name of a generic function")?;
+    let generic = env
+        .get(name)
+        .and_then(|value| ToAndFrom::<Generic<E>>::try_into_atom(value).ok())
+        .cloned()
+        .with_context(|| anyhow!("{} is not a generic function, see defgeneric", name))?;
+    // Kinds like "builtin function" have spaces, so may be written as strings
+    let kind = if let Ok(kind) = arguments[1].as_symbol() {
+        kind.0.clone()
+    } else if let Ok(kind) = ToAndFrom::<Str>::try_into_atom(&arguments[1]) {
+        kind.0.clone()
+    } else {
+        bail!(
+            "Method kinds must be symbols or strings, not {}",
+            arguments[1]
+        )
+    };
+    let method = lambda(&arguments[2..], env)
+        .with_context(|| anyhow!("Could not make the {} method of {}", kind, name))?;
+    generic.methods.borrow_mut().insert(kind, method);
+    Ok(generic.into())
+}
+
+pub fn set_environment<E: LispExpression>(env: &mut Environment<E>) {
+    env.set(
+        "defgeneric",
+        BuiltinMacro::new("defgeneric", defgeneric).with_doc(
+            "(defgeneric name)",
+            "Defines a function whose behaviour is chosen by the kind of its first argument",
+        ),
+    );
+    env.set(
+        "defmethod",
+        BuiltinMacro::new("defmethod", defmethod).with_doc(
+            "(defmethod name kind (parameter ...) body)",
+            "Adds the method used when the first argument is of a kind, e.g. number, a record type or default",
+        ),
+    );
+}
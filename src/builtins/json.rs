@@ -0,0 +1,310 @@
+//! JSON conversion. Objects become association lists of `("key" value)`
+//! pairs, `true` becomes `1`, and `false` and `null` become `()`. Writing does
+//! the reverse, so lists made only of pairs with string keys become objects.
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use core::{iter::Peekable, str::CharIndices};
+
+use crate::{
+    expression::{check_parse_depth, check_write_depth, LispExpression, ToAndFrom},
+    prelude::*,
+    BuiltinFunction, Char, Environment, Keyword, List, Number, Str,
+};
+
+pub fn parse<E: LispExpression>(input: &str) -> Result<E> {
+    let mut parser = Parser {
+        chars: input.char_indices().peekable(),
+        depth: 0,
+    };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    if let Some((position, c)) = parser.chars.next() {
+        bail!("Unexpected `{c}` after JSON value at {position}")
+    }
+    Ok(value)
+}
+
+pub fn write<E: LispExpression>(value: &E) -> Result<String> {
+    let mut output = String::new();
+    write_into(value, &mut output, 0)?;
+    Ok(output)
+}
+
+struct Parser<'a> {
+    chars: Peekable<CharIndices<'a>>,
+    /// How many arrays and objects the parser is inside, limited as lists in
+    /// source code are so that deep nesting fails rather than overflowing
+    /// the stack
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+    }
+
+    fn expect(&mut self, expected: char) -> Result<()> {
+        match self.chars.next() {
+            Some((_, c)) if c == expected => Ok(()),
+            Some((position, c)) => bail!("Expected `{expected}` but got `{c}` at {position}"),
+            None => bail!("Expected `{expected}` but the JSON ended"),
+        }
+    }
+
+    fn literal<E: LispExpression>(&mut self, word: &str, value: E) -> Result<E> {
+        for expected in word.chars() {
+            self.expect(expected)?;
+        }
+        Ok(value)
+    }
+
+    fn value<E: LispExpression>(&mut self) -> Result<E> {
+        self.skip_whitespace();
+        match self.chars.peek() {
+            Some((_, '{')) => self.nested(Self::object),
+            Some((_, '[')) => self.nested(Self::array),
+            Some((_, '"')) => Ok(Str(self.string()?).into()),
+            Some((_, 't')) => self.literal("true", Number(1.).into()),
+            Some((_, 'f')) => self.literal("false", E::null()),
+            Some((_, 'n')) => self.literal("null", E::null()),
+            Some((_, '-' | '0'..='9')) => self.number(),
+            Some((position, c)) => bail!("Unexpected `{c}` in JSON at {position}"),
+            None => bail!("Expected a JSON value but the input ended"),
+        }
+    }
+
+    fn nested<E>(&mut self, parse: impl FnOnce(&mut Self) -> Result<E>) -> Result<E> {
+        check_parse_depth(self.depth)?;
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn object<E: LispExpression>(&mut self) -> Result<E> {
+        self.expect('{')?;
+        let mut pairs = Vec::new();
+        self.skip_whitespace();
+        if self.chars.next_if(|(_, c)| *c == '}').is_some() {
+            return Ok(List::new(pairs).into());
+        }
+        loop {
+            self.skip_whitespace();
+            let key: E = Str(self.string()?).into();
+            self.skip_whitespace();
+            self.expect(':')?;
+            let value = self.value()?;
+            pairs.push(List::new(vec![key, value]).into());
+            self.skip_whitespace();
+            if self.chars.next_if(|(_, c)| *c == ',').is_none() {
+                self.expect('}')?;
+                return Ok(List::new(pairs).into());
+            }
+        }
+    }
+
+    fn array<E: LispExpression>(&mut self) -> Result<E> {
+        self.expect('[')?;
+        let mut elements = Vec::new();
+        self.skip_whitespace();
+        if self.chars.next_if(|(_, c)| *c == ']').is_some() {
+            return Ok(List::new(elements).into());
+        }
+        loop {
+            elements.push(self.value()?);
+            self.skip_whitespace();
+            if self.chars.next_if(|(_, c)| *c == ',').is_none() {
+                self.expect(']')?;
+                return Ok(List::new(elements).into());
+            }
+        }
+    }
+
+    fn number<E: LispExpression>(&mut self) -> Result<E> {
+        let mut number = String::new();
+        while let Some((_, c)) = self
+            .chars
+            .next_if(|(_, c)| matches!(c, '-' | '+' | '.' | 'e' | 'E' | '0'..='9'))
+        {
+            number.push(c);
+        }
+        let number: f64 = number
+            .parse()
+            .with_context(|| anyhow!("Invalid JSON number {number}"))?;
+        Ok(Number(number).into())
+    }
+
+    fn hex_escape(&mut self) -> Result<u32> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let (position, c) = self
+                .chars
+                .next()
+                .ok_or_else(|| anyhow!("Unterminated unicode escape in JSON"))?;
+            let digit = c
+                .to_digit(16)
+                .ok_or_else(|| anyhow!("Invalid unicode escape `{c}` at {position}"))?;
+            code = code * 16 + digit;
+        }
+        Ok(code)
+    }
+
+    fn string(&mut self) -> Result<String> {
+        self.expect('"')?;
+        let mut string = String::new();
+        loop {
+            match self.chars.next() {
+                Some((_, '"')) => return Ok(string),
+                Some((position, '\\')) => match self.chars.next() {
+                    Some((_, '"')) => string.push('"'),
+                    Some((_, '\\')) => string.push('\\'),
+                    Some((_, '/')) => string.push('/'),
+                    Some((_, 'b')) => string.push('\u{8}'),
+                    Some((_, 'f')) => string.push('\u{c}'),
+                    Some((_, 'n')) => string.push('\n'),
+                    Some((_, 'r')) => string.push('\r'),
+                    Some((_, 't')) => string.push('\t'),
+                    Some((_, 'u')) => {
+                        let mut code = self.hex_escape()?;
+                        if (0xd800..0xdc00).contains(&code) {
+                            // High surrogate, which must be followed by a low one
+                            self.expect('\\')?;
+                            self.expect('u')?;
+                            let low = self.hex_escape()?;
+                            ensure!(
+                                (0xdc00..0xe000).contains(&low),
+                                "Unpaired surrogate in JSON string at {position}"
+                            );
+                            code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                        }
+                        string.push(char::from_u32(code).ok_or_else(|| {
+                            anyhow!("Invalid unicode escape in JSON string at {position}")
+                        })?);
+                    }
+                    _ => bail!("Invalid escape in JSON string at {position}"),
+                },
+                Some((_, c)) => string.push(c),
+                None => bail!("Unterminated JSON string"),
+            }
+        }
+    }
+}
+
+fn write_string(string: &str, output: &mut String) {
+    output.push('"');
+    for c in string.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            c if c.is_control() => output.push_str(&format!("\\u{:04x}", c as u32)),
+            c => output.push(c),
+        }
+    }
+    output.push('"');
+}
+
+/// The pairs of a non-empty association list with string or keyword keys
+fn as_object<E: LispExpression>(list: &List<E>) -> Option<Vec<(&str, &E)>> {
+    if list.0.is_empty() {
+        return None;
+    }
+    list.0
+        .iter()
+        .map(|pair| match pair.as_list().ok()?.0.as_slice() {
+            [key, value] => {
+                let key = match ToAndFrom::<Str>::try_into_atom(key) {
+                    Ok(key) => &key.0,
+                    Err(_) => &ToAndFrom::<Keyword>::try_into_atom(key).ok()?.0,
+                };
+                Some((key.as_str(), value))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Writes `value` found inside `depth` arrays or objects, refusing to nest
+/// deeper than is parsed back
+fn write_into<E: LispExpression>(value: &E, output: &mut String, depth: usize) -> Result<()> {
+    if let Ok(number) = ToAndFrom::<Number>::try_into_atom(value) {
+        ensure!(
+            number.0.is_finite(),
+            "JSON cannot represent the number {}",
+            number.0
+        );
+        output.push_str(&number.0.to_string());
+    } else if let Ok(string) = ToAndFrom::<Str>::try_into_atom(value) {
+        write_string(&string.0, output);
+    } else if let Ok(c) = ToAndFrom::<Char>::try_into_atom(value) {
+        write_string(&c.0.to_string(), output);
+    } else if let Ok(symbol) = value.as_symbol() {
+        write_string(&symbol.0, output);
+    } else if let Ok(keyword) = ToAndFrom::<Keyword>::try_into_atom(value) {
+        write_string(&keyword.0, output);
+    } else if let Ok(list) = value.as_list() {
+        check_write_depth(depth)?;
+        if let Some(pairs) = as_object(list) {
+            output.push('{');
+            for (n, (key, value)) in pairs.into_iter().enumerate() {
+                if n > 0 {
+                    output.push(',');
+                }
+                write_string(key, output);
+                output.push(':');
+                write_into(value, output, depth + 1)?;
+            }
+            output.push('}');
+        } else {
+            output.push('[');
+            for (n, element) in list.0.iter().enumerate() {
+                if n > 0 {
+                    output.push(',');
+                }
+                write_into(element, output, depth + 1)?;
+            }
+            output.push(']');
+        }
+    } else {
+        bail!("Cannot write {} as JSON", value.variant())
+    }
+    Ok(())
+}
+
+pub fn json_parse<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    let input = super::single_string(arguments, "Json-parse")?;
+    parse(&input.0).context("Could not parse JSON")
+}
+
+pub fn json_write<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 1,
+        "Json-write must be called on exactly one argument"
+    );
+    Ok(Str(write(&arguments[0])?).into())
+}
+
+pub fn set_environment<E: LispExpression>(env: &mut Environment<E>) {
+    env.set(
+        "json-parse",
+        BuiltinFunction::new("json-parse", json_parse).with_doc(
+            "(json-parse \"json\")",
+            "Value of a JSON document, with objects as association lists",
+        ),
+    );
+    env.set(
+        "json-write",
+        BuiltinFunction::new("json-write", json_write).with_doc(
+            "(json-write value)",
+            "JSON text of a value, writing association lists as objects",
+        ),
+    );
+}
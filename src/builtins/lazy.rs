@@ -0,0 +1,153 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+
+use super::apply;
+use crate::{
+    expression::{LispExpression, ToAndFrom},
+    prelude::*,
+    BuiltinFunction, BuiltinMacro, Environment, LazySeq, LazyStep, Number,
+};
+
+/// The first step of the sequence or list that `value` holds
+pub(crate) fn step_of<E>(value: E, env: &mut Environment<E>) -> Result<LazyStep<E>>
+where
+    E: LispExpression,
+{
+    if let Ok(seq) = ToAndFrom::<LazySeq<E>>::try_into_atom(&value) {
+        seq.next(env)
+    } else if let Ok(list) = value.as_list() {
+        Ok(list
+            .0
+            .split_first()
+            .map(|(first, rest)| (first.clone(), LazySeq::from_vec(rest.to_vec()))))
+    } else {
+        bail!(
+            "Expected a list or lazy sequence and got {}",
+            value.variant()
+        )
+    }
+}
+
+/// A sequence of the elements `expression` evaluates to, evaluated in a copy
+/// of `env` the first time they are needed
+fn delay<E>(expression: &E, env: &Environment<E>) -> LazySeq<E>
+where
+    E: LispExpression,
+{
+    let expression = expression.clone();
+    let mut captured = env.clone();
+    LazySeq::new(move |env| {
+        let value = expression
+            .eval(&mut captured)
+            .with_context(|| anyhow!("Could not evaluate lazy expression {}", expression))?;
+        step_of(value, env)
+    })
+}
+
+pub fn lazy<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 1,
+        "Lazy must be called on exactly one argument"
+    );
+    Ok(delay(&arguments[0], env).into())
+}
+
+pub fn lazy_cons<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 2,
+        "Lazy-cons must be called on exactly two arguments"
+    );
+    let first = arguments[0]
+        .eval(env)
+        .context("Could not evaluate first element of lazy-cons")?;
+    Ok(LazySeq::cons(first, delay(&arguments[1], env)).into())
+}
+
+fn iterate_from<E>(function: E, value: E) -> LazySeq<E>
+where
+    E: LispExpression,
+{
+    let previous = value.clone();
+    LazySeq::cons(
+        value,
+        LazySeq::new(move |env| {
+            let next = apply(&function, core::slice::from_ref(&previous), env)?;
+            iterate_from(function, next).next(env)
+        }),
+    )
+}
+
+pub fn iterate<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 2,
+        "Iterate must be called on exactly two arguments"
+    );
+    Ok(iterate_from(arguments[0].clone(), arguments[1].clone()).into())
+}
+
+fn count_from<E>(start: f64, step: f64) -> LazySeq<E>
+where
+    E: LispExpression,
+{
+    LazySeq::new(move |_env| Ok(Some((Number(start).into(), count_from(start + step, step)))))
+}
+
+pub fn range_inf<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        matches!(arguments.len(), 1 | 2),
+        "Range-inf must be called on one or two arguments"
+    );
+    let numbers: Vec<f64> = arguments
+        .iter()
+        .map(|e| e.extract())
+        .collect::<Result<_>>()
+        .context("Arguments to range-inf should be numbers")?;
+    let step = numbers.get(1).copied().unwrap_or(1.);
+    ensure!(
+        numbers[0].is_finite() && step.is_finite(),
+        "Range-inf needs a finite start and step"
+    );
+    Ok(count_from(numbers[0], step).into())
+}
+
+pub fn set_environment<E: LispExpression>(env: &mut Environment<E>) {
+    env.set(
+        "lazy",
+        BuiltinMacro::new("lazy", lazy).with_doc(
+            "(lazy expression)",
+            "Lazy sequence of the list or sequence the expression gives when first needed",
+        ),
+    );
+    env.set(
+        "lazy-cons",
+        BuiltinMacro::new("lazy-cons", lazy_cons).with_doc(
+            "(lazy-cons first rest)",
+            "Lazy sequence of first followed by the elements of rest, evaluated when needed",
+        ),
+    );
+    env.set(
+        "iterate",
+        BuiltinFunction::new("iterate", iterate).with_doc(
+            "(iterate f x)",
+            "Infinite lazy sequence of x, (f x), (f (f x)) and so on",
+        ),
+    );
+    env.set(
+        "range-inf",
+        BuiltinFunction::new("range-inf", range_inf).with_doc(
+            "(range-inf start [step])",
+            "Infinite lazy sequence of numbers counting up from start",
+        ),
+    );
+}
@@ -0,0 +1,533 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+
+use super::apply;
+use crate::{
+    atoms::sequence_index,
+    errors::IndexOutOfRange,
+    expression::{LispExpression, ToAndFrom},
+    float,
+    prelude::*,
+    BuiltinFunction, Environment, LazySeq, List, Number, Vector,
+};
+
+fn list_argument<'a, E>(arguments: &'a [E], n: usize, name: &str) -> Result<&'a List<E>>
+where
+    E: LispExpression,
+{
+    arguments[n]
+        .as_list()
+        .with_context(|| anyhow!("Argument number {} to {name} should be a list", n + 1))
+}
+
+fn count_argument<E>(argument: &E, name: &str) -> Result<usize>
+where
+    E: LispExpression,
+{
+    let count: i64 = argument
+        .extract()
+        .with_context(|| anyhow!("{name} needs an integer count"))?;
+    ensure!(
+        count >= 0,
+        "{name} needs a count of at least 0, not {count}"
+    );
+    Ok(count as usize)
+}
+
+/// Runs `f` on the elements of a list or vector argument
+fn with_elements<E, R>(
+    arguments: &[E],
+    n: usize,
+    name: &str,
+    f: impl FnOnce(&[E]) -> Result<R>,
+) -> Result<R>
+where
+    E: LispExpression,
+{
+    if let Ok(list) = arguments[n].as_list() {
+        f(&list.0)
+    } else if let Ok(vector) = ToAndFrom::<Vector<E>>::try_into_atom(&arguments[n]) {
+        f(&vector.0.borrow())
+    } else {
+        bail!(
+            "Argument number {} to {name} should be a list or vector, not {}",
+            n + 1,
+            arguments[n].variant()
+        )
+    }
+}
+
+fn truth<E: LispExpression>(value: bool) -> E {
+    if value {
+        Number(1.).into()
+    } else {
+        E::null()
+    }
+}
+
+/// The index of the first element for which `predicate` is truthy
+fn first_match<E>(predicate: &E, list: &List<E>, env: &mut Environment<E>) -> Result<Option<usize>>
+where
+    E: LispExpression,
+{
+    for (n, element) in list.0.iter().enumerate() {
+        let matched = apply(predicate, core::slice::from_ref(element), env)
+            .with_context(|| anyhow!("Could not test element number {}: {}", n + 1, element))?;
+        if matched.is_truthy() {
+            return Ok(Some(n));
+        }
+    }
+    Ok(None)
+}
+
+pub fn assoc<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 2,
+        "Assoc must be called on exactly two arguments"
+    );
+    let pairs = list_argument(arguments, 1, "assoc")?;
+    Ok(pairs
+        .0
+        .iter()
+        .find(
+            |pair| matches!(pair.as_list(), Ok(List(pair)) if pair.first() == Some(&arguments[0])),
+        )
+        .cloned()
+        .unwrap_or_else(E::null))
+}
+
+pub fn member<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 2,
+        "Member? must be called on exactly two arguments"
+    );
+    let list = list_argument(arguments, 1, "member?")?;
+    Ok(truth(list.0.contains(&arguments[0])))
+}
+
+pub fn find<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 2,
+        "Find must be called on exactly two arguments"
+    );
+    let list = list_argument(arguments, 1, "find")?;
+    Ok(match first_match(&arguments[0], list, env)? {
+        Some(n) => list.0[n].clone(),
+        None => E::null(),
+    })
+}
+
+pub fn position<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 2,
+        "Position must be called on exactly two arguments"
+    );
+    let list = list_argument(arguments, 1, "position")?;
+    Ok(match first_match(&arguments[0], list, env)? {
+        Some(n) => Number(n as f64).into(),
+        None => E::null(),
+    })
+}
+
+pub fn nth<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 2,
+        "Nth must be called on a list and an index"
+    );
+    with_elements(arguments, 0, "nth", |elements| {
+        let index = sequence_index(&arguments[1], elements.len(), false)?;
+        Ok(elements[index].clone())
+    })
+}
+
+pub fn first<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 1,
+        "First must be called on exactly one argument"
+    );
+    with_elements(arguments, 0, "first", |elements| {
+        elements.first().cloned().ok_or_else(|| {
+            IndexOutOfRange {
+                index: 0.,
+                length: 0,
+            }
+            .into()
+        })
+    })
+}
+
+pub fn last<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 1,
+        "Last must be called on exactly one argument"
+    );
+    with_elements(arguments, 0, "last", |elements| {
+        elements.last().cloned().ok_or_else(|| {
+            IndexOutOfRange {
+                index: -1.,
+                length: 0,
+            }
+            .into()
+        })
+    })
+}
+
+pub fn slice<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        (2..=3).contains(&arguments.len()),
+        "Slice must be called on a list, a start and optionally an end"
+    );
+    let sliced = with_elements(arguments, 0, "slice", |elements| {
+        let start = sequence_index(&arguments[1], elements.len(), true)?;
+        let end = match arguments.get(2) {
+            Some(end) => sequence_index(end, elements.len(), true)?,
+            None => elements.len(),
+        };
+        Ok(elements[start..end.max(start)].to_vec())
+    })?;
+    if arguments[0].as_list().is_ok() {
+        Ok(List::new(sliced).into())
+    } else {
+        Ok(Vector::new(sliced).into())
+    }
+}
+
+pub fn index_of<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 2,
+        "Index-of must be called on a value and a list"
+    );
+    with_elements(arguments, 1, "index-of", |elements| {
+        Ok(elements
+            .iter()
+            .position(|element| element.is_equal(&arguments[0]))
+            .map_or_else(E::null, |index| Number(index as f64).into()))
+    })
+}
+
+pub fn take<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 2,
+        "Take must be called on exactly two arguments"
+    );
+    let count = count_argument(&arguments[0], "Take")?;
+    if let Ok(seq) = ToAndFrom::<LazySeq<E>>::try_into_atom(&arguments[1]) {
+        return Ok(List::new(seq.take(count, env)?).into());
+    }
+    let list = list_argument(arguments, 1, "take")?;
+    Ok(List::new(list.0.iter().take(count).cloned().collect()).into())
+}
+
+fn map_seq<E>(function: E, seq: LazySeq<E>) -> LazySeq<E>
+where
+    E: LispExpression,
+{
+    LazySeq::new(move |env| {
+        let Some((first, rest)) = seq.next(env)? else {
+            return Ok(None);
+        };
+        let first = apply(&function, &[first], env)?;
+        Ok(Some((first, map_seq(function, rest))))
+    })
+}
+
+pub fn map<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 2,
+        "Map must be called on exactly two arguments"
+    );
+    if let Ok(seq) = ToAndFrom::<LazySeq<E>>::try_into_atom(&arguments[1]) {
+        return Ok(map_seq(arguments[0].clone(), seq.clone()).into());
+    }
+    let list = list_argument(arguments, 1, "map")?;
+    let mapped = list
+        .0
+        .iter()
+        .enumerate()
+        .map(|(n, element)| {
+            apply(&arguments[0], core::slice::from_ref(element), env)
+                .with_context(|| anyhow!("Could not map element number {}: {}", n + 1, element))
+        })
+        .collect::<Result<_>>()?;
+    Ok(List::new(mapped).into())
+}
+
+fn filter_seq<E>(predicate: E, seq: LazySeq<E>) -> LazySeq<E>
+where
+    E: LispExpression,
+{
+    LazySeq::new(move |env| {
+        let mut seq = seq;
+        while let Some((first, rest)) = seq.next(env)? {
+            if apply(&predicate, core::slice::from_ref(&first), env)?.is_truthy() {
+                return Ok(Some((first, filter_seq(predicate, rest))));
+            }
+            seq = rest;
+        }
+        Ok(None)
+    })
+}
+
+pub fn filter<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 2,
+        "Filter must be called on exactly two arguments"
+    );
+    if let Ok(seq) = ToAndFrom::<LazySeq<E>>::try_into_atom(&arguments[1]) {
+        return Ok(filter_seq(arguments[0].clone(), seq.clone()).into());
+    }
+    let list = list_argument(arguments, 1, "filter")?;
+    let mut kept = Vec::new();
+    for (n, element) in list.0.iter().enumerate() {
+        let keep = apply(&arguments[0], core::slice::from_ref(element), env)
+            .with_context(|| anyhow!("Could not test element number {}: {}", n + 1, element))?;
+        if keep.is_truthy() {
+            kept.push(element.clone());
+        }
+    }
+    Ok(List::new(kept).into())
+}
+
+pub fn drop<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 2,
+        "Drop must be called on exactly two arguments"
+    );
+    let count = count_argument(&arguments[0], "Drop")?;
+    let list = list_argument(arguments, 1, "drop")?;
+    Ok(List::new(list.0.iter().skip(count).cloned().collect()).into())
+}
+
+pub fn zip<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    let lists: Vec<&List<E>> = (0..arguments.len())
+        .map(|n| list_argument(arguments, n, "zip"))
+        .collect::<Result<_>>()?;
+    let length = lists.iter().map(|list| list.0.len()).min().unwrap_or(0);
+    let zipped = (0..length)
+        .map(|n| {
+            env.consume_fuel()?;
+            Ok(List::new(lists.iter().map(|list| list.0[n].clone()).collect()).into())
+        })
+        .collect::<Result<_>>()?;
+    Ok(List::new(zipped).into())
+}
+
+pub fn flatten<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 1,
+        "Flatten must be called on exactly one argument"
+    );
+    let list = list_argument(arguments, 0, "flatten")?;
+    // Walk with an explicit stack so deep nesting cannot overflow
+    let mut flat = Vec::new();
+    let mut stack = vec![list.0.iter()];
+    while let Some(elements) = stack.last_mut() {
+        env.consume_fuel()?;
+        match elements.next() {
+            Some(element) => match element.as_list() {
+                Ok(inner) => stack.push(inner.0.iter()),
+                Err(_) => {
+                    env.check_allocation(flat.len() + 1)?;
+                    flat.push(element.clone());
+                }
+            },
+            None => {
+                stack.pop();
+            }
+        }
+    }
+    Ok(List::new(flat).into())
+}
+
+pub fn range<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    let numbers: Vec<f64> = arguments
+        .iter()
+        .enumerate()
+        .map(|(n, e)| {
+            e.extract()
+                .with_context(|| anyhow!("Argument number {}: {}", n + 1, e))
+        })
+        .collect::<Result<_>>()
+        .context("Arguments to range should be numbers")?;
+    let (start, end, step) = match numbers[..] {
+        [end] => (0., end, 1.),
+        [start, end] => (start, end, 1.),
+        [start, end, step] => (start, end, step),
+        _ => bail!("Range must be called on one to three arguments"),
+    };
+    ensure!(
+        start.is_finite() && end.is_finite() && step.is_finite() && step != 0.,
+        "Range needs finite bounds and a non-zero step"
+    );
+    let count = float::ceil((end - start) / step).max(0.) as usize;
+    env.check_allocation(count)?;
+    let mut numbers = Vec::new();
+    numbers
+        .try_reserve_exact(count)
+        .with_context(|| anyhow!("Range of {count} numbers is too long"))?;
+    for n in 0..count {
+        env.consume_fuel()?;
+        numbers.push(Number(start + n as f64 * step).into());
+    }
+    Ok(List::new(numbers).into())
+}
+
+pub fn set_environment<E: LispExpression>(env: &mut Environment<E>) {
+    env.set(
+        "assoc",
+        BuiltinFunction::new("assoc", assoc).with_doc(
+            "(assoc key association-list)",
+            "The first (key value) pair with the key, or ()",
+        ),
+    );
+    env.set(
+        "member?",
+        BuiltinFunction::new("member?", member).with_doc(
+            "(member? value list)",
+            "Returns 1 if the list contains the value, otherwise ()",
+        ),
+    );
+    env.set(
+        "find",
+        BuiltinFunction::new("find", find).with_doc(
+            "(find predicate list)",
+            "The first element satisfying the predicate, or ()",
+        ),
+    );
+    env.set(
+        "position",
+        BuiltinFunction::new("position", position).with_doc(
+            "(position predicate list)",
+            "The index from 0 of the first element satisfying the predicate, or ()",
+        ),
+    );
+    env.set(
+        "nth",
+        BuiltinFunction::new("nth", nth).with_doc(
+            "(nth list index)",
+            "The element of a list or vector at the index from 0, counting back from the end if negative",
+        ),
+    );
+    env.set(
+        "first",
+        BuiltinFunction::new("first", first).with_doc(
+            "(first list)",
+            "The first element of a non-empty list or vector",
+        ),
+    );
+    env.set(
+        "last",
+        BuiltinFunction::new("last", last).with_doc(
+            "(last list)",
+            "The last element of a non-empty list or vector",
+        ),
+    );
+    env.set(
+        "slice",
+        BuiltinFunction::new("slice", slice).with_doc(
+            "(slice list start [end])",
+            "The elements from start up to but excluding end, or to the end of the list or vector",
+        ),
+    );
+    env.set(
+        "index-of",
+        BuiltinFunction::new("index-of", index_of).with_doc(
+            "(index-of value list)",
+            "The index from 0 of the first element equal to the value, or ()",
+        ),
+    );
+    env.set(
+        "take",
+        BuiltinFunction::new("take", take).with_doc(
+            "(take n list)",
+            "The first n elements of a list or lazy sequence, as a list",
+        ),
+    );
+    env.set(
+        "map",
+        BuiltinFunction::new("map", map).with_doc(
+            "(map f list)",
+            "Applies f to each element, lazily if given a lazy sequence",
+        ),
+    );
+    env.set(
+        "filter",
+        BuiltinFunction::new("filter", filter).with_doc(
+            "(filter predicate list)",
+            "The elements satisfying the predicate, lazily if given a lazy sequence",
+        ),
+    );
+    env.set(
+        "drop",
+        BuiltinFunction::new("drop", drop)
+            .with_doc("(drop n list)", "The list without its first n elements"),
+    );
+    env.set(
+        "zip",
+        BuiltinFunction::new("zip", zip).with_doc(
+            "(zip list ...)",
+            "Lists of the elements at each index, as long as the shortest list",
+        ),
+    );
+    env.set(
+        "flatten",
+        BuiltinFunction::new("flatten", flatten).with_doc(
+            "(flatten list)",
+            "The non-list elements of a list and all the lists nested in it",
+        ),
+    );
+    env.set(
+        "range",
+        BuiltinFunction::new("range", range).with_doc(
+            "(range [start] end [step])",
+            "Numbers from start, 0 by default, up to but excluding end",
+        ),
+    );
+}
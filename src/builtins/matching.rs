@@ -0,0 +1,166 @@
+//! `match`, which picks the first clause whose pattern fits a value. Patterns
+//! are written as shallot source:
+//!
+//! - `_` matches anything and a symbol matches anything, binding it
+//! - numbers, strings, chars and keywords match equal values, as does a
+//!   quoted value such as `'done` or `'(1 2)`
+//! - `(number n)` matches a value of that type and then the pattern `n`,
+//!   for any type name such as `string`, `list` or `builtin-function`, so a
+//!   two element list pattern can't start with a variable of such a name
+//! - `(a b rest...)` matches a list of at least two elements, binding the
+//!   others as a list to `rest`, and `[a b]` matches a vector likewise
+//!
+//! A clause may add a guard after its pattern, as in `(n :when (<= 0 n) body)`,
+//! which has to be truthy for the clause to be taken.
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+
+use crate::{
+    atoms::ElementPatterns,
+    expression::{LispExpression, ToAndFrom},
+    prelude::*,
+    BuiltinMacro, Environment, Keyword, List, Symbol, Vector,
+};
+
+/// Matches `value` against `pattern`, adding the variables it binds to
+/// `bindings`. A variable used twice has to match equal values.
+pub(crate) fn match_into<E>(pattern: &E, value: &E, bindings: &mut Vec<(Symbol, E)>) -> bool
+where
+    E: LispExpression,
+{
+    if let Ok(symbol) = pattern.as_symbol() {
+        return bind(symbol, value, bindings);
+    }
+    if let Ok(vector) = ToAndFrom::<Vector<E>>::try_into_atom(pattern) {
+        let Ok(values) = ToAndFrom::<Vector<E>>::try_into_atom(value) else {
+            return false;
+        };
+        let patterns = vector.0.borrow().clone();
+        let values = values.0.borrow().clone();
+        return match_elements(&patterns, &values, bindings, |rest| {
+            Vector::new(rest).into()
+        });
+    }
+    let Ok(patterns) = pattern.as_list() else {
+        return pattern.is_equal(value);
+    };
+    match patterns.0.as_slice() {
+        [head, quoted] if head.as_symbol().is_ok_and(|s| s.0 == "'") => quoted.is_equal(value),
+        [head, inner] if type_name(head).is_some() => {
+            type_name(head).is_some_and(|name| name == value.variant())
+                && match_into(inner, value, bindings)
+        }
+        patterns => match value.as_list() {
+            Ok(values) => {
+                match_elements(patterns, &values.0, bindings, |rest| List::new(rest).into())
+            }
+            Err(_) => false,
+        },
+    }
+}
+
+fn bind<E: LispExpression>(symbol: &Symbol, value: &E, bindings: &mut Vec<(Symbol, E)>) -> bool {
+    if symbol.0 == "_" {
+        return true;
+    }
+    match bindings.iter().find(|(bound, _)| bound == symbol) {
+        Some((_, bound)) => bound.is_equal(value),
+        None => {
+            bindings.push((symbol.clone(), value.clone()));
+            true
+        }
+    }
+}
+
+/// Matches elements pairwise, with a final `name...` pattern taking the rest
+fn match_elements<E: LispExpression>(
+    patterns: &[E],
+    values: &[E],
+    bindings: &mut Vec<(Symbol, E)>,
+    collect_rest: impl FnOnce(Vec<E>) -> E,
+) -> bool {
+    let elements = ElementPatterns::new(patterns);
+    if !elements.fit(values)
+        || !elements
+            .fixed
+            .iter()
+            .zip(values)
+            .all(|(p, v)| match_into(p, v, bindings))
+    {
+        return false;
+    }
+    match elements.rest {
+        Some(rest) => bind(
+            &Symbol::from(rest),
+            &collect_rest(elements.rest_of(values).to_vec()),
+            bindings,
+        ),
+        None => true,
+    }
+}
+
+/// The atom name a `(type pattern)` head stands for, with `-` for spaces
+fn type_name<E: LispExpression>(head: &E) -> Option<String> {
+    let name = head.as_symbol().ok()?.0.replace('-', " ");
+    E::atom_names().contains(&name.as_str()).then_some(name)
+}
+
+pub fn match_clauses<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        !arguments.is_empty(),
+        "Match must be called on a value and clauses"
+    );
+    let value = arguments[0]
+        .eval(env)
+        .context("Could not evaluate the value to match")?;
+    for (n, clause) in arguments[1..].iter().enumerate() {
+        let clause = clause
+            .as_list()
+            .with_context(|| anyhow!("Clause number {} should be (pattern body)", n + 1))?;
+        let (pattern, guard, body) = match clause.0.as_slice() {
+            [pattern, body] => (pattern, None, body),
+            [pattern, keyword, guard, body]
+                if ToAndFrom::<Keyword>::try_into_atom(keyword).is_ok_and(|k| k.0 == "when") =>
+            {
+                (pattern, Some(guard), body)
+            }
+            _ => bail!(
+                "Clause number {} should be (pattern body) or (pattern :when guard body)",
+                n + 1
+            ),
+        };
+        let mut bindings = Vec::new();
+        if !match_into(pattern, &value, &mut bindings) {
+            continue;
+        }
+        let mut scope = env.scope();
+        for (symbol, bound) in bindings {
+            scope.set(symbol, bound);
+        }
+        if let Some(guard) = guard {
+            let passed = guard.eval(&mut scope).with_context(|| {
+                anyhow!("Could not evaluate the guard of clause number {}", n + 1)
+            })?;
+            if !passed.is_truthy() {
+                continue;
+            }
+        }
+        return body
+            .eval(&mut scope)
+            .with_context(|| anyhow!("Could not evaluate the body of clause number {}", n + 1));
+    }
+    bail!("No pattern matched {value}")
+}
+
+pub fn set_environment<E: LispExpression>(env: &mut Environment<E>) {
+    env.set(
+        "match",
+        BuiltinMacro::new("match", match_clauses).with_doc(
+            "(match value (pattern body) (pattern :when guard body) ...)",
+            "Evaluates the body of the first clause whose pattern fits the value, with the pattern's variables bound",
+        ),
+    );
+}
@@ -0,0 +1,83 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use core::f64::consts::PI;
+
+use crate::{expression::LispExpression, float, prelude::*, BuiltinFunction, Environment, Number};
+
+pub fn atan2<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 2,
+        "Atan2 must be called on exactly two arguments"
+    );
+    let y: f64 = arguments[0]
+        .extract()
+        .context("Argument number 1 to atan2")?;
+    let x: f64 = arguments[1]
+        .extract()
+        .context("Argument number 2 to atan2")?;
+    Ok(Number(float::atan2(y, x)).into())
+}
+
+pub fn log<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    let numbers: Vec<f64> = arguments
+        .iter()
+        .enumerate()
+        .map(|(n, e)| {
+            e.extract()
+                .with_context(|| anyhow!("Argument number {}: {}", n + 1, e))
+        })
+        .collect::<Result<_>>()
+        .context("Arguments to log should be numbers")?;
+    match numbers[..] {
+        [x] => Ok(Number(float::log10(x)).into()),
+        [x, base] => Ok(Number(float::ln(x) / float::ln(base)).into()),
+        _ => bail!("Log must be called on a number and optionally a base"),
+    }
+}
+
+pub fn set_environment<E: LispExpression>(env: &mut Environment<E>) {
+    env.set("pi", Number(PI));
+    env.set("e", Number(core::f64::consts::E));
+    env.set(
+        "sin",
+        BuiltinFunction::new_wrapped("sin", |x: &Number| Ok(Number(float::sin(x.0))))
+            .with_doc("(sin radians)", "Sine of an angle"),
+    );
+    env.set(
+        "cos",
+        BuiltinFunction::new_wrapped("cos", |x: &Number| Ok(Number(float::cos(x.0))))
+            .with_doc("(cos radians)", "Cosine of an angle"),
+    );
+    env.set(
+        "tan",
+        BuiltinFunction::new_wrapped("tan", |x: &Number| Ok(Number(float::tan(x.0))))
+            .with_doc("(tan radians)", "Tangent of an angle"),
+    );
+    env.set(
+        "atan2",
+        BuiltinFunction::new("atan2", atan2).with_doc(
+            "(atan2 y x)",
+            "Angle in radians from the x axis to the point (x, y)",
+        ),
+    );
+    env.set(
+        "exp",
+        BuiltinFunction::new_wrapped("exp", |x: &Number| Ok(Number(float::exp(x.0))))
+            .with_doc("(exp x)", "e raised to the power x"),
+    );
+    env.set(
+        "ln",
+        BuiltinFunction::new_wrapped("ln", |x: &Number| Ok(Number(float::ln(x.0))))
+            .with_doc("(ln x)", "Natural logarithm"),
+    );
+    env.set(
+        "log",
+        BuiltinFunction::new("log", log)
+            .with_doc("(log x [base])", "Logarithm in the base, 10 by default"),
+    );
+}
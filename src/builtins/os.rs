@@ -0,0 +1,143 @@
+use anyhow::{anyhow, ensure, Context, Result};
+
+use super::{single_number, single_string};
+use crate::{
+    expression::{LispExpression, ToAndFrom},
+    BuiltinFunction, Capability, Environment, Str,
+};
+
+pub fn getenv<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    let name = single_string(arguments, "Getenv")?;
+    Ok(match std::env::var(&name.0) {
+        Ok(value) => Str(value).into(),
+        Err(_) => E::null(),
+    })
+}
+
+pub fn setenv<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 2,
+        "Setenv must be called on exactly two arguments"
+    );
+    let name: &Str = ToAndFrom::<Str>::try_into_atom(&arguments[0])
+        .context("Name passed to setenv should be a string")?;
+    let value: &Str = ToAndFrom::<Str>::try_into_atom(&arguments[1])
+        .context("Value passed to setenv should be a string")?;
+    ensure!(
+        !name.0.is_empty() && !name.0.contains(['=', '\0']) && !value.0.contains('\0'),
+        "Cannot set environment variable {:?}",
+        name.0
+    );
+    std::env::set_var(&name.0, &value.0);
+    Ok(arguments[1].clone())
+}
+
+fn current_dir<E>() -> Result<E>
+where
+    E: LispExpression,
+{
+    let dir = std::env::current_dir().context("Could not get the working directory")?;
+    Ok(Str(dir.to_string_lossy().into_owned()).into())
+}
+
+pub fn cwd<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(arguments.is_empty(), "Cwd takes no arguments");
+    current_dir()
+}
+
+pub fn cd<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    let path = single_string(arguments, "Cd")?;
+    std::env::set_current_dir(&path.0)
+        .with_context(|| anyhow!("Could not change directory to {}", path.0))?;
+    current_dir()
+}
+
+pub fn platform<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(arguments.is_empty(), "Platform takes no arguments");
+    Ok(Str::from(std::env::consts::OS).into())
+}
+
+pub fn exit<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    let code = if arguments.is_empty() {
+        0
+    } else {
+        let code = single_number(arguments, "Exit")?;
+        ensure!(
+            code.0.fract() == 0. && code.0.abs() <= i32::MAX as f64,
+            "Exit needs an integer status, not {}",
+            code
+        );
+        code.0 as i32
+    };
+    std::process::exit(code)
+}
+
+pub fn set_environment<E: LispExpression>(env: &mut Environment<E>) {
+    env.set(
+        "getenv",
+        BuiltinFunction::new("getenv", getenv)
+            .with_doc(
+                "(getenv \"NAME\")",
+                "Returns the value of an environment variable, or () if it is unset",
+            )
+            .with_capabilities(&[Capability::Env]),
+    );
+    env.set(
+        "setenv",
+        BuiltinFunction::new("setenv", setenv)
+            .with_doc(
+                "(setenv \"NAME\" \"value\")",
+                "Sets an environment variable for this process and its children",
+            )
+            .with_capabilities(&[Capability::Env]),
+    );
+    env.set(
+        "cwd",
+        BuiltinFunction::new("cwd", cwd)
+            .with_doc("(cwd)", "Returns the working directory")
+            .with_capabilities(&[Capability::Fs]),
+    );
+    env.set(
+        "cd",
+        BuiltinFunction::new("cd", cd)
+            .with_doc(
+                "(cd \"path\")",
+                "Changes the working directory, returning the new one",
+            )
+            .with_capabilities(&[Capability::Fs]),
+    );
+    env.set(
+        "platform",
+        BuiltinFunction::new("platform", platform).with_doc(
+            "(platform)",
+            "Returns the name of the operating system, e.g. \"linux\"",
+        ),
+    );
+    env.set(
+        "exit",
+        BuiltinFunction::new("exit", exit)
+            .with_doc(
+                "(exit [status])",
+                "Ends the process with a status, 0 by default",
+            )
+            .with_capabilities(&[Capability::Proc]),
+    );
+}
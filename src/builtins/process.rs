@@ -0,0 +1,63 @@
+use anyhow::{anyhow, ensure, Context, Result};
+use std::process::Command;
+
+use crate::{
+    expression::{LispExpression, ToAndFrom},
+    BuiltinFunction, Capability, Environment, List, Number, Str,
+};
+
+pub fn shell<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        env.config.borrow().allow_shell,
+        "Running commands is disabled, see Config::allow_shell"
+    );
+    ensure!(!arguments.is_empty(), "Shell needs a command to run");
+    let words: Vec<&Str> = arguments
+        .iter()
+        .enumerate()
+        .map(|(n, e)| {
+            ToAndFrom::<Str>::try_into_atom(e)
+                .with_context(|| anyhow!("Argument number {}: {}", n + 1, e))
+        })
+        .collect::<Result<_>>()
+        .context("Arguments to shell should be strings")?;
+    let output = Command::new(&words[0].0)
+        .args(words[1..].iter().map(|word| &word.0))
+        .output()
+        .with_context(|| anyhow!("Could not run {}", words[0].0))?;
+
+    let status: E = match output.status.code() {
+        Some(code) => Number(code as f64).into(),
+        // Killed by a signal
+        None => E::null(),
+    };
+    let field =
+        |name: &str, value: E| -> E { List::new(vec![Str::from(name).into(), value]).into() };
+    Ok(List::new(vec![
+        field(
+            "stdout",
+            Str(String::from_utf8_lossy(&output.stdout).into_owned()).into(),
+        ),
+        field(
+            "stderr",
+            Str(String::from_utf8_lossy(&output.stderr).into_owned()).into(),
+        ),
+        field("status", status),
+    ])
+    .into())
+}
+
+pub fn set_environment<E: LispExpression>(env: &mut Environment<E>) {
+    env.set(
+        "shell",
+        BuiltinFunction::new("shell", shell)
+            .with_doc(
+                "(shell \"command\" \"argument\" ...)",
+                "Runs a command, returning its stdout, stderr and status as an association list",
+            )
+            .with_capabilities(&[Capability::Proc]),
+    );
+}
@@ -0,0 +1,39 @@
+use anyhow::{ensure, Context, Result};
+
+use crate::{
+    expression::{LispExpression, ToAndFrom},
+    prelude::*,
+    BuiltinFunction, Environment, Promise,
+};
+
+pub fn await_promise<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 1,
+        "Await must be called on exactly one argument"
+    );
+    // Awaiting anything else gives it back, so code can await values which
+    // may or may not be promises
+    let Ok(promise) = ToAndFrom::<Promise<E>>::try_into_atom(&arguments[0]) else {
+        return Ok(arguments[0].clone());
+    };
+    let awaiter = env
+        .awaiter
+        .clone()
+        .context("Promises can only be awaited in code evaluated by evaluate_async")?;
+    awaiter
+        .wait(promise.clone())
+        .with_context(|| format!("Could not await {}", promise))
+}
+
+pub fn set_environment<E: LispExpression>(env: &mut Environment<E>) {
+    env.set(
+        "await",
+        BuiltinFunction::new("await", await_promise).with_doc(
+            "(await promise)",
+            "Runs the async builtin call in a promise and gives its result",
+        ),
+    );
+}
@@ -0,0 +1,142 @@
+use anyhow::{anyhow, ensure, Context, Result};
+
+use super::expressions_to_homogeneous;
+use crate::{
+    expression::{LispExpression, ToAndFrom},
+    prelude::*,
+    shared::{Shared, Threadsafe},
+    BuiltinFunction, BuiltinMacro, Environment, Lambda, List, Number, Record, RecordType, Symbol,
+};
+
+fn builtin<E>(
+    name: &'static str,
+    function: impl Fn(&[E], &mut Environment<E>) -> Result<E> + Threadsafe + 'static,
+) -> BuiltinFunction<E> {
+    BuiltinFunction {
+        name,
+        function: Shared::new(function),
+        doc: Default::default(),
+    }
+}
+
+/// `(make-point x y)` is a lambda over the fields, so fields can be given by
+/// keyword and left out to curry. It calls the builtin making the record
+/// itself rather than a name for it, so that it displays with its fields
+/// and no field can shadow it.
+fn constructor<E>(name: Symbol, kind: &Shared<RecordType>, env: &Environment<E>) -> E
+where
+    E: LispExpression,
+{
+    let captured_kind = kind.clone();
+    let record = builtin("record constructor", move |values: &[E], _env| {
+        Ok(Record {
+            kind: captured_kind.clone(),
+            values: values.to_vec(),
+        }
+        .into())
+    });
+    let mut call: Vec<E> = vec![record.into()];
+    call.extend(kind.fields.iter().map(|field| field.clone().into()));
+    Lambda {
+        parameters: kind
+            .fields
+            .iter()
+            .map(|field| field.clone().into())
+            .collect(),
+        value: Shared::new(List::new(call).into()),
+        env: Shared::new(env.clone()),
+        name: Some(name),
+        recursive: None,
+    }
+    .into()
+}
+
+fn accessor<E>(kind: &Shared<RecordType>, index: usize) -> BuiltinFunction<E>
+where
+    E: LispExpression,
+{
+    let kind = kind.clone();
+    builtin("record accessor", move |arguments: &[E], _env| {
+        let field = format!("{}-{}", kind.name.0, kind.fields[index].0);
+        ensure!(
+            arguments.len() == 1,
+            "{field} must be called on exactly one argument"
+        );
+        let record: &Record<E> = arguments[0]
+            .try_into_atom()
+            .with_context(|| anyhow!("Argument to {field} should be a {} record", kind.name.0))?;
+        ensure!(
+            Shared::ptr_eq(&record.kind, &kind),
+            "Argument to {field} should be a {} record, not {}",
+            kind.name.0,
+            if record.kind.name == kind.name {
+                format!("one from an earlier definition of {}", kind.name.0)
+            } else {
+                format!("a {} record", record.kind.name.0)
+            }
+        );
+        Ok(record.values[index].clone())
+    })
+}
+
+fn predicate<E>(kind: &Shared<RecordType>) -> BuiltinFunction<E>
+where
+    E: LispExpression,
+{
+    let kind = kind.clone();
+    builtin("record predicate", move |arguments: &[E], _env| {
+        ensure!(
+            arguments.len() == 1,
+            "{}? must be called on exactly one argument",
+            kind.name.0
+        );
+        match ToAndFrom::<Record<E>>::try_into_atom(&arguments[0]) {
+            Ok(record) if Shared::ptr_eq(&record.kind, &kind) => Ok(Number(1.).into()),
+            _ => Ok(E::null()),
+        }
+    })
+}
+
+pub fn defstruct<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    let symbols: Vec<&Symbol> =
+        expressions_to_homogeneous(arguments).context("Defstruct takes a name and field names")?;
+    let (name, fields) = symbols
+        .split_first()
+        .context("Defstruct needs a name for the type")?;
+    for (n, field) in fields.iter().enumerate() {
+        ensure!(
+            !fields[..n].contains(field),
+            "Field {} is declared more than once",
+            field
+        );
+    }
+    let kind = Shared::new(RecordType {
+        name: (*name).clone(),
+        fields: fields.iter().map(|&field| field.clone()).collect(),
+    });
+
+    let make = Symbol(format!("make-{}", name.0));
+    let constructor = constructor(make.clone(), &kind, env);
+    env.define(make, constructor);
+    env.define(format!("{}?", name.0).as_str(), predicate(&kind));
+    for (index, field) in fields.iter().enumerate() {
+        env.define(
+            format!("{}-{}", name.0, field.0).as_str(),
+            accessor(&kind, index),
+        );
+    }
+    Ok((*name).clone().into())
+}
+
+pub fn set_environment<E: LispExpression>(env: &mut Environment<E>) {
+    env.set(
+        "defstruct",
+        BuiltinMacro::new("defstruct", defstruct).with_doc(
+            "(defstruct name field ...)",
+            "Defines make-name, a name? predicate and a name-field accessor for each field",
+        ),
+    );
+}
@@ -0,0 +1,123 @@
+use ::regex::{Captures, Regex};
+use anyhow::{anyhow, ensure, Context, Result};
+
+use super::expressions_to_homogeneous;
+use crate::{
+    expression::LispExpression, prelude::*, BuiltinFunction, Environment, List, Number, Str,
+};
+
+/// Checks the argument count, then compiles the pattern and returns it with
+/// the remaining string arguments
+fn pattern_and_strings<'a, E>(
+    arguments: &'a [E],
+    count: usize,
+    name: &str,
+) -> Result<(Regex, Vec<&'a Str>)>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == count,
+        "{name} must be called on exactly {count} arguments"
+    );
+    let strings: Vec<&Str> = expressions_to_homogeneous(arguments)
+        .with_context(|| anyhow!("Arguments to {name} should be strings"))?;
+    let regex = Regex::new(&strings[0].0)
+        .with_context(|| anyhow!("Invalid regular expression {:?}", strings[0].0))?;
+    Ok((regex, strings[1..].to_vec()))
+}
+
+/// The whole match followed by each group, with () for groups that did not
+/// participate in the match
+fn captures_to_list<E>(captures: &Captures) -> E
+where
+    E: LispExpression,
+{
+    List::new(
+        captures
+            .iter()
+            .map(|group| match group {
+                Some(group) => Str::from(group.as_str()).into(),
+                None => E::null(),
+            })
+            .collect(),
+    )
+    .into()
+}
+
+pub fn re_match<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    let (regex, strings) = pattern_and_strings(arguments, 2, "Re-match?")?;
+    if regex.is_match(&strings[0].0) {
+        Ok(Number(1.).into())
+    } else {
+        Ok(E::null())
+    }
+}
+
+pub fn re_find<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    let (regex, strings) = pattern_and_strings(arguments, 2, "Re-find")?;
+    Ok(match regex.captures(&strings[0].0) {
+        Some(captures) => captures_to_list(&captures),
+        None => E::null(),
+    })
+}
+
+pub fn re_findall<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    let (regex, strings) = pattern_and_strings(arguments, 2, "Re-findall")?;
+    Ok(List::new(
+        regex
+            .captures_iter(&strings[0].0)
+            .map(|captures| captures_to_list(&captures))
+            .collect(),
+    )
+    .into())
+}
+
+pub fn re_replace<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    let (regex, strings) = pattern_and_strings(arguments, 3, "Re-replace")?;
+    let replaced = regex.replace_all(&strings[0].0, strings[1].0.as_str());
+    Ok(Str(replaced.into_owned()).into())
+}
+
+pub fn set_environment<E: LispExpression>(env: &mut Environment<E>) {
+    env.set(
+        "re-match?",
+        BuiltinFunction::new("re-match?", re_match).with_doc(
+            "(re-match? \"pattern\" \"text\")",
+            "Returns 1 if the pattern matches anywhere in the text, otherwise ()",
+        ),
+    );
+    env.set(
+        "re-find",
+        BuiltinFunction::new("re-find", re_find).with_doc(
+            "(re-find \"pattern\" \"text\")",
+            "Returns the first match and its groups as a list, or () if there is none",
+        ),
+    );
+    env.set(
+        "re-findall",
+        BuiltinFunction::new("re-findall", re_findall).with_doc(
+            "(re-findall \"pattern\" \"text\")",
+            "Returns a list of every non-overlapping match, each with its groups",
+        ),
+    );
+    env.set(
+        "re-replace",
+        BuiltinFunction::new("re-replace", re_replace).with_doc(
+            "(re-replace \"pattern\" \"text\" \"replacement\")",
+            "Replaces every match, expanding $1 or ${name} in the replacement to groups",
+        ),
+    );
+}
@@ -0,0 +1,135 @@
+use anyhow::{anyhow, ensure, Context, Result};
+
+use crate::{
+    expression::{LispExpression, ToAndFrom},
+    prelude::*,
+    BuiltinFunction, Environment, Number, Set,
+};
+
+fn set_argument<'a, E>(arguments: &'a [E], n: usize, name: &str) -> Result<&'a Set<E>>
+where
+    E: LispExpression,
+{
+    ToAndFrom::<Set<E>>::try_into_atom(&arguments[n])
+        .with_context(|| anyhow!("Argument number {} to {name} should be a set", n + 1))
+}
+
+fn insert_all<'a, E>(set: &mut Set<E>, values: impl IntoIterator<Item = &'a E>) -> Result<()>
+where
+    E: LispExpression,
+{
+    for value in values {
+        set.insert(value.clone())
+            .with_context(|| anyhow!("Could not add {} to a set", value))?;
+    }
+    Ok(())
+}
+
+pub fn set<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    let mut set = Set::new();
+    insert_all(&mut set, arguments)?;
+    Ok(set.into())
+}
+
+pub fn set_add<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        !arguments.is_empty(),
+        "Set-add must be called on a set and the values to add"
+    );
+    let mut set = set_argument(arguments, 0, "set-add")?.clone();
+    insert_all(&mut set, &arguments[1..])?;
+    Ok(set.into())
+}
+
+pub fn set_member<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 2,
+        "Set-member? must be called on exactly two arguments"
+    );
+    let set = set_argument(arguments, 0, "set-member?")?;
+    // Unhashable values cannot be in any set
+    if set.contains(&arguments[1]).unwrap_or(false) {
+        Ok(Number(1.).into())
+    } else {
+        Ok(E::null())
+    }
+}
+
+pub fn union<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    let mut union = Set::new();
+    for n in 0..arguments.len() {
+        insert_all(&mut union, set_argument(arguments, n, "union")?.iter())?;
+    }
+    Ok(union.into())
+}
+
+pub fn intersection<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        !arguments.is_empty(),
+        "Intersection must be called on at least one set"
+    );
+    let sets: Vec<&Set<E>> = (0..arguments.len())
+        .map(|n| set_argument(arguments, n, "intersection"))
+        .collect::<Result<_>>()?;
+    let mut intersection = Set::new();
+    for value in sets[0].iter() {
+        if sets[1..]
+            .iter()
+            .all(|set| set.contains(value).unwrap_or(false))
+        {
+            intersection.insert(value.clone())?;
+        }
+    }
+    Ok(intersection.into())
+}
+
+pub fn set_environment<E: LispExpression>(env: &mut Environment<E>) {
+    env.set(
+        "set",
+        BuiltinFunction::new("set", set).with_doc(
+            "(set value ...)",
+            "A set of the distinct values, which must not be functions or boxes",
+        ),
+    );
+    env.set(
+        "set-add",
+        BuiltinFunction::new("set-add", set_add).with_doc(
+            "(set-add set value ...)",
+            "A copy of the set with the values added",
+        ),
+    );
+    env.set(
+        "set-member?",
+        BuiltinFunction::new("set-member?", set_member).with_doc(
+            "(set-member? set value)",
+            "Returns 1 if the set contains the value, otherwise ()",
+        ),
+    );
+    env.set(
+        "union",
+        BuiltinFunction::new("union", union)
+            .with_doc("(union set ...)", "The values in any of the sets"),
+    );
+    env.set(
+        "intersection",
+        BuiltinFunction::new("intersection", intersection).with_doc(
+            "(intersection set ...)",
+            "The values of the first set which are in all the others",
+        ),
+    );
+}
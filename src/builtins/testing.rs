@@ -0,0 +1,109 @@
+use anyhow::{bail, ensure, Context, Result};
+
+use super::{apply, lambda};
+use crate::{
+    expression::{LispExpression, ToAndFrom},
+    prelude::*,
+    BuiltinMacro, Environment, List, Number, Str, Symbol,
+};
+
+pub fn assert<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        matches!(arguments.len(), 1 | 2),
+        "Assert must be called on an expression and optionally a message"
+    );
+    let value = arguments[0].eval(env)?;
+    if value.is_truthy() {
+        return Ok(value);
+    }
+    match arguments.get(1) {
+        Some(message) => {
+            let message = message.eval(env)?;
+            match ToAndFrom::<Str>::try_into_atom(&message) {
+                Ok(message) => bail!("Assertion failed: {}: {}", message.0, arguments[0]),
+                Err(_) => bail!("Assertion failed: {}: {}", message, arguments[0]),
+            }
+        }
+        None => bail!("Assertion failed: {}", arguments[0]),
+    }
+}
+
+pub fn assert_eq<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 2,
+        "Assert-eq must be called on exactly two arguments"
+    );
+    let left = arguments[0].eval(env)?;
+    let right = arguments[1].eval(env)?;
+    ensure!(
+        left.is_equal(&right),
+        "Assertion failed: {} is {} but {} is {}",
+        arguments[0],
+        left,
+        arguments[1],
+        right
+    );
+    Ok(Number(1.).into())
+}
+
+pub fn deftest<E>(arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 2,
+        "Deftest must be called on a name and a body"
+    );
+    let name = arguments[0]
+        .as_symbol()
+        .context("Deftest needs a name for the test")?;
+    let test = lambda(&[List::new(vec![]).into(), arguments[1].clone()], env)?;
+    env.tests.borrow_mut().push((name.clone(), test));
+    Ok(name.clone().into())
+}
+
+/// Runs every test defined with `deftest` in order, returning each name with
+/// the error it failed with, if any
+pub fn run_tests<E>(env: &mut Environment<E>) -> Vec<(Symbol, Result<()>)>
+where
+    E: LispExpression,
+{
+    let tests = env.tests.borrow().clone();
+    tests
+        .into_iter()
+        .map(|(name, test)| {
+            let outcome = apply(&test, &[], env).map(|_| ());
+            (name, outcome)
+        })
+        .collect()
+}
+
+pub fn set_environment<E: LispExpression>(env: &mut Environment<E>) {
+    env.set(
+        "assert",
+        BuiltinMacro::new("assert", assert).with_doc(
+            "(assert expression [message])",
+            "Fails with the message and the expression unless it is truthy",
+        ),
+    );
+    env.set(
+        "assert-eq",
+        BuiltinMacro::new("assert-eq", assert_eq).with_doc(
+            "(assert-eq expected actual)",
+            "Fails showing both expressions and values unless they are equal?",
+        ),
+    );
+    env.set(
+        "deftest",
+        BuiltinMacro::new("deftest", deftest).with_doc(
+            "(deftest name body)",
+            "Defines a test to be run by shallot --test",
+        ),
+    );
+}
@@ -0,0 +1,115 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+
+use crate::{
+    expression::{LispExpression, ToAndFrom},
+    BuiltinFunction, Environment, Set, Vector,
+};
+
+fn vector_argument<'a, E>(arguments: &'a [E], n: usize, name: &str) -> Result<&'a Vector<E>>
+where
+    E: LispExpression,
+{
+    ToAndFrom::<Vector<E>>::try_into_atom(&arguments[n])
+        .with_context(|| anyhow!("Argument number {} to {name} should be a vector", n + 1))
+}
+
+pub fn vec<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 1,
+        "Vec must be called on exactly one argument"
+    );
+    let elements = if let Ok(list) = arguments[0].as_list() {
+        list.0.to_vec()
+    } else if let Ok(vector) = ToAndFrom::<Vector<E>>::try_into_atom(&arguments[0]) {
+        vector.0.borrow().clone()
+    } else if let Ok(set) = ToAndFrom::<Set<E>>::try_into_atom(&arguments[0]) {
+        set.iter().cloned().collect()
+    } else {
+        bail!(
+            "Vec needs a list, vector or set, not {}",
+            arguments[0].variant()
+        )
+    };
+    Ok(Vector::new(elements).into())
+}
+
+pub fn push<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        !arguments.is_empty(),
+        "Push must be called on a vector and the values to add"
+    );
+    let vector = vector_argument(arguments, 0, "push")?;
+    vector.0.borrow_mut().extend(arguments[1..].iter().cloned());
+    Ok(arguments[0].clone())
+}
+
+pub fn pop<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 1,
+        "Pop must be called on exactly one argument"
+    );
+    let vector = vector_argument(arguments, 0, "pop")?;
+    let popped = vector.0.borrow_mut().pop();
+    popped.context("Cannot pop from an empty vector")
+}
+
+pub fn vec_set<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    ensure!(
+        arguments.len() == 3,
+        "Vec-set! must be called on a vector, an index and a value"
+    );
+    let vector = vector_argument(arguments, 0, "vec-set!")?;
+    let index: usize = arguments[1]
+        .extract()
+        .context("Vec-set! needs an integer index")?;
+    let mut elements = vector.0.borrow_mut();
+    let length = elements.len();
+    let element = elements
+        .get_mut(index)
+        .with_context(|| anyhow!("Cannot set vector of length {length} at {index}"))?;
+    *element = arguments[2].clone();
+    Ok(arguments[2].clone())
+}
+
+pub fn set_environment<E: LispExpression>(env: &mut Environment<E>) {
+    env.set(
+        "vec",
+        BuiltinFunction::new("vec", vec).with_doc(
+            "(vec collection)",
+            "A new vector of the elements of a list, vector or set",
+        ),
+    );
+    env.set(
+        "push",
+        BuiltinFunction::new("push", push).with_doc(
+            "(push vector value ...)",
+            "Adds the values to the end of the vector, returning it",
+        ),
+    );
+    env.set(
+        "pop",
+        BuiltinFunction::new("pop", pop).with_doc(
+            "(pop vector)",
+            "Removes the last element of the vector, returning it",
+        ),
+    );
+    env.set(
+        "vec-set!",
+        BuiltinFunction::new("vec-set!", vec_set).with_doc(
+            "(vec-set! vector index value)",
+            "Replaces the element of the vector at the index, returning the value",
+        ),
+    );
+}
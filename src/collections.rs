@@ -0,0 +1,8 @@
+//! Hash maps and sets, from std or, without it, from `hashbrown`. Both have
+//! the same interface, so that only the import differs.
+
+#[cfg(feature = "std")]
+pub use std::collections::{HashMap, HashSet};
+
+#[cfg(not(feature = "std"))]
+pub use hashbrown::{HashMap, HashSet};
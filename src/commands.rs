@@ -0,0 +1,66 @@
+//! The table of `#commands` which layers add to the REPL. It lives in every
+//! environment so that layers can add commands without std, where there is
+//! no REPL to run them.
+
+use anyhow::Result;
+
+use crate::{
+    prelude::*,
+    shared::{Mutable, Shared},
+    Environment,
+};
+
+/// A REPL command added by a layer, given the text after its name and
+/// returning what to print
+pub type ReplCommand<E> = fn(&str, &mut Environment<E>) -> Result<String>;
+
+type CommandTable<E> = Vec<(String, ReplCommand<E>)>;
+
+/// REPL commands by name, such as `json` for `#json`. Clones share one table,
+/// like `ReaderMacros`.
+#[derive(Clone)]
+pub struct ReplCommands<E>(Shared<Mutable<CommandTable<E>>>);
+
+impl<E> Default for ReplCommands<E> {
+    fn default() -> Self {
+        ReplCommands(Shared::new(Mutable::new(Vec::new())))
+    }
+}
+
+impl<E> PartialEq for ReplCommands<E> {
+    fn eq(&self, other: &Self) -> bool {
+        Shared::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl<E> ReplCommands<E> {
+    /// Adds a command, replacing any with the same name
+    pub fn set(&self, name: impl Into<String>, command: ReplCommand<E>) {
+        let name = name.into();
+        let mut commands = self.0.borrow_mut();
+        commands.retain(|(existing, _)| *existing != name);
+        commands.push((name, command));
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.0
+            .borrow()
+            .iter()
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// The command a line such as `#json (list 1)` runs, with the rest of
+    /// the line
+    #[cfg(feature = "std")]
+    pub(crate) fn find<'a>(&self, line: &'a str) -> Option<(ReplCommand<E>, &'a str)> {
+        let (name, rest) = line
+            .strip_prefix('#')?
+            .trim_end()
+            .split_once(char::is_whitespace)
+            .unwrap_or((line[1..].trim_end(), ""));
+        let commands = self.0.borrow();
+        let (_, command) = commands.iter().find(|(existing, _)| existing == name)?;
+        Some((*command, rest))
+    }
+}
@@ -0,0 +1,41 @@
+/// Settings with which embedders restrict what evaluated code may do, shared
+/// by an environment and every closure captured from it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    /// Whether `shell` may run commands, off by default for sandboxing
+    pub allow_shell: bool,
+    /// Whether `builtins::set_environment` registers the `os` builtins, which
+    /// read and change process state such as environment variables
+    pub allow_os: bool,
+    /// How deeply calls may nest before evaluation fails with a
+    /// `RecursionLimit` error. The default suits the 8MB main thread stack of
+    /// a debug build, so lower it when evaluating on threads with less.
+    pub max_eval_depth: usize,
+    /// How deeply macros may expand into further macro calls
+    pub max_macro_depth: usize,
+    /// How many list elements, string bytes and bindings evaluation may build
+    /// in total, or `None` for no limit. This counts what was ever allocated
+    /// rather than what is live, so reset `Environment::allocated` between
+    /// evaluations as with fuel.
+    pub max_allocation: Option<usize>,
+    /// Whether `define` and `undefine!` may change the bindings a layer
+    /// protects, which otherwise only `set!-unsafe` can
+    pub allow_redefining_builtins: bool,
+    /// Decimals `print`, `display` and the REPL show numbers with, or `None`
+    /// for as many as needed to read back as the same number
+    pub number_precision: Option<usize>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            allow_shell: false,
+            allow_os: false,
+            max_eval_depth: 1000,
+            max_macro_depth: 100,
+            max_allocation: None,
+            allow_redefining_builtins: false,
+            number_precision: None,
+        }
+    }
+}
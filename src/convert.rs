@@ -0,0 +1,223 @@
+use alloc::collections::BTreeMap;
+use anyhow::{anyhow, ensure, Context, Result};
+
+use crate::{atoms::*, collections::HashMap, float, prelude::*, LispExpression};
+
+/// Rust values which can be turned into expressions, e.g. to bind them with
+/// `env.set("config", config.into_lisp())`
+pub trait IntoLisp<E> {
+    fn into_lisp(self) -> E;
+}
+
+/// Rust values which can be read out of an expression, see
+/// [`LispExpression::extract`]
+pub trait FromLisp<E>: Sized {
+    fn from_lisp(expression: &E) -> Result<Self>;
+}
+
+impl<E: LispExpression> FromLisp<E> for f64 {
+    fn from_lisp(expression: &E) -> Result<Self> {
+        let number: &Number = expression.try_into_atom()?;
+        Ok(number.0)
+    }
+}
+
+impl<E: LispExpression> FromLisp<E> for i64 {
+    fn from_lisp(expression: &E) -> Result<Self> {
+        let number: &Number = expression.try_into_atom()?;
+        ensure!(
+            // Up to but not including 2^63, which `i64::MAX as f64` rounds to
+            float::fract(number.0) == 0.
+                && number.0 >= i64::MIN as f64
+                && number.0 < -(i64::MIN as f64),
+            "Expected an integer and got {}",
+            number.0
+        );
+        Ok(number.0 as i64)
+    }
+}
+
+impl<E: LispExpression> FromLisp<E> for usize {
+    fn from_lisp(expression: &E) -> Result<Self> {
+        let number: &Number = expression.try_into_atom()?;
+        ensure!(
+            // Below one past usize::MAX, which is 2^64 on 64 bit targets where
+            // `usize::MAX as f64` rounds up to it
+            float::fract(number.0) == 0. && number.0 >= 0. && number.0 < usize::MAX as f64 + 1.,
+            "Expected a non-negative integer and got {}",
+            number.0
+        );
+        Ok(number.0 as usize)
+    }
+}
+
+impl<E: LispExpression> FromLisp<E> for String {
+    fn from_lisp(expression: &E) -> Result<Self> {
+        let string: &Str = expression.try_into_atom()?;
+        Ok(string.0.clone())
+    }
+}
+
+impl<E: LispExpression> FromLisp<E> for char {
+    fn from_lisp(expression: &E) -> Result<Self> {
+        let c: &Char = expression.try_into_atom()?;
+        Ok(c.0)
+    }
+}
+
+/// Follows the language's truthiness, so anything but `()` is true
+impl<E: LispExpression> FromLisp<E> for bool {
+    fn from_lisp(expression: &E) -> Result<Self> {
+        Ok(expression.is_truthy())
+    }
+}
+
+impl<E: LispExpression, T: FromLisp<E>> FromLisp<E> for Vec<T> {
+    fn from_lisp(expression: &E) -> Result<Self> {
+        expression
+            .as_list()?
+            .0
+            .iter()
+            .enumerate()
+            .map(|(n, e)| T::from_lisp(e).with_context(|| anyhow!("Element number {}", n + 1)))
+            .collect()
+    }
+}
+
+/// `()` becomes `None`
+impl<E: LispExpression, T: FromLisp<E>> FromLisp<E> for Option<T> {
+    fn from_lisp(expression: &E) -> Result<Self> {
+        if expression.is_truthy() {
+            T::from_lisp(expression).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+macro_rules! tuple_from_lisp {
+    ($($element:ident),+) => {
+        /// Lists with exactly as many elements as the tuple
+        impl<E: LispExpression, $($element: FromLisp<E>),+> FromLisp<E> for ($($element,)+) {
+            fn from_lisp(expression: &E) -> Result<Self> {
+                let list: &List<E> = expression.try_into_atom()?;
+                let length = [$(stringify!($element)),+].len();
+                ensure!(
+                    list.0.len() == length,
+                    "Expected a list of {} elements and got {}",
+                    length,
+                    list.0.len()
+                );
+                let mut elements = list.0.iter().enumerate();
+                Ok(($({
+                    // Note: the length has been checked above
+                    let (n, element) = elements.next().unwrap();
+                    $element::from_lisp(element)
+                        .with_context(|| anyhow!("Element number {}", n + 1))?
+                },)+))
+            }
+        }
+    };
+}
+
+tuple_from_lisp!(A);
+tuple_from_lisp!(A, B);
+tuple_from_lisp!(A, B, C);
+tuple_from_lisp!(A, B, C, D);
+tuple_from_lisp!(A, B, C, D, F);
+tuple_from_lisp!(A, B, C, D, F, G);
+
+impl<E: LispExpression> IntoLisp<E> for f64 {
+    fn into_lisp(self) -> E {
+        Number(self).into()
+    }
+}
+
+impl<E: LispExpression> IntoLisp<E> for i64 {
+    fn into_lisp(self) -> E {
+        Number(self as f64).into()
+    }
+}
+
+impl<E: LispExpression> IntoLisp<E> for char {
+    fn into_lisp(self) -> E {
+        Char(self).into()
+    }
+}
+
+impl<E: LispExpression> IntoLisp<E> for &str {
+    fn into_lisp(self) -> E {
+        Str::from(self).into()
+    }
+}
+
+impl<E: LispExpression> IntoLisp<E> for String {
+    fn into_lisp(self) -> E {
+        Str(self).into()
+    }
+}
+
+/// Like the comparison builtins: `1` for true and `()` for false
+impl<E: LispExpression> IntoLisp<E> for bool {
+    fn into_lisp(self) -> E {
+        if self {
+            Number(1.).into()
+        } else {
+            E::null()
+        }
+    }
+}
+
+impl<E: LispExpression, T: IntoLisp<E>> IntoLisp<E> for Vec<T> {
+    fn into_lisp(self) -> E {
+        List::new(self.into_iter().map(T::into_lisp).collect()).into()
+    }
+}
+
+/// `None` becomes `()`
+impl<E: LispExpression, T: IntoLisp<E>> IntoLisp<E> for Option<T> {
+    fn into_lisp(self) -> E {
+        match self {
+            Some(value) => value.into_lisp(),
+            None => E::null(),
+        }
+    }
+}
+
+/// Association list of `(key value)` pairs, sorted by key
+impl<E: LispExpression, K: IntoLisp<E> + Ord, V: IntoLisp<E>> IntoLisp<E> for HashMap<K, V> {
+    fn into_lisp(self) -> E {
+        self.into_iter().collect::<BTreeMap<K, V>>().into_lisp()
+    }
+}
+
+/// Association list of `(key value)` pairs, sorted by key
+impl<E: LispExpression, K: IntoLisp<E>, V: IntoLisp<E>> IntoLisp<E> for BTreeMap<K, V> {
+    fn into_lisp(self) -> E {
+        List::new(
+            self.into_iter()
+                .map(|(key, value)| List::new(vec![key.into_lisp(), value.into_lisp()]).into())
+                .collect(),
+        )
+        .into()
+    }
+}
+
+macro_rules! tuple_into_lisp {
+    ($($element:ident),+) => {
+        impl<E: LispExpression, $($element: IntoLisp<E>),+> IntoLisp<E> for ($($element,)+) {
+            #[allow(non_snake_case)]
+            fn into_lisp(self) -> E {
+                let ($($element,)+) = self;
+                List::new(vec![$($element.into_lisp()),+]).into()
+            }
+        }
+    };
+}
+
+tuple_into_lisp!(A);
+tuple_into_lisp!(A, B);
+tuple_into_lisp!(A, B, C);
+tuple_into_lisp!(A, B, C, D);
+tuple_into_lisp!(A, B, C, D, F);
+tuple_into_lisp!(A, B, C, D, F, G);
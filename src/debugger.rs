@@ -0,0 +1,163 @@
+use crate::collections::HashMap;
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    prelude::*,
+    shared::{Mutable, Shared},
+    Environment, LispExpression, Symbol,
+};
+
+/// Values longer than this are cut short when listing bindings
+const MAX_SHOWN_VALUE: usize = 60;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mode {
+    /// Stop before every call
+    Step,
+    /// Stop before the next call at most this deeply nested
+    Next(usize),
+    Continue,
+}
+
+struct Session<E> {
+    mode: Mode,
+    /// Bindings when debugging started, which are not shown as locals
+    globals: HashMap<Symbol, E>,
+}
+
+/// Single-steps evaluation for `#debug`, stopping before each call to show
+/// it and the local bindings and to read a command. Clones share one
+/// session, so calls inside closures stop too.
+#[derive(Clone)]
+pub struct Debugger<E>(Shared<Mutable<Option<Session<E>>>>);
+
+impl<E> Default for Debugger<E> {
+    fn default() -> Self {
+        Debugger(Default::default())
+    }
+}
+
+impl<E> PartialEq for Debugger<E> {
+    fn eq(&self, other: &Self) -> bool {
+        Shared::ptr_eq(&self.0, &other.0)
+    }
+}
+
+const HELP: &str = "step (s): stop at the next call, entering this one
+next (n): stop at the next call which is not inside this one
+continue (c): run to the end without stopping
+print (p) name: show the value of a variable
+quit (q): abandon the evaluation";
+
+impl<E: LispExpression> Debugger<E> {
+    /// Stops before the first call evaluated in `env` from now on
+    pub fn start(&self, env: &Environment<E>) {
+        *self.0.borrow_mut() = Some(Session {
+            mode: Mode::Step,
+            globals: env.inner.clone(),
+        });
+    }
+
+    pub fn stop(&self) {
+        *self.0.borrow_mut() = None;
+    }
+
+    pub fn is_active(&self) -> bool {
+        matches!(&*self.0.borrow(), Some(session) if session.mode != Mode::Continue)
+    }
+
+    /// Called before evaluating the call `expression`, returning whether it
+    /// stopped there so that the result can be shown afterwards
+    pub(crate) fn pause(&self, expression: &E, env: &Environment<E>) -> Result<bool> {
+        let depth = env.eval_depth.get();
+        let (stop, locals) = match &*self.0.borrow() {
+            Some(session) => {
+                let stop = match session.mode {
+                    Mode::Step => true,
+                    Mode::Next(next) => depth <= next,
+                    Mode::Continue => false,
+                };
+                (stop, stop.then(|| locals(&session.globals, env)))
+            }
+            None => (false, None),
+        };
+        if !stop {
+            return Ok(false);
+        }
+        let indent = "  ".repeat(depth.saturating_sub(1));
+        let mut text = format!("{indent}→ {expression}\n");
+        for line in locals.unwrap_or_default() {
+            text.push_str(&format!("{indent}  {line}\n"));
+        }
+        write(env, &text)?;
+        loop {
+            write(env, "debug> ")?;
+            let Some(line) = env.io.read_line().context("Could not read command")? else {
+                bail!("Debugging ended at the end of input");
+            };
+            let mut words = line.split_whitespace();
+            let mode = match words.next() {
+                Some("step" | "s") | None => Mode::Step,
+                Some("next" | "n") => Mode::Next(depth),
+                Some("continue" | "c") => Mode::Continue,
+                Some("print" | "p") => {
+                    let text = match words.next() {
+                        Some(name) => match env.get(&Symbol::from(name)) {
+                            Some(value) => format!("{name} = {value}\n"),
+                            None => format!("{name} is unbound\n"),
+                        },
+                        None => "Print which variable?\n".to_owned(),
+                    };
+                    write(env, &text)?;
+                    continue;
+                }
+                Some("quit" | "q") => bail!("Debugging stopped"),
+                Some(_) => {
+                    write(env, &format!("{HELP}\n"))?;
+                    continue;
+                }
+            };
+            if let Some(session) = &mut *self.0.borrow_mut() {
+                session.mode = mode;
+            }
+            return Ok(true);
+        }
+    }
+
+    /// Shows the result of a call the debugger stopped before
+    pub(crate) fn finished(&self, result: &Result<E>, env: &Environment<E>) -> Result<()> {
+        if !self.is_active() {
+            return Ok(());
+        }
+        let indent = "  ".repeat(env.eval_depth.get().saturating_sub(1));
+        match result {
+            Ok(value) => write(env, &format!("{indent}= {value}\n")),
+            Err(error) => write(env, &format!("{indent}! {}\n", error.root_cause())),
+        }
+    }
+}
+
+/// Bindings which were not there, or were different, when debugging started
+fn locals<E: LispExpression>(globals: &HashMap<Symbol, E>, env: &Environment<E>) -> Vec<String> {
+    let mut locals: Vec<String> = env
+        .inner
+        .iter()
+        .filter(|(symbol, value)| globals.get(*symbol) != Some(*value))
+        .map(|(symbol, value)| {
+            let mut value = value.to_string();
+            if value.chars().count() > MAX_SHOWN_VALUE {
+                // Reset colours in case the cut was inside a coloured value
+                value = value.chars().take(MAX_SHOWN_VALUE).collect::<String>() + "\x1b[0m…";
+            }
+            format!("{} = {value}", symbol.0)
+        })
+        .collect();
+    locals.sort();
+    locals
+}
+
+fn write<E>(env: &Environment<E>, text: &str) -> Result<()> {
+    env.io
+        .write_stdout(text)
+        .context("Could not write debugger output")
+}
@@ -0,0 +1,96 @@
+use core::fmt::Display;
+
+use crate::{
+    collections::HashSet,
+    prelude::*,
+    shared::{Mutable, Shared},
+};
+
+/// Where a top-level expression is in its source, from its first character
+/// up to but not including the end, counting lines and columns from 1
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SourceSpan {
+    pub line: usize,
+    pub column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+}
+
+/// Where the span starts, as a human would look for it
+impl Display for SourceSpan {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+/// A warning which did not stop evaluation, such as from `warn`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub message: String,
+    /// Where the top-level expression being evaluated is, if known
+    pub location: Option<SourceSpan>,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match &self.location {
+            Some(location) => write!(f, "warning at {location}: {}", self.message),
+            None => write!(f, "warning: {}", self.message),
+        }
+    }
+}
+
+#[derive(Default)]
+struct DiagnosticState {
+    warnings: Vec<Diagnostic>,
+    location: Option<SourceSpan>,
+    /// Messages given to `warn_once` so far
+    warned: HashSet<String>,
+}
+
+/// Collects warnings until the REPL or an embedder takes them. Clones share
+/// the collection, so warnings from inside closures are not lost.
+#[derive(Clone, Default)]
+pub struct Diagnostics(Shared<Mutable<DiagnosticState>>);
+
+impl PartialEq for Diagnostics {
+    fn eq(&self, other: &Self) -> bool {
+        Shared::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Diagnostics {
+    /// Records a warning at the current location
+    pub fn warn(&self, message: impl Into<String>) {
+        let mut state = self.0.borrow_mut();
+        let location = state.location;
+        state.warnings.push(Diagnostic {
+            message: message.into(),
+            location,
+        });
+    }
+
+    /// Records a warning unless the same message was already given to this
+    pub fn warn_once(&self, message: impl Into<String>) {
+        let message = message.into();
+        if self.0.borrow_mut().warned.insert(message.clone()) {
+            self.warn(message);
+        }
+    }
+
+    /// The warnings recorded since they were last taken
+    pub fn take(&self) -> Vec<Diagnostic> {
+        core::mem::take(&mut self.0.borrow_mut().warnings)
+    }
+
+    /// Where the top-level expression being evaluated is, if known
+    pub(crate) fn location(&self) -> Option<SourceSpan> {
+        self.0.borrow().location
+    }
+
+    /// Sets where the expression being evaluated is, returning where the one
+    /// before was
+    pub(crate) fn set_location(&self, location: Option<SourceSpan>) -> Option<SourceSpan> {
+        core::mem::replace(&mut self.0.borrow_mut().location, location)
+    }
+}
@@ -0,0 +1,210 @@
+//! `DynExpression`, an expression type whose atoms are trait objects rather
+//! than the variants of an enum made by `create_layer!`. Adding an atom is a
+//! call to `DynExpression::register_atom` at runtime instead of a new layer,
+//! so a plugin can bring its own atoms to an interpreter already compiled.
+//! Every value is behind a shared pointer and matched by downcasting, which
+//! is slower than the enum.
+
+use core::{
+    any::Any,
+    fmt::{Debug, Display},
+};
+
+use crate::{
+    atoms::*,
+    convert::IntoLisp,
+    errors::TypeError,
+    expression::{drop_nested, LispExpression, ToAndFrom},
+    global::Lock,
+    prelude::*,
+    shared::{Shared, Threadsafe},
+    token::Token,
+};
+
+/// An atom a `DynExpression` can hold, implemented for every comparable atom
+pub trait DynAtom: Atom<DynExpression> + Any + Threadsafe {
+    fn as_any(&self) -> &dyn Any;
+
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+
+    fn as_atom(&self) -> &dyn Atom<DynExpression>;
+
+    /// Whether `other` is an atom of the same type and equal to this one
+    fn eq_dyn(&self, other: &dyn DynAtom) -> bool;
+}
+
+impl<T> DynAtom for T
+where
+    T: Atom<DynExpression> + Any + Threadsafe + PartialEq,
+{
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn as_atom(&self) -> &dyn Atom<DynExpression> {
+        self
+    }
+
+    fn eq_dyn(&self, other: &dyn DynAtom) -> bool {
+        other
+            .as_any()
+            .downcast_ref::<T>()
+            .is_some_and(|other| self == other)
+    }
+}
+
+#[derive(Clone)]
+pub struct DynExpression(Shared<dyn DynAtom>);
+
+type TokenParser = fn(&Token) -> Option<DynExpression>;
+
+/// An atom type the parser tries tokens with, by its name
+struct Registered {
+    name: &'static str,
+    parse: TokenParser,
+}
+
+/// Atoms added by `register_atom`, for every `DynExpression` in the process
+static REGISTERED: Lock<Vec<Registered>> = Lock::new(Vec::new());
+
+macro_rules! core_atoms {
+    ($($atom:ty),* $(,)?) => {
+        /// The atoms every expression type has, in the order `create_layer!`
+        /// lists them
+        fn core_atoms() -> Vec<Registered> {
+            vec![
+                $(
+                Registered {
+                    name: <$atom as Atom<DynExpression>>::sized_name(),
+                    parse: |token| {
+                        <$atom as Atom<DynExpression>>::parse_from_token(token)
+                            .map(DynExpression::from)
+                    },
+                },
+                )*
+            ]
+        }
+    };
+}
+
+core_atoms!(
+    List<DynExpression>,
+    BuiltinFunction<DynExpression>,
+    BuiltinMacro<DynExpression>,
+    AsyncBuiltinFunction<DynExpression>,
+    Lambda<DynExpression>,
+    Macro<DynExpression>,
+    Cell<DynExpression>,
+    Char,
+    Generic<DynExpression>,
+    Foreign,
+    Keyword,
+    LazySeq<DynExpression>,
+    Number,
+    Promise<DynExpression>,
+    Record<DynExpression>,
+    ErrorValue<DynExpression>,
+    Set<DynExpression>,
+    Str,
+    Vector<DynExpression>,
+    Symbol,
+);
+
+impl DynExpression {
+    /// Adds atoms of type `T` to every `DynExpression` from now on: the
+    /// parser tries tokens with `T` before the atoms every expression has,
+    /// as it would for an atom of a layer
+    pub fn register_atom<T: DynAtom>() {
+        let mut registered = REGISTERED.lock();
+        let name = T::sized_name();
+        if registered.iter().any(|atom| atom.name == name) {
+            return;
+        }
+        registered.push(Registered {
+            name,
+            parse: |token| T::parse_from_token(token).map(DynExpression::from),
+        });
+    }
+}
+
+impl<T: DynAtom> From<T> for DynExpression {
+    fn from(value: T) -> Self {
+        DynExpression(Shared::new(value))
+    }
+}
+
+impl<T: DynAtom> ToAndFrom<T> for DynExpression {
+    fn try_into_atom(&self) -> core::result::Result<&T, TypeError> {
+        self.0.as_any().downcast_ref().ok_or_else(|| TypeError {
+            expected: T::sized_name(),
+            got: self.variant(),
+        })
+    }
+}
+
+impl IntoLisp<DynExpression> for DynExpression {
+    fn into_lisp(self) -> Self {
+        self
+    }
+}
+
+impl PartialEq for DynExpression {
+    fn eq(&self, other: &Self) -> bool {
+        // Shared atoms are equal without having to walk them
+        Shared::ptr_eq(&self.0, &other.0) || self.0.eq_dyn(&*other.0)
+    }
+}
+
+impl Drop for DynExpression {
+    fn drop(&mut self) {
+        drop_nested(self)
+    }
+}
+
+impl Display for DynExpression {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        Display::fmt(self.0.as_atom(), f)
+    }
+}
+
+impl Debug for DynExpression {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "DynExpression({})", self.variant())
+    }
+}
+
+impl LispExpression for DynExpression {
+    fn as_atom(&self) -> &dyn Atom<Self> {
+        self.0.as_atom()
+    }
+
+    fn atom_names() -> Vec<&'static str> {
+        let registered = REGISTERED.lock();
+        registered
+            .iter()
+            .chain(&core_atoms())
+            .map(|atom| atom.name)
+            .collect()
+    }
+
+    fn parse_from_token(token: &Token) -> Self {
+        let registered = REGISTERED.lock();
+        registered
+            .iter()
+            .chain(&core_atoms())
+            .find_map(|atom| (atom.parse)(token))
+            // This will never fail as symbols never fail parsing
+            .unwrap()
+    }
+
+    fn take_unshared_elements(&mut self) -> Option<Vec<Self>> {
+        let list = Shared::get_mut(&mut self.0)?
+            .as_any_mut()
+            .downcast_mut::<List<DynExpression>>()?;
+        Shared::get_mut(&mut list.0).map(core::mem::take)
+    }
+}
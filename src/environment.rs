@@ -1,32 +1,969 @@
-use std::{collections::HashMap, fmt::Display};
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use core::fmt::Display;
+#[cfg(feature = "std")]
+use std::path::{Path, PathBuf};
 
-use crate::atoms::Symbol;
+#[cfg(feature = "async")]
+use crate::asynchronous::Awaiter;
+use crate::{
+    atoms::{
+        bind_recursive, AsyncBuiltinFunction, BuiltinFunction, BuiltinMacro, Char, Doc, Keyword,
+        Lambda, List, Macro, Number, Parameter, Record, Set, Str, Symbol, Vector,
+    },
+    attributes::Accessors,
+    builtins,
+    cancellation::CancellationToken,
+    collections::{HashMap, HashSet},
+    commands::ReplCommands,
+    config::Config,
+    convert::IntoLisp,
+    debugger::Debugger,
+    diagnostics::Diagnostics,
+    errors::{
+        AllocationLimit, Cancelled, FuelExhausted, ProtectedBinding, RecursionLimit,
+        UnboundVariable,
+    },
+    events::{EventKind, Events},
+    expression::{check_write_depth, LispExpression, ToAndFrom},
+    hooks::EvalHooks,
+    io::Io,
+    layer::LayerInfo,
+    prelude::*,
+    printer::{self, Overspent, PrintBudget},
+    profiler::Profiler,
+    random::Rng,
+    reader::ReaderMacros,
+    shared::{Modify, Mutable, PerThread, Shared, Slot},
+    tracer::Tracer,
+};
 
 #[derive(Clone, PartialEq)]
 pub struct Environment<E> {
     pub inner: HashMap<Symbol, E>,
+    /// Other names for bindings, such as `lambda` for `λ`, looked up when
+    /// nothing is bound to the alias itself
+    pub aliases: HashMap<Symbol, Symbol>,
+    /// Aliases kept only for old scripts, which warn when looked up
+    pub deprecated: HashSet<Symbol>,
+    /// Names which `define` and `undefine!` refuse to change, such as builtins
+    pub protected: Shared<Mutable<HashSet<Symbol>>>,
+    /// The latest value `define` gave each name, shared with every closure.
+    /// A symbol bound nowhere in an environment is looked up here, so that a
+    /// function can call others defined after it was made.
+    pub globals: Shared<Mutable<HashMap<Symbol, E>>>,
+    /// Whether this is the scope of a call or a `let` rather than the top
+    /// level, where `define` binds only in the scope and not the globals
+    pub local: bool,
+    pub rng: Rng,
+    pub io: Io,
+    /// Files currently being evaluated, the innermost last
+    #[cfg(feature = "std")]
+    pub load_stack: Shared<Mutable<Vec<PathBuf>>>,
+    /// Canonical paths of every file evaluated so far, for `require`
+    #[cfg(feature = "std")]
+    pub loaded_files: Shared<Mutable<HashSet<PathBuf>>>,
+    pub config: Shared<Mutable<Config>>,
+    /// How `evaluate` reads prefixes such as `'`, which layers may add to
+    pub reader_macros: ReaderMacros<E>,
+    /// `#commands` which layers add to the REPL
+    pub repl_commands: ReplCommands<E>,
+    /// How `get-attr` reads the attributes of foreign values
+    pub accessors: Accessors<E>,
+    /// The value a `return-from` is carrying out to its block
+    pub returning: Shared<Mutable<Option<E>>>,
+    /// The value a `throw` is carrying out to the nearest `catch`
+    pub thrown: Shared<Mutable<Option<E>>>,
+    /// Tests defined by `deftest` in order, as functions of no arguments
+    pub tests: Shared<Mutable<Vec<(Symbol, E)>>>,
+    /// How many calls are being evaluated inside each other, on each thread
+    /// as each has its own stack
+    pub eval_depth: Shared<PerThread<usize>>,
+    /// How many macro expansions are being evaluated inside each other
+    pub macro_depth: Shared<PerThread<usize>>,
+    /// Steps evaluation may still take, or `None` for no limit
+    pub fuel: Shared<Slot<Option<u64>>>,
+    /// List elements, string bytes and bindings built so far, see
+    /// `Config::max_allocation`
+    pub allocated: Shared<Slot<usize>>,
+    /// Checked on every evaluation step, so that cancelling it stops evaluation
+    pub cancellation: CancellationToken,
+    /// Counts calls and their time while enabled by `profile` or `#profile on`
+    pub profiler: Profiler,
+    /// Stops before each call while `#debug` is stepping through evaluation
+    pub debugger: Debugger<E>,
+    /// Prints calls of the functions given to `trace`, or all with `#trace on`
+    pub tracer: Tracer<E>,
+    /// Closures the embedder runs around every call
+    pub hooks: EvalHooks<E>,
+    /// Subscribers to what evaluation does, such as an external debugger
+    pub events: Events<E>,
+    /// Warnings from `warn` and the interpreter, for the REPL to print
+    pub diagnostics: Diagnostics,
+    /// The layers whose builtins were set, from the bottom up
+    pub layers: Shared<Mutable<Vec<LayerInfo>>>,
+    /// Runs awaited promises when evaluating with `evaluate_async`
+    #[cfg(feature = "async")]
+    pub(crate) awaiter: Option<Awaiter<E>>,
 }
 
-impl<E> Default for Environment<E> {
+impl<E: LispExpression> Default for Environment<E> {
     fn default() -> Self {
         Environment {
             inner: Default::default(),
+            aliases: Default::default(),
+            deprecated: Default::default(),
+            protected: Default::default(),
+            globals: Default::default(),
+            local: false,
+            rng: Default::default(),
+            io: Default::default(),
+            #[cfg(feature = "std")]
+            load_stack: Default::default(),
+            #[cfg(feature = "std")]
+            loaded_files: Default::default(),
+            config: Default::default(),
+            reader_macros: Default::default(),
+            repl_commands: Default::default(),
+            accessors: Default::default(),
+            returning: Default::default(),
+            thrown: Default::default(),
+            tests: Default::default(),
+            eval_depth: Default::default(),
+            macro_depth: Default::default(),
+            fuel: Default::default(),
+            allocated: Default::default(),
+            cancellation: Default::default(),
+            profiler: Default::default(),
+            debugger: Default::default(),
+            tracer: Default::default(),
+            hooks: Default::default(),
+            events: Default::default(),
+            diagnostics: Default::default(),
+            layers: Default::default(),
+            #[cfg(feature = "async")]
+            awaiter: None,
         }
     }
 }
 
 impl<E> Environment<E> {
     pub fn get(&self, symbol: &Symbol) -> Option<&E> {
-        self.inner.get(symbol)
+        self.inner.get(symbol).or_else(|| {
+            let canonical = self.aliases.get(symbol)?;
+            self.inner.get(canonical)
+        })
     }
 
     pub fn set(&mut self, symbol: impl Into<Symbol>, value: impl Into<E>) {
         self.inner.insert(symbol.into(), value.into());
     }
+
+    /// The value of a symbol as evaluation sees it: bound here, or else
+    /// defined since in any environment sharing these globals. The first
+    /// lookup of a deprecated alias warns.
+    pub fn lookup(&self, symbol: &Symbol) -> Option<E>
+    where
+        E: Clone,
+    {
+        if self.deprecated.contains(symbol) && !self.inner.contains_key(symbol) {
+            if let Some(canonical) = self.aliases.get(symbol) {
+                self.diagnostics.warn_once(format!(
+                    "`{}` is deprecated, use `{}` instead",
+                    symbol.0, canonical.0
+                ));
+            }
+        }
+        self.get(symbol)
+            .cloned()
+            .or_else(|| self.globals.borrow().get(symbol).cloned())
+    }
+
+    /// Binds a symbol here and, at the top level, among the globals, as
+    /// `define` does. In a local scope a lambda is bound so that it can call
+    /// itself, as no global lets it see its own definition.
+    pub fn define(&mut self, symbol: impl Into<Symbol>, value: impl Into<E>)
+    where
+        E: LispExpression,
+    {
+        let (symbol, value) = (symbol.into(), value.into());
+        if self.events.is_active() {
+            let kind = EventKind::Define {
+                name: symbol.clone(),
+                value: value.clone(),
+            };
+            self.events.emit(kind, self);
+        }
+        if self.local {
+            bind_recursive(&Shared::new(vec![(symbol, value)]), self);
+            return;
+        }
+        self.globals
+            .borrow_mut()
+            .insert(symbol.clone(), value.clone());
+        self.inner.insert(symbol, value);
+    }
+
+    /// Removes a symbol's binding here and, at the top level, among the
+    /// globals, returning whether it had one
+    pub fn undefine(&mut self, symbol: &Symbol) -> bool {
+        let global = !self.local && self.globals.borrow_mut().remove(symbol).is_some();
+        self.inner.remove(symbol).is_some() || global
+    }
+
+    /// A copy to evaluate a body in, whose definitions stay local to it
+    pub fn scope(&self) -> Self
+    where
+        E: Clone,
+    {
+        Environment {
+            local: true,
+            ..self.clone()
+        }
+    }
+
+    /// Stops `define` and `undefine!` from changing what `symbol` is bound to
+    pub fn protect(&self, symbol: impl Into<Symbol>) {
+        self.protected.borrow_mut().insert(symbol.into());
+    }
+
+    /// The symbols bound here, which a layer takes before setting its
+    /// builtins to then protect only those with `protect_new_bindings`
+    pub fn bound_symbols(&self) -> HashSet<Symbol> {
+        self.inner.keys().cloned().collect()
+    }
+
+    /// Protects everything bound since `before` was taken with
+    /// `bound_symbols`, as layers do once their builtins are set
+    pub fn protect_new_bindings(&self, before: &HashSet<Symbol>) {
+        self.protected.borrow_mut().extend(
+            self.inner
+                .keys()
+                .filter(|symbol| !before.contains(*symbol))
+                .cloned(),
+        );
+    }
+
+    pub fn is_protected(&self, symbol: &Symbol) -> bool {
+        self.protected.borrow().contains(symbol)
+    }
+
+    /// Fails with `ProtectedBinding` if user code may not rebind `symbol`
+    pub fn check_redefinable(&self, symbol: &Symbol) -> Result<()> {
+        if self.is_protected(symbol) && !self.config.borrow().allow_redefining_builtins {
+            return Err(ProtectedBinding(symbol.clone()).into());
+        }
+        Ok(())
+    }
+
+    /// Makes `alias` another name for whatever `canonical` is bound to, now
+    /// or later, unless something is bound to `alias` itself
+    pub fn alias(&mut self, alias: impl Into<Symbol>, canonical: impl Into<Symbol>) {
+        self.aliases.insert(alias.into(), canonical.into());
+    }
+
+    /// Keeps `old` working as an alias of `new` after a builtin is renamed,
+    /// but warns the first time a script uses it
+    pub fn set_alias(&mut self, old: impl Into<Symbol>, new: impl Into<Symbol>) {
+        let old = old.into();
+        self.alias(old.clone(), new);
+        self.deprecated.insert(old);
+    }
+
+    /// The aliases of a name which are not deprecated, sorted
+    pub fn aliases_of(&self, canonical: &Symbol) -> Vec<&Symbol> {
+        let mut aliases: Vec<&Symbol> = self
+            .aliases
+            .iter()
+            .filter(|(alias, target)| *target == canonical && !self.deprecated.contains(*alias))
+            .map(|(alias, _)| alias)
+            .collect();
+        aliases.sort();
+        aliases
+    }
+
+    /// Limits the steps that evaluation in this environment and every closure
+    /// captured from it may take from now on, or lifts the limit with `None`.
+    /// Evaluating an expression and calling a builtin each take a step, and
+    /// builtins working through many elements take one for each.
+    pub fn set_fuel(&self, fuel: Option<u64>) {
+        self.fuel.set(fuel);
+    }
+
+    pub fn remaining_fuel(&self) -> Option<u64> {
+        self.fuel.get()
+    }
+
+    /// Takes a step of fuel, failing with `FuelExhausted` if none is left or
+    /// `Cancelled` if evaluation was cancelled. Builtins doing a lot of work
+    /// may call this to charge for it and stay interruptible.
+    pub fn consume_fuel(&self) -> Result<()> {
+        if self.cancellation.is_cancelled() {
+            return Err(Cancelled.into());
+        }
+        self.fuel.modify(|fuel| match fuel {
+            None => Ok(()),
+            Some(0) => Err(FuelExhausted.into()),
+            Some(fuel) => {
+                *fuel -= 1;
+                Ok(())
+            }
+        })
+    }
+
+    /// Fails with `AllocationLimit` if building `amount` more list elements,
+    /// string bytes or bindings would go over `Config::max_allocation`
+    pub fn check_allocation(&self, amount: usize) -> Result<()> {
+        match self.config.borrow().max_allocation {
+            Some(limit) if self.allocated.get().saturating_add(amount) > limit => {
+                Err(AllocationLimit { limit }.into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Counts `amount` list elements, string bytes or bindings as built,
+    /// failing if that goes over the limit
+    pub fn charge_allocation(&self, amount: usize) -> Result<()> {
+        let limit = self.config.borrow().max_allocation;
+        self.allocated.modify(|allocated| {
+            let total = allocated.saturating_add(amount);
+            match limit {
+                Some(limit) if total > limit => Err(AllocationLimit { limit }.into()),
+                _ => {
+                    *allocated = total;
+                    Ok(())
+                }
+            }
+        })
+    }
+
+    /// Runs `print`, failing with `AllocationLimit` rather than printing
+    /// lists as more text than `Config::max_allocation` has left. Walking
+    /// each element takes a step of fuel, failing with `FuelExhausted` when
+    /// none is left.
+    pub fn print_within_limits<R>(&self, print: impl FnOnce() -> R) -> Result<R> {
+        let limit = self.config.borrow().max_allocation;
+        let budget = PrintBudget {
+            bytes: limit.map(|limit| limit.saturating_sub(self.allocated.get())),
+            steps: self.fuel.get(),
+        };
+        match printer::with_print_budget(budget, print) {
+            Ok((result, left)) => {
+                self.fuel.set(left.steps);
+                Ok(result)
+            }
+            // Printing is only limited in bytes if there is a limit
+            Err(Overspent::Bytes) => Err(AllocationLimit {
+                limit: limit.unwrap_or_default(),
+            }
+            .into()),
+            Err(Overspent::Steps) => {
+                self.fuel.set(Some(0));
+                Err(FuelExhausted.into())
+            }
+        }
+    }
+
+    /// Counts a call as being evaluated until the guard is dropped, failing
+    /// if that nests calls deeper than the configured limit
+    pub(crate) fn enter_eval(&self) -> Result<DepthGuard> {
+        let limit = self.config.borrow().max_eval_depth;
+        DepthGuard::enter(&self.eval_depth, limit)
+            .ok_or_else(|| RecursionLimit::Evaluation { limit }.into())
+    }
+
+    /// Like `enter_eval`, for expanding a macro
+    pub(crate) fn enter_macro(&self) -> Result<DepthGuard> {
+        let limit = self.config.borrow().max_macro_depth;
+        DepthGuard::enter(&self.macro_depth, limit)
+            .ok_or_else(|| RecursionLimit::MacroExpansion { limit }.into())
+    }
+
+    /// The file being evaluated, if any
+    #[cfg(feature = "std")]
+    pub fn current_file(&self) -> Option<PathBuf> {
+        self.load_stack.borrow().last().cloned()
+    }
+
+    /// Resolves relative paths against the directory of the current file
+    /// rather than the working directory
+    #[cfg(feature = "std")]
+    pub fn resolve_path(&self, path: impl AsRef<Path>) -> PathBuf {
+        let path = path.as_ref();
+        match self.current_file() {
+            Some(file) if path.is_relative() => file
+                .parent()
+                .map(|directory| directory.join(path))
+                .unwrap_or_else(|| path.to_owned()),
+            _ => path.to_owned(),
+        }
+    }
+}
+
+/// What building a value counts against `Config::max_allocation`: the
+/// elements of a collection, the bytes of a string, or 1 for anything else
+pub(crate) fn allocation_size<E: LispExpression>(value: &E) -> usize {
+    if let Ok(list) = value.as_list() {
+        list.0.len()
+    } else if let Ok(string) = ToAndFrom::<Str>::try_into_atom(value) {
+        string.0.len()
+    } else if let Ok(set) = ToAndFrom::<Set<E>>::try_into_atom(value) {
+        set.len()
+    } else if let Ok(record) = ToAndFrom::<Record<E>>::try_into_atom(value) {
+        record.values.len()
+    } else if let Ok(vector) = ToAndFrom::<Vector<E>>::try_into_atom(value) {
+        vector.0.borrow().len()
+    } else {
+        1
+    }
+}
+
+/// One level of nesting, counted until dropped so that errors unwinding out
+/// of an evaluation still restore the depth
+pub(crate) struct DepthGuard(Shared<PerThread<usize>>);
+
+impl DepthGuard {
+    fn enter(depth: &Shared<PerThread<usize>>, limit: usize) -> Option<Self> {
+        let entered = depth.modify(|depth| {
+            let entered = *depth < limit;
+            if entered {
+                *depth += 1;
+            }
+            entered
+        });
+        entered.then(|| DepthGuard(depth.clone()))
+    }
+}
+
+impl Drop for DepthGuard {
+    fn drop(&mut self) {
+        self.0.modify(|depth| *depth -= 1);
+    }
+}
+
+/// A builtin as found in an environment, see [`Environment::builtins`]
+pub struct BuiltinEntry<'a> {
+    pub symbol: &'a Symbol,
+    /// `"builtin function"`, `"builtin macro"` or `"async builtin function"`
+    pub kind: &'static str,
+    pub doc: Doc,
+}
+
+/// The bindings of an environment at some moment, which
+/// [`Environment::restore`] puts back. Values are shared with the environment
+/// rather than copied, so taking one is cheap, but this also means that
+/// changes inside boxes and vectors are not undone.
+#[derive(Clone)]
+pub struct Snapshot<E> {
+    inner: HashMap<Symbol, E>,
+    aliases: HashMap<Symbol, Symbol>,
+    deprecated: HashSet<Symbol>,
+    protected: HashSet<Symbol>,
+    globals: HashMap<Symbol, E>,
+}
+
+impl<E: Clone> Environment<E> {
+    /// Saves the current bindings, e.g. before a speculative evaluation
+    pub fn snapshot(&self) -> Snapshot<E> {
+        Snapshot {
+            inner: self.inner.clone(),
+            aliases: self.aliases.clone(),
+            deprecated: self.deprecated.clone(),
+            protected: self.protected.borrow().clone(),
+            globals: self.globals.borrow().clone(),
+        }
+    }
+
+    /// Puts back the bindings saved in `snapshot`, undoing every definition
+    /// made since
+    pub fn restore(&mut self, snapshot: Snapshot<E>) {
+        self.inner = snapshot.inner;
+        self.aliases = snapshot.aliases;
+        self.deprecated = snapshot.deprecated;
+        *self.protected.borrow_mut() = snapshot.protected;
+        *self.globals.borrow_mut() = snapshot.globals;
+    }
+}
+
+impl<E: LispExpression> Environment<E> {
+    /// Writes every user made definition as a `(define ...)` form, all wrapped
+    /// in a single `(list ...)` so that evaluating the output restores them.
+    ///
+    /// Builtins bound under their own name and protected bindings are
+    /// skipped. Lambdas capture the environment at creation, so definitions
+    /// are ordered such that those a definition refers to come before it
+    /// whenever possible.
+    pub fn serialize_definitions(&self) -> Result<String> {
+        let mut output = "(list".to_owned();
+        for symbol in self.user_definitions() {
+            // Note: these values exist in our map for sure
+            let value = self.get(symbol).unwrap();
+            let source = value_source(value, self, 0)
+                .with_context(|| anyhow!("Could not serialize definition of `{symbol}`"))?;
+            output.push_str(&format!("\n  (define '{} {source})", symbol.0));
+        }
+        output.push_str("\n)\n");
+        Ok(output)
+    }
+
+    /// Renders the user made definitions as source for a library file, which
+    /// `evaluate_file` or `require` loads. Unlike `serialize_definitions`
+    /// this never fails: a definition with no source, such as one holding a
+    /// builtin bound under another name, is left as a comment saying why.
+    pub fn dump_source(&self) -> String {
+        let mut output = "; Definitions exported from a shallot session\n(list".to_owned();
+        for symbol in self.user_definitions() {
+            let value = self.get(symbol).unwrap();
+            match value_source(value, self, 0) {
+                Ok(source) => output.push_str(&format!("\n  (define '{} {source})", symbol.0)),
+                Err(error) => {
+                    output.push_str(&format!("\n  ; `{}` has no source: {error}", symbol.0))
+                }
+            }
+        }
+        output.push_str("\n)\n");
+        output
+    }
+
+    /// The symbols of user made definitions, each after those it refers to
+    /// where possible
+    fn user_definitions(&self) -> Vec<&Symbol> {
+        let mut user_symbols: Vec<&Symbol> = self
+            .inner
+            .iter()
+            .filter(|(symbol, value)| {
+                !is_builtin_named(*value, &symbol.0) && !self.is_protected(symbol)
+            })
+            .map(|(symbol, _)| symbol)
+            .collect();
+        user_symbols.sort();
+
+        let mut ordered = Vec::new();
+        let mut visited = HashSet::new();
+        for symbol in &user_symbols {
+            self.order_definitions(symbol, &user_symbols, &mut visited, &mut ordered);
+        }
+        ordered
+    }
+
+    /// Records `layer` as the origin of every builtin which has none yet
+    pub fn assign_layer(&mut self, layer: &'static str) {
+        for value in self.inner.values_mut() {
+            if let Ok(function) = ToAndFrom::<BuiltinFunction<E>>::try_into_atom(value) {
+                if function.doc.layer.is_empty() {
+                    let mut function = function.clone();
+                    function.doc.layer = layer;
+                    *value = function.into();
+                }
+            } else if let Ok(function) = ToAndFrom::<BuiltinMacro<E>>::try_into_atom(value) {
+                if function.doc.layer.is_empty() {
+                    let mut function = function.clone();
+                    function.doc.layer = layer;
+                    *value = function.into();
+                }
+            }
+        }
+    }
+
+    /// Records that a layer's builtins were set, replacing an earlier record
+    /// of the same layer
+    pub fn register_layer(&self, info: LayerInfo) {
+        let mut layers = self.layers.borrow_mut();
+        layers.retain(|layer| layer.name != info.name);
+        layers.push(info);
+    }
+
+    /// Every builtin bound in the environment, sorted by the name bound to
+    pub fn builtins(&self) -> Vec<BuiltinEntry<'_>> {
+        let mut builtins: Vec<BuiltinEntry> = self
+            .inner
+            .iter()
+            .filter_map(|(symbol, value)| {
+                let doc = if let Ok(f) = ToAndFrom::<BuiltinFunction<E>>::try_into_atom(value) {
+                    f.doc
+                } else if let Ok(f) = ToAndFrom::<BuiltinMacro<E>>::try_into_atom(value) {
+                    f.doc
+                } else if let Ok(f) = ToAndFrom::<AsyncBuiltinFunction<E>>::try_into_atom(value) {
+                    f.doc
+                } else {
+                    return None;
+                };
+                Some(BuiltinEntry {
+                    symbol,
+                    kind: value.variant(),
+                    doc,
+                })
+            })
+            .collect();
+        builtins.sort_by_key(|b| b.symbol);
+        builtins
+    }
+
+    /// Rebinds the builtin `name` to `function`, keeping it a function or a
+    /// macro as before. Closures which captured the old builtin use the new one.
+    pub fn replace_builtin(
+        &mut self,
+        name: &'static str,
+        function: fn(&[E], &mut Environment<E>) -> Result<E>,
+    ) -> Result<()> {
+        let symbol = Symbol::from(name);
+        let old = self
+            .get(&symbol)
+            .ok_or_else(|| anyhow!("There is no builtin `{name}` to replace"))?;
+        let new: E = if let Ok(old) = ToAndFrom::<BuiltinFunction<E>>::try_into_atom(old) {
+            BuiltinFunction {
+                doc: old.doc,
+                ..BuiltinFunction::new(name, function)
+            }
+            .into()
+        } else if let Ok(old) = ToAndFrom::<BuiltinMacro<E>>::try_into_atom(old) {
+            BuiltinMacro {
+                doc: old.doc,
+                ..BuiltinMacro::new(name, function)
+            }
+            .into()
+        } else {
+            bail!("`{name}` is bound to a {}, not a builtin", old.variant())
+        };
+        self.replace_everywhere(&symbol, &new);
+        Ok(())
+    }
+
+    /// Runs `layer` again, swapping in its builtins while keeping user definitions
+    pub fn reload_layer(&mut self, layer: impl Fn(&mut Environment<E>)) {
+        let mut fresh = Environment::default();
+        layer(&mut fresh);
+        for (symbol, value) in fresh.inner {
+            self.replace_everywhere(&symbol, &value);
+        }
+        self.aliases.extend(fresh.aliases);
+        self.deprecated.extend(fresh.deprecated);
+        *self.layers.borrow_mut() = fresh.layers.borrow().clone();
+        let protected = fresh.protected.borrow().clone();
+        self.protected.borrow_mut().extend(protected);
+    }
+
+    fn replace_everywhere(&mut self, symbol: &Symbol, value: &E) {
+        for bound in self.inner.values_mut() {
+            if let Some(updated) = with_replaced_builtin(bound, symbol, value) {
+                *bound = updated;
+            }
+        }
+        self.set(symbol.clone(), value.clone());
+    }
+
+    /// Adds `symbol` to `ordered` after the definitions it refers to, walking
+    /// them with an explicit stack so that long chains cannot overflow it
+    fn order_definitions<'a>(
+        &self,
+        symbol: &'a Symbol,
+        user_symbols: &[&'a Symbol],
+        visited: &mut HashSet<&'a Symbol>,
+        ordered: &mut Vec<&'a Symbol>,
+    ) {
+        if !visited.insert(symbol) {
+            return;
+        }
+        let mut stack = vec![(symbol, self.dependencies(symbol, user_symbols).into_iter())];
+        while let Some((symbol, dependencies)) = stack.last_mut() {
+            match dependencies.next() {
+                Some(dependency) if visited.insert(dependency) => {
+                    let dependencies = self.dependencies(dependency, user_symbols);
+                    stack.push((dependency, dependencies.into_iter()));
+                }
+                Some(_) => {}
+                None => {
+                    ordered.push(symbol);
+                    stack.pop();
+                }
+            }
+        }
+    }
+
+    /// The user definitions the value of `symbol` refers to
+    fn dependencies<'a>(&self, symbol: &Symbol, user_symbols: &[&'a Symbol]) -> Vec<&'a Symbol> {
+        let mut referenced = HashSet::new();
+        if let Some(value) = self.get(symbol) {
+            referenced_symbols(value, &mut referenced);
+        }
+        user_symbols
+            .iter()
+            .copied()
+            .filter(|dependency| referenced.contains(*dependency))
+            .collect()
+    }
+}
+
+/// A function for Rust code to call: the name it is bound to, or the
+/// function itself, such as a lambda a script gave to a builtin
+pub trait Callable<E> {
+    fn function(self, env: &Environment<E>) -> Result<E>;
+}
+
+impl<E: LispExpression> Callable<E> for &str {
+    fn function(self, env: &Environment<E>) -> Result<E> {
+        let symbol = Symbol::from(self);
+        env.lookup(&symbol)
+            .ok_or_else(|| UnboundVariable(symbol).into())
+    }
+}
+
+impl<E: LispExpression> Callable<E> for &E {
+    fn function(self, _env: &Environment<E>) -> Result<E> {
+        Ok(self.clone())
+    }
+}
+
+/// A Lisp function kept by Rust code, see [`Environment::callback`]
+pub type Callback<E, A> = Box<dyn Fn(&[A]) -> Result<E>>;
+
+impl<E: LispExpression> Environment<E> {
+    /// Calls a function with Rust values as its arguments, or expressions
+    /// where they are of different types
+    pub fn call_function<A>(&mut self, function: impl Callable<E>, arguments: &[A]) -> Result<E>
+    where
+        A: IntoLisp<E> + Clone,
+    {
+        let function = function.function(self)?;
+        let arguments: Vec<E> = arguments.iter().cloned().map(A::into_lisp).collect();
+        builtins::apply(&function, &arguments, self)
+            .with_context(|| anyhow!("Could not call {function}"))
+    }
+
+    /// A closure calling a function in a copy of this environment, for hosts
+    /// to keep as a hook such as a key binding. A name is looked up now, so
+    /// later definitions of it are not seen.
+    ///
+    /// Each call gets a fresh copy of the environment as it was when the
+    /// callback was made, so the function may run the callback again. Only
+    /// `define`s at the top level, which go to the shared globals, are seen
+    /// by later calls; bindings made here afterwards are not.
+    pub fn callback<A>(&self, function: impl Callable<E>) -> Result<Callback<E, A>>
+    where
+        A: IntoLisp<E> + Clone,
+    {
+        let function = function.function(self)?;
+        let env = self.clone();
+        Ok(Box::new(move |arguments: &[A]| {
+            env.clone().call_function(&function, arguments)
+        }))
+    }
+}
+
+fn is_builtin_named<E: LispExpression>(value: &E, name: &str) -> bool {
+    if let Ok(function) = ToAndFrom::<BuiltinFunction<E>>::try_into_atom(value) {
+        function.name == name
+    } else if let Ok(function) = ToAndFrom::<BuiltinMacro<E>>::try_into_atom(value) {
+        function.name == name
+    } else if let Ok(function) = ToAndFrom::<AsyncBuiltinFunction<E>>::try_into_atom(value) {
+        function.name == name
+    } else {
+        false
+    }
+}
+
+/// A copy of `expression` in which closures see `value` for the builtin
+/// `symbol`, or `None` if nothing in it captured that builtin
+fn with_replaced_builtin<E: LispExpression>(
+    expression: &E,
+    symbol: &Symbol,
+    value: &E,
+) -> Option<E> {
+    if let Ok(lambda) = ToAndFrom::<Lambda<E>>::try_into_atom(expression) {
+        let env = captured_with_replaced_builtin(&lambda.env, symbol, value)?;
+        Some(
+            Lambda {
+                env: Shared::new(env),
+                ..lambda.clone()
+            }
+            .into(),
+        )
+    } else if let Ok(macr) = ToAndFrom::<Macro<E>>::try_into_atom(expression) {
+        let env = captured_with_replaced_builtin(&macr.env, symbol, value)?;
+        Some(
+            Macro {
+                env: Shared::new(env),
+                ..macr.clone()
+            }
+            .into(),
+        )
+    } else if let Ok(list) = expression.as_list() {
+        let updated: Vec<Option<E>> = list
+            .0
+            .iter()
+            .map(|e| with_replaced_builtin(e, symbol, value))
+            .collect();
+        if updated.iter().all(Option::is_none) {
+            return None;
+        }
+        let elements = updated
+            .into_iter()
+            .zip(list.0.iter())
+            .map(|(updated, old)| updated.unwrap_or_else(|| old.clone()))
+            .collect();
+        Some(List::new(elements).into())
+    } else {
+        None
+    }
+}
+
+fn captured_with_replaced_builtin<E: LispExpression>(
+    env: &Environment<E>,
+    symbol: &Symbol,
+    value: &E,
+) -> Option<Environment<E>> {
+    let mut updated = None;
+    for (bound_symbol, bound) in &env.inner {
+        let replacement = if bound_symbol == symbol && is_builtin_named(bound, &symbol.0) {
+            Some(value.clone())
+        } else {
+            with_replaced_builtin(bound, symbol, value)
+        };
+        if let Some(replacement) = replacement {
+            updated
+                .get_or_insert_with(|| env.clone())
+                .set(bound_symbol.clone(), replacement);
+        }
+    }
+    updated
+}
+
+/// Adds the symbols in `expression` and the code of the closures in it to
+/// `symbols`, walking it with an explicit stack so deep nesting cannot
+/// overflow
+pub(crate) fn referenced_symbols<E: LispExpression>(expression: &E, symbols: &mut HashSet<Symbol>) {
+    let mut stack = vec![expression];
+    while let Some(expression) = stack.pop() {
+        if let Ok(symbol) = expression.as_symbol() {
+            symbols.insert(symbol.clone());
+        } else if let Ok(list) = expression.as_list() {
+            stack.extend(list.0.iter());
+        } else if let Ok(lambda) = ToAndFrom::<Lambda<E>>::try_into_atom(expression) {
+            stack.extend(lambda.parameters.iter().filter_map(|p| p.default.as_ref()));
+            stack.push(lambda.value.as_ref());
+        } else if let Ok(macr) = ToAndFrom::<Macro<E>>::try_into_atom(expression) {
+            stack.push(macr.value.as_ref());
+        }
+    }
+}
+
+/// Source which evaluates to `value` at the top level of `env`, quoting
+/// data where needed, for a value found inside `depth` lists or closures.
+/// Values nested too deeply to be parsed back are refused.
+fn value_source<E: LispExpression>(
+    value: &E,
+    env: &Environment<E>,
+    depth: usize,
+) -> Result<String> {
+    check_write_depth(depth)?;
+    if value.as_symbol().is_ok() || is_quotable(value, depth) {
+        Ok(format!("'{}", value.as_atom().to_source()?))
+    } else if let Ok(list) = value.as_list() {
+        let mut elements = vec!["list".to_owned()];
+        for element in list.0.iter() {
+            elements.push(value_source(element, env, depth + 1)?);
+        }
+        Ok(format!("({})", elements.join(" ")))
+    } else if let Ok(lambda) = ToAndFrom::<Lambda<E>>::try_into_atom(value) {
+        let mut recursive = Vec::new();
+        for (name, function) in lambda.recursive.iter().flat_map(|group| group.iter()) {
+            ensure!(
+                env.get(name)
+                    .is_some_and(|bound| bound.is_identical(function)),
+                "It calls `{}` from a letrec, which cannot be written as source",
+                name.0
+            );
+            recursive.push(name.clone());
+        }
+        closure_source(
+            value,
+            &lambda.parameters,
+            &lambda.env,
+            &recursive,
+            env,
+            depth,
+        )
+    } else if let Ok(macr) = ToAndFrom::<Macro<E>>::try_into_atom(value) {
+        closure_source(value, &macr.parameters, &macr.env, &[], env, depth)
+    } else {
+        value.as_atom().to_source()
+    }
+}
+
+/// Source of a lambda or macro, in a `let` binding the variables it uses
+/// from the environment it captured which the top level of `env` binds to
+/// something else or not at all, such as those of an enclosing function
+fn closure_source<E: LispExpression>(
+    value: &E,
+    parameters: &[Parameter<E>],
+    captured: &Environment<E>,
+    recursive: &[Symbol],
+    env: &Environment<E>,
+    depth: usize,
+) -> Result<String> {
+    let mut referenced = HashSet::new();
+    referenced_symbols(value, &mut referenced);
+    for parameter in parameters {
+        for variable in parameter.variables() {
+            referenced.remove(&variable);
+        }
+    }
+    let mut referenced: Vec<Symbol> = referenced
+        .into_iter()
+        .filter(|symbol| !recursive.contains(symbol))
+        .collect();
+    referenced.sort();
+
+    let mut bindings = Vec::new();
+    for symbol in referenced {
+        let Some(bound) = captured.get(&symbol) else {
+            continue;
+        };
+        if env.get(&symbol).is_some_and(|top| top.is_identical(bound)) {
+            continue;
+        }
+        let source = value_source(bound, env, depth + 1)
+            .with_context(|| anyhow!("Could not serialize the captured `{}`", symbol.0))?;
+        bindings.push(format!("({} {source})", symbol.0));
+    }
+    let source = value.as_atom().to_source()?;
+    if bindings.is_empty() {
+        Ok(source)
+    } else {
+        Ok(format!("(let ({}) {source})", bindings.join(" ")))
+    }
+}
+
+/// Whether a quoted list inside `depth` lists reads back as an equal value,
+/// i.e. it has no callables and is not nested too deeply to be parsed back.
+/// Walked with an explicit stack so that deep nesting cannot overflow it.
+fn is_quotable<E: LispExpression>(value: &E, depth: usize) -> bool {
+    let Ok(list) = value.as_list() else {
+        return false;
+    };
+    let mut stack = vec![(list, depth)];
+    while let Some((List(elements), depth)) = stack.pop() {
+        if check_write_depth(depth).is_err() {
+            return false;
+        }
+        for e in elements.iter() {
+            if let Ok(list) = e.as_list() {
+                stack.push((list, depth + 1));
+            } else if !(e.as_symbol().is_ok()
+                || ToAndFrom::<Number>::try_into_atom(e).is_ok()
+                || ToAndFrom::<Str>::try_into_atom(e).is_ok()
+                || ToAndFrom::<Char>::try_into_atom(e).is_ok()
+                || ToAndFrom::<Keyword>::try_into_atom(e).is_ok())
+            {
+                return false;
+            }
+        }
+    }
+    true
 }
 
 impl<E: Display> Display for Environment<E> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         let mut all_variables = self.inner.keys().collect::<Vec<_>>();
         all_variables.sort();
         let longest_var_length = all_variables.iter().map(|s| s.len()).max().unwrap_or(0);
@@ -38,8 +975,16 @@ impl<E: Display> Display for Environment<E> {
             first = false;
             // Note: these values exist in our map for sure
             let value = self.get(symbol).unwrap();
-            let symbol = &symbol.0;
-            write!(f, "{symbol:>longest_var_length$} -> {value}")?;
+            write!(f, "{:>longest_var_length$} -> {value}", symbol.0)?;
+            let aliases: Vec<&str> = self
+                .aliases_of(symbol)
+                .into_iter()
+                .filter(|alias| !self.inner.contains_key(*alias))
+                .map(|alias| alias.0.as_str())
+                .collect();
+            if !aliases.is_empty() {
+                write!(f, " (also {})", aliases.join(", "))?;
+            }
         }
         Ok(())
     }
@@ -1,4 +1,6 @@
-use std::{error::Error, fmt::Display};
+use core::{error::Error, fmt::Display};
+
+use crate::{atoms::Symbol, prelude::*, printer::number_source};
 
 #[derive(Copy, Clone, Debug)]
 pub struct TypeError {
@@ -7,7 +9,7 @@ pub struct TypeError {
 }
 
 impl Display for TypeError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(
             f,
             "Type error: expected {} and got {}",
@@ -17,3 +19,261 @@ impl Display for TypeError {
 }
 
 impl Error for TypeError {}
+
+#[derive(Clone, Debug)]
+pub struct UnboundVariable(pub Symbol);
+
+impl Display for UnboundVariable {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Variable `{}` unbound", self.0)
+    }
+}
+
+impl Error for UnboundVariable {}
+
+/// A callable given more arguments than it has parameters
+#[derive(Clone, Debug)]
+pub struct ArityError {
+    /// What was called, e.g. `"lambda"`
+    pub callee: &'static str,
+    /// The name it was defined with, if any
+    pub name: Option<Symbol>,
+    pub parameters: Vec<Symbol>,
+    pub given: usize,
+}
+
+impl Display for ArityError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Too many arguments to {}", self.callee)?;
+        if let Some(name) = &self.name {
+            write!(f, " `{name}`")?;
+        }
+        write!(
+            f,
+            ": expected at most {} and got {}",
+            self.parameters.len(),
+            self.given
+        )
+    }
+}
+
+impl Error for ArityError {}
+
+/// Positions count characters from 0, lines and columns count from 1
+#[derive(Clone, Copy, Debug)]
+pub enum UnbalancedParens {
+    /// The list or vector opened here is never closed by `close`
+    Unclosed {
+        position: usize,
+        line: usize,
+        column: usize,
+        close: char,
+    },
+    /// There is no list or vector for `close` to close here
+    UnexpectedClose {
+        position: usize,
+        line: usize,
+        column: usize,
+        close: char,
+    },
+}
+
+impl Display for UnbalancedParens {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            UnbalancedParens::Unclosed { line, column, .. } => {
+                write!(
+                    f,
+                    "List opened at line {line}, column {column} is never closed"
+                )
+            }
+            UnbalancedParens::UnexpectedClose { line, column, .. } => {
+                write!(
+                    f,
+                    "Unexpected close bracket at line {line}, column {column}"
+                )
+            }
+        }
+    }
+}
+
+impl Error for UnbalancedParens {}
+
+/// Input which ended partway through an expression, so that more of it could
+/// complete it
+#[derive(Clone, Copy, Debug)]
+pub struct IncompleteInput;
+
+impl Display for IncompleteInput {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Input ended partway through an expression")
+    }
+}
+
+impl Error for IncompleteInput {}
+
+/// A string literal with no closing quote
+#[derive(Clone, Copy, Debug)]
+pub struct UnterminatedString {
+    /// Where its opening quote is
+    pub position: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Display for UnterminatedString {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "String opened at line {}, column {} is never closed",
+            self.line, self.column
+        )
+    }
+}
+
+impl Error for UnterminatedString {}
+
+/// Unwinds evaluation out to the enclosing `block` with this name. The value
+/// travels separately in `Environment::returning`, as errors must be `Send`.
+#[derive(Clone, Debug)]
+pub struct ReturnFrom(pub Symbol);
+
+impl Display for ReturnFrom {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "Return-from {} outside of a block with that name",
+            self.0
+        )
+    }
+}
+
+impl Error for ReturnFrom {}
+
+/// Unwinds evaluation out to the nearest `catch`. The value travels in
+/// `Environment::thrown`, and this holds its text for when nothing catches it.
+#[derive(Clone, Debug)]
+pub struct Thrown(pub String);
+
+impl Display for Thrown {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Uncaught throw of {}", self.0)
+    }
+}
+
+impl Error for Thrown {}
+
+/// Nesting deeper than a limit, reported instead of overflowing the stack
+#[derive(Clone, Copy, Debug)]
+pub enum RecursionLimit {
+    /// Calls nested deeper than `Config::max_eval_depth`
+    Evaluation { limit: usize },
+    /// Macro expansions nested deeper than `Config::max_macro_depth`
+    MacroExpansion { limit: usize },
+    /// Lists in source code nested deeper than `MAX_PARSE_DEPTH`
+    Parsing { limit: usize },
+    /// Lists written as source or JSON nested deeper than `MAX_PARSE_DEPTH`,
+    /// which would not read back
+    Writing { limit: usize },
+}
+
+impl Display for RecursionLimit {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            RecursionLimit::Evaluation { limit } => {
+                write!(f, "Evaluation nested more than {limit} calls deep")
+            }
+            RecursionLimit::MacroExpansion { limit } => {
+                write!(f, "Macro expansion nested more than {limit} macros deep")
+            }
+            RecursionLimit::Parsing { limit } => {
+                write!(f, "Source nested more than {limit} lists deep")
+            }
+            RecursionLimit::Writing { limit } => {
+                write!(f, "Cannot write lists nested more than {limit} deep")
+            }
+        }
+    }
+}
+
+impl Error for RecursionLimit {}
+
+/// Evaluation took all the steps allowed by `Environment::set_fuel`
+#[derive(Clone, Copy, Debug)]
+pub struct FuelExhausted;
+
+impl Display for FuelExhausted {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Ran out of fuel before evaluation finished")
+    }
+}
+
+impl Error for FuelExhausted {}
+
+/// Evaluation built more than `Config::max_allocation` allows
+#[derive(Clone, Copy, Debug)]
+pub struct AllocationLimit {
+    pub limit: usize,
+}
+
+impl Display for AllocationLimit {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "Evaluation allocated more than {} list elements, string bytes and bindings",
+            self.limit
+        )
+    }
+}
+
+impl Error for AllocationLimit {}
+
+/// An index past either end of a list or vector, after counting negative
+/// indices back from the end. The index is the number as it was given.
+#[derive(Clone, Copy, Debug)]
+pub struct IndexOutOfRange {
+    pub index: f64,
+    pub length: usize,
+}
+
+impl Display for IndexOutOfRange {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "Index {} is out of range for a sequence of length {}",
+            number_source(self.index),
+            self.length
+        )
+    }
+}
+
+impl Error for IndexOutOfRange {}
+
+/// Code tried to redefine a binding which a layer protects, see
+/// `Environment::protect`
+#[derive(Clone, Debug)]
+pub struct ProtectedBinding(pub Symbol);
+
+impl Display for ProtectedBinding {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "`{}` is a protected builtin and cannot be redefined",
+            self.0
+        )
+    }
+}
+
+impl Error for ProtectedBinding {}
+
+/// Evaluation stopped because its `CancellationToken` was cancelled
+#[derive(Clone, Copy, Debug)]
+pub struct Cancelled;
+
+impl Display for Cancelled {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Evaluation was cancelled")
+    }
+}
+
+impl Error for Cancelled {}
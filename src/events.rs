@@ -0,0 +1,135 @@
+//! A stream of what evaluation does, for a debugger or editor outside the
+//! interpreter to follow, e.g. to show the value of each definition inline.
+//! Expressions don't keep where they were read from, so events carry the
+//! span of the top-level expression being evaluated.
+
+use crate::{
+    prelude::*,
+    printer,
+    shared::{Mutable, Shared, Threadsafe},
+    Environment, SourceSpan, Symbol,
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum EventKind<E> {
+    /// A list is about to be evaluated as a call
+    EnterCall {
+        call: E,
+    },
+    Define {
+        name: Symbol,
+        value: E,
+    },
+    /// A macro expanded into code, which is evaluated next
+    MacroExpand {
+        name: Option<Symbol>,
+        expansion: E,
+    },
+    /// A call failed. Only the innermost call an error comes out of reports
+    /// it, not every call it passes through.
+    Error {
+        call: E,
+        message: String,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Event<E> {
+    pub kind: EventKind<E>,
+    /// Where the top-level expression being evaluated is, if known
+    pub location: Option<SourceSpan>,
+}
+
+#[cfg(not(feature = "sync"))]
+pub type EventSink<E> = dyn Fn(&Event<E>);
+#[cfg(feature = "sync")]
+pub type EventSink<E> = dyn Fn(&Event<E>) + Send + Sync;
+
+struct EventState<E> {
+    sinks: Vec<Shared<EventSink<E>>>,
+    /// Whether the error coming out of calls was reported, so that the calls
+    /// it passes through don't report it again
+    reported_error: bool,
+}
+
+/// Where events are sent, if anywhere. Clones share the subscribers, so
+/// evaluation inside closures is followed too.
+#[derive(Clone)]
+pub struct Events<E>(Shared<Mutable<EventState<E>>>);
+
+impl<E> Default for Events<E> {
+    fn default() -> Self {
+        Events(Shared::new(Mutable::new(EventState {
+            sinks: Vec::new(),
+            reported_error: false,
+        })))
+    }
+}
+
+impl<E> PartialEq for Events<E> {
+    fn eq(&self, other: &Self) -> bool {
+        Shared::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl<E: Clone> Events<E> {
+    /// Calls `sink` with every event from now on
+    pub fn subscribe(&self, sink: impl Fn(&Event<E>) + Threadsafe + 'static) {
+        self.0.borrow_mut().sinks.push(Shared::new(sink));
+    }
+
+    /// Sends every event from now on to the receiver returned, e.g. for a
+    /// debugger on another thread
+    #[cfg(feature = "std")]
+    pub fn channel(&self) -> std::sync::mpsc::Receiver<Event<E>>
+    where
+        E: Threadsafe + 'static,
+    {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        self.subscribe(move |event: &Event<E>| {
+            // Events sent after the receiver is dropped go nowhere
+            let _ = sender.send(event.clone());
+        });
+        receiver
+    }
+
+    pub fn unsubscribe_all(&self) {
+        self.0.borrow_mut().sinks.clear();
+    }
+
+    pub fn is_active(&self) -> bool {
+        !self.0.borrow().sinks.is_empty()
+    }
+
+    pub(crate) fn emit(&self, kind: EventKind<E>, env: &Environment<E>) {
+        // Subscribers may subscribe others, so they run without the state
+        // borrowed
+        let sinks = self.0.borrow().sinks.clone();
+        let event = Event {
+            kind,
+            location: env.diagnostics.location(),
+        };
+        for sink in &sinks {
+            sink(&event);
+        }
+    }
+
+    pub(crate) fn enter_call(&self, call: &E, env: &Environment<E>) {
+        self.0.borrow_mut().reported_error = false;
+        self.emit(EventKind::EnterCall { call: call.clone() }, env);
+    }
+
+    pub(crate) fn error(&self, call: &E, error: &anyhow::Error, env: &Environment<E>) {
+        if core::mem::replace(&mut self.0.borrow_mut().reported_error, true) {
+            return;
+        }
+        let message = printer::strip_colours(&format!("{error:#}"));
+        self.emit(
+            EventKind::Error {
+                call: call.clone(),
+                message,
+            },
+            env,
+        );
+    }
+}
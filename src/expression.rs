@@ -1,14 +1,34 @@
 use anyhow::{anyhow, bail, Context, Result};
-use std::{fmt::Display, iter::Peekable};
+use core::{
+    any::{type_name, Any},
+    cmp::Ordering,
+    fmt::Display,
+    hash::Hasher,
+    iter::Peekable,
+};
 
-use crate::{atoms::*, errors::TypeError, token::Token, Environment};
+use crate::{
+    atoms::*,
+    collections::HashSet,
+    convert::FromLisp,
+    errors::{
+        IncompleteInput, RecursionLimit, TypeError, UnbalancedParens, UnboundVariable,
+        UnterminatedString,
+    },
+    prelude::*,
+    reader::{ReaderMacro, ReaderMacros},
+    shared::{Shared, Threadsafe},
+    token::{skip_comments, Token, TokenKind},
+    tracer, Environment,
+};
 
 pub trait ToAndFrom<T>: From<T> {
-    fn try_into_atom(&self) -> std::result::Result<&T, TypeError>;
+    fn try_into_atom(&self) -> core::result::Result<&T, TypeError>;
 }
 
 pub trait LispExpression:
     'static
+    + Threadsafe
     + Sized
     + Clone
     + PartialEq
@@ -19,101 +39,496 @@ pub trait LispExpression:
     + ToAndFrom<Macro<Self>>
     + ToAndFrom<BuiltinFunction<Self>>
     + ToAndFrom<BuiltinMacro<Self>>
+    + ToAndFrom<AsyncBuiltinFunction<Self>>
     + ToAndFrom<Number>
+    + ToAndFrom<Promise<Self>>
+    + ToAndFrom<Record<Self>>
+    + ToAndFrom<ErrorValue<Self>>
+    + ToAndFrom<Set<Self>>
+    + ToAndFrom<Cell<Self>>
+    + ToAndFrom<Char>
+    + ToAndFrom<Generic<Self>>
+    + ToAndFrom<Foreign>
+    + ToAndFrom<Keyword>
+    + ToAndFrom<LazySeq<Self>>
+    + ToAndFrom<Str>
+    + ToAndFrom<Vector<Self>>
 {
     fn as_atom(&self) -> &dyn Atom<Self>;
 
+    /// Names of every kind of atom this expression type is composed of
+    fn atom_names() -> Vec<&'static str>;
+
     fn null() -> Self {
-        List(vec![]).into()
+        List::new(vec![]).into()
     }
 
-    fn as_list(&self) -> std::result::Result<&List<Self>, TypeError> {
+    fn as_list(&self) -> core::result::Result<&List<Self>, TypeError> {
         self.try_into_atom()
     }
 
-    fn as_symbol(&self) -> std::result::Result<&Symbol, TypeError> {
+    fn as_symbol(&self) -> core::result::Result<&Symbol, TypeError> {
         self.try_into_atom()
     }
 
+    /// The embedder's value in a foreign atom, if it is a `T`
+    fn downcast_foreign<T: Any>(&self) -> Result<&T> {
+        let foreign: &Foreign = self.try_into_atom()?;
+        foreign.downcast_ref().ok_or_else(|| {
+            anyhow!(
+                "Expected a foreign {} and got a {}",
+                type_name::<T>(),
+                foreign.type_name
+            )
+        })
+    }
+
+    /// Reads a Rust value out of the expression, e.g. `expr.extract::<f64>()`
+    fn extract<T: FromLisp<Self>>(&self) -> Result<T> {
+        T::from_lisp(self)
+    }
+
     fn is_truthy(&self) -> bool {
         self.as_list().map(|l| !l.0.is_empty()).unwrap_or(true)
     }
 
     fn parse_from_token(token: &Token) -> Self;
 
+    /// Takes the elements out of a list which nothing else shares, leaving
+    /// it empty, so that `drop_nested` can drop them without recursing
+    fn take_unshared_elements(&mut self) -> Option<Vec<Self>>;
+
     fn variant(&self) -> &'static str {
         self.as_atom().name()
     }
 
+    /// Orders two numbers, strings, chars, symbols or keywords of the same
+    /// kind, or lists of them, failing on anything else
+    fn compare(&self, other: &Self) -> Result<Ordering> {
+        self.as_atom()
+            .compare(other)
+            .ok_or_else(|| anyhow!("Cannot compare {} with {}", self, other))
+    }
+
+    /// A hash consistent with `==`, failing for values such as functions and
+    /// boxes which cannot be set elements
+    fn hash_value(&self) -> Result<u64> {
+        #[cfg(feature = "std")]
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        // Also SipHash with fixed keys, deprecated but still in core
+        #[cfg(not(feature = "std"))]
+        #[allow(deprecated)]
+        let mut hasher = core::hash::SipHasher::new();
+        self.as_atom().hash_into(&mut hasher)?;
+        Ok(hasher.finish())
+    }
+
+    /// Whether two values are the same atom, without looking inside them:
+    /// numbers, strings, chars, symbols, keywords and builtins by value,
+    /// lists, lambdas, macros, boxes, lazy sequences and generic functions
+    /// by reference, so that a value is identical to itself and its copies.
+    /// Records and sets are copied with their contents, so never identical,
+    /// while empty lists are all identical.
+    fn is_identical(&self, other: &Self) -> bool {
+        if self.variant() != other.variant() {
+            return false;
+        }
+        if let (Ok(a), Ok(b)) = (self.as_list(), other.as_list()) {
+            return Shared::ptr_eq(&a.0, &b.0) || (a.0.is_empty() && b.0.is_empty());
+        }
+        if let (Ok(a), Ok(b)) = (
+            ToAndFrom::<Lambda<Self>>::try_into_atom(self),
+            ToAndFrom::<Lambda<Self>>::try_into_atom(other),
+        ) {
+            return Shared::ptr_eq(&a.value, &b.value) && Shared::ptr_eq(&a.env, &b.env);
+        }
+        if let (Ok(a), Ok(b)) = (
+            ToAndFrom::<Macro<Self>>::try_into_atom(self),
+            ToAndFrom::<Macro<Self>>::try_into_atom(other),
+        ) {
+            return Shared::ptr_eq(&a.value, &b.value) && Shared::ptr_eq(&a.env, &b.env);
+        }
+        let copied = ToAndFrom::<Record<Self>>::try_into_atom(self).is_ok()
+            || ToAndFrom::<Set<Self>>::try_into_atom(self).is_ok();
+        !copied && self == other
+    }
+
+    /// Whether two values have the same structure, comparing lists and
+    /// records element by element and boxes by their contents
+    fn is_equal(&self, other: &Self) -> bool {
+        deep_equal(self, other)
+    }
+
     fn parse<I>(tokens: &mut Peekable<I>) -> Result<Self>
     where
         I: Iterator<Item = Token>,
     {
-        match tokens.next() {
-            Some(token) if token.value == "(" => {
-                let mut expressions = Vec::new();
-                while !matches!(tokens.peek(), Some(token) if token.value == ")") {
-                    expressions.push(Self::parse(tokens).with_context(|| {
-                        format!("While parsing list that began at {}", token.position)
-                    })?);
-                }
-                tokens.next();
-                let mut quoted_expressions = Vec::new();
-                let mut expressions = expressions.into_iter().peekable();
-                while let Some(expr) = expressions.next() {
-                    // TODO Look at this methodology
-                    if expr == Symbol("'".to_owned()).into() {
-                        if let Some(next) = expressions.next() {
-                            quoted_expressions.push(List(vec![expr, next]).into())
-                        } else {
-                            bail!("Trailing quote in input")
-                        }
-                    } else {
-                        quoted_expressions.push(expr);
-                    }
-                }
-                Ok(List(quoted_expressions).into())
-            }
-            Some(token) if token.value == ")" => {
-                bail!("Unexpected close bracket at {}", token.position)
-            }
-            Some(token) => Ok(Self::parse_from_token(&token)),
-            None => bail!("Ran out of tokens"),
-        }
+        Self::parse_with(tokens, &ReaderMacros::default())
+    }
+
+    /// Parses one expression, reading prefixes with the given reader macros
+    fn parse_with<I>(tokens: &mut Peekable<I>, reader_macros: &ReaderMacros<Self>) -> Result<Self>
+    where
+        I: Iterator<Item = Token>,
+    {
+        parse_nested(tokens, reader_macros, 0)
     }
 
     fn eval(&self, env: &mut Environment<Self>) -> Result<Self> {
+        env.consume_fuel()?;
         if let Ok(list) = self.as_list() {
-            let function: Self = list
-                .0
-                .get(0)
-                .ok_or_else(|| anyhow!("Attempt to evaluate empty list"))
-                .and_then(|e| e.eval(env))
-                .with_context(|| anyhow!("Could not evaluate head of list"))?;
-            function.as_atom().call(&list.0[1..], env)
+            let _depth = env.enter_eval()?;
+            if !env.events.is_active() {
+                return eval_call(self, list, env);
+            }
+            let events = env.events.clone();
+            events.enter_call(self, env);
+            let result = eval_call(self, list, env);
+            if let Err(error) = &result {
+                events.error(self, error, env);
+            }
+            result
         } else if let Ok(symbol) = self.as_symbol() {
-            env.get(symbol)
-                .cloned()
-                .ok_or_else(|| anyhow!("Variable `{}` unbound", symbol))
+            env.lookup(symbol)
+                .ok_or_else(|| UnboundVariable(symbol.clone()).into())
+        } else if let Ok(vector) = ToAndFrom::<Vector<Self>>::try_into_atom(self) {
+            // Evaluating a vector literal makes a new vector each time
+            let elements = vector.0.borrow().clone();
+            let values = elements
+                .iter()
+                .map(|e| e.eval(env))
+                .collect::<Result<Vec<_>>>()
+                .context("Could not evaluate vector elements")?;
+            Ok(Vector::new(values).into())
         } else {
             Ok(self.clone())
         }
     }
 }
 
+/// How deeply lists may nest in source code
+pub const MAX_PARSE_DEPTH: usize = 1000;
+
+/// Parses an expression inside `depth` lists or reader macros, failing rather
+/// than overflowing the stack on absurdly nested input
+fn parse_nested<E, I>(
+    tokens: &mut Peekable<I>,
+    reader_macros: &ReaderMacros<E>,
+    depth: usize,
+) -> Result<E>
+where
+    E: LispExpression,
+    I: Iterator<Item = Token>,
+{
+    skip_comments(tokens);
+    let Some(token) = tokens.next() else {
+        return Err(IncompleteInput.into());
+    };
+    match token.kind {
+        TokenKind::OpenParen => {
+            parse_elements(tokens, reader_macros, &token, TokenKind::CloseParen, depth)
+                .map(|elements| List::new(elements).into())
+        }
+        TokenKind::OpenBracket => parse_elements(
+            tokens,
+            reader_macros,
+            &token,
+            TokenKind::CloseBracket,
+            depth,
+        )
+        .map(|elements| Vector::new(elements).into()),
+        TokenKind::CloseParen | TokenKind::CloseBracket => Err(UnbalancedParens::UnexpectedClose {
+            position: token.position,
+            line: token.line,
+            column: token.column,
+            close: if token.kind == TokenKind::CloseParen {
+                ')'
+            } else {
+                ']'
+            },
+        }
+        .into()),
+        TokenKind::UnterminatedString => Err(UnterminatedString {
+            position: token.position,
+            line: token.line,
+            column: token.column,
+        }
+        .into()),
+        _ => parse_token(tokens, reader_macros, token, depth),
+    }
+}
+
+/// Parses the elements of a list or vector after the token which opened it,
+/// up to the `close` token
+fn parse_elements<E, I>(
+    tokens: &mut Peekable<I>,
+    reader_macros: &ReaderMacros<E>,
+    open: &Token,
+    close: TokenKind,
+    depth: usize,
+) -> Result<Vec<E>>
+where
+    E: LispExpression,
+    I: Iterator<Item = Token>,
+{
+    check_parse_depth(depth)?;
+    let mut expressions = Vec::new();
+    loop {
+        skip_comments(tokens);
+        if matches!(tokens.peek(), Some(token) if token.kind == close) {
+            break;
+        }
+        if tokens.peek().is_none() {
+            return Err(UnbalancedParens::Unclosed {
+                position: open.position,
+                line: open.line,
+                column: open.column,
+                close: if close == TokenKind::CloseParen {
+                    ')'
+                } else {
+                    ']'
+                },
+            }
+            .into());
+        }
+        expressions.push(
+            parse_nested(tokens, reader_macros, depth + 1)
+                .with_context(|| format!("While parsing list that began at {}", open.location()))?,
+        );
+    }
+    tokens.next();
+    Ok(expressions)
+}
+
+/// Parses a token other than a parenthesis, applying the reader macro its
+/// prefix is for if there is one
+fn parse_token<E, I>(
+    tokens: &mut Peekable<I>,
+    reader_macros: &ReaderMacros<E>,
+    token: Token,
+    depth: usize,
+) -> Result<E>
+where
+    E: LispExpression,
+    I: Iterator<Item = Token>,
+{
+    let found = match token.kind {
+        TokenKind::Atom | TokenKind::Quote => reader_macros.find(&token.value),
+        _ => None,
+    };
+    let Some((length, reader_macro)) = found else {
+        return Ok(E::parse_from_token(&token));
+    };
+    let (prefix, rest) = token.value.split_at(length);
+    match reader_macro {
+        ReaderMacro::Token(read) => Ok(read(rest).unwrap_or_else(|| E::parse_from_token(&token))),
+        ReaderMacro::Expression(transform) => {
+            check_parse_depth(depth)?;
+            let expression = if prefix.ends_with('(') {
+                let elements = parse_elements(
+                    tokens,
+                    reader_macros,
+                    &token,
+                    TokenKind::CloseParen,
+                    depth + 1,
+                )?;
+                List::new(elements).into()
+            } else if rest.is_empty() {
+                skip_comments(tokens);
+                // Running out of input here is left to `parse_nested` to report
+                if matches!(
+                    tokens.peek().map(|next| next.kind),
+                    Some(TokenKind::CloseParen | TokenKind::CloseBracket)
+                ) {
+                    bail!("Trailing {} in input", prefix)
+                }
+                parse_nested(tokens, reader_macros, depth + 1)?
+            } else {
+                let rest = Token {
+                    kind: TokenKind::Atom,
+                    value: rest.to_owned(),
+                    position: token.position + prefix.chars().count(),
+                    line: token.line,
+                    column: token.column + prefix.chars().count(),
+                    end_line: token.end_line,
+                    end_column: token.end_column,
+                };
+                parse_token(tokens, reader_macros, rest, depth + 1)?
+            };
+            transform(expression)
+                .with_context(|| format!("Could not read {} at {}", prefix, token.location()))
+        }
+    }
+}
+
+pub(crate) fn check_parse_depth(depth: usize) -> Result<()> {
+    if depth >= MAX_PARSE_DEPTH {
+        return Err(RecursionLimit::Parsing {
+            limit: MAX_PARSE_DEPTH,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Fails for values written inside `depth` lists which would be nested too
+/// deeply to be parsed back
+pub(crate) fn check_write_depth(depth: usize) -> Result<()> {
+    if depth >= MAX_PARSE_DEPTH {
+        return Err(RecursionLimit::Writing {
+            limit: MAX_PARSE_DEPTH,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Evaluates a list as a call, stopping for the debugger and counting it for
+/// the profiler
+fn eval_call<E: LispExpression>(call: &E, list: &List<E>, env: &mut Environment<E>) -> Result<E> {
+    let paused = env.debugger.is_active() && env.debugger.pause(call, env)?;
+    let function: E = list
+        .0
+        .get(0)
+        .ok_or_else(|| anyhow!("Attempt to evaluate empty list"))
+        .and_then(|e| e.eval(env))
+        .with_context(|| anyhow!("Could not evaluate head of list"))?;
+    let _profiled = env
+        .profiler
+        .is_enabled()
+        .then(|| env.profiler.enter(profile_name(&list.0[0], &function)));
+    let result = tracer::call(&list.0[0], &function, &list.0[1..], env);
+    if paused {
+        env.debugger.finished(&result, env)?;
+    }
+    result
+}
+
+/// What the profiler calls a function: the symbol it was called through, or
+/// else its builtin name or kind
+fn profile_name<E: LispExpression>(head: &E, function: &E) -> String {
+    if let Ok(symbol) = head.as_symbol() {
+        symbol.0.clone()
+    } else if let Ok(builtin) = ToAndFrom::<BuiltinFunction<E>>::try_into_atom(function) {
+        builtin.name.to_owned()
+    } else if let Ok(builtin) = ToAndFrom::<BuiltinMacro<E>>::try_into_atom(function) {
+        builtin.name.to_owned()
+    } else {
+        function.variant().to_owned()
+    }
+}
+
+/// Drops the lists nested in an expression one at a time rather than each
+/// dropping its elements in turn, so that dropping a list nested thousands
+/// deep cannot overflow the stack. Expression types call this from `Drop`.
+pub fn drop_nested<E: LispExpression>(expression: &mut E) {
+    let Some(mut elements) = expression.take_unshared_elements() else {
+        return;
+    };
+    while let Some(mut element) = elements.pop() {
+        if let Some(nested) = element.take_unshared_elements() {
+            elements.extend(nested);
+        }
+    }
+}
+
+/// The addresses of two boxes or vectors being compared
+type BoxPair = (*const (), *const ());
+
+/// Compares with a stack of pairs still to compare rather than by recursing,
+/// so that deeply nested lists cannot overflow the stack. Boxes and vectors
+/// already compared are assumed equal, as a difference inside them would
+/// already have been found, so ones which contain themselves stop.
+fn deep_equal<E: LispExpression>(a: &E, b: &E) -> bool {
+    let mut pending = vec![(a.clone(), b.clone())];
+    let mut compared: HashSet<BoxPair> = HashSet::new();
+    while let Some((a, b)) = pending.pop() {
+        if let (Ok(a), Ok(b)) = (a.as_list(), b.as_list()) {
+            if a.0.len() != b.0.len() {
+                return false;
+            }
+            pending.extend(a.0.iter().cloned().zip(b.0.iter().cloned()));
+        } else if let (Ok(a), Ok(b)) = (
+            ToAndFrom::<Record<E>>::try_into_atom(&a),
+            ToAndFrom::<Record<E>>::try_into_atom(&b),
+        ) {
+            if !Shared::ptr_eq(&a.kind, &b.kind) {
+                return false;
+            }
+            pending.extend(a.values.iter().cloned().zip(b.values.iter().cloned()));
+        } else if let (Ok(a), Ok(b)) = (
+            ToAndFrom::<Cell<E>>::try_into_atom(&a),
+            ToAndFrom::<Cell<E>>::try_into_atom(&b),
+        ) {
+            let pair = (
+                Shared::as_ptr(&a.0) as *const (),
+                Shared::as_ptr(&b.0) as *const (),
+            );
+            if !Shared::ptr_eq(&a.0, &b.0) && compared.insert(pair) {
+                pending.push((a.0.borrow().clone(), b.0.borrow().clone()));
+            }
+        } else if let (Ok(a), Ok(b)) = (
+            ToAndFrom::<Vector<E>>::try_into_atom(&a),
+            ToAndFrom::<Vector<E>>::try_into_atom(&b),
+        ) {
+            let pair = (
+                Shared::as_ptr(&a.0) as *const (),
+                Shared::as_ptr(&b.0) as *const (),
+            );
+            if !Shared::ptr_eq(&a.0, &b.0) && compared.insert(pair) {
+                let (a, b) = (a.0.borrow(), b.0.borrow());
+                if a.len() != b.len() {
+                    return false;
+                }
+                pending.extend(a.iter().cloned().zip(b.iter().cloned()));
+            }
+        } else if a != b {
+            return false;
+        }
+    }
+    true
+}
+
 #[macro_export]
 macro_rules! create_expression {
     ($expression_name:ident, $($atom:tt$(<$g:tt>)?,)+) => {
-        #[derive(Clone, Debug, PartialEq)]
+        #[derive(Clone, Debug)]
         pub enum $expression_name {
             $(
             $atom($atom$(<$g>)?)
             ),+
         }
 
+        impl PartialEq for $expression_name {
+            fn eq(&self, other: &Self) -> bool {
+                // Shared structure is equal without having to walk it
+                core::ptr::eq(self, other)
+                    || match (self, other) {
+                        $(
+                        ($expression_name::$atom(a), $expression_name::$atom(b)) => a == b,
+                        )*
+                        #[allow(unreachable_patterns)]
+                        _ => false,
+                    }
+            }
+        }
+
+        /// Expressions are passed through, so arguments of several types can
+        /// be given to `call_function` as expressions
+        impl IntoLisp<$expression_name> for $expression_name {
+            fn into_lisp(self) -> Self {
+                self
+            }
+        }
 
-        impl std::fmt::Display for $expression_name {
-            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        impl Drop for $expression_name {
+            fn drop(&mut self) {
+                drop_nested(self)
+            }
+        }
+
+        impl core::fmt::Display for $expression_name {
+            fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
                 self.as_atom().fmt(f)
             }
         }
@@ -127,6 +542,14 @@ macro_rules! create_expression {
                 }
             }
 
+            fn atom_names() -> Vec<&'static str> {
+                vec![
+                    $(
+                    <$atom$(<$g>)? as Atom<Self>>::sized_name(),
+                    )*
+                ]
+            }
+
             fn parse_from_token(token: &Token) -> Self {
                 None
                     $(
@@ -135,6 +558,15 @@ macro_rules! create_expression {
                     // This will never fail as symbols never fail parsing
                     .unwrap()
             }
+
+            fn take_unshared_elements(&mut self) -> Option<Vec<Self>> {
+                match self {
+                    $expression_name::List(list) => {
+                        $crate::shared::Shared::get_mut(&mut list.0).map(core::mem::take)
+                    }
+                    _ => None,
+                }
+            }
         }
 
         $(
@@ -145,7 +577,7 @@ macro_rules! create_expression {
         }
 
         impl ToAndFrom<$atom$(<$g>)?> for $expression_name {
-            fn try_into_atom(&self) -> std::result::Result<&$atom$(<$g>)?, TypeError> {
+            fn try_into_atom(&self) -> core::result::Result<&$atom$(<$g>)?, TypeError> {
                 match self {
                     $expression_name::$atom(inner) => Ok(inner),
                     _ => Err(TypeError {
@@ -0,0 +1,221 @@
+//! A C API for embedding shallot in programs written in other languages, be
+//! it C or Python and Ruby through their foreign function interfaces. Build
+//! it with the `ffi` feature as a C library, e.g. with
+//! `cargo rustc --lib --release --features ffi --crate-type cdylib`, and
+//! declare the functions with `include/shallot.h`.
+//!
+//! Values never cross into the host. Evaluating gives a handle, a number
+//! naming a value the interpreter holds on to until it is released, which
+//! the host reads the value through. Handles are never reused, so a stale
+//! one is an error rather than another value. Interpreters evaluate with
+//! `DynExpression`, as a host has no layers to compile.
+
+use std::{
+    ffi::{c_char, CStr, CString},
+    panic::{catch_unwind, AssertUnwindSafe},
+    ptr,
+};
+
+use anyhow::{anyhow, ensure, Context, Result};
+
+use crate::{
+    builtins, collections::HashMap, evaluate_program, printer, DynExpression, Environment, Number,
+    ToAndFrom,
+};
+
+/// Names a value held by an interpreter. Zero names no value, and is what
+/// failing functions return.
+pub type ShallotHandle = u64;
+
+/// An interpreter and the values it has handed out handles to
+pub struct ShallotInterpreter {
+    environment: Environment<DynExpression>,
+    values: HashMap<ShallotHandle, DynExpression>,
+    last_handle: ShallotHandle,
+    last_error: Option<CString>,
+}
+
+impl ShallotInterpreter {
+    fn hold(&mut self, value: DynExpression) -> ShallotHandle {
+        self.last_handle += 1;
+        self.values.insert(self.last_handle, value);
+        self.last_handle
+    }
+
+    fn value(&self, handle: ShallotHandle) -> Result<&DynExpression> {
+        self.values
+            .get(&handle)
+            .ok_or_else(|| anyhow!("No value has the handle {handle}"))
+    }
+
+    /// Records `error` for `shallot_last_error`, without terminal colours
+    fn fail(&mut self, error: anyhow::Error) {
+        let message = printer::strip_colours(&format!("{error:#}")).replace('\0', "\\0");
+        self.last_error = CString::new(message).ok();
+    }
+
+    /// Runs `f`, recording its error or a panic, as unwinding into the host
+    /// would abort it. Success clears the last error, so that it is always
+    /// that of the last call.
+    fn guard<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T>) -> Option<T> {
+        let result = catch_unwind(AssertUnwindSafe(|| f(&mut *self)))
+            .unwrap_or_else(|_| Err(anyhow!("The interpreter panicked")));
+        match result {
+            Ok(value) => {
+                self.last_error = None;
+                Some(value)
+            }
+            Err(error) => {
+                self.fail(error);
+                None
+            }
+        }
+    }
+}
+
+/// A string from the host, which has to be UTF-8
+///
+/// # Safety
+///
+/// `text` must be null or point to a nul terminated string
+unsafe fn host_str<'a>(text: *const c_char) -> Result<&'a str> {
+    ensure!(!text.is_null(), "Expected a string and got a null pointer");
+    CStr::from_ptr(text)
+        .to_str()
+        .context("Strings passed to shallot must be UTF-8")
+}
+
+/// An interpreter with the core builtins, to be freed with `shallot_free`
+#[no_mangle]
+pub extern "C" fn shallot_new() -> *mut ShallotInterpreter {
+    let mut environment = Environment::default();
+    builtins::set_environment(&mut environment);
+    Box::into_raw(Box::new(ShallotInterpreter {
+        environment,
+        values: HashMap::new(),
+        last_handle: 0,
+        last_error: None,
+    }))
+}
+
+/// Frees an interpreter along with every value it holds
+///
+/// # Safety
+///
+/// `interpreter` must be null or come from `shallot_new`, and is invalid
+/// afterwards
+#[no_mangle]
+pub unsafe extern "C" fn shallot_free(interpreter: *mut ShallotInterpreter) {
+    if !interpreter.is_null() {
+        drop(Box::from_raw(interpreter));
+    }
+}
+
+/// Evaluates the expressions in `input`, returning a handle to the value of
+/// the last, or zero if one failed
+///
+/// # Safety
+///
+/// `interpreter` must come from `shallot_new`, and `input` must be a nul
+/// terminated string
+#[no_mangle]
+pub unsafe extern "C" fn shallot_eval(
+    interpreter: *mut ShallotInterpreter,
+    input: *const c_char,
+) -> ShallotHandle {
+    let interpreter = &mut *interpreter;
+    interpreter
+        .guard(|interpreter| {
+            let value = evaluate_program(host_str(input)?, &mut interpreter.environment)?;
+            Ok(interpreter.hold(value))
+        })
+        .unwrap_or(0)
+}
+
+/// The text of a value, a string as itself and anything else as it is
+/// written, or null if there is no such value. It is the host's to free with
+/// `shallot_free_string`.
+///
+/// # Safety
+///
+/// `interpreter` must come from `shallot_new`
+#[no_mangle]
+pub unsafe extern "C" fn shallot_get_string(
+    interpreter: *mut ShallotInterpreter,
+    handle: ShallotHandle,
+) -> *mut c_char {
+    let interpreter = &mut *interpreter;
+    interpreter
+        .guard(|interpreter| {
+            let text = printer::plain_text(interpreter.value(handle)?);
+            CString::new(text).context("Strings containing a nul byte can't be passed to C")
+        })
+        .map_or(ptr::null_mut(), CString::into_raw)
+}
+
+/// Stores a number value in `number`, returning whether the value is one
+///
+/// # Safety
+///
+/// `interpreter` must come from `shallot_new`, and `number` must be valid to
+/// write to
+#[no_mangle]
+pub unsafe extern "C" fn shallot_get_number(
+    interpreter: *mut ShallotInterpreter,
+    handle: ShallotHandle,
+    number: *mut f64,
+) -> bool {
+    let interpreter = &mut *interpreter;
+    let value = interpreter.guard(|interpreter| {
+        let value: &Number = ToAndFrom::<Number>::try_into_atom(interpreter.value(handle)?)?;
+        Ok(value.0)
+    });
+    match value {
+        Some(value) => {
+            *number = value;
+            true
+        }
+        None => false,
+    }
+}
+
+/// Lets go of a value, after which its handle names nothing
+///
+/// # Safety
+///
+/// `interpreter` must come from `shallot_new`
+#[no_mangle]
+pub unsafe extern "C" fn shallot_release(
+    interpreter: *mut ShallotInterpreter,
+    handle: ShallotHandle,
+) {
+    (*interpreter).values.remove(&handle);
+}
+
+/// The message of the error from the last call to evaluate or read a value,
+/// or null if it succeeded. It stays valid until the next such call or the
+/// interpreter is freed.
+///
+/// # Safety
+///
+/// `interpreter` must come from `shallot_new`
+#[no_mangle]
+pub unsafe extern "C" fn shallot_last_error(interpreter: *mut ShallotInterpreter) -> *const c_char {
+    (*interpreter)
+        .last_error
+        .as_ref()
+        .map_or(ptr::null(), |error| error.as_ptr())
+}
+
+/// Frees a string returned by `shallot_get_string`
+///
+/// # Safety
+///
+/// `text` must be null or come from `shallot_get_string`, and is invalid
+/// afterwards
+#[no_mangle]
+pub unsafe extern "C" fn shallot_free_string(text: *mut c_char) {
+    if !text.is_null() {
+        drop(CString::from_raw(text));
+    }
+}
@@ -0,0 +1,45 @@
+//! The floating point functions which std provides as methods of `f64`, or
+//! `libm` does without it
+
+macro_rules! float_functions {
+    ($($name:ident => $std:ident / $libm:ident),* $(,)?) => {
+        $(
+        #[cfg(feature = "std")]
+        pub(crate) fn $name(x: f64) -> f64 {
+            x.$std()
+        }
+
+        #[cfg(not(feature = "std"))]
+        pub(crate) fn $name(x: f64) -> f64 {
+            libm::$libm(x)
+        }
+        )*
+    };
+}
+
+float_functions!(
+    floor => floor / floor,
+    ceil => ceil / ceil,
+    trunc => trunc / trunc,
+    sin => sin / sin,
+    cos => cos / cos,
+    tan => tan / tan,
+    ln => ln / log,
+    log10 => log10 / log10,
+    exp => exp / exp,
+);
+
+/// The fractional part, with the sign of `x`
+pub(crate) fn fract(x: f64) -> f64 {
+    x - trunc(x)
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn atan2(y: f64, x: f64) -> f64 {
+    y.atan2(x)
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) fn atan2(y: f64, x: f64) -> f64 {
+    libm::atan2(y, x)
+}
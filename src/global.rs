@@ -0,0 +1,54 @@
+//! State shared by the whole program or a thread. With std this is a
+//! `Mutex` or `thread_local!`, and without it a spin lock, as there may be
+//! no threads or operating system to block on.
+
+use core::ops::DerefMut;
+
+/// A lock for statics, which ignores poisoning as the state it guards stays
+/// usable after a panic
+pub(crate) struct Lock<T>(
+    #[cfg(feature = "std")] std::sync::Mutex<T>,
+    #[cfg(not(feature = "std"))] spin::Mutex<T>,
+);
+
+impl<T> Lock<T> {
+    pub(crate) const fn new(value: T) -> Self {
+        #[cfg(feature = "std")]
+        return Lock(std::sync::Mutex::new(value));
+        #[cfg(not(feature = "std"))]
+        return Lock(spin::Mutex::new(value));
+    }
+
+    pub(crate) fn lock(&self) -> impl DerefMut<Target = T> + '_ {
+        #[cfg(feature = "std")]
+        return self
+            .0
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        #[cfg(not(feature = "std"))]
+        return self.0.lock();
+    }
+
+    /// Calls `f` with the locked value, as `thread_local!` statics are used
+    #[cfg(not(feature = "std"))]
+    pub(crate) fn with<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        f(&self.lock())
+    }
+}
+
+/// A `thread_local!` static with std. Without it there is one for the whole
+/// program, which must be `Send` as it is behind a `Lock`.
+macro_rules! thread_state {
+    ($(#[$attribute:meta])* static $name:ident: $type:ty = $value:expr;) => {
+        #[cfg(feature = "std")]
+        std::thread_local! {
+            $(#[$attribute])*
+            static $name: $type = const { $value };
+        }
+        #[cfg(not(feature = "std"))]
+        $(#[$attribute])*
+        static $name: $crate::global::Lock<$type> = $crate::global::Lock::new($value);
+    };
+}
+
+pub(crate) use thread_state;
@@ -0,0 +1,93 @@
+//! Closures an embedder runs around every call, e.g. to log calls, record
+//! coverage or refuse those a security policy forbids, without changing
+//! `LispExpression::eval`. They run where a function is applied, so calls
+//! made by `apply`, `map` and the like, or through an alias, are seen too.
+
+use anyhow::Result;
+
+use crate::{
+    prelude::*,
+    shared::{Mutable, Shared},
+    Environment,
+};
+
+/// Runs before a call, given the call and the function it calls. Failing
+/// stops the call with that error.
+#[cfg(not(feature = "sync"))]
+pub type BeforeEval<E> = dyn Fn(&E, &E, &Environment<E>) -> Result<()>;
+#[cfg(feature = "sync")]
+pub type BeforeEval<E> = dyn Fn(&E, &E, &Environment<E>) -> Result<()> + Send + Sync;
+
+/// Runs after a call, given the call, the function it called and its value
+/// or error
+#[cfg(not(feature = "sync"))]
+pub type AfterEval<E> = dyn Fn(&E, &E, &Result<E>, &Environment<E>);
+#[cfg(feature = "sync")]
+pub type AfterEval<E> = dyn Fn(&E, &E, &Result<E>, &Environment<E>) + Send + Sync;
+
+struct HookState<E> {
+    before: Vec<Shared<BeforeEval<E>>>,
+    after: Vec<Shared<AfterEval<E>>>,
+}
+
+/// The hooks run around calls, in the order they were added. Clones share
+/// them, so calls inside closures run them too.
+#[derive(Clone)]
+pub struct EvalHooks<E>(Shared<Mutable<HookState<E>>>);
+
+impl<E> Default for EvalHooks<E> {
+    fn default() -> Self {
+        EvalHooks(Shared::new(Mutable::new(HookState {
+            before: Vec::new(),
+            after: Vec::new(),
+        })))
+    }
+}
+
+impl<E> PartialEq for EvalHooks<E> {
+    fn eq(&self, other: &Self) -> bool {
+        Shared::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl<E> EvalHooks<E> {
+    pub fn add_before(
+        &self,
+        hook: impl Fn(&E, &E, &Environment<E>) -> Result<()> + crate::shared::Threadsafe + 'static,
+    ) {
+        self.0.borrow_mut().before.push(Shared::new(hook));
+    }
+
+    pub fn add_after(
+        &self,
+        hook: impl Fn(&E, &E, &Result<E>, &Environment<E>) + crate::shared::Threadsafe + 'static,
+    ) {
+        self.0.borrow_mut().after.push(Shared::new(hook));
+    }
+
+    pub fn clear(&self) {
+        let mut state = self.0.borrow_mut();
+        state.before.clear();
+        state.after.clear();
+    }
+
+    pub fn is_active(&self) -> bool {
+        let state = self.0.borrow();
+        !state.before.is_empty() || !state.after.is_empty()
+    }
+
+    /// Runs the hooks before `call` of `function`, stopping at the first to
+    /// fail
+    pub(crate) fn before(&self, call: &E, function: &E, env: &Environment<E>) -> Result<()> {
+        // Hooks may add hooks, so they run without the state borrowed
+        let hooks = self.0.borrow().before.clone();
+        hooks.iter().try_for_each(|hook| hook(call, function, env))
+    }
+
+    pub(crate) fn after(&self, call: &E, function: &E, result: &Result<E>, env: &Environment<E>) {
+        let hooks = self.0.borrow().after.clone();
+        for hook in &hooks {
+            hook(call, function, result, env);
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use alloc::collections::VecDeque;
+use anyhow::Result;
+#[cfg(feature = "std")]
+use std::io::Write;
+
+use crate::{
+    prelude::*,
+    shared::{Mutable, Shared, Threadsafe},
+};
+
+/// Everything the interpreter reads or writes goes through one of these, so
+/// that embedders can redirect or capture it
+pub trait IoHandler: Threadsafe {
+    fn write_stdout(&mut self, text: &str) -> Result<()>;
+
+    fn write_stderr(&mut self, text: &str) -> Result<()>;
+
+    /// Next line of input including its newline, or `None` at the end of input
+    fn read_line(&mut self) -> Result<Option<String>>;
+}
+
+/// The process's real standard streams
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StdIo;
+
+#[cfg(feature = "std")]
+impl IoHandler for StdIo {
+    fn write_stdout(&mut self, text: &str) -> Result<()> {
+        let mut stdout = std::io::stdout();
+        stdout.write_all(text.as_bytes())?;
+        Ok(stdout.flush()?)
+    }
+
+    fn write_stderr(&mut self, text: &str) -> Result<()> {
+        Ok(std::io::stderr().write_all(text.as_bytes())?)
+    }
+
+    fn read_line(&mut self) -> Result<Option<String>> {
+        let mut line = String::new();
+        if std::io::stdin().read_line(&mut line)? == 0 {
+            Ok(None)
+        } else {
+            Ok(Some(line))
+        }
+    }
+}
+
+/// Streams which discard output and have no input, the default without std
+/// as there are no standard streams to use
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NullIo;
+
+impl IoHandler for NullIo {
+    fn write_stdout(&mut self, _text: &str) -> Result<()> {
+        Ok(())
+    }
+
+    fn write_stderr(&mut self, _text: &str) -> Result<()> {
+        Ok(())
+    }
+
+    fn read_line(&mut self) -> Result<Option<String>> {
+        Ok(None)
+    }
+}
+
+/// In-memory streams for tests and embedding: input is served from `stdin`
+/// and output accumulates in `stdout` and `stderr`
+#[derive(Clone, Debug, Default)]
+pub struct CapturedIo {
+    pub stdin: VecDeque<String>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl CapturedIo {
+    pub fn with_input(input: &str) -> Self {
+        Self {
+            stdin: input.split_inclusive('\n').map(str::to_owned).collect(),
+            ..Default::default()
+        }
+    }
+}
+
+impl IoHandler for CapturedIo {
+    fn write_stdout(&mut self, text: &str) -> Result<()> {
+        self.stdout.push_str(text);
+        Ok(())
+    }
+
+    fn write_stderr(&mut self, text: &str) -> Result<()> {
+        self.stderr.push_str(text);
+        Ok(())
+    }
+
+    fn read_line(&mut self) -> Result<Option<String>> {
+        Ok(self.stdin.pop_front())
+    }
+}
+
+/// The I/O handler of an environment, shared with every closure captured from
+/// it. Keep a clone of the pointer given to `From` to inspect a handler later.
+#[derive(Clone)]
+pub struct Io(pub Shared<Mutable<dyn IoHandler>>);
+
+impl Io {
+    pub fn new(handler: impl IoHandler + 'static) -> Self {
+        Self(Shared::new(Mutable::new(handler)))
+    }
+
+    pub fn write_stdout(&self, text: &str) -> Result<()> {
+        self.0.borrow_mut().write_stdout(text)
+    }
+
+    pub fn write_stderr(&self, text: &str) -> Result<()> {
+        self.0.borrow_mut().write_stderr(text)
+    }
+
+    pub fn read_line(&self) -> Result<Option<String>> {
+        self.0.borrow_mut().read_line()
+    }
+}
+
+impl Default for Io {
+    #[cfg(feature = "std")]
+    fn default() -> Self {
+        Self::new(StdIo)
+    }
+
+    #[cfg(not(feature = "std"))]
+    fn default() -> Self {
+        Self::new(NullIo)
+    }
+}
+
+impl<T: IoHandler + 'static> From<Shared<Mutable<T>>> for Io {
+    fn from(handler: Shared<Mutable<T>>) -> Self {
+        Self(handler)
+    }
+}
+
+impl PartialEq for Io {
+    fn eq(&self, other: &Self) -> bool {
+        Shared::ptr_eq(&self.0, &other.0)
+    }
+}
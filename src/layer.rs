@@ -1,36 +1,80 @@
+use crate::prelude::*;
+
+/// What a layer made by `create_layer!` adds to the interpreter, as listed
+/// by its generated `layers()` and the `layers` builtin
+#[derive(Clone, Debug, PartialEq)]
+pub struct LayerInfo {
+    pub name: &'static str,
+    pub version: &'static str,
+    /// Names of the atoms it adds, e.g. `"string"`
+    pub atoms: Vec<&'static str>,
+    pub builtins: Vec<String>,
+}
+
+/// Makes a layer in the calling module: an `Expression` type with the atoms
+/// of the layer and those below it, a `set_environment` binding their
+/// builtins, its own `layer_info()` and `layers()` for it and those below.
+/// It may start with `layer "name" version "1.0" |`, and is otherwise named
+/// after its module with its crate's version.
+///
+/// Before its builtins a layer can add reader macros and REPL commands, as in
+/// `| reader "#{" -> Expression(read_map) | commands "inspect" -> inspect`.
 #[macro_export]
 macro_rules! create_layer {
+    (over $($rest:tt)*) => {
+        create_layer!(layer (module_path!()) | over $($rest)*);
+    };
+    (atoms $($rest:tt)*) => {
+        create_layer!(layer (module_path!()) | atoms $($rest)*);
+    };
+    // The layer defines a macro of its own, whose `$`s are passed in as `$d`
+    (layer $($rest:tt)*) => {
+        create_layer!(@with_dollar ($) layer $($rest)*);
+    };
+
     (
-     over $first_sublayer:ident $($other_sublayer:ident)*
+     @with_dollar ($d:tt)
+     layer $layer_name:tt $(version $layer_version:tt)?
+     | over $first_sublayer:ident $($other_sublayer:ident)*
      | atoms $($atom:ident$(<$generic:tt>)?),*
+     $(| reader $($prefix:literal -> $kind:ident($reader:path)),*)?
+     $(| commands $($command:literal -> $handler:path),*)?
      | builtins $($name:literal -> $value:expr),*
     ) => {
+        create_layer!(
+            @info $layer_name $(version $layer_version)?
+            | $($atom$(<$generic>)?),*
+            | $($($prefix -> $kind($reader)),*)?
+            | $($($command -> $handler),*)?
+            | $($name),*
+        );
+
         #[macro_export]
         macro_rules! add_my_atoms {
            (
-             $$top_layer:ident $$($$layer:ident)* ;  // Layers to go
-             $$($$unfolded_layer:ident)* ;           // Layers already done
-             $$($$atom:ident$$(<$$generic:tt>)?,)* ; // Atoms in the done layers
-             $$($$name:literal -> $$value:expr)*     // Builtins of the top layer
+             $d top_layer:ident $d($d layer:ident)* ;  // Layers to go
+             $d($d unfolded_layer:ident)* ;           // Layers already done
+             $d($d atom:ident$d(<$d generic:tt>)?,)* ; // Atoms in the done layers
+             $d($d name:literal -> $d value:expr)*     // Builtins of the top layer
            ) => {
-             $$top_layer::add_my_atoms!(
-               $$($$layer)* $first_sublayer $($other_sublayer)*;
-               $$top_layer $$($$unfolded_layer)* ;
-               $($atom$(<$generic>)?,)* $$($$atom$$(<$$generic>)?,)* ;
-               $$($$name -> $$value)* // Builtins of the top layer
+             $d top_layer::add_my_atoms!(
+               $d($d layer)* $first_sublayer $($other_sublayer)*;
+               $d top_layer $d($d unfolded_layer)* ;
+               $($atom$(<$generic>)?,)* $d($d atom$d(<$d generic>)?,)* ;
+               $d($d name -> $d value)* // Builtins of the top layer
              );
            };
            (
              ;  // Layers to go
-             $$($$unfolded_layer:ident)* ;           // Layers already done
-             $$($$atom:ident$$(<$$generic:tt>)?,)* ; // Atoms in the done layers
-             $$($$name:literal -> $$value:expr)*     // Builtins of the top layer
+             $d($d unfolded_layer:ident)* ;           // Layers already done
+             $d($d atom:ident$d(<$d generic:tt>)?,)* ; // Atoms in the done layers
+             $d($d name:literal -> $d value:expr)*     // Builtins of the top layer
            ) => {
              $first_sublayer::add_my_atoms!(
                $($other_sublayer)*;
-               $first_sublayer $$($$unfolded_layer)* ;
-               $($atom$(<$generic>)?,)* $$($$atom$$(<$$generic>)?,)* ;
-               $$($$name -> $$value)* // Builtins of the top layer
+               $first_sublayer $d($d unfolded_layer)* ;
+               $($atom$(<$generic>)?,)* $d($d atom$d(<$d generic>)?,)* ;
+               $d($d name -> $d value)* // Builtins of the top layer
              );
            }
         }
@@ -44,28 +88,53 @@ macro_rules! create_layer {
     };
 
     (
-     atoms $($atom:ident$(<$generic:tt>)?),*
+     @with_dollar ($d:tt)
+     layer $layer_name:tt $(version $layer_version:tt)?
+     | atoms $($atom:ident$(<$generic:tt>)?),*
+     $(| reader $($prefix:literal -> $kind:ident($reader:path)),*)?
+     $(| commands $($command:literal -> $handler:path),*)?
      | builtins $($name:literal -> $value:expr),*
     ) => {
+        create_layer!(
+            @info $layer_name $(version $layer_version)?
+            | $($atom$(<$generic>)?),*
+            | $($($prefix -> $kind($reader)),*)?
+            | $($($command -> $handler),*)?
+            | $($name),*
+        );
+
         #[macro_export]
         macro_rules! add_my_atoms {
            (
             ; // Layers to go
-            $$($$unfolded_layer:ident)* ; // Layers already done
-            $$($$atom:ident$$(<$$generic:tt>)?,)* ; // Atoms in the done layers
-            $$($$name:literal -> $$value:expr)* // Builtins of the top layer
+            $d($d unfolded_layer:ident)* ; // Layers already done
+            $d($d atom:ident$d(<$d generic:tt>)?,)* ; // Atoms in the done layers
+            $d($d name:literal -> $d value:expr)* // Builtins of the top layer
            ) => {
              dedup_call!(create_expression! ,
                (
                Expression,
                $($atom$(<$generic>)?,)*
-               $$($$atom$$(<$$generic>)?,)*
+               $d($d atom$d(<$d generic>)?,)*
                List<Expression>,
                BuiltinFunction<Expression>,
                BuiltinMacro<Expression>,
+               AsyncBuiltinFunction<Expression>,
                Lambda<Expression>,
                Macro<Expression>,
+               Cell<Expression>,
+               Char,
+               Generic<Expression>,
+               Foreign,
+               Keyword,
+               LazySeq<Expression>,
                Number,
+               Promise<Expression>,
+               Record<Expression>,
+               ErrorValue<Expression>,
+               Set<Expression>,
+               Str,
+               Vector<Expression>,
                Symbol
                )
              );
@@ -74,32 +143,77 @@ macro_rules! create_layer {
              where
                 Expression:
                     $(ToAndFrom<$atom$(<$generic>)?> + )*
-                    $$(ToAndFrom<$$atom$$(<$$generic>)?> + )*
+                    $d(ToAndFrom<$d atom$d(<$d generic>)?> + )*
                     LispExpression
              {
-                $$(
-                $$unfolded_layer::set_environment(env);
+                let before = env.bound_symbols();
+                $d(
+                $d unfolded_layer::set_environment(env);
                 )*
-                $$(
-                env.set($$name, $$value);
+                $d(
+                env.set($d name, $d value);
                 )*
+                register_hooks(env);
+                env.assign_layer(layer_info().name);
+                env.register_layer(layer_info());
+                env.protect_new_bindings(&before);
+             }
+
+             /// The layers below this one from the bottom up, then this one
+             pub fn layers() -> Vec<LayerInfo> {
+                let mut layers = vec![$d($d unfolded_layer::layer_info()),*];
+                layers.reverse();
+                layers.push(layer_info());
+                layers
              }
            };
            (
-             $$top_layer:ident $$($$layer:ident)* ;  // Layers to go
-             $$($$unfolded_layer:ident)* ;           // Layers already done
-             $$($$atom:ident$$(<$$generic:tt>)?,)* ; // Atoms in the done layers
-             $$($$name:literal -> $$value:expr)*     // Builtins of the top layer
+             $d top_layer:ident $d($d layer:ident)* ;  // Layers to go
+             $d($d unfolded_layer:ident)* ;           // Layers already done
+             $d($d atom:ident$d(<$d generic:tt>)?,)* ; // Atoms in the done layers
+             $d($d name:literal -> $d value:expr)*     // Builtins of the top layer
            ) => {
-             $$top_layer::add_my_atoms!(
-               $$($$layer)*;
-               $$top_layer $$($$unfolded_layer)* ;
-               $($atom$(<$generic>)?,)* $$($$atom$$(<$$generic>)?,)* ;
-               $$($$name -> $$value)* // Builtins of the top layer
+             $d top_layer::add_my_atoms!(
+               $d($d layer)*;
+               $d top_layer $d($d unfolded_layer)* ;
+               $($atom$(<$generic>)?,)* $d($d atom$d(<$d generic>)?,)* ;
+               $d($d name -> $d value)* // Builtins of the top layer
              );
            }
         }
 
         add_my_atoms!( ; ; ; $($name -> $value)*);
     };
+
+    (
+     @info $layer_name:tt $(version $layer_version:tt)?
+     | $($atom:ident$(<$generic:tt>)?),*
+     | $($prefix:literal -> $kind:ident($reader:path)),*
+     | $($command:literal -> $handler:path),*
+     | $($name:literal),*
+    ) => {
+        /// Adds this layer's reader macros and REPL commands
+        #[allow(unused_variables)]
+        fn register_hooks<Expression>(env: &mut Environment<Expression>)
+        where
+            Expression: $(ToAndFrom<$atom$(<$generic>)?> + )* LispExpression
+        {
+            $(
+            env.reader_macros.set($prefix, ReaderMacro::$kind($reader));
+            )*
+            $(
+            env.repl_commands.set($command, $handler);
+            )*
+        }
+
+        /// What this layer itself adds, see `layers` for those below it too
+        pub fn layer_info() -> LayerInfo {
+            LayerInfo {
+                name: $layer_name,
+                version: [$($layer_version,)? env!("CARGO_PKG_VERSION")][0],
+                atoms: vec![$(<$atom$(<$generic>)? as Atom<Expression>>::sized_name()),*],
+                builtins: vec![$($name.to_owned()),*],
+            }
+        }
+    };
 }
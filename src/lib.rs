@@ -1,32 +1,139 @@
-#![feature(macro_metavar_expr)]
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(not(any(feature = "std", feature = "alloc")))]
+compile_error!("shallot needs the std feature or, to build without std, the alloc feature");
+
 use anyhow::{anyhow, bail, Context, Result};
+use prelude::*;
 
+extern crate alloc;
 extern crate shallot_dedup;
 pub use shallot_dedup::dedup_call;
 
+/// What the std prelude brings into scope, for modules to import so that
+/// they build without std too
+mod prelude {
+    pub use alloc::{
+        borrow::ToOwned,
+        boxed::Box,
+        format,
+        string::{String, ToString},
+        vec,
+        vec::Vec,
+    };
+}
+
 mod atoms;
 pub use atoms::*;
 mod token;
-pub use token::{tokenize, Token};
+pub use token::{tokenize, tokenize_classified, Span, Token, TokenClass, TokenKind};
 pub mod builtins;
 pub use builtins::set_environment;
+#[cfg(feature = "async")]
+mod asynchronous;
+#[cfg(feature = "async")]
+pub use asynchronous::evaluate_async;
+mod attributes;
+pub use attributes::{Accessor, Accessors};
+mod audit;
+#[cfg(feature = "std")]
+pub mod bench_programs;
+pub use audit::audit;
+mod cancellation;
+pub use cancellation::CancellationToken;
+pub mod collections;
+mod commands;
+pub use commands::{ReplCommand, ReplCommands};
+mod config;
+pub use config::Config;
+mod convert;
+mod debugger;
+pub use convert::{FromLisp, IntoLisp};
+pub use debugger::Debugger;
+mod diagnostics;
+pub use diagnostics::{Diagnostic, Diagnostics, SourceSpan};
+mod dynamic;
+pub use dynamic::{DynAtom, DynExpression};
 mod environment;
 mod errors;
+mod events;
+pub use events::{Event, EventKind, EventSink, Events};
+mod hooks;
+pub use hooks::{AfterEval, BeforeEval, EvalHooks};
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod float;
+mod global;
+mod io;
+#[cfg(feature = "std")]
+pub use io::StdIo;
+pub use io::{CapturedIo, Io, IoHandler, NullIo};
+mod parser;
+pub use parser::{is_incomplete, Parser};
+pub mod printer;
+mod profiler;
+pub use profiler::{ProfileEntry, ProfileReport, Profiler};
+mod random;
+mod reader;
 pub use environment::*;
-pub use errors::TypeError;
+pub use errors::{
+    AllocationLimit, ArityError, Cancelled, FuelExhausted, IncompleteInput, IndexOutOfRange,
+    ProtectedBinding, RecursionLimit, ReturnFrom, Thrown, TypeError, UnbalancedParens,
+    UnboundVariable, UnterminatedString,
+};
+pub use random::Rng;
+pub use reader::{ReaderMacro, ReaderMacros};
 mod expression;
-pub use expression::{LispExpression, ToAndFrom};
+pub use expression::{drop_nested, LispExpression, ToAndFrom, MAX_PARSE_DEPTH};
+
+pub mod pattern;
+pub mod plugin;
+#[cfg(feature = "serde")]
+pub mod serialization;
+pub mod shared;
+#[cfg(feature = "std")]
+pub mod testing;
+mod tracer;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+pub use pattern::{match_pattern, Pattern};
+pub use tracer::Tracer;
 
+#[cfg(feature = "std")]
 mod repl;
+#[cfg(feature = "std")]
 pub use repl::run_repl;
 mod layer;
+pub use layer::LayerInfo;
+#[cfg(feature = "std")]
+mod lsp;
 
 // TODO Symbol interning?
 
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Human readable description of this build, as shown by `--version`: the
+/// cargo features it was built with and the layers loaded into `env`
+pub fn version_info<E: LispExpression>(env: &Environment<E>) -> String {
+    let features: Vec<&str> = builtins::enabled_features().collect();
+    let layers: Vec<String> = env
+        .layers
+        .borrow()
+        .iter()
+        .map(|layer| format!("{} {}", layer.name, layer.version))
+        .collect();
+    format!(
+        "shallot {VERSION}\nfeatures: {}\nlayers: {}",
+        features.join(", "),
+        layers.join(", ")
+    )
+}
+
 pub fn evaluate<E: LispExpression>(input: &str, env: &mut Environment<E>) -> Result<E> {
     let mut tokens = tokenize(input).peekable();
-    let expression =
-        E::parse(&mut tokens).with_context(|| anyhow!("Could not parse input {}", input))?;
+    let expression = E::parse_with(&mut tokens, &env.reader_macros)
+        .with_context(|| anyhow!("Could not parse input {}", input))?;
+    token::skip_comments(&mut tokens);
     if tokens.peek().is_some() {
         bail!("Extra tokens in line")
     }
@@ -34,3 +141,200 @@ pub fn evaluate<E: LispExpression>(input: &str, env: &mut Environment<E>) -> Res
         .eval(env)
         .with_context(|| anyhow!("Could not evaluate input {}", input))
 }
+
+/// Evaluates each expression of a program in turn, returning the value of
+/// the last or `()` if there are none. Definitions take effect before the
+/// expressions after them are read, so these may use new reader macros.
+pub fn evaluate_program<E: LispExpression>(input: &str, env: &mut Environment<E>) -> Result<E> {
+    // Parsing stops at the last token of an expression, which this records
+    let last_end = core::cell::Cell::new((1, 1));
+    let mut tokens = tokenize(input)
+        .inspect(|token| last_end.set((token.end_line, token.end_column)))
+        .peekable();
+    let outer_location = env.diagnostics.set_location(None);
+    let mut evaluate_each = || {
+        let mut value = E::null();
+        loop {
+            token::skip_comments(&mut tokens);
+            let Some(start) = tokens.peek() else {
+                return Ok(value);
+            };
+            let mut location = SourceSpan {
+                line: start.line,
+                column: start.column,
+                end_line: start.end_line,
+                end_column: start.end_column,
+            };
+            env.diagnostics.set_location(Some(location));
+            let expression = E::parse_with(&mut tokens, &env.reader_macros)
+                .with_context(|| anyhow!("Could not parse the expression at {location}"))?;
+            (location.end_line, location.end_column) = last_end.get();
+            env.diagnostics.set_location(Some(location));
+            value = expression
+                .eval(env)
+                .with_context(|| anyhow!("Could not evaluate the expression at {location}"))?;
+        }
+    };
+    let result = evaluate_each();
+    env.diagnostics.set_location(outer_location);
+    result
+}
+
+/// Evaluates the expressions in a file, during which relative paths resolve
+/// against the file's directory and `*file*` is bound to its path
+#[cfg(feature = "std")]
+pub fn evaluate_file<E: LispExpression>(
+    path: impl AsRef<std::path::Path>,
+    env: &mut Environment<E>,
+) -> Result<E> {
+    let path = env.resolve_path(path);
+    let input = std::fs::read_to_string(&path)
+        .with_context(|| anyhow!("Could not read from {}", path.display()))?;
+    let path = path.canonicalize().unwrap_or(path);
+    if env.load_stack.borrow().contains(&path) {
+        let chain: Vec<String> = env
+            .load_stack
+            .borrow()
+            .iter()
+            .skip_while(|loading| **loading != path)
+            .chain([&path])
+            .map(|loading| loading.display().to_string())
+            .collect();
+        bail!("Circular load: {}", chain.join(" -> "))
+    }
+    env.loaded_files.borrow_mut().insert(path.clone());
+
+    let file_symbol = Symbol::from("*file*");
+    let outer_file = env.get(&file_symbol).cloned();
+    env.set(file_symbol.clone(), Str(path.display().to_string()));
+    env.load_stack.borrow_mut().push(path.clone());
+    let result = evaluate_program(&input, env);
+    env.load_stack.borrow_mut().pop();
+    match outer_file {
+        Some(outer_file) => env.set(file_symbol, outer_file),
+        None => {
+            env.inner.remove(&file_symbol);
+        }
+    }
+    result.with_context(|| anyhow!("Could not evaluate file {}", path.display()))
+}
+
+/// One-stop entry point for embedding: an environment with builtins set up
+/// and methods to evaluate code and exchange values with it
+pub struct Interpreter<E> {
+    pub environment: Environment<E>,
+}
+
+impl<E: LispExpression> Interpreter<E> {
+    /// Interpreter with the core builtins
+    pub fn new() -> Self {
+        Self::with_layer(builtins::set_environment)
+    }
+
+    /// Interpreter with the builtins of a layer, e.g. the `set_environment`
+    /// generated by `create_layer!`
+    pub fn with_layer(layer: fn(&mut Environment<E>)) -> Self {
+        let mut environment = Environment::default();
+        layer(&mut environment);
+        Self { environment }
+    }
+
+    pub fn eval(&mut self, input: &str) -> Result<E> {
+        evaluate(input, &mut self.environment)
+    }
+
+    #[cfg(feature = "std")]
+    pub fn eval_file(&mut self, path: impl AsRef<std::path::Path>) -> Result<E> {
+        evaluate_file(path, &mut self.environment)
+    }
+
+    /// Binds `name` to a builtin function implemented in Rust
+    pub fn define_fn(
+        &mut self,
+        name: &'static str,
+        function: impl Fn(&[E], &mut Environment<E>) -> Result<E> + shared::Threadsafe + 'static,
+    ) {
+        let function = BuiltinFunction {
+            name,
+            function: shared::Shared::new(function),
+            doc: Default::default(),
+        };
+        self.environment.set(name, function);
+    }
+
+    /// Adds the method for foreign values of `type_name` to the generic
+    /// function `name`, defining it unless it is one already, so that Lisp
+    /// code calls it as `(name value argument ...)`
+    pub fn define_method<T: core::any::Any>(
+        &mut self,
+        type_name: &'static str,
+        name: &'static str,
+        method: impl Fn(&T, &[E], &mut Environment<E>) -> Result<E> + shared::Threadsafe + 'static,
+    ) {
+        let generic = match self
+            .get(name)
+            .and_then(|value| ToAndFrom::<Generic<E>>::try_into_atom(value).ok())
+        {
+            Some(generic) => generic.clone(),
+            None => {
+                let generic = Generic::new(name.into());
+                self.environment.set(name, generic.clone());
+                generic
+            }
+        };
+        let function = BuiltinFunction {
+            name,
+            // Generic functions only call methods with their first argument
+            function: shared::Shared::new(move |arguments: &[E], env: &mut Environment<E>| {
+                method(arguments[0].downcast_foreign()?, &arguments[1..], env)
+            }),
+            doc: Default::default(),
+        };
+        generic
+            .methods
+            .borrow_mut()
+            .insert(type_name.to_owned(), function.into());
+    }
+
+    /// Binds `name` to an async builtin function, whose promises
+    /// `eval_async` awaits on the caller's runtime
+    #[cfg(feature = "async")]
+    pub fn define_async_fn<F, R>(&mut self, name: &'static str, function: F)
+    where
+        F: Fn(Vec<E>) -> R + Send + Sync + 'static,
+        R: std::future::Future<Output = Result<E>> + Send + 'static,
+    {
+        self.environment
+            .set(name, AsyncBuiltinFunction::new(name, function));
+    }
+
+    #[cfg(feature = "async")]
+    pub async fn eval_async(&mut self, input: &str) -> Result<E> {
+        evaluate_async(input, &mut self.environment).await
+    }
+
+    pub fn get(&self, name: &str) -> Option<&E> {
+        self.environment.get(&name.into())
+    }
+
+    /// The warnings since they were last taken, e.g. after `eval`
+    pub fn take_warnings(&mut self) -> Vec<Diagnostic> {
+        self.environment.diagnostics.take()
+    }
+
+    /// Calls the function bound to `name` with already evaluated arguments
+    pub fn call(&mut self, name: &str, arguments: &[E]) -> Result<E> {
+        let function = self
+            .get(name)
+            .cloned()
+            .ok_or_else(|| anyhow!("Variable `{name}` unbound"))?;
+        builtins::apply(&function, arguments, &mut self.environment)
+            .with_context(|| anyhow!("Could not call `{name}`"))
+    }
+}
+
+impl<E: LispExpression> Default for Interpreter<E> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
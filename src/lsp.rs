@@ -0,0 +1,550 @@
+//! A language server on standard input and output, run by `shallot --lsp`,
+//! which shows parse errors, highlights tokens, completes and describes
+//! builtins and lists the definitions in a file. It never evaluates the
+//! files it is given, so it knows the builtins of the environment it starts
+//! with and the definitions it finds written in the file, but nothing a file
+//! defines some other way.
+//!
+//! Positions are sent and received in UTF-16 code units, which LSP counts
+//! in unless told otherwise, while tokens count characters.
+
+use std::{
+    collections::HashMap,
+    io::{BufRead, Write},
+};
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+
+use crate::{
+    builtins::json,
+    errors::{UnbalancedParens, UnterminatedString},
+    repl::json_string,
+    token::{skip_comments, tokenize, tokenize_classified, Token, TokenClass, TokenKind},
+    BuiltinEntry, Environment, LispExpression, Number, Str, ToAndFrom,
+};
+
+/// The forms whose second element names what they define
+const DEFINING_FORMS: [&str; 6] = [
+    "define",
+    "defun",
+    "defstruct",
+    "defgeneric",
+    "defmethod",
+    "deftest",
+];
+
+/// The longest message read, so that a bad Content-Length can't make the
+/// server allocate more than it is ever sent
+const MAX_MESSAGE_LENGTH: usize = 64 * 1024 * 1024;
+
+/// LSP symbol and completion item kinds
+const FUNCTION_SYMBOL: usize = 12;
+const VARIABLE_SYMBOL: usize = 13;
+const STRUCT_SYMBOL: usize = 23;
+const FUNCTION_COMPLETION: usize = 3;
+const VARIABLE_COMPLETION: usize = 6;
+
+/// The classes highlighted as semantic tokens, in the order of the token
+/// types in the legend of `CAPABILITIES`
+const SEMANTIC_TOKEN_CLASSES: [TokenClass; 6] = [
+    TokenClass::Comment,
+    TokenClass::String,
+    TokenClass::Number,
+    TokenClass::Symbol,
+    TokenClass::Keyword,
+    TokenClass::Quote,
+];
+
+const CAPABILITIES: &str = r#"{"capabilities":{"textDocumentSync":1,"completionProvider":{},"hoverProvider":true,"documentSymbolProvider":true,"semanticTokensProvider":{"legend":{"tokenTypes":["comment","string","number","variable","enumMember","operator"],"tokenModifiers":[]},"full":true}},"serverInfo":{"name":"shallot"}}"#;
+
+/// A definition written in a file, with the positions LSP counts from 0
+struct Definition {
+    name: String,
+    kind: usize,
+    /// Where the whole form starts and ends
+    start: (usize, usize),
+    /// Unknown if the form is never closed
+    end: Option<(usize, usize)>,
+    /// Where its name is
+    name_start: (usize, usize),
+}
+
+/// Serves requests until the client asks the server to exit
+pub fn run_lsp<E: LispExpression>(environment: &Environment<E>) -> Result<()> {
+    let mut input = std::io::stdin().lock();
+    let mut documents: HashMap<String, String> = HashMap::new();
+    while let Some(body) = read_message(&mut input)? {
+        let message: E = match json::parse(&body) {
+            Ok(message) => message,
+            Err(error) => {
+                send(&format!(
+                    r#"{{"jsonrpc":"2.0","id":null,"error":{{"code":-32700,"message":{}}}}}"#,
+                    json_string(&format!("{error:#}"))
+                ))?;
+                continue;
+            }
+        };
+        let method = member(&message, "method").and_then(text).unwrap_or("");
+        let id = member(&message, "id").map(json::write).transpose()?;
+        let params = member(&message, "params");
+        let document = params
+            .and_then(|params| member(params, "textDocument"))
+            .and_then(|document| member(document, "uri"))
+            .and_then(text);
+        let result = match (method, document) {
+            ("initialize", _) => Some(CAPABILITIES.to_owned()),
+            ("shutdown", _) => Some("null".to_owned()),
+            ("exit", _) => return Ok(()),
+            ("textDocument/didOpen", Some(uri)) => {
+                let text = params
+                    .and_then(|params| member(params, "textDocument"))
+                    .and_then(|document| member(document, "text"))
+                    .and_then(text)
+                    .unwrap_or("");
+                documents.insert(uri.to_owned(), text.to_owned());
+                publish_diagnostics(uri, text, environment)?;
+                None
+            }
+            ("textDocument/didChange", Some(uri)) => {
+                // The whole text is sent on each change, as the server
+                // declares with `textDocumentSync`
+                let changes = params
+                    .and_then(|params| member(params, "contentChanges"))
+                    .and_then(|changes| changes.as_list().ok());
+                if let Some(text) = changes
+                    .and_then(|changes| changes.0.last())
+                    .and_then(|change| member(change, "text"))
+                    .and_then(text)
+                {
+                    documents.insert(uri.to_owned(), text.to_owned());
+                    publish_diagnostics(uri, text, environment)?;
+                }
+                None
+            }
+            ("textDocument/didClose", Some(uri)) => {
+                documents.remove(uri);
+                publish_diagnostics(uri, "", environment)?;
+                None
+            }
+            ("textDocument/completion", Some(uri)) => {
+                let text = documents.get(uri).map_or("", String::as_str);
+                Some(completion(text, environment))
+            }
+            ("textDocument/hover", Some(uri)) => {
+                let text = documents.get(uri).map_or("", String::as_str);
+                let position = params.and_then(|params| member(params, "position"));
+                Some(hover(text, position, environment))
+            }
+            ("textDocument/documentSymbol", Some(uri)) => {
+                let text = documents.get(uri).map_or("", String::as_str);
+                Some(document_symbols(text))
+            }
+            ("textDocument/semanticTokens/full", Some(uri)) => {
+                let text = documents.get(uri).map_or("", String::as_str);
+                Some(semantic_tokens(text))
+            }
+            _ => {
+                if let Some(id) = id {
+                    send(&format!(
+                        r#"{{"jsonrpc":"2.0","id":{id},"error":{{"code":-32601,"message":{}}}}}"#,
+                        json_string(&format!("Unsupported method {method}"))
+                    ))?;
+                }
+                continue;
+            }
+        };
+        if let (Some(id), Some(result)) = (id, result) {
+            send(&format!(
+                r#"{{"jsonrpc":"2.0","id":{id},"result":{result}}}"#
+            ))?;
+        }
+    }
+    Ok(())
+}
+
+/// The body of the next message, or `None` once the input ends
+fn read_message(input: &mut impl BufRead) -> Result<Option<String>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = Some(
+                    value
+                        .trim()
+                        .parse::<usize>()
+                        .with_context(|| anyhow!("Invalid Content-Length {value}"))?,
+                );
+            }
+        }
+    }
+    let Some(length) = length else {
+        bail!("LSP message without a Content-Length")
+    };
+    ensure!(
+        length <= MAX_MESSAGE_LENGTH,
+        "LSP message of {length} bytes is over the limit of {MAX_MESSAGE_LENGTH}"
+    );
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    Ok(Some(
+        String::from_utf8(body).context("LSP messages must be UTF-8")?,
+    ))
+}
+
+fn send(body: &str) -> Result<()> {
+    let mut output = std::io::stdout().lock();
+    write!(output, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+    Ok(output.flush()?)
+}
+
+/// The value of a key in a JSON object, read as an association list
+fn member<'a, E: LispExpression>(object: &'a E, key: &str) -> Option<&'a E> {
+    object
+        .as_list()
+        .ok()?
+        .0
+        .iter()
+        .find_map(|pair| match pair.as_list().ok()?.0.as_slice() {
+            [name, value] if ToAndFrom::<Str>::try_into_atom(name).ok()?.0 == key => Some(value),
+            _ => None,
+        })
+}
+
+fn text<E: LispExpression>(value: &E) -> Option<&str> {
+    ToAndFrom::<Str>::try_into_atom(value)
+        .ok()
+        .map(|string| string.0.as_str())
+}
+
+fn number<E: LispExpression>(value: &E) -> Option<usize> {
+    ToAndFrom::<Number>::try_into_atom(value)
+        .ok()
+        .map(|number| number.0 as usize)
+}
+
+/// An LSP range in `text` from positions counting characters from 0
+fn range(text: &str, start: (usize, usize), end: (usize, usize)) -> String {
+    let (start, end) = (utf16_position(text, start), utf16_position(text, end));
+    format!(
+        r#"{{"start":{{"line":{},"character":{}}},"end":{{"line":{},"character":{}}}}}"#,
+        start.0, start.1, end.0, end.1
+    )
+}
+
+/// A position counting characters as a position counting UTF-16 code units
+fn utf16_position(text: &str, (line, column): (usize, usize)) -> (usize, usize) {
+    let units = text.split('\n').nth(line).map_or(column, |line| {
+        let units: usize = line.chars().take(column).map(char::len_utf16).sum();
+        // Past the end of the line, as at the end of an unclosed string
+        units + column.saturating_sub(line.chars().count())
+    });
+    (line, units)
+}
+
+/// Where a token starts, counting from 0
+fn start_of(token: &Token) -> (usize, usize) {
+    (token.line - 1, token.column - 1)
+}
+
+/// Parses every expression in `text`, reporting the first which fails. Where
+/// the error doesn't say where it is, it is put at the start of the
+/// expression.
+fn publish_diagnostics<E: LispExpression>(
+    uri: &str,
+    text: &str,
+    environment: &Environment<E>,
+) -> Result<()> {
+    let mut diagnostics = Vec::new();
+    let mut tokens = tokenize(text).peekable();
+    loop {
+        skip_comments(&mut tokens);
+        let Some(start) = tokens.peek().map(start_of) else {
+            break;
+        };
+        let Err(error) = E::parse_with(&mut tokens, &environment.reader_macros) else {
+            continue;
+        };
+        let position = match error.downcast_ref() {
+            Some(
+                UnbalancedParens::Unclosed { line, column, .. }
+                | UnbalancedParens::UnexpectedClose { line, column, .. },
+            ) => (line - 1, column - 1),
+            None => match error.downcast_ref::<UnterminatedString>() {
+                Some(string) => (string.line - 1, string.column - 1),
+                None => start,
+            },
+        };
+        diagnostics.push(format!(
+            r#"{{"range":{},"severity":1,"source":"shallot","message":{}}}"#,
+            range(text, position, (position.0, position.1 + 1)),
+            json_string(&format!("{error:#}"))
+        ));
+        // What follows an error can't be told apart from what it swallowed
+        break;
+    }
+    send(&format!(
+        r#"{{"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{{"uri":{},"diagnostics":[{}]}}}}"#,
+        json_string(uri),
+        diagnostics.join(",")
+    ))
+}
+
+/// The forms at the top level of `text` which define a name
+fn definitions(text: &str) -> Vec<Definition> {
+    let tokens: Vec<Token> = tokenize(text)
+        .filter(|token| token.kind != TokenKind::Comment)
+        .collect();
+    let mut definitions = Vec::new();
+    let mut depth = 0;
+    for (n, token) in tokens.iter().enumerate() {
+        match token.kind {
+            TokenKind::OpenParen | TokenKind::OpenBracket => depth += 1,
+            TokenKind::CloseParen | TokenKind::CloseBracket => {
+                depth -= 1;
+                if depth == 0 {
+                    if let Some(definition) = definitions
+                        .last_mut()
+                        .filter(|definition: &&mut Definition| definition.end.is_none())
+                    {
+                        definition.end = Some((token.line - 1, token.column));
+                    }
+                }
+            }
+            _ => {}
+        }
+        if depth != 1 || token.kind != TokenKind::OpenParen {
+            continue;
+        }
+        let Some(form) = tokens.get(n + 1) else {
+            continue;
+        };
+        if form.kind != TokenKind::Atom || !DEFINING_FORMS.contains(&form.value.as_str()) {
+            continue;
+        }
+        // `define` takes its name quoted
+        let name_at = match tokens.get(n + 2) {
+            Some(quote) if quote.kind == TokenKind::Quote => n + 3,
+            _ => n + 2,
+        };
+        let Some(name) = tokens
+            .get(name_at)
+            .filter(|name| name.kind == TokenKind::Atom)
+        else {
+            continue;
+        };
+        let defines_function = matches!(
+            (tokens.get(name_at + 1), tokens.get(name_at + 2)),
+            (Some(open), Some(head)) if open.kind == TokenKind::OpenParen
+                && matches!(head.value.as_str(), "lambda" | "λ")
+        );
+        let kind = match form.value.as_str() {
+            "define" if defines_function => FUNCTION_SYMBOL,
+            "define" => VARIABLE_SYMBOL,
+            "defstruct" => STRUCT_SYMBOL,
+            _ => FUNCTION_SYMBOL,
+        };
+        definitions.push(Definition {
+            name: name.value.clone(),
+            kind,
+            start: start_of(token),
+            end: None,
+            name_start: start_of(name),
+        });
+    }
+    definitions
+}
+
+fn document_symbols(text: &str) -> String {
+    let symbols: Vec<String> = definitions(text)
+        .iter()
+        .map(|definition| {
+            let name_end = (
+                definition.name_start.0,
+                definition.name_start.1 + definition.name.chars().count(),
+            );
+            // An unclosed form runs to its name, as far as is known
+            let end = definition.end.unwrap_or(name_end);
+            format!(
+                r#"{{"name":{},"kind":{},"range":{},"selectionRange":{}}}"#,
+                json_string(&definition.name),
+                definition.kind,
+                range(text, definition.start, end),
+                range(text, definition.name_start, name_end)
+            )
+        })
+        .collect();
+    format!("[{}]", symbols.join(","))
+}
+
+/// Encodes tokens as LSP semantic tokens: for each, its line and start
+/// relative to the one before, its length and its type
+struct SemanticTokens {
+    chars: Vec<char>,
+    /// How far through `chars` has been read, and the line and column there
+    /// in UTF-16 code units
+    at: usize,
+    line: usize,
+    column: usize,
+    /// The line and start of the last token
+    previous: (usize, usize),
+    data: Vec<String>,
+}
+
+impl SemanticTokens {
+    /// Reads up to `end`, encoding what is read as a token of `token_type`
+    /// if there is one. A token spanning lines, such as a string, becomes
+    /// one token per line.
+    fn read_to(&mut self, end: usize, token_type: Option<usize>) {
+        let mut start = self.column;
+        while self.at < end {
+            if self.chars[self.at] == '\n' {
+                self.push(start, token_type);
+                self.line += 1;
+                self.column = 0;
+                start = 0;
+            } else {
+                self.column += self.chars[self.at].len_utf16();
+            }
+            self.at += 1;
+        }
+        self.push(start, token_type);
+    }
+
+    /// Encodes the current line from `start` to the current column
+    fn push(&mut self, start: usize, token_type: Option<usize>) {
+        let Some(token_type) = token_type.filter(|_| self.column > start) else {
+            return;
+        };
+        let (line, previous_start) = self.previous;
+        let start_delta = if self.line == line {
+            start - previous_start
+        } else {
+            start
+        };
+        self.data.push(format!(
+            "{},{start_delta},{},{token_type},0",
+            self.line - line,
+            self.column - start
+        ));
+        self.previous = (self.line, start);
+    }
+}
+
+fn semantic_tokens(text: &str) -> String {
+    let mut tokens = SemanticTokens {
+        chars: text.chars().collect(),
+        at: 0,
+        line: 0,
+        column: 0,
+        previous: (0, 0),
+        data: Vec::new(),
+    };
+    for (span, class) in tokenize_classified(text) {
+        // LSP has no type for characters, so they are highlighted as strings
+        let class = match class {
+            TokenClass::Char => TokenClass::String,
+            class => class,
+        };
+        let token_type = SEMANTIC_TOKEN_CLASSES.iter().position(|c| *c == class);
+        tokens.read_to(span.start, None);
+        tokens.read_to(span.end, token_type);
+    }
+    format!(r#"{{"data":[{}]}}"#, tokens.data.join(","))
+}
+
+fn completion<E: LispExpression>(text: &str, environment: &Environment<E>) -> String {
+    let mut items: Vec<String> = environment
+        .builtins()
+        .into_iter()
+        .map(|BuiltinEntry { symbol, doc, .. }| {
+            format!(
+                r#"{{"label":{},"kind":{FUNCTION_COMPLETION},"detail":{},"documentation":{}}}"#,
+                json_string(&symbol.0),
+                json_string(doc.signature),
+                json_string(doc.description)
+            )
+        })
+        .collect();
+    for definition in definitions(text) {
+        let kind = if definition.kind == VARIABLE_SYMBOL {
+            VARIABLE_COMPLETION
+        } else {
+            FUNCTION_COMPLETION
+        };
+        items.push(format!(
+            r#"{{"label":{},"kind":{kind},"detail":{}}}"#,
+            json_string(&definition.name),
+            json_string(&format!("defined at line {}", definition.start.0 + 1))
+        ));
+    }
+    format!("[{}]", items.join(","))
+}
+
+/// The symbol under a position counting from 0, in UTF-16 code units
+fn word_at(text: &str, line: usize, character: usize) -> Option<String> {
+    let line: Vec<char> = text.lines().nth(line)?.chars().collect();
+    let mut units = 0;
+    let character = line
+        .iter()
+        .position(|c| {
+            units += c.len_utf16();
+            units > character
+        })
+        .unwrap_or(line.len());
+    let is_delimiter = |c: &char| c.is_whitespace() || "()[]\"';`,".contains(*c);
+    let start = line[..character.min(line.len())]
+        .iter()
+        .rposition(is_delimiter)
+        .map_or(0, |n| n + 1);
+    let end = line[start..]
+        .iter()
+        .position(is_delimiter)
+        .map_or(line.len(), |n| start + n);
+    (start < end).then(|| line[start..end].iter().collect())
+}
+
+fn hover<E: LispExpression>(
+    text: &str,
+    position: Option<&E>,
+    environment: &Environment<E>,
+) -> String {
+    let word = position.and_then(|position| {
+        let line = number(member(position, "line")?)?;
+        let character = number(member(position, "character")?)?;
+        word_at(text, line, character)
+    });
+    let Some(word) = word else {
+        return "null".to_owned();
+    };
+    let builtin = environment
+        .builtins()
+        .into_iter()
+        .find(|entry| entry.symbol.0 == word);
+    let contents = if let Some(BuiltinEntry { kind, doc, .. }) = builtin {
+        let signature = if doc.signature.is_empty() {
+            word.clone()
+        } else {
+            doc.signature.to_owned()
+        };
+        format!("```\n{signature}\n```\n{}\n\n*{kind}*", doc.description)
+    } else if let Some(definition) = definitions(text)
+        .into_iter()
+        .find(|definition| definition.name == word)
+    {
+        format!("`{word}`, defined at line {}", definition.start.0 + 1)
+    } else {
+        return "null".to_owned();
+    };
+    format!(
+        r#"{{"contents":{{"kind":"markdown","value":{}}}}}"#,
+        json_string(&contents)
+    )
+}
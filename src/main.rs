@@ -1,4 +1,3 @@
-#![feature(macro_metavar_expr)]
 use anyhow::Result;
 use shallot::*;
 
@@ -6,6 +5,11 @@ create_layer!(atoms | builtins);
 
 fn main() -> Result<()> {
     let mut environment: Environment<Expression> = Environment::default();
+    *environment.config.borrow_mut() = Config {
+        allow_shell: true,
+        allow_os: true,
+        ..Default::default()
+    };
     shallot::builtins::set_environment(&mut environment);
-    run_repl::<Expression>(&mut environment)
+    run_repl::<Expression>(&mut environment, shallot::builtins::set_environment)
 }
@@ -0,0 +1,151 @@
+use anyhow::Result;
+
+use crate::{
+    errors::{IncompleteInput, UnbalancedParens, UnterminatedString},
+    prelude::*,
+    token::skip_comments,
+    tokenize, LispExpression, ReaderMacros, Token,
+};
+
+/// Parses expressions out of source which arrives in pieces, such as lines
+/// typed at the REPL, an editor's buffer or messages from a socket. Unlike
+/// `LispExpression::parse`, input which stops partway through an expression
+/// is not an error but waits for more to be fed.
+pub struct Parser<E> {
+    /// Source fed but not yet parsed
+    pending: String,
+    /// Where `pending` starts in all the source fed
+    origin: Origin,
+    reader_macros: ReaderMacros<E>,
+}
+
+/// A place in source, so that tokens and errors locate themselves in all
+/// the source fed rather than in the part not yet parsed
+#[derive(Clone, Copy)]
+struct Origin {
+    position: usize,
+    line: usize,
+    column: usize,
+}
+
+impl Origin {
+    /// A token of the source starting here, located in the source before
+    fn locate(self, mut token: Token) -> Token {
+        if token.line == 1 {
+            token.column += self.column - 1;
+        }
+        if token.end_line == 1 {
+            token.end_column += self.column - 1;
+        }
+        token.position += self.position;
+        token.line += self.line - 1;
+        token.end_line += self.line - 1;
+        token
+    }
+
+    /// Moves past `source`
+    fn advance(&mut self, source: &str) {
+        for c in source.chars() {
+            self.position += 1;
+            if c == '\n' {
+                self.line += 1;
+                self.column = 1;
+            } else {
+                self.column += 1;
+            }
+        }
+    }
+}
+
+impl<E: LispExpression> Default for Parser<E> {
+    fn default() -> Self {
+        Self::with_reader_macros(ReaderMacros::default())
+    }
+}
+
+impl<E: LispExpression> Parser<E> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parser reading prefixes with the given reader macros, e.g. those of
+    /// an environment
+    pub fn with_reader_macros(reader_macros: ReaderMacros<E>) -> Self {
+        Parser {
+            pending: String::new(),
+            origin: Origin {
+                position: 0,
+                line: 1,
+                column: 1,
+            },
+            reader_macros,
+        }
+    }
+
+    /// Adds more source after what has been fed so far
+    pub fn feed(&mut self, source: &str) {
+        self.pending.push_str(source);
+    }
+
+    /// The next whole expression fed, or `None` if the source so far holds
+    /// none or stops partway through one. Source which can never parse is an
+    /// error, after which it is discarded.
+    pub fn next_expression(&mut self) -> Result<Option<E>> {
+        let origin = self.origin;
+        let mut tokens = tokenize(&self.pending)
+            .map(|token| origin.locate(token))
+            .peekable();
+        skip_comments(&mut tokens);
+        if tokens.peek().is_none() {
+            self.consume(None);
+            return Ok(None);
+        }
+        match E::parse_with(&mut tokens, &self.reader_macros) {
+            Ok(expression) => {
+                skip_comments(&mut tokens);
+                let consumed = tokens.peek().map(|next| next.position - origin.position);
+                self.consume(consumed);
+                Ok(Some(expression))
+            }
+            Err(error) if is_incomplete(&error) => Ok(None),
+            Err(error) => {
+                self.consume(None);
+                Err(error)
+            }
+        }
+    }
+
+    /// Whether source has been fed which is not yet a whole expression
+    pub fn is_incomplete(&self) -> bool {
+        let mut tokens = tokenize(&self.pending).peekable();
+        skip_comments(&mut tokens);
+        tokens.peek().is_some()
+    }
+
+    /// Discards the source fed but not yet parsed, returning it
+    pub fn reset(&mut self) -> String {
+        self.origin.advance(&self.pending);
+        core::mem::take(&mut self.pending)
+    }
+
+    /// Removes the source before the character at `consumed`, or all of it
+    fn consume(&mut self, consumed: Option<usize>) {
+        let end = consumed
+            .and_then(|position| self.pending.char_indices().nth(position))
+            .map_or(self.pending.len(), |(index, _)| index);
+        self.origin.advance(&self.pending[..end]);
+        self.pending.drain(..end);
+    }
+}
+
+/// Whether a parse error only means that the input ended too soon
+pub fn is_incomplete(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        cause.is::<IncompleteInput>()
+            || cause.is::<UnterminatedString>()
+            || matches!(
+                cause.downcast_ref(),
+                Some(UnbalancedParens::Unclosed { .. })
+            )
+    })
+}
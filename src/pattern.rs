@@ -0,0 +1,95 @@
+use anyhow::{anyhow, ensure, Context, Result};
+
+use crate::{
+    atoms::List, collections::HashMap, prelude::*, token::skip_comments, tokenize, LispExpression,
+};
+
+pub type Bindings<E> = HashMap<String, E>;
+
+/// Shape of an expression to destructure from Rust, written as shallot source
+/// in which `$name` matches anything, `$_` matches anything without binding
+/// it and a final `$name...` in a list matches the rest of the list, e.g.
+/// `(define '$name $value)` or `(list $first $rest...)`
+pub struct Pattern<E> {
+    pattern: E,
+}
+
+impl<E: LispExpression> Pattern<E> {
+    pub fn parse(source: &str) -> Result<Self> {
+        let mut tokens = tokenize(source).peekable();
+        let pattern =
+            E::parse(&mut tokens).with_context(|| anyhow!("Could not parse pattern {}", source))?;
+        skip_comments(&mut tokens);
+        ensure!(tokens.peek().is_none(), "Extra tokens in pattern");
+        Ok(Self { pattern })
+    }
+
+    /// The bound variables (without `$`) if `expression` has this shape
+    pub fn matches(&self, expression: &E) -> Option<Bindings<E>> {
+        let mut bindings = HashMap::new();
+        match_into(&self.pattern, expression, &mut bindings).then_some(bindings)
+    }
+}
+
+/// Parses `pattern` and matches `expression` against it, see [`Pattern`]
+pub fn match_pattern<E: LispExpression>(
+    pattern: &str,
+    expression: &E,
+) -> Result<Option<Bindings<E>>> {
+    Ok(Pattern::parse(pattern)?.matches(expression))
+}
+
+fn variable_name<E: LispExpression>(pattern: &E) -> Option<&str> {
+    pattern.as_symbol().ok()?.0.strip_prefix('$')
+}
+
+fn bind<E: LispExpression>(name: &str, expression: &E, bindings: &mut Bindings<E>) -> bool {
+    if name == "_" {
+        return true;
+    }
+    match bindings.get(name) {
+        // A variable used twice has to match the same thing twice
+        Some(bound) => bound == expression,
+        None => {
+            bindings.insert(name.to_owned(), expression.clone());
+            true
+        }
+    }
+}
+
+fn match_into<E: LispExpression>(pattern: &E, expression: &E, bindings: &mut Bindings<E>) -> bool {
+    if let Some(name) = variable_name(pattern) {
+        return bind(name, expression, bindings);
+    }
+    let (Ok(patterns), Ok(expressions)) = (pattern.as_list(), expression.as_list()) else {
+        return pattern == expression;
+    };
+    let rest = patterns
+        .0
+        .last()
+        .and_then(variable_name)
+        .and_then(|name| name.strip_suffix("..."));
+    match rest {
+        Some(rest) => {
+            let fixed = patterns.0.len() - 1;
+            expressions.0.len() >= fixed
+                && patterns.0[..fixed]
+                    .iter()
+                    .zip(expressions.0.iter())
+                    .all(|(p, e)| match_into(p, e, bindings))
+                && bind(
+                    rest,
+                    &List::new(expressions.0[fixed..].to_vec()).into(),
+                    bindings,
+                )
+        }
+        None => {
+            patterns.0.len() == expressions.0.len()
+                && patterns
+                    .0
+                    .iter()
+                    .zip(expressions.0.iter())
+                    .all(|(p, e)| match_into(p, e, bindings))
+        }
+    }
+}
@@ -0,0 +1,188 @@
+//! Packages of builtins added to an interpreter without forking it. A plugin
+//! is compiled into the embedding program and installed with `install`, or,
+//! with the `plugins` feature, built as a shared library which the REPL loads
+//! from its plugins directory at startup.
+//!
+//! A shared library plugin declares itself with `declare_plugin!`. Rust has
+//! no stable ABI, so it has to be built by the same compiler against the same
+//! version of shallot, with an expression type of the same layers as the
+//! interpreter loading it. The library exports a marker of the version and
+//! atoms it was built with, which `load_library` checks before calling into
+//! it; the compiler is up to whoever builds it.
+
+use crate::{expression::LispExpression, prelude::*, Environment, LayerInfo};
+
+/// A package of builtins, which it binds when registered
+pub trait Plugin<E> {
+    /// Name of the layer its builtins are listed under, e.g. by `layers`
+    fn name(&self) -> &'static str;
+
+    fn version(&self) -> &'static str {
+        "0.0.0"
+    }
+
+    /// Binds the plugin's builtins, and may add reader macros and REPL
+    /// commands, to `env`
+    fn register(&self, env: &mut Environment<E>);
+}
+
+/// Registers a plugin, recording it as a layer and protecting the bindings
+/// it adds as those of other layers are
+pub fn install<E: LispExpression>(plugin: &dyn Plugin<E>, env: &mut Environment<E>) {
+    let before = env.bound_symbols();
+    plugin.register(env);
+    env.assign_layer(plugin.name());
+    let builtins = env
+        .builtins()
+        .iter()
+        .filter(|entry| entry.doc.layer == plugin.name())
+        .map(|entry| entry.symbol.0.clone())
+        .collect();
+    env.register_layer(LayerInfo {
+        name: plugin.name(),
+        version: plugin.version(),
+        atoms: Vec::new(),
+        builtins,
+    });
+    env.protect_new_bindings(&before);
+}
+
+/// The version of shallot and the atoms of `E`, which a plugin library must
+/// have been built with to be loaded by an interpreter of `E`
+#[doc(hidden)]
+pub fn abi_marker<E: LispExpression>() -> String {
+    format!("shallot {} ({})", crate::VERSION, E::atom_names().join(" "))
+}
+
+/// Exports the constructor of a plugin from a shared library, for
+/// `load_library`, e.g. `declare_plugin!(Expression, Inspector::default())`
+#[macro_export]
+macro_rules! declare_plugin {
+    ($expression:ty, $constructor:expr) => {
+        #[no_mangle]
+        pub extern "C" fn shallot_plugin_abi() -> *const ::std::ffi::c_char {
+            static MARKER: ::std::sync::OnceLock<::std::ffi::CString> =
+                ::std::sync::OnceLock::new();
+            MARKER
+                .get_or_init(|| {
+                    let marker = $crate::plugin::abi_marker::<$expression>();
+                    ::std::ffi::CString::new(marker).unwrap_or_default()
+                })
+                .as_ptr()
+        }
+
+        #[no_mangle]
+        pub fn shallot_plugin() -> Box<dyn $crate::plugin::Plugin<$expression>> {
+            Box::new($constructor)
+        }
+    };
+}
+
+#[cfg(all(unix, feature = "plugins"))]
+pub use library::{load_directory, load_library};
+
+#[cfg(all(unix, feature = "plugins"))]
+mod library {
+    use std::{
+        ffi::{c_char, c_int, c_void, CStr, CString},
+        os::unix::ffi::OsStrExt,
+        path::{Path, PathBuf},
+    };
+
+    use anyhow::{anyhow, bail, Context, Result};
+
+    use super::{abi_marker, install, Plugin};
+    use crate::{expression::LispExpression, Environment};
+
+    const RTLD_NOW: c_int = 2;
+
+    #[cfg_attr(target_os = "linux", link(name = "dl"))]
+    extern "C" {
+        fn dlopen(filename: *const c_char, flags: c_int) -> *mut c_void;
+        fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+        fn dlerror() -> *const c_char;
+    }
+
+    /// The message of the last failure of `dlopen` or `dlsym`
+    fn last_error() -> String {
+        // Safety: dlerror returns null or a string valid until the next call
+        unsafe {
+            let message = dlerror();
+            if message.is_null() {
+                "unknown error".to_string()
+            } else {
+                CStr::from_ptr(message).to_string_lossy().into_owned()
+            }
+        }
+    }
+
+    /// Loads the shared library at `path` and installs the plugin it
+    /// declares, returning the plugin's name. The library stays loaded for
+    /// the rest of the process, as the builtins it bound point into it.
+    ///
+    /// # Safety
+    ///
+    /// The library runs arbitrary code, and has to have been built as the
+    /// module documentation describes for its plugin to be of type `E`.
+    pub unsafe fn load_library<E: LispExpression>(
+        path: &Path,
+        env: &mut Environment<E>,
+    ) -> Result<&'static str> {
+        let filename = CString::new(path.as_os_str().as_bytes())
+            .context("The path of a plugin can't contain a nul byte")?;
+        let handle = dlopen(filename.as_ptr(), RTLD_NOW);
+        if handle.is_null() {
+            bail!("Could not load plugin {}: {}", path.display(), last_error());
+        }
+        let marker = dlsym(handle, c"shallot_plugin_abi".as_ptr());
+        if marker.is_null() {
+            bail!(
+                "{} does not declare a plugin with declare_plugin! of this version of shallot",
+                path.display()
+            );
+        }
+        let marker: extern "C" fn() -> *const c_char = std::mem::transmute(marker);
+        let (theirs, ours) = (CStr::from_ptr(marker()), abi_marker::<E>());
+        if theirs.to_bytes() != ours.as_bytes() {
+            bail!(
+                "{} was built for {}, not {ours}",
+                path.display(),
+                theirs.to_string_lossy()
+            );
+        }
+        let constructor = dlsym(handle, c"shallot_plugin".as_ptr());
+        if constructor.is_null() {
+            bail!(
+                "{} does not declare a plugin with declare_plugin!",
+                path.display()
+            );
+        }
+        let constructor: fn() -> Box<dyn Plugin<E>> = std::mem::transmute(constructor);
+        let plugin = constructor();
+        install(&*plugin, env);
+        Ok(plugin.name())
+    }
+
+    /// Loads every shared library in `directory` as a plugin, in order of
+    /// their file names, returning the name or failure of each
+    ///
+    /// # Safety
+    ///
+    /// As for `load_library`, for every library in the directory
+    pub unsafe fn load_directory<E: LispExpression>(
+        directory: &Path,
+        env: &mut Environment<E>,
+    ) -> Result<Vec<Result<&'static str>>> {
+        let entries = std::fs::read_dir(directory)
+            .with_context(|| anyhow!("Could not read plugins from {}", directory.display()))?;
+        let mut paths: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension == "so" || extension == "dylib")
+            })
+            .collect();
+        paths.sort();
+        Ok(paths.iter().map(|path| load_library(path, env)).collect())
+    }
+}
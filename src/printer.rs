@@ -0,0 +1,202 @@
+//! How values become text. There are two ways to print a value:
+//!
+//! - displayed, for people: a string is its text without quotes, as `print`
+//!   and `display` show it
+//! - written, for programs: source code without colours which reads back as
+//!   an equal value where possible, as `write` and `to-string` give it
+//!
+//! The REPL echoes results in the written form, coloured for the terminal.
+//!
+//! Numbers are shown with the fewest digits which read back as the same
+//! number, or displayed with the fixed number of decimals of the
+//! environment's `Config::number_precision`, which is passed in here.
+//! Printing lists is limited by the text the environment may still allocate
+//! and the fuel it has left in the same way, see `with_print_budget`.
+
+use core::cell::Cell;
+
+use crate::{
+    expression::ToAndFrom, global::thread_state, prelude::*, tokenize_classified, Char,
+    LispExpression, Str, TokenClass,
+};
+
+thread_state! {
+    /// Decimals numbers are displayed with while `with_number_precision`
+    /// runs, or `None` for as many as needed
+    static NUMBER_PRECISION: Cell<Option<usize>> = Cell::new(None);
+}
+
+/// What lists may still print while `with_print_budget` runs, each `None`
+/// for no limit: bytes of text, and steps of walking from one element or
+/// end of a list to the next
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PrintBudget {
+    pub bytes: Option<usize>,
+    pub steps: Option<u64>,
+}
+
+/// The part of a `PrintBudget` which a list ran out of
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Overspent {
+    Bytes,
+    Steps,
+}
+
+thread_state! {
+    /// What lists may still print, see `with_print_budget`
+    static PRINT_BUDGET: Cell<PrintBudget> = Cell::new(PrintBudget {
+        bytes: None,
+        steps: None,
+    });
+}
+
+thread_state! {
+    /// Which part of `PRINT_BUDGET` a list ran out of and stopped printing
+    static OVERSPENT: Cell<Option<Overspent>> = Cell::new(None);
+}
+
+/// Runs `display`, in which numbers display with `precision` decimals, or
+/// with as many as needed for `None`. Written numbers are never rounded.
+pub fn with_number_precision<R>(precision: Option<usize>, display: impl FnOnce() -> R) -> R {
+    let outer = NUMBER_PRECISION.with(|current| current.replace(precision));
+    let result = display();
+    NUMBER_PRECISION.with(|current| current.set(outer));
+    result
+}
+
+/// Runs `print`, in which lists stop printing when they run out of
+/// `budget`. Gives what is left of it, or what ran out rather than the cut
+/// short text.
+pub fn with_print_budget<R>(
+    budget: PrintBudget,
+    print: impl FnOnce() -> R,
+) -> core::result::Result<(R, PrintBudget), Overspent> {
+    let outer_budget = PRINT_BUDGET.with(|current| current.replace(budget));
+    let outer_overspent = OVERSPENT.with(|current| current.replace(None));
+    let result = print();
+    let left = PRINT_BUDGET.with(|current| current.replace(outer_budget));
+    match OVERSPENT.with(|current| current.replace(outer_overspent)) {
+        Some(overspent) => Err(overspent),
+        None => Ok((result, left)),
+    }
+}
+
+/// Takes a step and the `bytes` of text it printed from the budget of
+/// `with_print_budget`, giving whether printing may go on
+pub(crate) fn spend_print(bytes: usize) -> bool {
+    if OVERSPENT.with(Cell::get).is_some() {
+        return false;
+    }
+    let mut budget = PRINT_BUDGET.with(Cell::get);
+    let overspent = if budget.bytes.is_some_and(|left| bytes > left) {
+        Some(Overspent::Bytes)
+    } else if budget.steps == Some(0) {
+        Some(Overspent::Steps)
+    } else {
+        budget.bytes = budget.bytes.map(|left| left - bytes);
+        budget.steps = budget.steps.map(|left| left - 1);
+        None
+    };
+    PRINT_BUDGET.with(|current| current.set(budget));
+    OVERSPENT.with(|current| current.set(overspent));
+    overspent.is_none()
+}
+
+/// A number as it displays, see `with_number_precision`
+pub fn number_text(number: f64) -> String {
+    match NUMBER_PRECISION.with(Cell::get) {
+        Some(precision) if number.is_finite() => format!("{number:.precision$}"),
+        _ => number_source(number),
+    }
+}
+
+/// The shortest text which reads back as the same number. Like JavaScript,
+/// very large and very small magnitudes use an exponent rather than a long
+/// run of zeros.
+pub fn number_source(number: f64) -> String {
+    let magnitude = number.abs();
+    if number.is_finite() && magnitude != 0. && !(1e-7..1e21).contains(&magnitude) {
+        format!("{number:e}")
+    } else {
+        number.to_string()
+    }
+}
+
+/// A value as `print` shows it, i.e. strings without quotes, with numbers
+/// to `precision` decimals
+pub fn display_text<E: LispExpression>(value: &E, precision: Option<usize>) -> String {
+    match ToAndFrom::<Str>::try_into_atom(value) {
+        Ok(string) => string.0.clone(),
+        Err(_) => with_number_precision(precision, || value.to_string()),
+    }
+}
+
+/// A value as source code without colours, or as it displays if it has no
+/// source, such as a builtin bound under another name
+pub fn write_text<E: LispExpression>(value: &E) -> String {
+    value
+        .as_atom()
+        .to_source()
+        .unwrap_or_else(|_| strip_colours(&value.to_string()))
+}
+
+/// Text for a `{}` in a format string: strings and chars as themselves,
+/// other values written
+pub fn plain_text<E: LispExpression>(value: &E) -> String {
+    if let Ok(string) = ToAndFrom::<Str>::try_into_atom(value) {
+        string.0.clone()
+    } else if let Ok(c) = ToAndFrom::<Char>::try_into_atom(value) {
+        c.0.to_string()
+    } else {
+        write_text(value)
+    }
+}
+
+/// A result as the REPL echoes it, with numbers to `precision` decimals,
+/// highlighted as source typed at the prompt would be
+pub fn echo_text<E: LispExpression>(value: &E, precision: Option<usize>) -> String {
+    let text = with_number_precision(precision, || value.to_string());
+    highlight(&strip_colours(&text))
+}
+
+/// Source code coloured for the terminal, each token as the value it reads
+/// as is displayed
+pub fn highlight(source: &str) -> String {
+    let chars: Vec<char> = source.chars().collect();
+    let mut highlighted = String::new();
+    let mut copied = 0;
+    for (span, class) in tokenize_classified(source) {
+        highlighted.extend(&chars[copied..span.start]);
+        let text: String = chars[span.start..span.end].iter().collect();
+        let colour = match class {
+            TokenClass::Symbol => Some("32"),
+            TokenClass::Number => Some("36"),
+            TokenClass::String => Some("33"),
+            TokenClass::Char => Some("34"),
+            TokenClass::Keyword => Some("35"),
+            TokenClass::Comment => Some("90"),
+            TokenClass::Paren | TokenClass::Quote => None,
+        };
+        match colour {
+            Some(colour) => highlighted.push_str(&format!("\x1b[0;{colour}m{text}\x1b[0m")),
+            None => highlighted.push_str(&text),
+        }
+        copied = span.end;
+    }
+    highlighted.extend(&chars[copied..]);
+    highlighted
+}
+
+/// Removes the terminal colour codes which values are displayed with
+pub fn strip_colours(text: &str) -> String {
+    let mut plain = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            chars.by_ref().find(|c| c.is_ascii_alphabetic());
+        } else {
+            plain.push(c);
+        }
+    }
+    plain
+}
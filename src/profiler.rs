@@ -0,0 +1,133 @@
+use core::{fmt::Display, time::Duration};
+
+use crate::{
+    collections::HashMap,
+    prelude::*,
+    shared::{Mutable, Shared},
+};
+
+/// Calls of one function seen by a profiler
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ProfileEntry {
+    pub calls: u64,
+    /// Time spent inside the function, counting a recursive call only once.
+    /// Without std there is no clock, so this stays zero.
+    pub time: Duration,
+    active: u32,
+}
+
+/// Counts calls and time per function while enabled. Clones share one table,
+/// so closures record into the profile of the environment they came from.
+#[derive(Clone, Debug, Default)]
+pub struct Profiler(Shared<Mutable<Option<HashMap<String, ProfileEntry>>>>);
+
+impl Profiler {
+    /// Starts recording into an empty table
+    pub fn enable(&self) {
+        *self.0.borrow_mut() = Some(HashMap::new());
+    }
+
+    /// Stops recording, giving the report of what was recorded
+    pub fn disable(&self) -> Option<ProfileReport> {
+        let entries = self.0.borrow_mut().take()?;
+        Some(ProfileReport::new(entries))
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.0.borrow().is_some()
+    }
+
+    /// What has been recorded so far
+    pub fn report(&self) -> Option<ProfileReport> {
+        self.0.borrow().clone().map(ProfileReport::new)
+    }
+
+    /// Sets aside whatever is being recorded, to be continued by `resume`
+    pub(crate) fn suspend(&self) -> Suspended {
+        Suspended(self.0.borrow_mut().take())
+    }
+
+    pub(crate) fn resume(&self, suspended: Suspended) {
+        *self.0.borrow_mut() = suspended.0;
+    }
+
+    /// Counts a call of `name`, timing it until the guard is dropped
+    pub(crate) fn enter(&self, name: String) -> ProfileGuard {
+        if let Some(entries) = &mut *self.0.borrow_mut() {
+            let entry = entries.entry(name.clone()).or_default();
+            entry.calls += 1;
+            entry.active += 1;
+        }
+        ProfileGuard {
+            profiler: self.clone(),
+            name,
+            #[cfg(feature = "std")]
+            start: std::time::Instant::now(),
+        }
+    }
+}
+
+impl PartialEq for Profiler {
+    fn eq(&self, other: &Self) -> bool {
+        Shared::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// A profile set aside while another is recorded
+pub(crate) struct Suspended(Option<HashMap<String, ProfileEntry>>);
+
+/// One call being timed, recorded when dropped so that calls which fail
+/// still count
+pub(crate) struct ProfileGuard {
+    profiler: Profiler,
+    name: String,
+    #[cfg(feature = "std")]
+    start: std::time::Instant,
+}
+
+impl Drop for ProfileGuard {
+    fn drop(&mut self) {
+        if let Some(entries) = &mut *self.profiler.0.borrow_mut() {
+            if let Some(entry) = entries.get_mut(&self.name) {
+                entry.active = entry.active.saturating_sub(1);
+                // Only the outermost of recursive calls adds its time
+                #[cfg(feature = "std")]
+                if entry.active == 0 {
+                    entry.time += self.start.elapsed();
+                }
+            }
+        }
+    }
+}
+
+/// Functions by the time spent in them, most first
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProfileReport(pub Vec<(String, ProfileEntry)>);
+
+impl ProfileReport {
+    fn new(entries: HashMap<String, ProfileEntry>) -> Self {
+        let mut entries: Vec<(String, ProfileEntry)> = entries.into_iter().collect();
+        entries.sort_by(|(a_name, a), (b_name, b)| {
+            b.time
+                .cmp(&a.time)
+                .then(b.calls.cmp(&a.calls))
+                .then(a_name.cmp(b_name))
+        });
+        ProfileReport(entries)
+    }
+}
+
+impl Display for ProfileReport {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:>10}  {:>12}  function", "calls", "time")?;
+        for (name, entry) in &self.0 {
+            write!(
+                f,
+                "\n{:>10}  {:>12}  {name}",
+                entry.calls,
+                format!("{:.3?}", entry.time)
+            )?;
+        }
+        Ok(())
+    }
+}
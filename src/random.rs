@@ -0,0 +1,65 @@
+use core::fmt::Debug;
+#[cfg(feature = "std")]
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+};
+
+use crate::shared::{Shared, Slot};
+
+/// Random number generator shared between an environment and every closure
+/// captured from it, so that seeding it makes a whole program deterministic
+#[derive(Clone)]
+pub struct Rng(Shared<Slot<u64>>);
+
+impl Rng {
+    pub fn seeded(seed: u64) -> Self {
+        Self(Shared::new(Slot::new(seed)))
+    }
+
+    pub fn seed(&self, seed: u64) {
+        self.0.set(seed)
+    }
+
+    /// Next output of the SplitMix64 generator
+    pub fn next_u64(&self) -> u64 {
+        let state = self.0.get().wrapping_add(0x9e3779b97f4a7c15);
+        self.0.set(state);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniformly distributed in `[0, 1)`
+    pub fn next_f64(&self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+impl Default for Rng {
+    #[cfg(feature = "std")]
+    fn default() -> Self {
+        // The standard library seeds each `RandomState` from the OS
+        Self::seeded(RandomState::new().build_hasher().finish())
+    }
+
+    /// Without std there is nothing to seed from, so embedders should call
+    /// `seed` with what entropy their platform has
+    #[cfg(not(feature = "std"))]
+    fn default() -> Self {
+        Self::seeded(0)
+    }
+}
+
+impl PartialEq for Rng {
+    fn eq(&self, other: &Self) -> bool {
+        Shared::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Debug for Rng {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Rng")
+    }
+}
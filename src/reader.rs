@@ -0,0 +1,170 @@
+//! Reader macros: prefixes which change how the parser reads what follows
+//! them. The standard ones are
+//!
+//! - `'x` reads as `(' x)`
+//! - `` `x `` reads as `(quasiquote x)` and `,x` as `(unquote x)`
+//! - `#(+ % 1)` reads as `(λ (%) (+ % 1))`, with `%1`, `%2`… for several
+//!   arguments
+//! - `:name` reads as a keyword
+//!
+//! Layers and embedders can add their own to an environment's table, which
+//! `evaluate` parses with.
+
+use anyhow::{bail, Result};
+
+use crate::{
+    prelude::*,
+    shared::{Mutable, Shared},
+    Keyword, LispExpression, List, Symbol,
+};
+
+/// What a reader macro does with the source after its prefix
+pub enum ReaderMacro<E> {
+    /// Transforms the expression read after the prefix, which may be written
+    /// attached to it as in `,x`. A prefix ending in `(` opens a list, which
+    /// is the expression transformed.
+    Expression(fn(E) -> Result<E>),
+    /// Reads the rest of a token starting with the prefix, or gives `None`
+    /// for the token to be read as usual
+    Token(fn(&str) -> Option<E>),
+}
+
+impl<E> Clone for ReaderMacro<E> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<E> Copy for ReaderMacro<E> {}
+
+type Table<E> = Vec<(String, ReaderMacro<E>)>;
+
+/// Reader macros by prefix. Clones share one table, so macros added by a
+/// layer apply wherever its environment is used.
+#[derive(Clone)]
+pub struct ReaderMacros<E>(Shared<Mutable<Table<E>>>);
+
+impl<E: LispExpression> Default for ReaderMacros<E> {
+    fn default() -> Self {
+        ReaderMacros(Shared::new(Mutable::new(vec![
+            ("'".to_owned(), ReaderMacro::Expression(quote)),
+            ("`".to_owned(), ReaderMacro::Expression(quasiquote)),
+            (",".to_owned(), ReaderMacro::Expression(unquote)),
+            ("#(".to_owned(), ReaderMacro::Expression(anonymous_function)),
+            (":".to_owned(), ReaderMacro::Token(keyword)),
+        ])))
+    }
+}
+
+impl<E> PartialEq for ReaderMacros<E> {
+    fn eq(&self, other: &Self) -> bool {
+        Shared::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl<E> ReaderMacros<E> {
+    /// A table without even the standard reader macros
+    pub fn empty() -> Self {
+        ReaderMacros(Shared::new(Mutable::new(Vec::new())))
+    }
+
+    /// Adds a reader macro, replacing any with the same prefix
+    pub fn set(&self, prefix: impl Into<String>, reader_macro: ReaderMacro<E>) {
+        let prefix = prefix.into();
+        let mut macros = self.0.borrow_mut();
+        macros.retain(|(existing, _)| *existing != prefix);
+        macros.push((prefix, reader_macro));
+    }
+
+    /// Removes the reader macro for a prefix, returning whether there was one
+    pub fn remove(&self, prefix: &str) -> bool {
+        let mut macros = self.0.borrow_mut();
+        let before = macros.len();
+        macros.retain(|(existing, _)| existing != prefix);
+        macros.len() != before
+    }
+
+    pub fn prefixes(&self) -> Vec<String> {
+        self.0
+            .borrow()
+            .iter()
+            .map(|(prefix, _)| prefix.clone())
+            .collect()
+    }
+
+    /// The reader macro with the longest prefix that `token` starts with
+    pub(crate) fn find(&self, token: &str) -> Option<(usize, ReaderMacro<E>)> {
+        self.0
+            .borrow()
+            .iter()
+            .filter(|(prefix, _)| token.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(prefix, reader_macro)| (prefix.len(), *reader_macro))
+    }
+}
+
+fn wrap<E: LispExpression>(head: &str, expression: E) -> E {
+    List::new(vec![Symbol::from(head).into(), expression]).into()
+}
+
+fn quote<E: LispExpression>(expression: E) -> Result<E> {
+    Ok(wrap("'", expression))
+}
+
+fn quasiquote<E: LispExpression>(expression: E) -> Result<E> {
+    Ok(wrap("quasiquote", expression))
+}
+
+fn unquote<E: LispExpression>(expression: E) -> Result<E> {
+    Ok(wrap("unquote", expression))
+}
+
+fn keyword<E: LispExpression>(name: &str) -> Option<E> {
+    (!name.is_empty()).then(|| Keyword(name.to_owned()).into())
+}
+
+/// The number of a `%` argument in an anonymous function, 0 for plain `%`
+fn argument_number(symbol: &Symbol) -> Option<usize> {
+    match symbol.0.strip_prefix('%')? {
+        "" => Some(0),
+        number => number.parse().ok().filter(|n| *n > 0),
+    }
+}
+
+/// The highest `%` argument used anywhere in `expression`
+fn highest_argument<E: LispExpression>(expression: &E, plain: &mut bool) -> usize {
+    if let Ok(list) = expression.as_list() {
+        list.0
+            .iter()
+            .map(|e| highest_argument(e, plain))
+            .max()
+            .unwrap_or(0)
+    } else {
+        match expression.as_symbol().ok().and_then(argument_number) {
+            Some(0) => {
+                *plain = true;
+                0
+            }
+            Some(number) => number,
+            None => 0,
+        }
+    }
+}
+
+fn anonymous_function<E: LispExpression>(body: E) -> Result<E> {
+    let mut plain = false;
+    let highest = highest_argument(&body, &mut plain);
+    let parameters: Vec<E> = match (plain, highest) {
+        (true, 0) => vec![Symbol::from("%").into()],
+        (true, _) => bail!("An anonymous function cannot use both % and numbered arguments"),
+        (false, highest) => (1..=highest)
+            .map(|n| Symbol(format!("%{n}")).into())
+            .collect(),
+    };
+    Ok(List::new(vec![
+        Symbol::from("λ").into(),
+        List::new(parameters).into(),
+        body,
+    ])
+    .into())
+}
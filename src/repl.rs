@@ -1,13 +1,29 @@
-use std::io::{Read, Write};
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::io::IsTerminal;
 use std::path::PathBuf;
 
 use crate::*;
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 
 #[derive(Debug, Default)]
 struct Arguments {
     path: Option<PathBuf>,
     interactive: bool,
+    version: bool,
+    list_builtins: bool,
+    reference_format: ReferenceFormat,
+    audit: bool,
+    test: bool,
+    lsp: bool,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+enum ReferenceFormat {
+    #[default]
+    Text,
+    Json,
+    Markdown,
 }
 
 fn get_arguments() -> Arguments {
@@ -17,84 +33,670 @@ fn get_arguments() -> Arguments {
     for argument in std::env::args().skip(1) {
         if argument == "-i" {
             arguments.interactive = true;
+        } else if argument == "--version" {
+            arguments.version = true;
+        } else if argument == "--audit" {
+            arguments.audit = true;
+        } else if argument == "--test" {
+            arguments.test = true;
+        } else if argument == "--lsp" {
+            arguments.lsp = true;
+        } else if argument == "--list-builtins" {
+            arguments.list_builtins = true;
+        } else if argument == "--json" {
+            arguments.reference_format = ReferenceFormat::Json;
+        } else if argument == "--markdown" {
+            arguments.reference_format = ReferenceFormat::Markdown;
         } else {
             arguments.path = Some(argument.into());
         }
     }
-    if arguments.path.is_none() {
+    if arguments.path.is_none()
+        && !arguments.list_builtins
+        && !arguments.audit
+        && !arguments.test
+        && !arguments.lsp
+    {
         arguments.interactive = true;
     }
     arguments
 }
 
-fn print<E>(arguments: &[E], _env: &mut Environment<E>) -> Result<E>
+pub(crate) fn json_string(text: &str) -> String {
+    let mut escaped = String::from('"');
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+/// Reference of every builtin in `environment`, generated from their docs
+fn builtin_reference<E>(environment: &Environment<E>, format: ReferenceFormat) -> String
+where
+    E: LispExpression,
+{
+    let builtins = environment.builtins();
+    let mut output = String::new();
+    match format {
+        ReferenceFormat::Text => {
+            for BuiltinEntry { symbol, kind, doc } in builtins {
+                output.push_str(&format!("{}  ({kind}, {})\n", symbol.0, doc.layer));
+                if !doc.signature.is_empty() {
+                    output.push_str(&format!("    {}\n", doc.signature));
+                }
+                if !doc.description.is_empty() {
+                    output.push_str(&format!("    {}\n", doc.description));
+                }
+            }
+        }
+        ReferenceFormat::Json => {
+            let entries: Vec<String> = builtins
+                .into_iter()
+                .map(|BuiltinEntry { symbol, kind, doc }| {
+                    format!(
+                        "  {{\"name\": {}, \"kind\": {}, \"signature\": {}, \"description\": {}, \"layer\": {}}}",
+                        json_string(&symbol.0),
+                        json_string(kind),
+                        json_string(doc.signature),
+                        json_string(doc.description),
+                        json_string(doc.layer)
+                    )
+                })
+                .collect();
+            output.push_str(&format!("[\n{}\n]\n", entries.join(",\n")));
+        }
+        ReferenceFormat::Markdown => {
+            output.push_str("| Name | Kind | Signature | Description | Layer |\n");
+            output.push_str("| --- | --- | --- | --- | --- |\n");
+            for BuiltinEntry { symbol, kind, doc } in builtins {
+                let signature = if doc.signature.is_empty() {
+                    String::new()
+                } else {
+                    format!("`{}`", doc.signature)
+                };
+                output.push_str(&format!(
+                    "| `{}` | {kind} | {signature} | {} | {} |\n",
+                    symbol.0.replace('|', "\\|"),
+                    doc.description,
+                    doc.layer
+                ));
+            }
+        }
+    }
+    output
+}
+
+fn save_session<E>(path: &str, environment: &Environment<E>) -> Result<()>
+where
+    E: LispExpression,
+{
+    let source = environment.serialize_definitions()?;
+    std::fs::write(path, source).with_context(|| anyhow!("Could not write session to {path}"))
+}
+
+fn restore_session<E>(path: &str, environment: &mut Environment<E>) -> Result<()>
+where
+    E: LispExpression,
+{
+    let source = std::fs::read_to_string(path)
+        .with_context(|| anyhow!("Could not read session from {path}"))?;
+    evaluate(&source, environment)?;
+    Ok(())
+}
+
+/// Number of single-character edits needed to turn `a` into `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// A one-line remedy for errors the REPL knows how to fix
+fn suggestion<E>(error: &anyhow::Error, environment: &Environment<E>) -> Option<String> {
+    for cause in error.chain() {
+        if let Some(UnboundVariable(symbol)) = cause.downcast_ref() {
+            let closest = environment
+                .inner
+                .keys()
+                .chain(environment.aliases.keys())
+                .map(|candidate| (edit_distance(&symbol.0, &candidate.0), &candidate.0))
+                .filter(|(distance, _)| {
+                    *distance <= (symbol.0.len() / 3).max(1) && *distance < symbol.0.len()
+                })
+                .min()?;
+            return Some(format!("did you mean `{}`?", closest.1));
+        }
+        if let Some(arity) = cause.downcast_ref::<ArityError>() {
+            let parameters: Vec<&str> = arity.parameters.iter().map(|p| p.0.as_str()).collect();
+            let head = arity.name.as_ref().map_or("…", |name| name.0.as_str());
+            return Some(format!(
+                "this {} takes at most {} arguments: ({head} {})",
+                arity.callee,
+                parameters.len(),
+                parameters.join(" ")
+            ));
+        }
+        if let Some(unbalanced) = cause.downcast_ref::<UnbalancedParens>() {
+            return Some(match unbalanced {
+                UnbalancedParens::Unclosed {
+                    line,
+                    column,
+                    close,
+                    ..
+                } => {
+                    format!(
+                        "add a `{close}` to close the list opened at line {line}, column {column}"
+                    )
+                }
+                UnbalancedParens::UnexpectedClose {
+                    line,
+                    column,
+                    close,
+                    ..
+                } => {
+                    let open = if *close == ')' { '(' } else { '[' };
+                    format!(
+                        "remove the `{close}` at line {line}, column {column} or add a matching `{open}`"
+                    )
+                }
+            });
+        }
+        if let Some(UnterminatedString { line, column, .. }) = cause.downcast_ref() {
+            return Some(format!(
+                "add a `\"` to close the string opened at line {line}, column {column}"
+            ));
+        }
+        if let Some(ProtectedBinding(symbol)) = cause.downcast_ref() {
+            return Some(format!(
+                "use (set!-unsafe '{symbol} …) if you really mean to replace the builtin"
+            ));
+        }
+        if let Some(RecursionLimit::Evaluation { .. }) = cause.downcast_ref() {
+            return Some("check for recursion without a base case".to_owned());
+        }
+    }
+    None
+}
+
+/// The `#env` listing: user definitions, or with `--all` every binding
+/// grouped by the layer it came from, in either case only those matching a
+/// name or a `prefix*` if one is given
+fn env_listing<E>(
+    environment: &Environment<E>,
+    layer: fn(&mut Environment<E>),
+    options: &str,
+) -> String
 where
     E: LispExpression,
 {
-    for argument in arguments {
-        println!("{argument}");
+    let mut all = false;
+    let mut pattern = None;
+    for option in options.split_whitespace() {
+        match option {
+            "--all" => all = true,
+            _ => pattern = Some(option),
+        }
+    }
+    let matches = |symbol: &Symbol| match pattern {
+        Some(pattern) => match pattern.strip_suffix('*') {
+            Some(prefix) => symbol.0.starts_with(prefix),
+            None => symbol.0 == pattern,
+        },
+        None => true,
+    };
+
+    // Bindings the layer makes itself and which have not been redefined
+    let mut fresh = Environment::default();
+    *fresh.config.borrow_mut() = environment.config.borrow().clone();
+    layer(&mut fresh);
+    let mut groups: BTreeMap<&str, Vec<&Symbol>> = BTreeMap::new();
+    let mut user = Vec::new();
+    let mut hidden = 0;
+    for (symbol, value) in &environment.inner {
+        if !matches(symbol) {
+            continue;
+        }
+        if fresh.inner.get(symbol) != Some(value) {
+            user.push(symbol);
+        } else if all {
+            groups.entry(layer_of(value)).or_default().push(symbol);
+        } else {
+            hidden += 1;
+        }
+    }
+
+    let mut listing = Vec::new();
+    if !user.is_empty() {
+        listing.push(env_group(environment, "user", user));
     }
-    Ok(List(vec![]).into())
+    for (name, symbols) in groups {
+        listing.push(env_group(environment, name, symbols));
+    }
+    if hidden > 0 {
+        listing.push(format!("({hidden} builtins hidden, see #env --all)"));
+    }
+    if listing.is_empty() {
+        return "Nothing is bound which matches".to_owned();
+    }
+    listing.join("\n\n")
 }
 
-pub fn run_repl<E>(environment: &mut Environment<E>) -> Result<()>
+/// The layer a builtin value came from, as far as its documentation says,
+/// with the values which are not functions grouped together
+fn layer_of<E: LispExpression>(value: &E) -> &'static str {
+    let layer = if let Ok(function) = ToAndFrom::<BuiltinFunction<E>>::try_into_atom(value) {
+        function.doc.layer
+    } else if let Ok(function) = ToAndFrom::<BuiltinMacro<E>>::try_into_atom(value) {
+        function.doc.layer
+    } else if let Ok(function) = ToAndFrom::<AsyncBuiltinFunction<E>>::try_into_atom(value) {
+        function.doc.layer
+    } else {
+        ""
+    };
+    if layer.is_empty() {
+        "constants"
+    } else {
+        layer
+    }
+}
+
+fn env_group<E>(environment: &Environment<E>, name: &str, mut symbols: Vec<&Symbol>) -> String
+where
+    E: LispExpression,
+{
+    symbols.sort();
+    let longest = symbols.iter().map(|s| s.len()).max().unwrap_or(0);
+    let mut group = format!("; {name}");
+    for symbol in symbols {
+        group.push_str(&format!(
+            "\n{:>longest$} -> {}",
+            symbol.0, environment.inner[symbol]
+        ));
+        let aliases: Vec<&str> = environment
+            .aliases_of(symbol)
+            .into_iter()
+            .filter(|alias| !environment.inner.contains_key(*alias))
+            .map(|alias| alias.0.as_str())
+            .collect();
+        if !aliases.is_empty() {
+            group.push_str(&format!(" (also {})", aliases.join(", ")));
+        }
+    }
+    group
+}
+
+/// Prints the warnings collected since the last, in yellow
+fn report_warnings<E>(environment: &Environment<E>) -> Result<()> {
+    for warning in environment.diagnostics.take() {
+        output(environment, format!("\x1b[0;33m{warning}\x1b[0m"))?;
+    }
+    Ok(())
+}
+
+/// Prints an error followed by a suggested fix, if there is one
+fn report_error<E>(environment: &Environment<E>, error: anyhow::Error) -> Result<()> {
+    output(environment, format!("{error:?}"))?;
+    if let Some(suggestion) = suggestion(&error, environment) {
+        output(environment, format!("hint: {suggestion}"))?;
+    }
+    Ok(())
+}
+
+/// Loads the plugins in `$SHALLOT_PLUGINS`, or in `~/.shallot/plugins` if
+/// that exists. Failures, including an unreadable directory, are written to
+/// stderr and don't stop the REPL from starting.
+#[cfg(all(unix, feature = "plugins"))]
+fn load_plugins<E: LispExpression>(environment: &mut Environment<E>) {
+    let directory = match std::env::var_os("SHALLOT_PLUGINS") {
+        Some(directory) => PathBuf::from(directory),
+        None => {
+            let Some(home) = std::env::var_os("HOME") else {
+                return;
+            };
+            let directory = PathBuf::from(home).join(".shallot").join("plugins");
+            if !directory.is_dir() {
+                return;
+            }
+            directory
+        }
+    };
+    // Safety: whoever put the libraries in the directory built them to be
+    // loaded by this interpreter
+    let loaded = match unsafe { plugin::load_directory(&directory, environment) } {
+        Ok(loaded) => loaded,
+        Err(error) => vec![Err(error)],
+    };
+    for error in loaded.into_iter().filter_map(Result::err) {
+        // There is nowhere left to report failing to write to stderr
+        let _ = environment.io.write_stderr(&format!("{error:#}\n"));
+    }
+}
+
+#[cfg(unix)]
+static INTERRUPTED: std::sync::OnceLock<CancellationToken> = std::sync::OnceLock::new();
+
+/// Makes Ctrl-C cancel the evaluation in progress rather than end the process
+#[cfg(unix)]
+fn cancel_on_interrupt(token: &CancellationToken) {
+    const SIGINT: i32 = 2;
+    extern "C" {
+        fn signal(signal: i32, handler: extern "C" fn(i32)) -> usize;
+    }
+    extern "C" fn handle_interrupt(_: i32) {
+        if let Some(token) = INTERRUPTED.get() {
+            token.cancel();
+        }
+    }
+    if INTERRUPTED.set(token.clone()).is_ok() {
+        // Safety: the handler only stores to an atomic flag
+        unsafe {
+            signal(SIGINT, handle_interrupt);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn cancel_on_interrupt(_token: &CancellationToken) {}
+
+/// Writes a line of REPL output through the environment's I/O handler
+fn output<E>(environment: &Environment<E>, text: impl Display) -> Result<()> {
+    environment
+        .io
+        .write_stdout(&format!("{text}\n"))
+        .context("Could not write output")
+}
+
+pub fn run_repl<E>(environment: &mut Environment<E>, layer: fn(&mut Environment<E>)) -> Result<()>
 where
     E: LispExpression,
 {
     let arguments = get_arguments();
-    environment.set(
-        Symbol("print".to_owned()),
-        BuiltinFunction::new("print", print),
-    );
+    if arguments.version {
+        return output(environment, version_info(environment));
+    }
+    if arguments.lsp {
+        return crate::lsp::run_lsp(environment);
+    }
+    #[cfg(all(unix, feature = "plugins"))]
+    load_plugins(environment);
+    if arguments.list_builtins {
+        let reference = builtin_reference(environment, arguments.reference_format);
+        return output(environment, reference.trim_end());
+    }
 
-    if let Some(path) = arguments.path {
-        let input = match path.to_str() {
-            Some("-") => {
-                let mut input = String::new();
-                std::io::stdin()
-                    .read_to_string(&mut input)
-                    .context("Could not read line")?;
-                input
-            }
-            _ => std::fs::read_to_string(&path).with_context(|| {
-                anyhow!(
-                    "Could not read from {}",
-                    path.to_str().unwrap_or("<Non-UTF8-Path>")
-                )
-            })?,
-        };
+    if arguments.audit {
+        let path = arguments
+            .path
+            .context("An audit needs the path of a script")?;
+        let source = std::fs::read_to_string(&path)
+            .with_context(|| anyhow!("Could not read from {}", path.display()))?;
+        let report = audit(&source, environment)?;
+        if report.is_empty() {
+            return output(environment, "No capabilities required");
+        }
+        for (capability, builtins) in report {
+            let builtins: Vec<String> = builtins.into_iter().collect();
+            output(
+                environment,
+                format!("{capability}: {}", builtins.join(", ")),
+            )?;
+        }
+        return Ok(());
+    }
 
-        let result = evaluate(&input, environment)?;
+    if arguments.test {
+        let path = arguments
+            .path
+            .context("Testing needs the path of a script")?;
+        evaluate_file(&path, environment)?;
+        let outcomes = builtins::testing::run_tests(environment);
+        let failed = outcomes
+            .iter()
+            .filter(|(_, outcome)| outcome.is_err())
+            .count();
+        for (name, outcome) in &outcomes {
+            match outcome {
+                Ok(()) => output(environment, format!("ok {}", name.0))?,
+                Err(error) => output(environment, format!("FAILED {}\n    {error:#}", name.0))?,
+            }
+        }
+        output(
+            environment,
+            format!("{} passed, {failed} failed", outcomes.len() - failed),
+        )?;
+        if failed > 0 {
+            bail!("{failed} of {} tests failed", outcomes.len());
+        }
+        return Ok(());
+    }
 
-        println!("{}", result);
+    if let Some(path) = arguments.path {
+        let result = if path.to_str() == Some("-") {
+            let mut input = String::new();
+            while let Some(line) = environment.io.read_line().context("Could not read line")? {
+                input.push_str(&line);
+            }
+            evaluate(&input, environment)
+        } else {
+            evaluate_file(&path, environment)
+        };
+        report_warnings(environment)?;
+        let precision = environment.config.borrow().number_precision;
+        let result = result?;
+        let text = environment.print_within_limits(|| printer::echo_text(&result, precision));
+        output(environment, text?)?;
     }
     if arguments.interactive {
+        cancel_on_interrupt(&environment.cancellation);
+        if std::io::stdin().is_terminal() {
+            output(
+                environment,
+                format!("shallot {VERSION} (type #version for details)"),
+            )?;
+        }
+        // Lines are fed to the parser until they make whole expressions
+        let mut parser = Parser::with_reader_macros(environment.reader_macros.clone());
+        let mut checkpoints = Vec::new();
         'repl: loop {
-            print!("🧅 ");
-            std::io::stdout()
-                .flush()
-                .context("Could not flush prompt")?;
-            let mut input_line = String::new();
-            std::io::stdin()
-                .read_line(&mut input_line)
-                .context("Could not read line")?;
-            if input_line.is_empty() {
+            let continuing = parser.is_incomplete();
+            environment
+                .io
+                .write_stdout(if continuing { "…  " } else { "🧅 " })
+                .context("Could not write prompt")?;
+            let Some(input_line) = environment.io.read_line().context("Could not read line")?
+            else {
+                if continuing {
+                    report_error(environment, IncompleteInput.into())?;
+                }
                 break 'repl;
+            };
+            if continuing {
+                parser.feed(&input_line);
+                evaluate_parsed(&mut parser, environment)?;
+                continue 'repl;
             }
             if input_line.chars().all(|c| c.is_whitespace()) {
                 continue;
             }
-            if input_line == "#env\n" {
-                println!("{environment}");
+            if let Some(options) = input_line.strip_prefix("#env") {
+                if options.starts_with(char::is_whitespace) {
+                    output(environment, env_listing(environment, layer, options))?;
+                    continue 'repl;
+                }
+            }
+            if input_line == "#version\n" {
+                output(environment, version_info(environment))?;
                 continue 'repl;
             }
-            let result = evaluate(&input_line, environment);
-            match result {
-                Ok(result) => println!("{result}"),
-                Err(error) => println!("{error:?}"),
+            if input_line == "#reload-layer\n" {
+                environment.reload_layer(layer);
+                output(environment, "Reloaded builtins")?;
+                continue 'repl;
+            }
+            if let Some(input) = input_line.strip_prefix("#debug ") {
+                environment.debugger.start(environment);
+                let result = evaluate(input, environment);
+                environment.debugger.stop();
+                report_warnings(environment)?;
+                let precision = environment.config.borrow().number_precision;
+                let text = result.and_then(|result| {
+                    environment.print_within_limits(|| printer::echo_text(&result, precision))
+                });
+                match text {
+                    Ok(text) => output(environment, text)?,
+                    Err(error) => report_error(environment, error)?,
+                }
+                continue 'repl;
+            }
+            if input_line == "#checkpoint\n" {
+                checkpoints.push(environment.snapshot());
+                output(
+                    environment,
+                    format!(
+                        "Saved checkpoint {}, #rollback to return to it",
+                        checkpoints.len()
+                    ),
+                )?;
+                continue 'repl;
+            }
+            if input_line == "#rollback\n" {
+                match checkpoints.pop() {
+                    Some(snapshot) => {
+                        environment.restore(snapshot);
+                        let message =
+                            format!("Rolled back to checkpoint {}", checkpoints.len() + 1);
+                        output(environment, message)?;
+                    }
+                    None => output(environment, "No checkpoint to roll back to")?,
+                }
+                continue 'repl;
+            }
+            if let Some(precision) = input_line.strip_prefix("#precision ") {
+                match precision.trim() {
+                    "off" => environment.config.borrow_mut().number_precision = None,
+                    precision => match precision.parse() {
+                        Ok(precision) => {
+                            environment.config.borrow_mut().number_precision = Some(precision)
+                        }
+                        Err(_) => {
+                            output(
+                                environment,
+                                "Use #precision with a number of decimals or off",
+                            )?;
+                            continue 'repl;
+                        }
+                    },
+                }
+                let precision = environment.config.borrow().number_precision;
+                let message = match precision {
+                    Some(precision) => format!("Displaying numbers with {precision} decimals"),
+                    None => "Displaying numbers with as many decimals as needed".to_owned(),
+                };
+                output(environment, message)?;
+                continue 'repl;
+            }
+            if input_line == "#profile on\n" {
+                environment.profiler.enable();
+                output(environment, "Profiling every evaluation until #profile off")?;
+                continue 'repl;
+            }
+            if input_line == "#profile off\n" {
+                match environment.profiler.disable() {
+                    Some(report) => output(environment, report)?,
+                    None => output(environment, "Profiling was not on")?,
+                }
+                continue 'repl;
+            }
+            if input_line == "#trace on\n" {
+                environment.tracer.trace_all(true);
+                output(environment, "Tracing every function call until #trace off")?;
+                continue 'repl;
+            }
+            if input_line == "#trace off\n" {
+                environment.tracer.trace_all(false);
+                output(environment, "Tracing only functions given to trace")?;
+                continue 'repl;
             }
+            if let Some(path) = input_line.strip_prefix("#save ") {
+                if let Err(error) = save_session(path.trim(), environment) {
+                    report_error(environment, error)?;
+                }
+                continue 'repl;
+            }
+            if let Some(path) = input_line.strip_prefix("#export ") {
+                let path = path.trim();
+                match std::fs::write(path, environment.dump_source()) {
+                    Ok(()) => output(environment, format!("Exported definitions to {path}"))?,
+                    Err(error) => report_error(
+                        environment,
+                        anyhow!(error).context(format!("Could not export definitions to {path}")),
+                    )?,
+                }
+                continue 'repl;
+            }
+            if let Some(path) = input_line.strip_prefix("#restore ") {
+                if let Err(error) = restore_session(path.trim(), environment) {
+                    report_error(environment, error)?;
+                }
+                continue 'repl;
+            }
+            if let Some((command, rest)) = environment.repl_commands.find(&input_line) {
+                match command(rest, environment) {
+                    Ok(text) => output(environment, text)?,
+                    Err(error) => report_error(environment, error)?,
+                }
+                continue 'repl;
+            }
+            parser.feed(&input_line);
+            evaluate_parsed(&mut parser, environment)?;
         }
     }
 
     Ok(())
 }
+
+/// Evaluates each whole expression the parser has, leaving any incomplete
+/// one for more lines to finish
+fn evaluate_parsed<E>(parser: &mut Parser<E>, environment: &mut Environment<E>) -> Result<()>
+where
+    E: LispExpression,
+{
+    loop {
+        let expression = match parser.next_expression() {
+            Ok(Some(expression)) => expression,
+            Ok(None) => return Ok(()),
+            Err(error) => {
+                return report_error(environment, error.context("Could not parse input"));
+            }
+        };
+        // An interrupt at the prompt should not cancel the next evaluation
+        environment.cancellation.reset();
+        let result = expression
+            .eval(environment)
+            .with_context(|| anyhow!("Could not evaluate input {}", expression));
+        report_warnings(environment)?;
+        let precision = environment.config.borrow().number_precision;
+        let text = result.and_then(|result| {
+            environment.print_within_limits(|| printer::echo_text(&result, precision))
+        });
+        match text {
+            Ok(text) => output(environment, text)?,
+            Err(error) => report_error(environment, error)?,
+        }
+    }
+}
@@ -0,0 +1,150 @@
+//! Serde support for the data subset of expressions: numbers, strings,
+//! characters, symbols, keywords and lists. Symbols are written as
+//! `{"symbol": name}` and keywords as `{"keyword": name}` so that they stay
+//! distinct from strings, and other maps read as association lists.
+//! Characters are written as chars, which most formats store as
+//! one-character strings. Sets and vectors are written as sequences, so read
+//! back as lists. Callables cannot be serialized.
+
+use core::{fmt, marker::PhantomData};
+use serde::{
+    de::{self, MapAccess, SeqAccess, Visitor},
+    ser::{self, SerializeMap, SerializeSeq},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use crate::{atoms::*, expression::ToAndFrom, prelude::*, LispExpression};
+
+/// Serializes the borrowed expression, e.g. `serde_json::to_string(&Serialized(&expr))`
+pub struct Serialized<'a, E>(pub &'a E);
+
+/// Deserializes into an expression, e.g.
+/// `serde_json::from_str::<Deserialized<Expression>>(json)?.0`
+pub struct Deserialized<E>(pub E);
+
+impl<E: LispExpression> Serialize for Serialized<'_, E> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let expression = self.0;
+        if let Ok(number) = ToAndFrom::<Number>::try_into_atom(expression) {
+            serializer.serialize_f64(number.0)
+        } else if let Ok(string) = ToAndFrom::<Str>::try_into_atom(expression) {
+            serializer.serialize_str(&string.0)
+        } else if let Ok(c) = ToAndFrom::<Char>::try_into_atom(expression) {
+            serializer.serialize_char(c.0)
+        } else if let Ok(symbol) = expression.as_symbol() {
+            let mut map = serializer.serialize_map(Some(1))?;
+            map.serialize_entry("symbol", &symbol.0)?;
+            map.end()
+        } else if let Ok(keyword) = ToAndFrom::<Keyword>::try_into_atom(expression) {
+            let mut map = serializer.serialize_map(Some(1))?;
+            map.serialize_entry("keyword", &keyword.0)?;
+            map.end()
+        } else if let Ok(list) = expression.as_list() {
+            let mut seq = serializer.serialize_seq(Some(list.0.len()))?;
+            for element in list.0.iter() {
+                seq.serialize_element(&Serialized(element))?;
+            }
+            seq.end()
+        } else if let Ok(set) = ToAndFrom::<Set<E>>::try_into_atom(expression) {
+            let mut seq = serializer.serialize_seq(Some(set.len()))?;
+            for element in set.iter() {
+                seq.serialize_element(&Serialized(element))?;
+            }
+            seq.end()
+        } else if let Ok(vector) = ToAndFrom::<Vector<E>>::try_into_atom(expression) {
+            let elements = vector.0.borrow();
+            let mut seq = serializer.serialize_seq(Some(elements.len()))?;
+            for element in elements.iter() {
+                seq.serialize_element(&Serialized(element))?;
+            }
+            seq.end()
+        } else {
+            Err(ser::Error::custom(format!(
+                "Cannot serialize {}",
+                expression.variant()
+            )))
+        }
+    }
+}
+
+impl<'de, E: LispExpression> Deserialize<'de> for Deserialized<E> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer
+            .deserialize_any(ExpressionVisitor(PhantomData))
+            .map(Deserialized)
+    }
+}
+
+struct ExpressionVisitor<E>(PhantomData<E>);
+
+impl<'de, E: LispExpression> Visitor<'de> for ExpressionVisitor<E> {
+    type Value = E;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a number, string, symbol, list or map")
+    }
+
+    fn visit_bool<Err: de::Error>(self, value: bool) -> Result<E, Err> {
+        Ok(if value { Number(1.).into() } else { E::null() })
+    }
+
+    fn visit_i64<Err: de::Error>(self, value: i64) -> Result<E, Err> {
+        Ok(Number(value as f64).into())
+    }
+
+    fn visit_u64<Err: de::Error>(self, value: u64) -> Result<E, Err> {
+        Ok(Number(value as f64).into())
+    }
+
+    fn visit_f64<Err: de::Error>(self, value: f64) -> Result<E, Err> {
+        Ok(Number(value).into())
+    }
+
+    fn visit_str<Err: de::Error>(self, value: &str) -> Result<E, Err> {
+        Ok(Str::from(value).into())
+    }
+
+    fn visit_unit<Err: de::Error>(self) -> Result<E, Err> {
+        Ok(E::null())
+    }
+
+    fn visit_none<Err: de::Error>(self) -> Result<E, Err> {
+        Ok(E::null())
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<E, D::Error> {
+        Deserialized::deserialize(deserializer).map(|d| d.0)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<E, A::Error> {
+        let mut elements = Vec::new();
+        while let Some(Deserialized(element)) = seq.next_element()? {
+            elements.push(element);
+        }
+        Ok(List::new(elements).into())
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<E, A::Error> {
+        let mut pairs: Vec<E> = Vec::new();
+        while let Some((Deserialized(key), Deserialized(value))) =
+            map.next_entry::<Deserialized<E>, Deserialized<E>>()?
+        {
+            pairs.push(List::new(vec![key, value]).into());
+        }
+        // A lone `symbol` or `keyword` key holding a string is how symbols
+        // and keywords are written
+        if let [pair] = pairs.as_slice() {
+            let pair = pair.as_list().map_err(de::Error::custom)?;
+            let key = ToAndFrom::<Str>::try_into_atom(&pair.0[0]);
+            let name = ToAndFrom::<Str>::try_into_atom(&pair.0[1]);
+            if let (Ok(key), Ok(name)) = (key, name) {
+                match key.0.as_str() {
+                    "symbol" => return Ok(Symbol(name.0.clone()).into()),
+                    "keyword" => return Ok(Keyword(name.0.clone()).into()),
+                    _ => {}
+                }
+            }
+        }
+        Ok(List::new(pairs).into())
+    }
+}
@@ -0,0 +1,206 @@
+//! The pointer and interior mutability types that atoms and environments
+//! share state through.
+//!
+//! By default these are `Rc`, `RefCell` and `Cell`, so an environment and the
+//! values made in it stay on one thread. With the `sync` feature they are
+//! `Arc` and locks instead, and every expression and environment is
+//! `Send + Sync`, so that a server can keep an environment per worker thread
+//! or share a read-only one between them.
+
+#[cfg(not(feature = "sync"))]
+mod implementation {
+    pub type Shared<T> = alloc::rc::Rc<T>;
+    pub type Mutable<T> = core::cell::RefCell<T>;
+    pub type Slot<T> = core::cell::Cell<T>;
+    /// A `Slot` for a count of what a thread is doing, such as how deeply it
+    /// is nesting calls, which every thread keeps separately with `sync`
+    pub type PerThread<T> = Slot<T>;
+
+    /// Required of builtin closures, thunks and I/O handlers, so that they
+    /// are `Send + Sync` with the `sync` feature and unconstrained without it
+    pub trait Threadsafe {}
+
+    impl<T: ?Sized> Threadsafe for T {}
+
+    /// A value of any type, as held by foreign atoms
+    pub type AnyValue = dyn core::any::Any;
+
+    impl<T: Copy> super::Modify<T> for Slot<T> {
+        fn modify<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+            let mut value = self.get();
+            let result = f(&mut value);
+            self.set(value);
+            result
+        }
+    }
+}
+
+#[cfg(feature = "sync")]
+mod implementation {
+    use std::{
+        collections::HashMap,
+        fmt::Debug,
+        sync::{
+            Mutex, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError,
+            TryLockResult,
+        },
+        thread::{self, ThreadId},
+    };
+
+    pub type Shared<T> = std::sync::Arc<T>;
+
+    /// Required of builtin closures, thunks and I/O handlers, so that they
+    /// are `Send + Sync` with the `sync` feature and unconstrained without it
+    pub trait Threadsafe: Send + Sync {}
+
+    impl<T: Send + Sync + ?Sized> Threadsafe for T {}
+
+    /// A value of any type, as held by foreign atoms
+    pub type AnyValue = dyn core::any::Any + Send + Sync;
+
+    /// A lock with the interface of a `RefCell`. A panic while it is held
+    /// does not poison it, as a `RefCell` would be usable afterwards too.
+    #[derive(Default)]
+    pub struct Mutable<T: ?Sized>(RwLock<T>);
+
+    impl<T> Mutable<T> {
+        pub fn new(value: T) -> Self {
+            Mutable(RwLock::new(value))
+        }
+    }
+
+    impl<T: ?Sized> Mutable<T> {
+        pub fn borrow(&self) -> RwLockReadGuard<'_, T> {
+            self.0.read().unwrap_or_else(PoisonError::into_inner)
+        }
+
+        pub fn borrow_mut(&self) -> RwLockWriteGuard<'_, T> {
+            self.0.write().unwrap_or_else(PoisonError::into_inner)
+        }
+
+        pub fn try_borrow(&self) -> TryLockResult<RwLockReadGuard<'_, T>> {
+            self.0.try_read().or_else(|error| match error {
+                TryLockError::Poisoned(poisoned) => Ok(poisoned.into_inner()),
+                TryLockError::WouldBlock => Err(TryLockError::WouldBlock),
+            })
+        }
+
+        pub fn try_borrow_mut(&self) -> TryLockResult<RwLockWriteGuard<'_, T>> {
+            self.0.try_write().or_else(|error| match error {
+                TryLockError::Poisoned(poisoned) => Ok(poisoned.into_inner()),
+                TryLockError::WouldBlock => Err(TryLockError::WouldBlock),
+            })
+        }
+    }
+
+    impl<T: PartialEq + ?Sized> PartialEq for Mutable<T> {
+        fn eq(&self, other: &Self) -> bool {
+            *self.borrow() == *other.borrow()
+        }
+    }
+
+    impl<T: Debug + ?Sized> Debug for Mutable<T> {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            self.borrow().fmt(f)
+        }
+    }
+
+    /// A lock with the interface of a `Cell`
+    #[derive(Default)]
+    pub struct Slot<T>(Mutex<T>);
+
+    impl<T: Copy> Slot<T> {
+        pub fn new(value: T) -> Self {
+            Slot(Mutex::new(value))
+        }
+
+        pub fn get(&self) -> T {
+            *self.0.lock().unwrap_or_else(PoisonError::into_inner)
+        }
+
+        pub fn set(&self, value: T) {
+            *self.0.lock().unwrap_or_else(PoisonError::into_inner) = value;
+        }
+    }
+
+    impl<T: Copy> super::Modify<T> for Slot<T> {
+        fn modify<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+            f(&mut self.0.lock().unwrap_or_else(PoisonError::into_inner))
+        }
+    }
+
+    impl<T: Copy + PartialEq> PartialEq for Slot<T> {
+        fn eq(&self, other: &Self) -> bool {
+            self.get() == other.get()
+        }
+    }
+
+    impl<T: Copy + Debug> Debug for Slot<T> {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            self.get().fmt(f)
+        }
+    }
+
+    /// A `Slot` for a count of what a thread is doing, such as how deeply it
+    /// is nesting calls, which every thread keeps separately. A thread whose
+    /// count is back to the default has no entry.
+    #[derive(Default)]
+    pub struct PerThread<T>(Mutex<HashMap<ThreadId, T>>);
+
+    impl<T: Copy + Default + PartialEq> PerThread<T> {
+        pub fn new(value: T) -> Self {
+            let counts = PerThread(Mutex::default());
+            counts.set(value);
+            counts
+        }
+
+        /// The current thread's count
+        pub fn get(&self) -> T {
+            let counts = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+            counts
+                .get(&thread::current().id())
+                .copied()
+                .unwrap_or_default()
+        }
+
+        pub fn set(&self, value: T) {
+            super::Modify::modify(self, |count| *count = value);
+        }
+    }
+
+    impl<T: Copy + Default + PartialEq> super::Modify<T> for PerThread<T> {
+        fn modify<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+            let mut counts = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+            let id = thread::current().id();
+            let count = counts.entry(id).or_default();
+            let result = f(count);
+            if *count == T::default() {
+                counts.remove(&id);
+            }
+            result
+        }
+    }
+
+    impl<T: Copy + Default + PartialEq> PartialEq for PerThread<T> {
+        fn eq(&self, other: &Self) -> bool {
+            self.get() == other.get()
+        }
+    }
+
+    /// Fails to compile if anything in an environment is not `Send + Sync`
+    #[allow(dead_code)]
+    fn threadsafe<E: crate::LispExpression>(
+        environment: crate::Environment<E>,
+    ) -> impl Send + Sync {
+        environment
+    }
+}
+
+pub use implementation::*;
+
+/// Reading and changing a `Slot` in one step. With `sync` its lock is held
+/// throughout, so threads sharing a counter can't both read it before
+/// either writes.
+pub trait Modify<T> {
+    fn modify<R>(&self, f: impl FnOnce(&mut T) -> R) -> R;
+}
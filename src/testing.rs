@@ -0,0 +1,104 @@
+//! Golden tests of the language itself. Each `name.shl` fixture in a
+//! directory is evaluated, and what it prints followed by its value or error
+//! is compared with `name.expected` beside it. Run with `SHALLOT_BLESS=1` to
+//! write the expected files from the current output instead.
+
+use anyhow::{anyhow, Context, Result};
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    evaluate_file,
+    printer::strip_colours,
+    shared::{Mutable, Shared},
+    CapturedIo, Environment, LispExpression,
+};
+
+/// A fixture whose output differs from its expected file
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mismatch {
+    pub fixture: PathBuf,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl Display for Mismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} differs from its expected output",
+            self.fixture.display()
+        )?;
+        writeln!(f, "--- expected\n{}", self.expected)?;
+        write!(f, "--- actual\n{}", self.actual)
+    }
+}
+
+/// Evaluates a fixture in a fresh environment with the layer's builtins,
+/// returning its printed output and warnings followed by its value, or its error's root
+/// cause so that the output does not depend on where the fixture is
+pub fn run_fixture<E>(path: impl AsRef<Path>, layer: fn(&mut Environment<E>)) -> String
+where
+    E: LispExpression,
+{
+    let mut env = Environment::default();
+    layer(&mut env);
+    let io = Shared::new(Mutable::new(CapturedIo::default()));
+    env.io = io.clone().into();
+    let result = evaluate_file(path, &mut env);
+    let io = io.borrow();
+    let mut output = format!("{}{}", io.stdout, io.stderr);
+    for warning in env.diagnostics.take() {
+        output.push_str(&format!("{warning}\n"));
+    }
+    match result {
+        Ok(value) => output.push_str(&format!("{value}\n")),
+        Err(error) => output.push_str(&format!("error: {}\n", error.root_cause())),
+    }
+    strip_colours(&output)
+}
+
+/// Runs every fixture in the directory in name order, returning those whose
+/// output does not match, or blessing them all if `SHALLOT_BLESS` is set
+pub fn check_fixtures<E>(
+    directory: impl AsRef<Path>,
+    layer: fn(&mut Environment<E>),
+) -> Result<Vec<Mismatch>>
+where
+    E: LispExpression,
+{
+    let directory = directory.as_ref();
+    let bless = std::env::var_os("SHALLOT_BLESS").is_some();
+    let mut fixtures: Vec<PathBuf> = std::fs::read_dir(directory)
+        .with_context(|| anyhow!("Could not read fixtures from {}", directory.display()))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<_>>()?;
+    fixtures.retain(|path| path.extension().is_some_and(|extension| extension == "shl"));
+    fixtures.sort();
+    let mut mismatches = Vec::new();
+    for fixture in fixtures {
+        let actual = run_fixture(&fixture, layer);
+        let expected_path = fixture.with_extension("expected");
+        if bless {
+            std::fs::write(&expected_path, &actual)
+                .with_context(|| anyhow!("Could not write {}", expected_path.display()))?;
+            continue;
+        }
+        let expected = std::fs::read_to_string(&expected_path).with_context(|| {
+            anyhow!(
+                "Could not read {}, run with SHALLOT_BLESS=1 to create it",
+                expected_path.display()
+            )
+        })?;
+        if expected != actual {
+            mismatches.push(Mismatch {
+                fixture,
+                expected,
+                actual,
+            });
+        }
+    }
+    Ok(mismatches)
+}
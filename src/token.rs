@@ -1,44 +1,172 @@
-use std::{
+use core::{
     iter::{Enumerate, Peekable},
     str::Chars,
 };
 
+use crate::{atoms::parse_number, prelude::*};
+
+/// What sort of text a token is, so that the parser need not guess from its value
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenKind {
+    OpenParen,
+    CloseParen,
+    OpenBracket,
+    CloseBracket,
+    /// A string literal, with its quotes. One which runs to the end of the
+    /// input without a closing quote is `UnterminatedString`
+    StringLiteral,
+    UnterminatedString,
+    /// `'`, `` ` `` or `,`, which are always tokens by themselves
+    Quote,
+    /// A comment from `;` to the end of the line
+    Comment,
+    /// Anything else: numbers, symbols, characters, reader macro prefixes
+    Atom,
+}
+
 #[derive(Debug)]
 pub struct Token {
+    pub kind: TokenKind,
     pub value: String,
+    /// Characters before the token in the input
     pub position: usize,
+    /// Where the token starts, counting both from 1
+    pub line: usize,
+    pub column: usize,
+    /// The line and column just past the token's last character
+    pub end_line: usize,
+    pub end_column: usize,
+}
+
+impl Token {
+    /// Where the token starts, as a human would look for it
+    pub fn location(&self) -> String {
+        format!("line {}, column {}", self.line, self.column)
+    }
+
+    /// What the token is for highlighting. Reader macros other than `:` and
+    /// `#(` are not known here, so tokens they read count as symbols.
+    pub fn class(&self) -> TokenClass {
+        match self.kind {
+            TokenKind::OpenParen
+            | TokenKind::CloseParen
+            | TokenKind::OpenBracket
+            | TokenKind::CloseBracket => TokenClass::Paren,
+            TokenKind::StringLiteral | TokenKind::UnterminatedString => TokenClass::String,
+            TokenKind::Quote => TokenClass::Quote,
+            TokenKind::Comment => TokenClass::Comment,
+            TokenKind::Atom => match self.value.as_str() {
+                "#(" => TokenClass::Paren,
+                value if value.starts_with("#\\") => TokenClass::Char,
+                value if value.len() > 1 && value.starts_with(':') => TokenClass::Keyword,
+                value if parse_number(value).is_some() => TokenClass::Number,
+                _ => TokenClass::Symbol,
+            },
+        }
+    }
+}
+
+/// What a token is for highlighting, which unlike its `TokenKind` tells
+/// numbers, symbols and keywords apart
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenClass {
+    /// Parentheses and brackets, including the `#(` of an anonymous function
+    Paren,
+    /// `'` and the other quoting prefixes
+    Quote,
+    Number,
+    String,
+    /// Character literals such as `#\a`
+    Char,
+    Symbol,
+    Keyword,
+    Comment,
+}
+
+/// Where a token is in its input, in characters from the start as
+/// `Token::position` counts them, up to but not including `end`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
 }
 
 pub struct TokenIterator<'a> {
     input: Peekable<Enumerate<Chars<'a>>>,
+    /// Follows behind `input` to count the lines and columns up to each token
+    behind: Chars<'a>,
+    behind_position: usize,
+    line: usize,
+    column: usize,
 }
 
 impl<'a> Iterator for TokenIterator<'a> {
     type Item = Token;
 
     fn next(&mut self) -> Option<Self::Item> {
+        let (kind, value, position) = self.read()?;
+        let (line, column) = self.locate(position);
+        // Reading stops at the first character past the token
+        let end = self.input.peek().map_or(usize::MAX, |c| c.0);
+        let (end_line, end_column) = self.locate(end);
+        Some(Token {
+            kind,
+            value,
+            position,
+            line,
+            column,
+            end_line,
+            end_column,
+        })
+    }
+}
+
+impl<'a> TokenIterator<'a> {
+    /// The line and column of a position at or after the last one located,
+    /// or of the end of the input for a position past it
+    fn locate(&mut self, position: usize) -> (usize, usize) {
+        while self.behind_position < position {
+            match self.behind.next() {
+                Some('\n') => {
+                    self.line += 1;
+                    self.column = 1;
+                }
+                Some(_) => self.column += 1,
+                None => break,
+            }
+            self.behind_position += 1;
+        }
+        (self.line, self.column)
+    }
+
+    /// The kind, text and position of the next token
+    fn read(&mut self) -> Option<(TokenKind, String, usize)> {
         while self.input.next_if(|c| c.1.is_whitespace()).is_some() {}
 
         // NOTE: The first character is not whitespace
         match self.input.peek() {
-            Some((_, '(' | ')' | '\'')) => self.input.next().map(|c| Token {
-                value: c.1.into(),
-                position: c.0,
-            }),
+            Some((_, c @ ('(' | ')' | '[' | ']' | '\'' | '`' | ','))) => {
+                let kind = match c {
+                    '(' => TokenKind::OpenParen,
+                    ')' => TokenKind::CloseParen,
+                    '[' => TokenKind::OpenBracket,
+                    ']' => TokenKind::CloseBracket,
+                    _ => TokenKind::Quote,
+                };
+                self.input.next().map(|c| (kind, c.1.into(), c.0))
+            }
             Some((position, ';')) => {
                 let position = *position;
                 let mut comment_token = String::new();
                 while let Some(c) = self.input.next_if(|c| c.1 != '\n') {
                     comment_token.push(c.1)
                 }
-                Some(Token {
-                    value: comment_token,
-                    position,
-                })
+                Some((TokenKind::Comment, comment_token, position))
             }
             Some((position, '"')) => {
                 let position = *position;
                 let mut multi_word_token = "\"".to_owned();
+                let mut kind = TokenKind::UnterminatedString;
                 self.input.next();
                 loop {
                     match self.input.peek() {
@@ -66,6 +194,7 @@ impl<'a> Iterator for TokenIterator<'a> {
                         Some((_, '"')) => {
                             multi_word_token.push('"');
                             self.input.next();
+                            kind = TokenKind::StringLiteral;
                             break;
                         }
                         Some((_, c)) => {
@@ -75,32 +204,73 @@ impl<'a> Iterator for TokenIterator<'a> {
                         None => break,
                     }
                 }
-                Some(Token {
-                    value: multi_word_token,
-                    position,
-                })
+                Some((kind, multi_word_token, position))
             }
             Some((position, _)) => {
                 let position = *position;
                 let mut token = String::new();
                 while let Some(c) = self
                     .input
-                    .next_if(|c| !(c.1.is_whitespace() || "()".contains(c.1)))
+                    .next_if(|c| !(c.1.is_whitespace() || "()[]".contains(c.1)))
                 {
-                    token.push(c.1)
+                    token.push(c.1);
+                    // A character literal may be a delimiter itself, as in #\(
+                    if token == "#\\" {
+                        if let Some(c) = self.input.next() {
+                            token.push(c.1)
+                        }
+                    }
                 }
-                Some(Token {
-                    value: token,
-                    position,
-                })
+                // `#(` opens the list of an anonymous function
+                if token == "#" {
+                    if let Some(c) = self.input.next_if(|c| c.1 == '(') {
+                        token.push(c.1)
+                    }
+                }
+                Some((TokenKind::Atom, token, position))
             }
             None => None,
         }
     }
 }
 
+/// Skips any comments at the front of `tokens`, so that peeking sees the next
+/// token which is part of an expression
+pub(crate) fn skip_comments<I>(tokens: &mut Peekable<I>)
+where
+    I: Iterator<Item = Token>,
+{
+    while tokens
+        .next_if(|token| token.kind == TokenKind::Comment)
+        .is_some()
+    {}
+}
+
+/// The span and class of each token in `input`, for highlighting source
+/// without parsing it. The text between the spans is whitespace.
+pub fn tokenize_classified(input: &str) -> Vec<(Span, TokenClass)> {
+    let length = input.chars().count();
+    let mut tokens = tokenize(input);
+    let mut classified = Vec::new();
+    while let Some(token) = tokens.next() {
+        // Whitespace is skipped before a token rather than after, so the
+        // next character is the first one past it
+        let end = tokens.input.peek().map_or(length, |c| c.0);
+        let span = Span {
+            start: token.position,
+            end,
+        };
+        classified.push((span, token.class()));
+    }
+    classified
+}
+
 pub fn tokenize(input: &str) -> TokenIterator {
     TokenIterator {
         input: input.chars().enumerate().peekable(),
+        behind: input.chars(),
+        behind_position: 0,
+        line: 1,
+        column: 1,
     }
 }
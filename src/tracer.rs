@@ -0,0 +1,176 @@
+use anyhow::{anyhow, Context, Result};
+
+use crate::{
+    builtins::quote,
+    expression::ToAndFrom,
+    prelude::*,
+    shared::{Mutable, Shared},
+    AsyncBuiltinFunction, BuiltinFunction, BuiltinMacro, Environment, Generic, Lambda,
+    LispExpression, List, Symbol,
+};
+
+struct TraceState<E> {
+    /// Whether `#trace on` is tracing every function
+    all: bool,
+    /// Functions given to `trace`, under the names they were traced by
+    functions: Vec<(Symbol, E)>,
+    /// How many traced calls are being evaluated inside each other
+    depth: usize,
+}
+
+/// Prints the arguments and results of calls to traced functions, indented
+/// by how deeply they are nested. Clones share what is traced, so calls
+/// inside closures are traced too.
+#[derive(Clone)]
+pub struct Tracer<E>(Shared<Mutable<TraceState<E>>>);
+
+impl<E> Default for Tracer<E> {
+    fn default() -> Self {
+        Tracer(Shared::new(Mutable::new(TraceState {
+            all: false,
+            functions: Vec::new(),
+            depth: 0,
+        })))
+    }
+}
+
+impl<E> PartialEq for Tracer<E> {
+    fn eq(&self, other: &Self) -> bool {
+        Shared::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl<E: LispExpression> Tracer<E> {
+    /// Traces calls of every function, or only those given to `trace`
+    pub fn trace_all(&self, all: bool) {
+        self.0.borrow_mut().all = all;
+    }
+
+    pub fn trace(&self, name: Symbol, function: E) {
+        let mut state = self.0.borrow_mut();
+        state.functions.retain(|(traced, _)| *traced != name);
+        state.functions.push((name, function));
+    }
+
+    /// Stops tracing the function traced as `name`, returning whether it was
+    pub fn untrace(&self, name: &Symbol) -> bool {
+        let mut state = self.0.borrow_mut();
+        let before = state.functions.len();
+        state.functions.retain(|(traced, _)| traced != name);
+        state.functions.len() != before
+    }
+
+    pub fn is_active(&self) -> bool {
+        let state = self.0.borrow();
+        state.all || !state.functions.is_empty()
+    }
+
+    /// The name to show a call of `function` under, or `None` if it is not
+    /// traced. Calls are shown under the symbol they were made through.
+    fn traced_name(&self, head: &E, function: &E) -> Option<String> {
+        let state = self.0.borrow();
+        let traced = state
+            .functions
+            .iter()
+            .find(|(_, traced)| traced == function)
+            .map(|(name, _)| name.0.clone());
+        if traced.is_none() && !state.all {
+            return None;
+        }
+        if let Ok(symbol) = head.as_symbol() {
+            return Some(symbol.0.clone());
+        }
+        traced.or_else(
+            || match ToAndFrom::<BuiltinFunction<E>>::try_into_atom(function) {
+                Ok(builtin) => Some(builtin.name.to_owned()),
+                Err(_) => Some(function.variant().to_owned()),
+            },
+        )
+    }
+
+    fn write(&self, env: &Environment<E>, text: &str) -> Result<()> {
+        let indent = "  ".repeat(self.0.borrow().depth);
+        env.io
+            .write_stdout(&format!("{indent}{text}\n"))
+            .context("Could not write trace")
+    }
+}
+
+/// Only functions are traced, as macros see their arguments unevaluated
+pub(crate) fn is_function<E: LispExpression>(value: &E) -> bool {
+    ToAndFrom::<Lambda<E>>::try_into_atom(value).is_ok()
+        || ToAndFrom::<BuiltinFunction<E>>::try_into_atom(value).is_ok()
+        || ToAndFrom::<Generic<E>>::try_into_atom(value).is_ok()
+        || ToAndFrom::<AsyncBuiltinFunction<E>>::try_into_atom(value).is_ok()
+}
+
+/// Calls `function`, which `head` evaluated to, on unevaluated `arguments`,
+/// running the environment's hooks around it
+pub(crate) fn call<E>(
+    head: &E,
+    function: &E,
+    arguments: &[E],
+    env: &mut Environment<E>,
+) -> Result<E>
+where
+    E: LispExpression,
+{
+    if !env.hooks.is_active() {
+        return traced_call(head, function, arguments, env);
+    }
+    let hooks = env.hooks.clone();
+    let call: E = List::new(
+        core::iter::once(head.clone())
+            .chain(arguments.iter().cloned())
+            .collect(),
+    )
+    .into();
+    hooks.before(&call, function, env)?;
+    let result = traced_call(head, function, arguments, env);
+    hooks.after(&call, function, &result, env);
+    result
+}
+
+/// Calls `function` as `call` does, printing the call and its result if the
+/// function is traced
+fn traced_call<E>(head: &E, function: &E, arguments: &[E], env: &mut Environment<E>) -> Result<E>
+where
+    E: LispExpression,
+{
+    let tracer = env.tracer.clone();
+    let name = match tracer.is_active() && is_function(function) {
+        true => tracer.traced_name(head, function),
+        false => None,
+    };
+    let Some(name) = name else {
+        return function.as_atom().call(arguments, env);
+    };
+
+    let values: Vec<E> = arguments
+        .iter()
+        .enumerate()
+        .map(|(n, e)| {
+            e.eval(env)
+                .with_context(|| anyhow!("Argument number {}: {}", n + 1, e))
+        })
+        .collect::<Result<Vec<_>>>()
+        .with_context(|| anyhow!("Could not evaluate arguments to {}", name))?;
+    let shown: Vec<String> = core::iter::once(name)
+        .chain(values.iter().map(ToString::to_string))
+        .collect();
+    tracer.write(env, &format!("({})", shown.join(" ")))?;
+
+    // The arguments are already evaluated, so quote them for the call
+    let quoted: Vec<E> = values
+        .into_iter()
+        .map(|value| List::new(vec![BuiltinMacro::new("'", quote).into(), value]).into())
+        .collect();
+    tracer.0.borrow_mut().depth += 1;
+    let result = function.as_atom().call(&quoted, env);
+    tracer.0.borrow_mut().depth -= 1;
+    match &result {
+        Ok(value) => tracer.write(env, &format!("= {value}"))?,
+        Err(error) => tracer.write(env, &format!("! {}", error.root_cause()))?,
+    }
+    result
+}
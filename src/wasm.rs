@@ -0,0 +1,67 @@
+//! Bindings for running shallot in a browser, e.g. behind a playground page.
+//! Build for `wasm32-unknown-unknown` with the `wasm` feature and generate
+//! the JavaScript side with `wasm-bindgen`. Sessions evaluate with
+//! `DynExpression`, so atoms can be added without a layer, and capture what
+//! code prints, as a page has no standard streams to print to.
+
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    builtins, evaluate_program, printer,
+    shared::{Mutable, Shared},
+    CapturedIo, DynExpression, Environment,
+};
+
+/// An environment kept between evaluations, like the REPL's
+#[wasm_bindgen]
+pub struct Session {
+    environment: Environment<DynExpression>,
+    io: Shared<Mutable<CapturedIo>>,
+}
+
+/// A session with the core builtins, minus those needing files, processes or
+/// a clock
+#[wasm_bindgen]
+pub fn new_session() -> Session {
+    let mut environment = Environment::default();
+    builtins::set_environment(&mut environment);
+    let io = Shared::new(Mutable::new(CapturedIo::default()));
+    environment.io = io.clone().into();
+    Session { environment, io }
+}
+
+#[wasm_bindgen]
+impl Session {
+    /// Evaluates the expressions in `input`, returning what they printed and
+    /// any warnings followed by the value of the last, or the error, without
+    /// terminal colours
+    pub fn eval_to_string(&mut self, input: &str) -> String {
+        let result = evaluate_program(input, &mut self.environment);
+        let mut output = {
+            let mut io = self.io.borrow_mut();
+            let output = format!("{}{}", io.stdout, io.stderr);
+            io.stdout.clear();
+            io.stderr.clear();
+            output
+        };
+        for warning in self.environment.diagnostics.take() {
+            output.push_str(&format!("{warning}\n"));
+        }
+        let precision = self.environment.config.borrow().number_precision;
+        let text = result.and_then(|value| {
+            self.environment
+                .print_within_limits(|| printer::echo_text(&value, precision))
+        });
+        match text {
+            Ok(text) => output.push_str(&text),
+            Err(error) => output.push_str(&format!("error: {error:#}")),
+        }
+        printer::strip_colours(&output)
+    }
+}
+
+/// Evaluates `input` in a session of its own, for one-off snippets
+#[wasm_bindgen]
+pub fn eval_to_string(input: &str) -> String {
+    new_session().eval_to_string(input)
+}
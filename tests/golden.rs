@@ -0,0 +1,383 @@
+use shallot::*;
+
+create_layer!(atoms | builtins);
+
+#[test]
+fn fixtures() {
+    let fixtures = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+    let mismatches =
+        testing::check_fixtures::<Expression>(fixtures, builtins::set_environment).unwrap();
+    let report: Vec<String> = mismatches.iter().map(ToString::to_string).collect();
+    assert!(mismatches.is_empty(), "{}", report.join("\n\n"));
+}
+
+#[test]
+fn fixtures_with_dyn_expression() {
+    let fixtures = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+    let mismatches =
+        testing::check_fixtures::<DynExpression>(fixtures, builtins::set_environment).unwrap();
+    let report: Vec<String> = mismatches.iter().map(ToString::to_string).collect();
+    assert!(mismatches.is_empty(), "{}", report.join("\n\n"));
+}
+
+fn limited_environment(env: &mut Environment<Expression>) {
+    builtins::set_environment(env);
+    env.config.borrow_mut().max_allocation = Some(100_000);
+}
+
+#[test]
+fn fixtures_within_allocation_limit() {
+    let fixtures = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/allocation_limit"
+    );
+    let mismatches = testing::check_fixtures::<Expression>(fixtures, limited_environment).unwrap();
+    let report: Vec<String> = mismatches.iter().map(ToString::to_string).collect();
+    assert!(mismatches.is_empty(), "{}", report.join("\n\n"));
+}
+
+fn fuelled_environment(env: &mut Environment<Expression>) {
+    builtins::set_environment(env);
+    env.set_fuel(Some(1000));
+}
+
+#[test]
+fn fixtures_within_fuel() {
+    let fixtures = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/fuel_limit");
+    let mismatches = testing::check_fixtures::<Expression>(fixtures, fuelled_environment).unwrap();
+    let report: Vec<String> = mismatches.iter().map(ToString::to_string).collect();
+    assert!(mismatches.is_empty(), "{}", report.join("\n\n"));
+}
+
+std::thread_local! {
+    static CALLBACK: std::cell::RefCell<Option<Callback<Expression, Expression>>> =
+        const { std::cell::RefCell::new(None) };
+}
+
+#[test]
+fn callbacks_are_reentrant() {
+    let mut interpreter = Interpreter::<Expression>::new();
+    interpreter.define_fn("host", |arguments, _env| {
+        CALLBACK.with(|callback| callback.borrow().as_ref().unwrap()(arguments))
+    });
+    interpreter
+        .eval("(define 'count-down (λ (n) (cond (= n 0) 'done (host (- n 1)))))")
+        .unwrap();
+    let callback = interpreter.environment.callback("count-down").unwrap();
+    CALLBACK.with(|slot| *slot.borrow_mut() = Some(callback));
+    let result =
+        CALLBACK.with(|callback| callback.borrow().as_ref().unwrap()(&[Number(3.).into()]));
+    assert_eq!(result.unwrap(), Symbol::from("done").into());
+}
+
+#[test]
+fn layers_protect_only_their_own_bindings() {
+    let mut env = Environment::<Expression>::default();
+    env.define("mine", Number(1.));
+    builtins::set_environment(&mut env);
+    assert!(!env.is_protected(&"mine".into()));
+    assert!(env.is_protected(&"list".into()));
+}
+
+fn builtin_name(function: &Expression) -> Option<&'static str> {
+    ToAndFrom::<BuiltinFunction<Expression>>::try_into_atom(function)
+        .ok()
+        .map(|builtin| builtin.name)
+}
+
+#[test]
+fn hooks_run_around_every_application() {
+    let mut interpreter = Interpreter::<Expression>::new();
+    let hooks = interpreter.environment.hooks.clone();
+    hooks.add_before(|_call, function, _env| match builtin_name(function) {
+        Some("to-string") => anyhow::bail!("to-string is forbidden"),
+        _ => Ok(()),
+    });
+    let called = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let recorded = called.clone();
+    hooks.add_after(move |_call, function, result, _env| {
+        if let (Some(name), Ok(value)) = (builtin_name(function), result) {
+            let entry = printer::strip_colours(&format!("{name} = {value}"));
+            recorded.lock().unwrap().push(entry);
+        }
+    });
+
+    interpreter.eval("(map list '(1 2))").unwrap();
+    assert_eq!(
+        *called.lock().unwrap(),
+        ["list = (1)", "list = (2)", "map = ((1) (2))"]
+    );
+
+    let refused = [
+        "(to-string 1)",
+        "(map to-string '(1))",
+        "(filter to-string '(1))",
+        "(list (define 'show to-string) (show 1))",
+    ];
+    for source in refused {
+        let error = interpreter.eval(source).unwrap_err();
+        assert_eq!(error.root_cause().to_string(), "to-string is forbidden");
+    }
+}
+
+#[test]
+fn events_follow_evaluation_and_report_an_error_once() {
+    let mut interpreter = Interpreter::<Expression>::new();
+    let events = interpreter.environment.events.channel();
+    let source = "(define 'x 2)\n(+ x\n   (car 5))";
+    evaluate_program(source, &mut interpreter.environment).unwrap_err();
+    let events: Vec<String> = events
+        .try_iter()
+        .map(|event| {
+            let kind = match &event.kind {
+                EventKind::EnterCall { call } => format!("enter {call}"),
+                EventKind::Define { name, value } => format!("define {name} {value}"),
+                EventKind::MacroExpand { expansion, .. } => format!("expand {expansion}"),
+                EventKind::Error { call, .. } => format!("error {call}"),
+            };
+            let span = event.location.unwrap();
+            let span = format!(
+                "{}:{}-{}:{}",
+                span.line, span.column, span.end_line, span.end_column
+            );
+            printer::strip_colours(&format!("{span} {kind}"))
+        })
+        .collect();
+    // The error passes through `(+ ...)` too, but is only reported once
+    assert_eq!(
+        events,
+        [
+            "1:1-1:14 enter (define (' x) 2)",
+            "1:1-1:14 enter (' x)",
+            "1:1-1:14 define x 2",
+            "2:1-3:12 enter (+ x (car 5))",
+            "2:1-3:12 enter (car 5)",
+            "2:1-3:12 error (car 5)",
+        ]
+    );
+}
+
+#[test]
+fn saved_sessions_keep_captured_bindings() {
+    let mut interpreter = Interpreter::<Expression>::new();
+    let session = "(define 'make-adder (λ (n) (λ (x) (+ x n))))
+        (define 'add5 (make-adder 5))
+        (define 'y 1)
+        (define 'get-y (λ () y))
+        (define 'y 2)
+        (defun count-down (n) (cond (= n 0) 'done (count-down (- n 1))))";
+    evaluate_program(session, &mut interpreter.environment).unwrap();
+    let saved = interpreter.environment.serialize_definitions().unwrap();
+
+    let mut restored = Interpreter::<Expression>::new();
+    evaluate_program(&saved, &mut restored.environment).unwrap();
+    assert_eq!(restored.eval("(add5 1)").unwrap(), Number(6.).into());
+    assert_eq!(restored.eval("(get-y)").unwrap(), Number(1.).into());
+    assert_eq!(
+        restored.eval("(count-down 3)").unwrap(),
+        Symbol::from("done").into()
+    );
+
+    interpreter
+        .eval("(define 'is-even (letrec ((even (λ (n) (cond (= n 0) 1 (even (- n 1)))))) even))")
+        .unwrap();
+    let error = interpreter.environment.serialize_definitions().unwrap_err();
+    assert_eq!(
+        error.root_cause().to_string(),
+        "It calls `even` from a letrec, which cannot be written as source"
+    );
+}
+
+#[test]
+fn saved_sessions_refuse_lists_too_deep_to_read_back() {
+    let mut interpreter = Interpreter::<Expression>::new();
+    interpreter
+        .eval("(define 'd (last (take 100000 (iterate (λ (x) (list x)) '()))))")
+        .unwrap();
+    let error = interpreter.environment.serialize_definitions().unwrap_err();
+    assert_eq!(
+        error.root_cause().to_string(),
+        "Cannot write lists nested more than 1000 deep"
+    );
+    assert!(interpreter
+        .environment
+        .dump_source()
+        .contains("; `d` has no source: Cannot write lists nested more than 1000 deep"));
+}
+
+#[cfg(feature = "ffi")]
+#[test]
+fn ffi_hands_out_handles_and_reports_errors() {
+    use shallot::ffi::*;
+    use std::ffi::{CStr, CString};
+
+    unsafe fn text(pointer: *const std::ffi::c_char) -> Option<String> {
+        (!pointer.is_null()).then(|| CStr::from_ptr(pointer).to_str().unwrap().to_owned())
+    }
+
+    unsafe {
+        let interpreter = shallot_new();
+        let eval = |source: &str| shallot_eval(interpreter, CString::new(source).unwrap().as_ptr());
+
+        let sum = eval("(define 'x 2) (+ x 3)");
+        assert_ne!(sum, 0);
+        assert_eq!(shallot_last_error(interpreter), std::ptr::null());
+        let mut number = 0.;
+        assert!(shallot_get_number(interpreter, sum, &mut number));
+        assert_eq!(number, 5.);
+
+        let greeting = eval("\"hello\"");
+        let string = shallot_get_string(interpreter, greeting);
+        assert_eq!(text(string).unwrap(), "hello");
+        shallot_free_string(string);
+        assert!(!shallot_get_number(interpreter, greeting, &mut number));
+        assert_eq!(number, 5.);
+
+        assert_eq!(eval("(car 5)"), 0);
+        assert!(text(shallot_last_error(interpreter)).is_some());
+        // A success clears the error of the call before
+        assert_ne!(eval("x"), 0);
+        assert_eq!(shallot_last_error(interpreter), std::ptr::null());
+
+        shallot_release(interpreter, sum);
+        assert!(shallot_get_string(interpreter, sum).is_null());
+        assert_eq!(
+            text(shallot_last_error(interpreter)).unwrap(),
+            format!("No value has the handle {sum}")
+        );
+        // Handles are never reused, so the released one stays stale
+        assert_ne!(eval("1"), sum);
+        assert!(!shallot_get_number(interpreter, sum, &mut number));
+
+        shallot_free(interpreter);
+    }
+}
+
+struct Counter(f64);
+
+#[test]
+fn methods_dispatch_on_foreign_values() {
+    let mut interpreter = Interpreter::<Expression>::new();
+    interpreter
+        .environment
+        .set("counter", Foreign::new("counter", Counter(3.)));
+    interpreter.define_method("counter", "count", |counter: &Counter, arguments, _env| {
+        let step: f64 = arguments.first().map_or(Ok(0.), |step| step.extract())?;
+        Ok(Number(counter.0 + step).into())
+    });
+    assert_eq!(
+        interpreter.eval("(count counter)").unwrap(),
+        Number(3.).into()
+    );
+    assert_eq!(
+        interpreter.eval("(count counter 2)").unwrap(),
+        Number(5.).into()
+    );
+
+    // A method expecting another Rust type errors rather than panicking
+    interpreter.define_method("counter", "label", |label: &String, _arguments, _env| {
+        Ok(Str(label.clone()).into())
+    });
+    let error = interpreter.eval("(label counter)").unwrap_err();
+    assert_eq!(
+        error.root_cause().to_string(),
+        "Expected a foreign alloc::string::String and got a counter"
+    );
+    let error = interpreter.eval("(count 1)").unwrap_err();
+    assert_eq!(
+        error.root_cause().to_string(),
+        "Generic function count has no method for a number"
+    );
+}
+
+fn counter_attribute(foreign: &Foreign, name: &str) -> Option<Expression> {
+    let counter: &Counter = foreign.downcast_ref()?;
+    match name {
+        "count" => Some(Number(counter.0).into()),
+        "double" => Some(Number(counter.0 * 2.).into()),
+        _ => None,
+    }
+}
+
+#[test]
+fn accessors_read_attributes_of_foreign_values() {
+    let mut interpreter = Interpreter::<Expression>::new();
+    interpreter
+        .environment
+        .set("counter", Foreign::new("counter", Counter(4.)));
+    let error = interpreter.eval("(get-attr counter 'count)").unwrap_err();
+    assert_eq!(
+        error.root_cause().to_string(),
+        "Foreign counter values have no accessor"
+    );
+
+    interpreter
+        .environment
+        .accessors
+        .set("counter", counter_attribute);
+    assert_eq!(
+        interpreter.eval("(get-attr counter 'count)").unwrap(),
+        Number(4.).into()
+    );
+    assert_eq!(
+        interpreter.eval("(get-attr counter :double)").unwrap(),
+        Number(8.).into()
+    );
+    assert_eq!(
+        interpreter.eval("(. counter count)").unwrap(),
+        Number(4.).into()
+    );
+    let error = interpreter.eval("(get-attr counter \"size\")").unwrap_err();
+    assert_eq!(
+        error.root_cause().to_string(),
+        "Foreign counter values have no attribute size"
+    );
+}
+
+#[test]
+fn tokens_are_classified_with_their_spans() {
+    let source = "(f 'x 1.5 \"hé\" :k) ; done";
+    let classified: Vec<(usize, usize, TokenClass)> = tokenize_classified(source)
+        .into_iter()
+        .map(|(span, class)| (span.start, span.end, class))
+        .collect();
+    assert_eq!(
+        classified,
+        [
+            (0, 1, TokenClass::Paren),
+            (1, 2, TokenClass::Symbol),
+            (3, 4, TokenClass::Quote),
+            (4, 5, TokenClass::Symbol),
+            (6, 9, TokenClass::Number),
+            (10, 14, TokenClass::String),
+            (15, 17, TokenClass::Keyword),
+            (17, 18, TokenClass::Paren),
+            (19, 25, TokenClass::Comment),
+        ]
+    );
+    assert_eq!(
+        printer::highlight("(+ 1 x)"),
+        "(\x1b[0;32m+\x1b[0m \x1b[0;36m1\x1b[0m \x1b[0;32mx\x1b[0m)"
+    );
+    // Quasiquote and unquote are split off what they quote, as `'` is
+    let classes: Vec<TokenClass> = tokenize_classified("`a ,b ,@c")
+        .into_iter()
+        .map(|(_, class)| class)
+        .collect();
+    assert_eq!(
+        classes,
+        [
+            TokenClass::Quote,
+            TokenClass::Symbol,
+            TokenClass::Quote,
+            TokenClass::Symbol,
+            TokenClass::Quote,
+            TokenClass::Symbol,
+        ]
+    );
+    // Characters have a class and colour of their own, apart from strings
+    assert_eq!(
+        printer::highlight("#\\a \"a\""),
+        "\x1b[0;34m#\\a\x1b[0m \x1b[0;33m\"a\"\x1b[0m"
+    );
+}